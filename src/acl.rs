@@ -0,0 +1,124 @@
+/// Access control lists.
+///
+/// Only the `default` user exists and it may run every command, but users are
+/// already looked up by name here so that rules can be enforced later.
+use bytes::Bytes;
+
+use std::collections::HashMap;
+
+use crate::proto::Value;
+
+/// The command categories reported by `ACL CAT`.
+pub const CATEGORIES: &[&str] = &[
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "hash",
+    "string",
+    "bitmap",
+    "hyperloglog",
+    "geo",
+    "stream",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+];
+
+pub const DEFAULT_USER: &str = "default";
+
+pub struct User {
+    name: String,
+    enabled: bool,
+    /// Passwords are not supported yet, so every user is `nopass`.
+    nopass: bool,
+}
+
+impl User {
+    fn default_user() -> Self {
+        Self {
+            name: String::from(DEFAULT_USER),
+            enabled: true,
+            nopass: true,
+        }
+    }
+
+    fn flags(&self) -> Vec<&'static str> {
+        let mut flags = Vec::with_capacity(2);
+
+        flags.push(if self.enabled { "on" } else { "off" });
+
+        if self.nopass {
+            flags.push("nopass");
+        }
+
+        flags
+    }
+
+    /// The rules of this user in the format used by `ACL LIST`.
+    pub fn describe(&self) -> String {
+        let mut description = format!("user {}", self.name);
+
+        for flag in self.flags() {
+            description.push(' ');
+            description.push_str(flag);
+        }
+
+        description.push_str(" ~* &* +@all");
+
+        description
+    }
+
+    /// The reply to `ACL GETUSER`.
+    pub fn to_value(&self) -> Value {
+        let flags = self
+            .flags()
+            .into_iter()
+            .map(|flag| Value::BulkString(Bytes::from(flag)))
+            .collect();
+
+        Value::Array(vec![
+            Value::BulkString(Bytes::from("flags")),
+            Value::Array(flags),
+            Value::BulkString(Bytes::from("passwords")),
+            Value::Array(Vec::new()),
+            Value::BulkString(Bytes::from("commands")),
+            Value::BulkString(Bytes::from("+@all")),
+            Value::BulkString(Bytes::from("keys")),
+            Value::BulkString(Bytes::from("~*")),
+            Value::BulkString(Bytes::from("channels")),
+            Value::BulkString(Bytes::from("&*")),
+            Value::BulkString(Bytes::from("selectors")),
+            Value::Array(Vec::new()),
+        ])
+    }
+}
+
+pub struct Acl {
+    users: HashMap<String, User>,
+}
+
+impl Acl {
+    pub fn new() -> Self {
+        let mut users = HashMap::new();
+        users.insert(String::from(DEFAULT_USER), User::default_user());
+
+        Self { users }
+    }
+
+    pub fn user(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+}
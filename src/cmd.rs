@@ -1,15 +1,29 @@
-use log::error;
+use bytes::Bytes;
 
 use std::{
     collections::VecDeque,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    ops::Bound,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
-    db::Db,
-    proto::{ParseError, Value},
+    acl::{self, DEFAULT_USER},
+    command_table::{self, COMMANDS},
+    connection::ConnectionState,
+    db::{Db, SetOutcome},
+    dump,
+    geo::{self, Center, Order, Shape},
+    info,
+    object::Object,
+    proto::{parse_bytes, ParseError, ProtocolVersion, RedisError, Value},
+    stream::{self, IdSpec, PendingFilter, StreamEntry, StreamId, TrimStrategy},
+    zset::{self, AddCondition, ScoreUpdate},
 };
 
+/// The Redis version reported to clients, which decide on the available
+/// features based on it.
+pub const REDIS_VERSION: &str = "7.0.0";
+
 pub enum SetBehaviour {
     Force,
     OnlyIfNotExists,
@@ -24,335 +38,7423 @@ pub enum ExpireBehaviour {
     OnlyIfLess,
 }
 
+/// The range BITCOUNT counts in.
+pub struct BitRange {
+    pub start: i64,
+    pub end: i64,
+    /// Whether start and end are bit indices instead of byte indices.
+    pub in_bits: bool,
+}
+
+/// Bit offsets must be within the largest string Redis allows, 512MB.
+const MAX_BIT_OFFSET: i64 = 512 * 1024 * 1024 * 8 - 1;
+
+pub enum GetExBehaviour {
+    Keep,
+    Expire(Duration),
+    Persist,
+}
+
+/// The reply of `XREAD` and `XREADGROUP`, nil if no stream has entries.
+fn stream_entries_to_value(results: Vec<(String, Vec<StreamEntry>)>) -> Value {
+    if results.is_empty() {
+        return Value::NullArray;
+    }
+
+    let streams = results
+        .into_iter()
+        .map(|(key, entries)| {
+            let entries = entries.iter().map(|entry| entry.to_value()).collect();
+
+            Value::Array(vec![
+                Value::BulkString(Bytes::from(key)),
+                Value::Array(entries),
+            ])
+        })
+        .collect();
+
+    Value::Array(streams)
+}
+
+/// The reply of `LPOP` and `RPOP`, a single element unless a count was
+/// given.
+fn pop_reply(result: Result<Option<Vec<String>>, Value>, with_count: bool) -> Value {
+    match result {
+        Ok(Some(elements)) if with_count => Value::Array(
+            elements
+                .into_iter()
+                .map(Bytes::from)
+                .map(Value::BulkString)
+                .collect(),
+        ),
+        Ok(Some(mut elements)) => elements.pop().map_or(Value::NullString, |value| {
+            Value::BulkString(Bytes::from(value))
+        }),
+        Ok(None) if with_count => Value::NullArray,
+        Ok(None) => Value::NullString,
+        Err(err) => err,
+    }
+}
+
+/// The reply of `BLPOP` and `BRPOP`, the key and the popped element.
+fn blocking_pop_reply(result: Result<Option<(String, String)>, Value>) -> Value {
+    match result {
+        Ok(Some((key, element))) => Value::Array(vec![
+            Value::BulkString(Bytes::from(key)),
+            Value::BulkString(Bytes::from(element)),
+        ]),
+        Ok(None) => Value::NullArray,
+        Err(err) => err,
+    }
+}
+
+/// The reply of the introspection commands for a missing key.
+fn no_such_key_error() -> Value {
+    Value::Error(RedisError {
+        message: String::from("ERR no such key"),
+    })
+}
+
+/// Redis deliberately answers missing keys differently per command (an
+/// error, nil, 0, -2 or an empty array), so every command operating on keys
+/// documents its reply for a missing key. `missing_key_replies_work` pins them.
 pub enum RedisCommand {
     /// https://redis.io/commands/command/ - no arguments
     Command,
+    /// https://redis.io/commands/command-info/ - array of command names
+    CommandInfo(Vec<String>),
     /// https://redis.io/commands/command-docs/ - array of command names
     CommandDocs(Vec<String>),
+    /// https://redis.io/commands/command-count/ - no arguments
+    CommandCount,
+    /// https://redis.io/commands/command-list/ - no arguments
+    CommandList,
     /// https://redis.io/commands/config-get/ - array of config parameters
     ConfigGet(Vec<String>),
+    /// https://redis.io/commands/config-set/ - parameters and values
+    ConfigSet(Vec<(String, String)>),
+    /// https://redis.io/commands/auth/ - optional string of user name and
+    /// string of password
+    Auth {
+        username: Option<String>,
+        password: String,
+    },
+    /// https://redis.io/commands/hello/ - optional protocol version, which
+    /// may be followed by AUTH with user name and password and SETNAME with
+    /// a client name
+    Hello {
+        version: Option<i64>,
+        auth: Option<(String, String)>,
+        name: Option<String>,
+    },
+    /// https://redis.io/commands/ping/ - optional message
+    Ping(Option<Bytes>),
+    /// https://redis.io/commands/echo/ - string of message
+    Echo(Bytes),
+    /// https://redis.io/commands/lolwut/ - the server version, optionally
+    /// introduced by the requested art version
+    Lolwut(Option<i64>),
+    /// https://redis.io/commands/lastsave/ - Unix time of the last save,
+    /// which is the start time without persistence
+    LastSave,
+    /// https://redis.io/commands/time/ - Unix time in seconds and the
+    /// microseconds within the second
+    Time,
+    /// https://redis.io/commands/select/ - integer of database index, only
+    /// database 0 exists
+    Select(i64),
+    /// https://redis.io/commands/multi/ - no arguments
+    Multi,
+    /// https://redis.io/commands/exec/ - no arguments
+    Exec,
+    /// https://redis.io/commands/discard/ - no arguments
+    Discard,
+    /// https://redis.io/commands/watch/ - array of keys
+    ///
+    /// Missing key: watched like any other key, creating it aborts EXEC
+    Watch(Vec<String>),
+    /// https://redis.io/commands/unwatch/ - no arguments
+    Unwatch,
+    /// https://redis.io/commands/reset/ - no arguments
+    Reset,
+    /// https://redis.io/commands/subscribe/ - array of channels
+    Subscribe(Vec<String>),
+    /// https://redis.io/commands/unsubscribe/ - array of channels, all if
+    /// empty
+    Unsubscribe(Vec<String>),
+    /// https://redis.io/commands/publish/ - string of channel and string of
+    /// message
+    Publish { channel: String, message: Bytes },
     /// https://redis.io/commands/get/ - string of key name
+    ///
+    /// Missing key: nil
     Get(String),
+    /// https://redis.io/commands/getdel/ - string of key name
+    ///
+    /// Missing key: nil
+    GetDel(String),
+    /// https://redis.io/commands/getex/ - get key and change its TTL
+    ///
+    /// Missing key: nil
+    GetEx {
+        key: String,
+        behaviour: GetExBehaviour,
+    },
+    /// https://redis.io/commands/mget/ - array of key names
+    ///
+    /// Missing key: nil in its place
+    MGet(Vec<String>),
     /// https://redis.io/commands/set/ - set key to value with options
+    ///
+    /// Missing key: nil with XX or GET, OK otherwise
     Set {
         key: String,
-        value: Value,
+        value: Bytes,
         expiry: Option<Duration>,
         behaviour: SetBehaviour,
         return_old: bool,
         keep_ttl: bool,
     },
+    /// https://redis.io/commands/getset/ - set key to value and return the
+    /// old value, dropping any TTL
+    ///
+    /// Missing key: nil
+    GetSet { key: String, value: Bytes },
+    /// https://redis.io/commands/setnx/ - set key to value if it does not
+    /// exist
+    ///
+    /// Missing key: 1
+    SetNx { key: String, value: Bytes },
+    /// https://redis.io/commands/mset/ - set multiple keys to values
+    MSet(Vec<(String, Bytes)>),
+    /// https://redis.io/commands/msetnx/ - set multiple keys to values if none exist
+    MSetNx(Vec<(String, Bytes)>),
     /// https://redis.io/commands/del/ - delete keys
+    ///
+    /// Missing key: not counted
     Del(Vec<String>),
+    /// https://redis.io/commands/unlink/ - delete keys, freeing their values
+    /// in the background
+    ///
+    /// Missing key: not counted
+    Unlink(Vec<String>),
+    /// https://redis.io/commands/rename/ - rename key, keeping its TTL
+    ///
+    /// Missing key: error
+    Rename { src: String, dst: String },
+    /// https://redis.io/commands/renamenx/ - rename key if the new name is unused
+    ///
+    /// Missing key: error
+    RenameNx { src: String, dst: String },
+    /// https://redis.io/commands/copy/ - copy key with its TTL, optional
+    /// REPLACE to overwrite dst
+    ///
+    /// Missing key: 0
+    Copy {
+        src: String,
+        dst: String,
+        replace: bool,
+    },
+    /// https://redis.io/commands/dump/ - serialized value of key
+    ///
+    /// Missing key: nil
+    Dump(String),
+    /// https://redis.io/commands/restore/ - store value deserialized from a
+    /// DUMP payload at key with a TTL in ms or 0 for none, optional REPLACE
+    /// to overwrite key
+    ///
+    /// Missing key: value is stored
+    Restore {
+        key: String,
+        ttl: Option<Duration>,
+        value: Object,
+        replace: bool,
+    },
     /// https://redis.io/commands/ttl/ - TTL for key
+    ///
+    /// Missing key: -2
     Ttl(String),
     /// https://redis.io/commands/pttl/ - TTL in ms for key
+    ///
+    /// Missing key: -2
     Pttl(String),
-    /// https://redis.io/commands/expire/ - set TTL for key
+    /// https://redis.io/commands/expire/ - set TTL for key, also PEXPIRE,
+    /// EXPIREAT and PEXPIREAT with the time converted to a timeout
+    ///
+    /// Missing key: 0
     Expire {
         key: String,
-        seconds: u64,
+        timeout: Duration,
         behaviour: ExpireBehaviour,
     },
+    /// https://redis.io/commands/persist/ - remove TTL of key
+    ///
+    /// Missing key: 0
+    Persist(String),
+    /// https://redis.io/commands/exists/ - count existing keys
+    ///
+    /// Missing key: not counted
+    Exists(Vec<String>),
+    /// https://redis.io/commands/touch/ - count existing keys and mark them
+    /// as accessed
+    ///
+    /// Missing key: not counted
+    Touch(Vec<String>),
+    /// https://redis.io/commands/incr/ - increment integer value of key by one
+    ///
+    /// Missing key: treated as 0
+    Incr(String),
+    /// https://redis.io/commands/decr/ - decrement integer value of key by one
+    ///
+    /// Missing key: treated as 0
+    Decr(String),
+    /// https://redis.io/commands/incrby/ - increment integer value of key
+    ///
+    /// Missing key: treated as 0
+    IncrBy { key: String, increment: i64 },
+    /// https://redis.io/commands/decrby/ - decrement integer value of key
+    ///
+    /// Missing key: treated as 0
+    DecrBy { key: String, decrement: i64 },
+    /// https://redis.io/commands/incrbyfloat/ - increment float value of key
+    ///
+    /// Missing key: treated as 0
+    IncrByFloat { key: String, increment: f64 },
+    /// https://redis.io/commands/append/ - append value to string of key
+    ///
+    /// Missing key: created like SET
+    Append { key: String, value: Bytes },
+    /// https://redis.io/commands/setrange/ - overwrite string of key from
+    /// offset on with value
+    ///
+    /// Missing key: created with zero bytes unless value is empty
+    SetRange {
+        key: String,
+        offset: usize,
+        value: Bytes,
+    },
+    /// https://redis.io/commands/strlen/ - length of string of key
+    ///
+    /// Missing key: 0
+    Strlen(String),
+    /// https://redis.io/commands/setbit/ - set or clear bit at offset of
+    /// string of key
+    ///
+    /// Missing key: created with zero bytes
+    SetBit {
+        key: String,
+        offset: usize,
+        bit: bool,
+    },
+    /// https://redis.io/commands/getbit/ - bit at offset of string of key
+    ///
+    /// Missing key: 0
+    GetBit { key: String, offset: usize },
+    /// https://redis.io/commands/bitcount/ - count set bits of string of key,
+    /// optionally within a range of bytes or bits
+    ///
+    /// Missing key: 0
+    BitCount {
+        key: String,
+        range: Option<BitRange>,
+    },
+    /// https://redis.io/commands/lpush/ - prepend elements to list of key
+    ///
+    /// Missing key: list is created
+    LPush { key: String, elements: Vec<String> },
+    /// https://redis.io/commands/rpush/ - append elements to list of key
+    ///
+    /// Missing key: list is created
+    RPush { key: String, elements: Vec<String> },
+    /// https://redis.io/commands/lpop/ - remove elements from head of list
+    ///
+    /// Missing key: nil, or nil array with a count
+    LPop { key: String, count: Option<usize> },
+    /// https://redis.io/commands/rpop/ - remove elements from tail of list
+    ///
+    /// Missing key: nil, or nil array with a count
+    RPop { key: String, count: Option<usize> },
+    /// https://redis.io/commands/blpop/ - remove the head of the first
+    /// non-empty list of keys, waiting up to the timeout for one
+    ///
+    /// Missing key: waits for the list to be created
+    BLPop {
+        keys: Vec<String>,
+        timeout: Option<Duration>,
+    },
+    /// https://redis.io/commands/brpop/ - remove the tail of the first
+    /// non-empty list of keys, waiting up to the timeout for one
+    ///
+    /// Missing key: waits for the list to be created
+    BRPop {
+        keys: Vec<String>,
+        timeout: Option<Duration>,
+    },
+    /// https://redis.io/commands/llen/ - length of list of key
+    ///
+    /// Missing key: 0
+    LLen(String),
+    /// https://redis.io/commands/lrange/ - elements of list between indices
+    ///
+    /// Missing key: empty array
+    LRange { key: String, start: i64, stop: i64 },
+    /// https://redis.io/commands/hset/ - set fields of hash of key
+    ///
+    /// Missing key: hash is created
+    HSet {
+        key: String,
+        pairs: Vec<(String, String)>,
+    },
+    /// https://redis.io/commands/hget/ - value of field of hash of key
+    ///
+    /// Missing key: nil
+    HGet { key: String, field: String },
+    /// https://redis.io/commands/hdel/ - remove fields of hash of key
+    ///
+    /// Missing key: 0
+    HDel { key: String, fields: Vec<String> },
+    /// https://redis.io/commands/hgetall/ - fields and values of hash of key
+    ///
+    /// Missing key: empty array
+    HGetAll(String),
+    /// https://redis.io/commands/hscan/ - key, cursor, optional glob-style
+    /// pattern and count hint
+    ///
+    /// Missing key: cursor 0 and empty array
+    HScan {
+        key: String,
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+    },
+    /// https://redis.io/commands/sadd/ - add members to set of key
+    ///
+    /// Missing key: set is created
+    SAdd { key: String, members: Vec<String> },
+    /// https://redis.io/commands/srem/ - remove members from set of key
+    ///
+    /// Missing key: 0
+    SRem { key: String, members: Vec<String> },
+    /// https://redis.io/commands/sismember/ - whether member is in set of key
+    ///
+    /// Missing key: 0
+    SIsMember { key: String, member: String },
+    /// https://redis.io/commands/smembers/ - members of set of key
+    ///
+    /// Missing key: empty array
+    SMembers(String),
+    /// https://redis.io/commands/sscan/ - key, cursor, optional glob-style
+    /// pattern and count hint
+    ///
+    /// Missing key: cursor 0 and empty array
+    SScan {
+        key: String,
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+    },
+    /// https://redis.io/commands/scard/ - number of members of set of key
+    ///
+    /// Missing key: 0
+    SCard(String),
+    /// https://redis.io/commands/spop/ - remove random members of set of key
+    ///
+    /// Missing key: nil, or empty array with a count
+    SPop { key: String, count: Option<usize> },
+    /// https://redis.io/commands/zadd/ - add members with scores to sorted
+    /// set of key, optionally only new or existing ones, only raising or
+    /// lowering scores and counting changed rather than added members
+    ///
+    /// Missing key: sorted set is created, unless with XX
+    ZAdd {
+        key: String,
+        members: Vec<(f64, String)>,
+        condition: AddCondition,
+        update: ScoreUpdate,
+        count_changed: bool,
+    },
+    /// https://redis.io/commands/zscore/ - score of member of sorted set of
+    /// key
+    ///
+    /// Missing key: nil
+    ZScore { key: String, member: String },
+    /// https://redis.io/commands/zcard/ - number of members of sorted set of
+    /// key
+    ///
+    /// Missing key: 0
+    ZCard(String),
+    /// https://redis.io/commands/zrange/ - members of sorted set of key
+    /// between two ranks, optionally from the highest score and with scores
+    ///
+    /// Missing key: empty array
+    ZRange {
+        key: String,
+        start: i64,
+        stop: i64,
+        reverse: bool,
+        with_scores: bool,
+    },
+    /// https://redis.io/commands/zrangebyscore/ - members of sorted set of key
+    /// between two scores, optionally with scores and limited to a window
+    ///
+    /// Missing key: empty array
+    ZRangeByScore {
+        key: String,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        with_scores: bool,
+        offset: usize,
+        count: Option<usize>,
+    },
+    /// https://redis.io/commands/zincrby/ - add increment to score of member
+    /// of sorted set of key
+    ///
+    /// Missing key: sorted set is created with the increment as score
+    ZIncrBy {
+        key: String,
+        increment: f64,
+        member: String,
+    },
+    /// https://redis.io/commands/geopos/ - longitudes and latitudes of
+    /// members of geospatial index of key. GEOADD is parsed as ZADD.
+    ///
+    /// Missing key: nil for every member
+    GeoPos { key: String, members: Vec<String> },
+    /// https://redis.io/commands/geodist/ - distance between two members of
+    /// geospatial index of key in a unit, meters by default
+    ///
+    /// Missing key: nil
+    GeoDist {
+        key: String,
+        from: String,
+        to: String,
+        unit: f64,
+    },
+    /// https://redis.io/commands/geosearch/ - members of geospatial index of
+    /// key within a radius or box around a member or position, optionally
+    /// sorted by distance, limited and with coordinates, distances and
+    /// geohashes
+    ///
+    /// Missing key: empty array
+    GeoSearch {
+        key: String,
+        center: Center,
+        shape: Shape,
+        unit: f64,
+        order: Option<Order>,
+        count: Option<usize>,
+        any: bool,
+        with_coord: bool,
+        with_dist: bool,
+        with_hash: bool,
+    },
+    /// https://redis.io/commands/xadd/ - append entry to stream of key
+    ///
+    /// Missing key: stream is created
+    XAdd {
+        key: String,
+        trim: Option<TrimStrategy>,
+        id: IdSpec,
+        fields: Vec<(String, String)>,
+    },
+    /// https://redis.io/commands/xtrim/ - remove oldest entries of stream
+    ///
+    /// Missing key: 0
+    XTrim { key: String, strategy: TrimStrategy },
+    /// https://redis.io/commands/xlen/ - string of key name
+    ///
+    /// Missing key: 0
+    XLen(String),
+    /// https://redis.io/commands/xrange/ - entries of stream between IDs
+    ///
+    /// Missing key: empty array
+    XRange {
+        key: String,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+    },
+    /// https://redis.io/commands/xread/ - entries of streams after IDs
+    ///
+    /// Missing key: skipped, nil if no stream has new entries
+    XRead {
+        /// The ID is `None` for `$`, the last entry.
+        streams: Vec<(String, Option<StreamId>)>,
+        count: Option<usize>,
+        /// Blocks until an entry is added or the timeout elapsed, a timeout
+        /// of zero blocks forever.
+        block: Option<Duration>,
+    },
+    /// https://redis.io/commands/xgroup-create/ - create consumer group of stream
+    ///
+    /// Missing key: error, stream is created with MKSTREAM
+    XGroupCreate {
+        key: String,
+        group: String,
+        /// The ID is `None` for `$`, the last entry.
+        id: Option<StreamId>,
+        mkstream: bool,
+    },
+    /// https://redis.io/commands/xreadgroup/ - entries of streams for consumer
+    ///
+    /// Missing key: error
+    XReadGroup {
+        group: String,
+        consumer: String,
+        /// The ID is `None` for `>`, entries never delivered to the group.
+        streams: Vec<(String, Option<StreamId>)>,
+        count: Option<usize>,
+        noack: bool,
+    },
+    /// https://redis.io/commands/xack/ - acknowledge entries of consumer group
+    ///
+    /// Missing key: 0
+    XAck {
+        key: String,
+        group: String,
+        ids: Vec<StreamId>,
+    },
+    /// https://redis.io/commands/xpending/ - pending entries of consumer group
+    ///
+    /// Missing key: error
+    XPending {
+        key: String,
+        group: String,
+        /// The summary is returned without a filter.
+        filter: Option<PendingFilter>,
+    },
+    /// https://redis.io/commands/type/ - type of value of key
+    ///
+    /// Missing key: none
+    Type(String),
     /// https://redis.io/commands/keys/ - get all keys for pattern
+    ///
+    /// No matching key: empty array
     Keys(String),
+    /// https://redis.io/commands/randomkey/ - no arguments
+    RandomKey,
+    /// https://redis.io/commands/scan/ - cursor, optional glob-style pattern
+    /// and count hint
+    Scan {
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+    },
+    /// https://redis.io/commands/dbsize/ - no arguments
+    DbSize,
+    /// https://redis.io/commands/wait/ - integers of replica count and
+    /// timeout, replies right away since there are no replicas
+    Wait,
+    /// https://redis.io/commands/info/ - optional string of section name
+    Info(Option<String>),
+    /// https://redis.io/commands/client-id/ - no arguments
+    ClientId,
+    /// https://redis.io/commands/client-getname/ - no arguments
+    ClientGetName,
+    /// https://redis.io/commands/client-info/ - no arguments
+    ClientInfo,
+    /// https://redis.io/commands/client-setname/ - string of connection
+    /// name, empty to remove it
+    ClientSetName(String),
+    /// https://redis.io/commands/flushdb/ - optional ASYNC or SYNC
+    FlushDb,
+    /// https://redis.io/commands/flushall/ - optional ASYNC or SYNC
+    FlushAll,
+    /// https://redis.io/commands/object-encoding/ - string of key name
+    ///
+    /// Missing key: nil
+    ObjectEncoding(String),
+    /// https://redis.io/commands/object-refcount/ - string of key name,
+    /// values are never shared
+    ///
+    /// Missing key: nil
+    ObjectRefCount(String),
+    /// https://redis.io/commands/object-idletime/ - string of key name
+    ///
+    /// Missing key: nil
+    ObjectIdleTime(String),
+    /// https://redis.io/commands/memory-usage/ - string of key name and
+    /// optional SAMPLES with a count, which is ignored
+    ///
+    /// Missing key: nil
+    MemoryUsage(String),
+    /// https://redis.io/commands/debug/ - SLEEP with the seconds to wait
+    /// before replying, without blocking other clients
+    DebugSleep(Duration),
+    /// https://redis.io/commands/debug/ - JMAP, which does nothing
+    DebugJmap,
+    /// https://redis.io/commands/debug/ - SET-ACTIVE-EXPIRE with 0 or 1,
+    /// which does nothing
+    DebugSetActiveExpire,
+    /// https://redis.io/commands/debug/ - OBJECT with a key name
+    ///
+    /// Missing key: error
+    DebugObject(String),
+    /// https://redis.io/commands/latency-history/ - string of event name
+    LatencyHistory(String),
+    /// https://redis.io/commands/latency-latest/ - no arguments
+    LatencyLatest,
+    /// https://redis.io/commands/latency-reset/ - array of event names
+    LatencyReset(Vec<String>),
+    /// https://redis.io/commands/acl-whoami/ - no arguments
+    AclWhoAmI,
+    /// https://redis.io/commands/acl-list/ - no arguments
+    AclList,
+    /// https://redis.io/commands/acl-cat/ - no arguments
+    AclCat,
+    /// https://redis.io/commands/acl-getuser/ - string of user name
+    AclGetUser(String),
+    /// https://redis.io/commands/function-list/ - optional LIBRARYNAME and
+    /// WITHCODE, there are never any libraries
+    FunctionList,
+    /// https://redis.io/commands/function-dump/ - no arguments, there is
+    /// nothing to dump
+    FunctionDump,
+    /// https://redis.io/commands/fcall/ - function name, number of keys,
+    /// keys and arguments, also FCALL_RO. There are no functions to call.
+    FCall,
 }
 
-impl RedisCommand {
-    pub async fn apply(self, db: &Db) -> Value {
-        match self {
-            RedisCommand::Command => {
-                // This is mainly for redis-cli compatibility
-                Value::Array(Vec::new())
-            }
-            RedisCommand::CommandDocs(_) => {
-                // This is mainly for redis-cli compatibility
-                Value::Array(Vec::new())
-            }
-            RedisCommand::ConfigGet(_) => {
-                // TODO: This needs to be at least partially supported
-                // Mainly for redis-benchmark compatibility
-                Value::Array(Vec::new())
-            }
-            RedisCommand::Get(key) => {
-                // Technically GET can only work with strings
-                // but who cares for now?
-                if let Some(value) = db.get(&key) {
-                    value
-                } else {
-                    Value::NullString
-                }
-            }
-            RedisCommand::Set {
-                key,
-                value,
-                expiry,
-                behaviour,
-                return_old,
-                keep_ttl,
-            } => {
-                let old = db.set(key, value, expiry, behaviour, keep_ttl).await;
-
-                if return_old {
-                    if let Some(value) = old {
-                        value
-                    } else {
-                        Value::NullString
-                    }
-                } else {
-                    if old.is_some() {
-                        Value::SimpleString(String::from("OK"))
-                    } else {
-                        Value::NullString
-                    }
-                }
-            }
-            RedisCommand::Del(keys) => {
-                let count = db.remove(keys);
+/// The reply of the scan commands.
+fn scan_reply(cursor: u64, elements: Vec<Value>) -> Value {
+    Value::Array(vec![
+        Value::BulkString(Bytes::from(cursor.to_string())),
+        Value::Array(elements),
+    ])
+}
 
-                Value::Integer(count as i64)
-            }
-            RedisCommand::Ttl(key) => {
-                let ttl = db.ttl(&key);
+/// The reply of the sorted set range commands, with each score following
+/// its member if requested.
+fn zset_range_reply(members: Vec<(String, f64)>, with_scores: bool) -> Value {
+    let mut elements = Vec::with_capacity(members.len() * 2);
 
-                Value::Integer(ttl)
-            }
-            RedisCommand::Pttl(key) => {
-                let pttl = db.pttl(&key);
+    for (member, score) in members {
+        elements.push(Value::BulkString(Bytes::from(member)));
 
-                Value::Integer(pttl)
-            }
-            RedisCommand::Expire {
-                key,
-                seconds,
-                behaviour,
-            } => todo!(),
-            RedisCommand::Keys(_) => todo!(),
+        if with_scores {
+            elements.push(Value::Double(score));
         }
     }
+
+    Value::Array(elements)
 }
 
-pub struct CommandParser {
-    buffer: VecDeque<Value>,
+/// The longitude and latitude of a geohash.
+fn geo_position_reply(hash: u64) -> Value {
+    let (longitude, latitude) = geo::decode(hash);
+
+    Value::Array(vec![Value::Double(longitude), Value::Double(latitude)])
 }
 
-impl CommandParser {
-    pub fn new(buffer: Vec<Value>) -> Self {
-        Self {
-            buffer: VecDeque::from(buffer),
-        }
-    }
+/// A distance with four decimals like in Redis, which RESP2 clients get as
+/// printed.
+fn geo_distance_reply(distance: f64, protocol: ProtocolVersion) -> Value {
+    let printed = format!("{distance:.4}");
 
-    fn peek(&self) -> Option<&Value> {
-        self.buffer.get(0)
+    match protocol {
+        ProtocolVersion::Resp2 => Value::BulkString(Bytes::from(printed)),
+        ProtocolVersion::Resp3 => Value::Double(printed.parse().unwrap()),
     }
+}
 
-    fn skip(&mut self) {
-        self.buffer.pop_front();
+/// The confirmation of subscribing to or unsubscribing from a channel.
+fn subscription_reply(kind: &'static str, channel: Option<String>, count: usize) -> Value {
+    Value::Push(vec![
+        Value::BulkString(Bytes::from(kind)),
+        channel.map_or(Value::NullString, |channel| {
+            Value::BulkString(Bytes::from(channel))
+        }),
+        Value::Integer(count as i64),
+    ])
+}
+
+/// Replies to a command with several replies by queueing all but the last
+/// one, which the connection writes first.
+fn reply_many(connection: &ConnectionState, mut replies: Vec<Value>) -> Value {
+    let last = replies.pop().unwrap();
+
+    for reply in replies {
+        connection.push_reply(reply);
     }
 
-    fn expect_string(&mut self) -> Result<String, ParseError> {
-        match self.buffer.pop_front() {
-            Some(Value::BulkString(string)) | Some(Value::SimpleString(string)) => Ok(string),
-            _ => Err(ParseError::ExpectedString),
-        }
+    last
+}
+
+impl RedisCommand {
+    /// Whether the command may wait for writes by other clients, which it
+    /// must not keep from running while waiting.
+    pub fn may_block(&self) -> bool {
+        matches!(
+            self,
+            RedisCommand::BLPop { .. }
+                | RedisCommand::BRPop { .. }
+                | RedisCommand::XRead { block: Some(_), .. }
+        )
     }
 
-    fn expect_integer(&mut self) -> Result<i64, ParseError> {
-        match self.buffer.pop_front() {
-            Some(Value::Integer(integer)) => Ok(integer),
-            _ => Err(ParseError::ExpectedInteger),
-        }
+    /// Whether the command may use more memory, which makes it fail once
+    /// maxmemory is reached and no key can be evicted.
+    fn may_grow_memory(&self) -> bool {
+        matches!(
+            self,
+            RedisCommand::Set { .. }
+                | RedisCommand::GetSet { .. }
+                | RedisCommand::SetNx { .. }
+                | RedisCommand::MSet(_)
+                | RedisCommand::MSetNx(_)
+                | RedisCommand::Copy { .. }
+                | RedisCommand::Restore { .. }
+                | RedisCommand::Incr(_)
+                | RedisCommand::Decr(_)
+                | RedisCommand::IncrBy { .. }
+                | RedisCommand::DecrBy { .. }
+                | RedisCommand::IncrByFloat { .. }
+                | RedisCommand::Append { .. }
+                | RedisCommand::SetRange { .. }
+                | RedisCommand::SetBit { .. }
+                | RedisCommand::LPush { .. }
+                | RedisCommand::RPush { .. }
+                | RedisCommand::HSet { .. }
+                | RedisCommand::SAdd { .. }
+                | RedisCommand::ZAdd { .. }
+                | RedisCommand::ZIncrBy { .. }
+                | RedisCommand::XAdd { .. }
+                | RedisCommand::XGroupCreate { .. }
+        )
     }
 
-    fn expect_any(&mut self) -> Result<Value, ParseError> {
-        match self.buffer.pop_front() {
-            Some(value) => Ok(value),
-            _ => Err(ParseError::ExpectedAny),
+    pub async fn apply(self, db: &Db, connection: &ConnectionState) -> Value {
+        let allowed_before_auth = matches!(
+            self,
+            RedisCommand::Auth { .. }
+                | RedisCommand::Hello { .. }
+                | RedisCommand::Ping(_)
+                | RedisCommand::Reset
+        );
+
+        if !allowed_before_auth
+            && !connection.is_authenticated()
+            && !db.config().value("requirepass").is_empty()
+        {
+            return Value::Error(RedisError {
+                message: String::from("NOAUTH Authentication required."),
+            });
         }
-    }
 
-    pub fn parse(mut self) -> Result<RedisCommand, ParseError> {
-        let mut command_name = self.expect_string()?;
-        command_name.make_ascii_uppercase();
+        // Commands that control the transaction itself are never queued
+        let controls_transaction = matches!(
+            self,
+            RedisCommand::Multi
+                | RedisCommand::Exec
+                | RedisCommand::Discard
+                | RedisCommand::Watch(_)
+                | RedisCommand::Reset
+        );
 
-        // Some commands might have a subcommand
-        if command_name == "COMMAND" {
-            if let Ok(mut subcommand) = self.expect_string() {
-                subcommand.make_ascii_uppercase();
+        if !controls_transaction && connection.in_transaction() {
+            connection.queue(self);
 
-                command_name.push(' ');
-                command_name.push_str(&subcommand);
+            return Value::SimpleString(String::from("QUEUED"));
+        }
+
+        // EXEC runs exclusively, see below, and blocking commands only hold
+        // the lock while checking their keys
+        let _shared = if matches!(self, RedisCommand::Exec) || self.may_block() {
+            None
+        } else {
+            db.lock_shared().await
+        };
+
+        if self.may_grow_memory() {
+            if let Err(error) = db.evict_if_needed() {
+                return error;
             }
-        } else if command_name == "CONFIG" {
-            let mut subcommand = self.expect_string()?;
-            subcommand.make_ascii_uppercase();
-            command_name.push(' ');
-            command_name.push_str(&subcommand);
         }
 
-        // Now parse the arguments
-        match command_name.as_str() {
-            "COMMAND" => Ok(RedisCommand::Command),
-            "COMMAND DOCS" => {
-                let mut command_names = Vec::with_capacity(self.buffer.len());
+        match self {
+            RedisCommand::Command => {
+                let commands = COMMANDS.iter().map(|spec| spec.to_value()).collect();
 
-                while let Ok(command_name) = self.expect_string() {
-                    command_names.push(command_name);
-                }
+                Value::Array(commands)
+            }
+            RedisCommand::CommandInfo(command_names) => {
+                let commands = command_names
+                    .iter()
+                    .map(|name| {
+                        command_table::lookup(name).map_or(Value::NullArray, |spec| spec.to_value())
+                    })
+                    .collect();
 
-                Ok(RedisCommand::CommandDocs(command_names))
+                Value::Array(commands)
             }
-            "CONFIG GET" => {
-                let mut parameter_globs = Vec::with_capacity(self.buffer.len());
+            RedisCommand::CommandDocs(_command_names) => {
+                // This is mainly for redis-cli compatibility
+                Value::Array(Vec::new())
+            }
+            RedisCommand::CommandCount => Value::Integer(COMMANDS.len() as i64),
+            RedisCommand::CommandList => {
+                let names = COMMANDS
+                    .iter()
+                    .map(|spec| Value::BulkString(Bytes::from(spec.name)))
+                    .collect();
 
-                while let Ok(glob) = self.expect_string() {
-                    parameter_globs.push(glob);
-                }
+                Value::Array(names)
+            }
+            RedisCommand::ConfigGet(parameter_globs) => {
+                let parameters = db
+                    .config()
+                    .get(&parameter_globs)
+                    .into_iter()
+                    .map(|(name, value)| {
+                        (
+                            Value::BulkString(Bytes::from(name)),
+                            Value::BulkString(Bytes::from(value)),
+                        )
+                    })
+                    .collect();
 
-                Ok(RedisCommand::ConfigGet(parameter_globs))
+                Value::Map(parameters)
             }
-            "GET" => {
-                let key = self.expect_string()?;
+            RedisCommand::ConfigSet(parameters) => match db.config_set(parameters) {
+                Ok(()) => Value::SimpleString(String::from("OK")),
+                Err(message) => Value::Error(RedisError { message }),
+            },
+            RedisCommand::Auth { username, password } => {
+                if db.config().value("requirepass").is_empty() && username.is_none() {
+                    return Value::Error(RedisError {
+                        message: String::from("ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"),
+                    });
+                }
 
-                Ok(RedisCommand::Get(key))
+                match check_credentials(db, username.as_deref(), &password) {
+                    Ok(()) => {
+                        connection.authenticate();
+
+                        Value::SimpleString(String::from("OK"))
+                    }
+                    Err(error) => error,
+                }
             }
-            "SET" => {
-                let key = self.expect_string()?;
-                let value = self.expect_any()?;
+            RedisCommand::Hello {
+                version,
+                auth,
+                name,
+            } => {
+                let protocol = match version {
+                    Some(2) => Some(ProtocolVersion::Resp2),
+                    Some(3) => Some(ProtocolVersion::Resp3),
+                    Some(_) => {
+                        return Value::Error(RedisError {
+                            message: String::from("NOPROTO unsupported protocol version"),
+                        })
+                    }
+                    None => None,
+                };
 
-                let behaviour = match self.peek().and_then(Value::try_as_string).as_deref() {
-                    Some("NX") => {
-                        self.skip();
-                        SetBehaviour::OnlyIfNotExists
+                // Nothing changes unless every option succeeds
+                if let Some((username, password)) = &auth {
+                    if let Err(error) = check_credentials(db, Some(username), password) {
+                        return error;
                     }
-                    Some("XX") => {
-                        self.skip();
-                        SetBehaviour::OnlyIfExists
+                } else if !connection.is_authenticated()
+                    && !db.config().value("requirepass").is_empty()
+                {
+                    return Value::Error(RedisError {
+                        message: String::from("NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time"),
+                    });
+                }
+
+                if let Some(name) = &name {
+                    if let Err(error) = check_client_name(name) {
+                        return error;
                     }
-                    _ => SetBehaviour::Force,
+                }
+
+                if auth.is_some() {
+                    connection.authenticate();
+                }
+
+                if let Some(name) = name {
+                    connection.set_name((!name.is_empty()).then_some(name));
+                }
+
+                if let Some(protocol) = protocol {
+                    connection.set_protocol(protocol);
+                }
+
+                let proto = match connection.protocol() {
+                    ProtocolVersion::Resp2 => 2,
+                    ProtocolVersion::Resp3 => 3,
                 };
+                let field = |name: &'static str| Value::BulkString(Bytes::from(name));
 
-                let return_old = if matches!(
-                    self.peek().and_then(Value::try_as_string).as_deref(),
-                    Some("GET")
-                ) {
-                    self.skip();
-                    true
+                Value::Map(vec![
+                    (field("server"), field("redis")),
+                    (field("version"), field(REDIS_VERSION)),
+                    (field("proto"), Value::Integer(proto)),
+                    (field("id"), Value::Integer(connection.id() as i64)),
+                    (field("mode"), field("standalone")),
+                    (field("role"), field("master")),
+                    (field("modules"), Value::Array(Vec::new())),
+                ])
+            }
+            // Subscribed RESP2 clients expect the format of messages
+            RedisCommand::Ping(message)
+                if connection.protocol() == ProtocolVersion::Resp2
+                    && !connection.channels().is_empty() =>
+            {
+                Value::Array(vec![
+                    Value::BulkString(Bytes::from_static(b"pong")),
+                    Value::BulkString(message.unwrap_or_default()),
+                ])
+            }
+            RedisCommand::Ping(message) => match message {
+                Some(message) => Value::BulkString(message),
+                None => Value::SimpleString(String::from("PONG")),
+            },
+            RedisCommand::Echo(message) => Value::BulkString(message),
+            RedisCommand::LastSave => Value::Integer(db.last_save() as i64),
+            RedisCommand::Time => {
+                let since_unix = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+
+                Value::Array(vec![
+                    Value::BulkString(Bytes::from(since_unix.as_secs().to_string())),
+                    Value::BulkString(Bytes::from(since_unix.subsec_micros().to_string())),
+                ])
+            }
+            // There is no art, but the last line matches Redis
+            RedisCommand::Lolwut(version) => {
+                let art = version.map_or_else(String::new, |version| {
+                    format!("LOLWUT version {version} is not drawn by xylon.\n")
+                });
+
+                Value::BulkString(Bytes::from(format!("{art}Redis ver. {REDIS_VERSION}\n")))
+            }
+            RedisCommand::Select(0) => {
+                connection.select(0);
+
+                Value::SimpleString(String::from("OK"))
+            }
+            RedisCommand::Select(_) => Value::Error(RedisError {
+                message: String::from("ERR DB index is out of range"),
+            }),
+            RedisCommand::Multi => {
+                if connection.begin_transaction() {
+                    Value::SimpleString(String::from("OK"))
                 } else {
-                    false
+                    Value::Error(RedisError {
+                        message: String::from("ERR MULTI calls can not be nested"),
+                    })
+                }
+            }
+            RedisCommand::Exec => {
+                let Some(transaction) = connection.take_transaction() else {
+                    return Value::Error(RedisError {
+                        message: String::from("ERR EXEC without MULTI"),
+                    });
                 };
 
-                let (expiry, keep_ttl) = match self.peek().and_then(Value::try_as_string).as_deref()
-                {
-                    Some("EX") => {
-                        println!("{:?}", self.buffer);
-                        self.skip();
-                        let seconds = self.expect_integer()?;
-                        let duration = Duration::from_secs(seconds as u64);
-
-                        (Some(duration), false)
-                    }
-                    Some("PX") => {
-                        self.skip();
-                        let millis = self.expect_integer()?;
-                        let duration = Duration::from_millis(millis as u64);
+                let watched = connection.take_watched();
 
-                        (Some(duration), false)
-                    }
-                    Some("EXAT") => {
-                        self.skip();
-                        let seconds = self.expect_integer()?;
-                        let since_unix = Duration::from_secs(seconds as u64);
-                        let system_time = UNIX_EPOCH + since_unix;
-                        let duration = system_time.duration_since(SystemTime::now()).ok();
+                if transaction.failed {
+                    return Value::Error(RedisError {
+                        message: String::from(
+                            "EXECABORT Transaction discarded because of previous errors.",
+                        ),
+                    });
+                }
 
-                        (duration, false)
+                // No other command may run between checking the watched keys
+                // and the end of the transaction
+                db.run_exclusively(async {
+                    if watched
+                        .iter()
+                        .any(|(key, version)| db.version(key) != *version)
+                    {
+                        return Value::NullArray;
                     }
-                    Some("PXAT") => {
-                        self.skip();
-                        let millis = self.expect_integer()?;
-                        let since_unix = Duration::from_millis(millis as u64);
-                        let system_time = UNIX_EPOCH + since_unix;
-                        let duration = system_time.duration_since(SystemTime::now()).ok();
 
-                        (duration, false)
-                    }
-                    Some("KEEPTTL") => {
-                        self.skip();
+                    let mut replies = Vec::with_capacity(transaction.commands.len());
 
-                        (None, true)
+                    for command in transaction.commands {
+                        // Boxed because the future would contain itself otherwise
+                        replies.push(Box::pin(command.apply(db, connection)).await);
                     }
-                    _ => (None, false),
-                };
 
-                Ok(RedisCommand::Set {
-                    key,
-                    value,
-                    expiry,
-                    behaviour,
-                    return_old,
-                    keep_ttl,
+                    Value::Array(replies)
                 })
+                .await
             }
-            "DEL" => {
-                let mut keys = Vec::with_capacity(self.buffer.len());
+            RedisCommand::Discard => {
+                if connection.take_transaction().is_none() {
+                    return Value::Error(RedisError {
+                        message: String::from("ERR DISCARD without MULTI"),
+                    });
+                }
+
+                connection.take_watched();
+
+                Value::SimpleString(String::from("OK"))
+            }
+            RedisCommand::Watch(keys) => {
+                if connection.in_transaction() {
+                    return Value::Error(RedisError {
+                        message: String::from("ERR WATCH inside MULTI is not allowed"),
+                    });
+                }
 
-                while let Ok(key) = self.expect_string() {
-                    keys.push(key);
+                for key in keys {
+                    let version = db.version(&key);
+                    connection.watch(key, version);
                 }
 
-                Ok(RedisCommand::Del(keys))
+                Value::SimpleString(String::from("OK"))
             }
-            "TTL" => {
-                let key = self.expect_string()?;
+            RedisCommand::Unwatch => {
+                connection.take_watched();
 
-                Ok(RedisCommand::Ttl(key))
+                Value::SimpleString(String::from("OK"))
             }
-            "PTTL" => {
-                let key = self.expect_string()?;
+            RedisCommand::Reset => {
+                for channel in connection.reset() {
+                    db.pubsub().unsubscribe(&channel, connection.id());
+                }
 
-                Ok(RedisCommand::Pttl(key))
+                Value::SimpleString(String::from("RESET"))
             }
-            "EXPIRE" => {
-                let key = self.expect_string()?;
-                let seconds = self.expect_integer()? as u64;
+            RedisCommand::Subscribe(channels) => {
+                let confirmations = channels
+                    .into_iter()
+                    .map(|channel| {
+                        let count = connection.subscribe(channel.clone());
+                        db.pubsub().subscribe(
+                            channel.clone(),
+                            connection.id(),
+                            connection.messages().clone(),
+                        );
 
-                let behaviour = match self.peek().and_then(Value::try_as_string).as_deref() {
-                    Some("NX") => {
-                        self.skip();
-                        ExpireBehaviour::OnlyIfNoExpiry
+                        subscription_reply("subscribe", Some(channel), count)
+                    })
+                    .collect();
+
+                reply_many(connection, confirmations)
+            }
+            RedisCommand::Unsubscribe(mut channels) => {
+                if channels.is_empty() {
+                    channels = connection.channels();
+                }
+
+                if channels.is_empty() {
+                    return subscription_reply("unsubscribe", None, 0);
+                }
+
+                let confirmations = channels
+                    .into_iter()
+                    .map(|channel| {
+                        let count = connection.unsubscribe(&channel);
+                        db.pubsub().unsubscribe(&channel, connection.id());
+
+                        subscription_reply("unsubscribe", Some(channel), count)
+                    })
+                    .collect();
+
+                reply_many(connection, confirmations)
+            }
+            RedisCommand::Publish { channel, message } => {
+                Value::Integer(db.pubsub().publish(&channel, message) as i64)
+            }
+            RedisCommand::Get(key) => match db.get(&key) {
+                Ok(value) => value.map_or(Value::NullString, Value::BulkString),
+                Err(err) => err,
+            },
+            RedisCommand::GetDel(key) => match db.get_del(&key) {
+                Ok(value) => value.map_or(Value::NullString, Value::BulkString),
+                Err(err) => err,
+            },
+            RedisCommand::GetEx { key, behaviour } => match db.getex(&key, behaviour) {
+                Ok(value) => value.map_or(Value::NullString, Value::BulkString),
+                Err(err) => err,
+            },
+            RedisCommand::MGet(keys) => {
+                let values = db
+                    .mget(&keys)
+                    .into_iter()
+                    .map(|value| value.map_or(Value::NullString, Value::BulkString))
+                    .collect();
+
+                Value::Array(values)
+            }
+            RedisCommand::Set {
+                key,
+                value,
+                expiry,
+                behaviour,
+                return_old,
+                keep_ttl,
+            } => {
+                let outcome = db.set(key, value, expiry, behaviour, keep_ttl, return_old);
+
+                match outcome {
+                    Err(err) => err,
+                    Ok(SetOutcome::NotPerformed) => Value::NullString,
+                    Ok(SetOutcome::Replaced(Object::String(old))) if return_old => {
+                        Value::BulkString(old)
                     }
-                    Some("XX") => {
-                        self.skip();
-                        ExpireBehaviour::OnlyIfExpiry
+                    Ok(SetOutcome::Inserted) if return_old => Value::NullString,
+                    Ok(SetOutcome::Inserted | SetOutcome::Replaced(_)) => {
+                        Value::SimpleString(String::from("OK"))
                     }
-                    Some("GT") => {
-                        self.skip();
-                        ExpireBehaviour::OnlyIfGreater
+                }
+            }
+            RedisCommand::GetSet { key, value } => {
+                match db.set(key, value, None, SetBehaviour::Force, false, true) {
+                    Err(err) => err,
+                    Ok(SetOutcome::Replaced(Object::String(old))) => Value::BulkString(old),
+                    Ok(_) => Value::NullString,
+                }
+            }
+            RedisCommand::SetNx { key, value } => {
+                let outcome = db.set(
+                    key,
+                    value,
+                    None,
+                    SetBehaviour::OnlyIfNotExists,
+                    false,
+                    false,
+                );
+
+                match outcome {
+                    Err(err) => err,
+                    Ok(SetOutcome::Inserted) => Value::Integer(1),
+                    Ok(SetOutcome::NotPerformed | SetOutcome::Replaced(_)) => Value::Integer(0),
+                }
+            }
+            RedisCommand::MSet(pairs) => {
+                db.mset(pairs);
+
+                Value::SimpleString(String::from("OK"))
+            }
+            RedisCommand::MSetNx(pairs) => {
+                let performed = db.msetnx(pairs);
+
+                Value::Integer(performed as i64)
+            }
+            RedisCommand::Del(keys) => {
+                let count = db.remove(keys);
+
+                Value::Integer(count as i64)
+            }
+            RedisCommand::Unlink(keys) => {
+                let count = db.unlink(keys);
+
+                Value::Integer(count as i64)
+            }
+            RedisCommand::Rename { src, dst } => match db.rename(&src, dst, false) {
+                Ok(_) => Value::SimpleString(String::from("OK")),
+                Err(err) => err,
+            },
+            RedisCommand::RenameNx { src, dst } => match db.rename(&src, dst, true) {
+                Ok(renamed) => Value::Integer(renamed as i64),
+                Err(err) => err,
+            },
+            RedisCommand::Copy { src, dst, replace } => match db.copy(&src, dst, replace) {
+                Ok(copied) => Value::Integer(copied as i64),
+                Err(err) => err,
+            },
+            RedisCommand::Dump(key) => db.dump(&key).map_or(Value::NullString, Value::BulkString),
+            RedisCommand::Restore {
+                key,
+                ttl,
+                value,
+                replace,
+            } => match db.restore(key, value, ttl, replace) {
+                Ok(()) => Value::SimpleString(String::from("OK")),
+                Err(err) => err,
+            },
+            RedisCommand::Exists(keys) => {
+                let count = db.exists(&keys);
+
+                Value::Integer(count)
+            }
+            RedisCommand::Touch(keys) => {
+                let count = db.touch(&keys);
+
+                Value::Integer(count)
+            }
+            RedisCommand::Ttl(key) => {
+                let ttl = db.ttl(&key);
+
+                Value::Integer(ttl)
+            }
+            RedisCommand::Pttl(key) => {
+                let pttl = db.pttl(&key);
+
+                Value::Integer(pttl)
+            }
+            RedisCommand::Expire {
+                key,
+                timeout,
+                behaviour,
+            } => {
+                let applied = db.set_expiry(&key, Instant::now() + timeout, behaviour);
+
+                Value::Integer(applied as i64)
+            }
+            RedisCommand::Persist(key) => {
+                let removed = db.persist(&key);
+
+                Value::Integer(removed)
+            }
+            RedisCommand::Incr(key) => match db.incr_by(key, 1) {
+                Ok(value) => Value::Integer(value),
+                Err(err) => err,
+            },
+            RedisCommand::Decr(key) => match db.incr_by(key, -1) {
+                Ok(value) => Value::Integer(value),
+                Err(err) => err,
+            },
+            RedisCommand::IncrBy { key, increment } => match db.incr_by(key, increment) {
+                Ok(value) => Value::Integer(value),
+                Err(err) => err,
+            },
+            RedisCommand::DecrBy { key, decrement } => {
+                if let Some(delta) = decrement.checked_neg() {
+                    match db.incr_by(key, delta) {
+                        Ok(value) => Value::Integer(value),
+                        Err(err) => err,
                     }
-                    Some("LT") => {
-                        self.skip();
-                        ExpireBehaviour::OnlyIfLess
+                } else {
+                    Value::Error(RedisError {
+                        message: String::from("ERR decrement would overflow"),
+                    })
+                }
+            }
+            RedisCommand::IncrByFloat { key, increment } => {
+                match db.incr_by_float(key, increment) {
+                    // RESP2 clients get the value as stored
+                    Ok(value) if connection.protocol() == ProtocolVersion::Resp3 => {
+                        Value::Double(value.parse().unwrap())
                     }
-                    _ => ExpireBehaviour::Force,
+                    Ok(value) => Value::BulkString(Bytes::from(value)),
+                    Err(err) => err,
+                }
+            }
+            RedisCommand::Append { key, value } => match db.append(key, value) {
+                Ok(length) => Value::Integer(length),
+                Err(err) => err,
+            },
+            RedisCommand::SetRange { key, offset, value } => {
+                match db.setrange(key, offset, value) {
+                    Ok(length) => Value::Integer(length),
+                    Err(err) => err,
+                }
+            }
+            RedisCommand::Strlen(key) => match db.strlen(&key) {
+                Ok(length) => Value::Integer(length),
+                Err(err) => err,
+            },
+            RedisCommand::SetBit { key, offset, bit } => match db.setbit(key, offset, bit) {
+                Ok(previous) => Value::Integer(previous as i64),
+                Err(err) => err,
+            },
+            RedisCommand::GetBit { key, offset } => match db.getbit(&key, offset) {
+                Ok(bit) => Value::Integer(bit as i64),
+                Err(err) => err,
+            },
+            RedisCommand::BitCount { key, range } => match db.bitcount(&key, range) {
+                Ok(count) => Value::Integer(count),
+                Err(err) => err,
+            },
+            RedisCommand::LPush { key, elements } => match db.lpush(key, elements) {
+                Ok(length) => Value::Integer(length),
+                Err(err) => err,
+            },
+            RedisCommand::RPush { key, elements } => match db.rpush(key, elements) {
+                Ok(length) => Value::Integer(length),
+                Err(err) => err,
+            },
+            RedisCommand::LPop { key, count } => {
+                pop_reply(db.lpop(&key, count.unwrap_or(1)), count.is_some())
+            }
+            RedisCommand::RPop { key, count } => {
+                pop_reply(db.rpop(&key, count.unwrap_or(1)), count.is_some())
+            }
+            RedisCommand::BLPop { keys, timeout } => {
+                blocking_pop_reply(db.blocking_pop(&keys, timeout, true).await)
+            }
+            RedisCommand::BRPop { keys, timeout } => {
+                blocking_pop_reply(db.blocking_pop(&keys, timeout, false).await)
+            }
+            RedisCommand::LLen(key) => match db.llen(&key) {
+                Ok(length) => Value::Integer(length),
+                Err(err) => err,
+            },
+            RedisCommand::LRange { key, start, stop } => match db.lrange(&key, start, stop) {
+                Ok(elements) => Value::Array(
+                    elements
+                        .into_iter()
+                        .map(Bytes::from)
+                        .map(Value::BulkString)
+                        .collect(),
+                ),
+                Err(err) => err,
+            },
+            RedisCommand::HSet { key, pairs } => match db.hset(key, pairs) {
+                Ok(added) => Value::Integer(added),
+                Err(err) => err,
+            },
+            RedisCommand::HGet { key, field } => match db.hget(&key, &field) {
+                Ok(value) => value.map_or(Value::NullString, |value| {
+                    Value::BulkString(Bytes::from(value))
+                }),
+                Err(err) => err,
+            },
+            RedisCommand::HDel { key, fields } => match db.hdel(&key, &fields) {
+                Ok(removed) => Value::Integer(removed),
+                Err(err) => err,
+            },
+            RedisCommand::HGetAll(key) => match db.hgetall(&key) {
+                Ok(pairs) => Value::Map(
+                    pairs
+                        .into_iter()
+                        .map(|(field, value)| {
+                            (
+                                Value::BulkString(Bytes::from(field)),
+                                Value::BulkString(Bytes::from(value)),
+                            )
+                        })
+                        .collect(),
+                ),
+                Err(err) => err,
+            },
+            RedisCommand::HScan {
+                key,
+                cursor,
+                pattern,
+                count,
+            } => match db.hscan(&key, cursor, pattern.as_deref(), count) {
+                Ok((cursor, pairs)) => {
+                    let elements = pairs
+                        .into_iter()
+                        .flat_map(|(field, value)| [field, value])
+                        .map(Bytes::from)
+                        .map(Value::BulkString)
+                        .collect();
+
+                    scan_reply(cursor, elements)
+                }
+                Err(err) => err,
+            },
+            RedisCommand::SAdd { key, members } => match db.sadd(key, members) {
+                Ok(added) => Value::Integer(added),
+                Err(err) => err,
+            },
+            RedisCommand::SRem { key, members } => match db.srem(&key, &members) {
+                Ok(removed) => Value::Integer(removed),
+                Err(err) => err,
+            },
+            RedisCommand::SIsMember { key, member } => match db.sismember(&key, &member) {
+                Ok(is_member) => Value::Integer(is_member as i64),
+                Err(err) => err,
+            },
+            RedisCommand::SMembers(key) => match db.smembers(&key) {
+                Ok(members) => Value::Array(
+                    members
+                        .into_iter()
+                        .map(Bytes::from)
+                        .map(Value::BulkString)
+                        .collect(),
+                ),
+                Err(err) => err,
+            },
+            RedisCommand::SScan {
+                key,
+                cursor,
+                pattern,
+                count,
+            } => match db.sscan(&key, cursor, pattern.as_deref(), count) {
+                Ok((cursor, members)) => {
+                    let elements = members
+                        .into_iter()
+                        .map(Bytes::from)
+                        .map(Value::BulkString)
+                        .collect();
+
+                    scan_reply(cursor, elements)
+                }
+                Err(err) => err,
+            },
+            RedisCommand::SCard(key) => match db.scard(&key) {
+                Ok(cardinality) => Value::Integer(cardinality),
+                Err(err) => err,
+            },
+            RedisCommand::SPop { key, count } => match db.spop(&key, count.unwrap_or(1)) {
+                Ok(members) if count.is_some() => Value::Array(
+                    members
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(Bytes::from)
+                        .map(Value::BulkString)
+                        .collect(),
+                ),
+                Ok(members) => members
+                    .and_then(|mut members| members.pop())
+                    .map_or(Value::NullString, |value| {
+                        Value::BulkString(Bytes::from(value))
+                    }),
+                Err(err) => err,
+            },
+            RedisCommand::ZAdd {
+                key,
+                members,
+                condition,
+                update,
+                count_changed,
+            } => match db.zadd(key, members, condition, update) {
+                Ok((added, updated)) if count_changed => Value::Integer(added + updated),
+                Ok((added, _)) => Value::Integer(added),
+                Err(err) => err,
+            },
+            RedisCommand::ZScore { key, member } => match db.zscore(&key, &member) {
+                Ok(score) => score.map_or(Value::NullString, Value::Double),
+                Err(err) => err,
+            },
+            RedisCommand::ZCard(key) => match db.zcard(&key) {
+                Ok(cardinality) => Value::Integer(cardinality),
+                Err(err) => err,
+            },
+            RedisCommand::ZRange {
+                key,
+                start,
+                stop,
+                reverse,
+                with_scores,
+            } => match db.zrange(&key, start, stop, reverse) {
+                Ok(members) => zset_range_reply(members, with_scores),
+                Err(err) => err,
+            },
+            RedisCommand::ZRangeByScore {
+                key,
+                min,
+                max,
+                with_scores,
+                offset,
+                count,
+            } => match db.zrangebyscore(&key, min, max, offset, count) {
+                Ok(members) => zset_range_reply(members, with_scores),
+                Err(err) => err,
+            },
+            RedisCommand::ZIncrBy {
+                key,
+                increment,
+                member,
+            } => match db.zincrby(key, increment, member) {
+                Ok(score) => Value::Double(score),
+                Err(err) => err,
+            },
+            RedisCommand::GeoPos { key, members } => match db.zmscore(&key, &members) {
+                Ok(scores) => {
+                    let positions = scores
+                        .into_iter()
+                        .map(|score| match score {
+                            Some(score) => geo_position_reply(score as u64),
+                            None => Value::NullArray,
+                        })
+                        .collect();
+
+                    Value::Array(positions)
+                }
+                Err(err) => err,
+            },
+            RedisCommand::GeoDist {
+                key,
+                from,
+                to,
+                unit,
+            } => match db.zmscore(&key, &[from, to]) {
+                Ok(scores) => match scores[..] {
+                    [Some(from), Some(to)] => {
+                        let distance =
+                            geo::distance(geo::decode(from as u64), geo::decode(to as u64));
+
+                        geo_distance_reply(distance / unit, connection.protocol())
+                    }
+                    _ => Value::NullString,
+                },
+                Err(err) => err,
+            },
+            RedisCommand::GeoSearch {
+                key,
+                center,
+                shape,
+                unit,
+                order,
+                count,
+                any,
+                with_coord,
+                with_dist,
+                with_hash,
+            } => {
+                let mut neighbors = match db.geosearch(&key, &center, shape) {
+                    Ok(neighbors) => neighbors,
+                    Err(err) => return err,
                 };
 
-                Ok(RedisCommand::Expire {
-                    key,
-                    seconds,
-                    behaviour,
-                })
+                // ANY takes the first matches, otherwise the closest ones
+                let order = match order {
+                    None if count.is_some() && !any => Some(Order::Ascending),
+                    order => order,
+                };
+
+                if any {
+                    neighbors.truncate(count.unwrap_or(usize::MAX));
+                }
+
+                match order {
+                    Some(Order::Ascending) => {
+                        neighbors.sort_by(|a, b| a.distance.total_cmp(&b.distance))
+                    }
+                    Some(Order::Descending) => {
+                        neighbors.sort_by(|a, b| b.distance.total_cmp(&a.distance))
+                    }
+                    None => {}
+                }
+
+                neighbors.truncate(count.unwrap_or(usize::MAX));
+
+                let replies = neighbors
+                    .into_iter()
+                    .map(|neighbor| {
+                        let member = Value::BulkString(Bytes::from(neighbor.member));
+
+                        if !with_coord && !with_dist && !with_hash {
+                            return member;
+                        }
+
+                        let mut reply = vec![member];
+
+                        if with_dist {
+                            reply.push(geo_distance_reply(
+                                neighbor.distance / unit,
+                                connection.protocol(),
+                            ));
+                        }
+
+                        if with_hash {
+                            reply.push(Value::Integer(neighbor.hash as i64));
+                        }
+
+                        if with_coord {
+                            reply.push(geo_position_reply(neighbor.hash));
+                        }
+
+                        Value::Array(reply)
+                    })
+                    .collect();
+
+                Value::Array(replies)
             }
-            "KEYS" => {
-                let glob = self.expect_string()?;
+            RedisCommand::XAdd {
+                key,
+                trim,
+                id,
+                fields,
+            } => match db.xadd(key, id, fields, trim) {
+                Ok(id) => Value::BulkString(Bytes::from(id.to_string())),
+                Err(err) => err,
+            },
+            RedisCommand::XTrim { key, strategy } => match db.xtrim(&key, &strategy) {
+                Ok(removed) => Value::Integer(removed),
+                Err(err) => err,
+            },
+            RedisCommand::XLen(key) => match db.xlen(&key) {
+                Ok(length) => Value::Integer(length),
+                Err(err) => err,
+            },
+            RedisCommand::XRange {
+                key,
+                start,
+                end,
+                count,
+            } => match db.xrange(&key, start, end, count) {
+                Ok(entries) => Value::Array(entries.iter().map(|entry| entry.to_value()).collect()),
+                Err(err) => err,
+            },
+            RedisCommand::XRead {
+                streams,
+                count,
+                block: None,
+            } => match db.xread(&streams, count) {
+                Ok(results) => stream_entries_to_value(results),
+                Err(err) => err,
+            },
+            RedisCommand::XRead {
+                streams,
+                count,
+                block: Some(timeout),
+            } => {
+                let streams = db.resolve_last_ids(streams);
+                let keys: Vec<String> = streams.iter().map(|(key, _)| key.clone()).collect();
+                let timeout = Some(timeout).filter(|timeout| !timeout.is_zero());
 
-                Ok(RedisCommand::Keys(glob))
+                let result = db
+                    .block_on_keys(&keys, timeout, || match db.xread(&streams, count) {
+                        Ok(results) if results.is_empty() => None,
+                        result => Some(result),
+                    })
+                    .await;
+
+                match result {
+                    Some(Ok(results)) => stream_entries_to_value(results),
+                    Some(Err(err)) => err,
+                    None => Value::NullArray,
+                }
             }
-            cmd => {
-                error!("Unimplemented command: {cmd}");
-                unimplemented!()
+            RedisCommand::XGroupCreate {
+                key,
+                group,
+                id,
+                mkstream,
+            } => match db.xgroup_create(key, group, id, mkstream) {
+                Ok(()) => Value::SimpleString(String::from("OK")),
+                Err(err) => err,
+            },
+            RedisCommand::XReadGroup {
+                group,
+                consumer,
+                streams,
+                count,
+                noack,
+            } => match db.xreadgroup(&group, &consumer, &streams, count, noack) {
+                Ok(results) => stream_entries_to_value(results),
+                Err(err) => err,
+            },
+            RedisCommand::XAck { key, group, ids } => match db.xack(&key, &group, &ids) {
+                Ok(count) => Value::Integer(count),
+                Err(err) => err,
+            },
+            RedisCommand::XPending {
+                key,
+                group,
+                filter: None,
+            } => match db.xpending(&key, &group, &PendingFilter::default()) {
+                Ok(pending) if pending.is_empty() => Value::Array(vec![
+                    Value::Integer(0),
+                    Value::NullString,
+                    Value::NullString,
+                    Value::NullArray,
+                ]),
+                Ok(pending) => {
+                    let mut consumers: Vec<(String, i64)> = Vec::new();
+
+                    for info in &pending {
+                        match consumers
+                            .iter_mut()
+                            .find(|(consumer, _)| *consumer == info.consumer)
+                        {
+                            Some((_, count)) => *count += 1,
+                            None => consumers.push((info.consumer.clone(), 1)),
+                        }
+                    }
+
+                    consumers.sort();
+
+                    let consumers = consumers
+                        .into_iter()
+                        .map(|(consumer, count)| {
+                            Value::Array(vec![
+                                Value::BulkString(Bytes::from(consumer)),
+                                Value::BulkString(Bytes::from(count.to_string())),
+                            ])
+                        })
+                        .collect();
+
+                    Value::Array(vec![
+                        Value::Integer(pending.len() as i64),
+                        Value::BulkString(Bytes::from(pending[0].id.to_string())),
+                        Value::BulkString(Bytes::from(pending[pending.len() - 1].id.to_string())),
+                        Value::Array(consumers),
+                    ])
+                }
+                Err(err) => err,
+            },
+            RedisCommand::XPending {
+                key,
+                group,
+                filter: Some(filter),
+            } => match db.xpending(&key, &group, &filter) {
+                Ok(pending) => {
+                    let pending = pending
+                        .into_iter()
+                        .map(|info| {
+                            Value::Array(vec![
+                                Value::BulkString(Bytes::from(info.id.to_string())),
+                                Value::BulkString(Bytes::from(info.consumer)),
+                                Value::Integer(info.idle.as_millis() as i64),
+                                Value::Integer(info.deliveries as i64),
+                            ])
+                        })
+                        .collect();
+
+                    Value::Array(pending)
+                }
+                Err(err) => err,
+            },
+            RedisCommand::Type(key) => Value::SimpleString(String::from(db.type_of(&key))),
+            RedisCommand::DbSize => Value::Integer(db.len() as i64),
+            RedisCommand::Wait => Value::Integer(0),
+            RedisCommand::ClientId => Value::Integer(connection.id() as i64),
+            RedisCommand::ClientGetName => match connection.name() {
+                Some(name) => Value::BulkString(Bytes::from(name)),
+                None => Value::NullString,
+            },
+            RedisCommand::ClientInfo => {
+                let proto = match connection.protocol() {
+                    ProtocolVersion::Resp2 => 2,
+                    ProtocolVersion::Resp3 => 3,
+                };
+
+                // The fields of CLIENT LIST in Redis that are known here
+                let info = format!(
+                    "id={} name={} db={} sub={} resp={}\n",
+                    connection.id(),
+                    connection.name().unwrap_or_default(),
+                    connection.db_index(),
+                    connection.channels().len(),
+                    proto,
+                );
+
+                Value::BulkString(Bytes::from(info))
+            }
+            RedisCommand::ClientSetName(name) => {
+                if let Err(error) = check_client_name(&name) {
+                    return error;
+                }
+
+                connection.set_name((!name.is_empty()).then_some(name));
+
+                Value::SimpleString(String::from("OK"))
+            }
+            RedisCommand::Info(section) => {
+                Value::BulkString(Bytes::from(info::render(db, section.as_deref())))
+            }
+            RedisCommand::FlushDb | RedisCommand::FlushAll => {
+                // There is only a single database for now
+                db.flush();
+
+                Value::SimpleString(String::from("OK"))
+            }
+            RedisCommand::Scan {
+                cursor,
+                pattern,
+                count,
+            } => {
+                let (cursor, keys) = db.scan(cursor, pattern.as_deref(), count);
+                let keys = keys
+                    .into_iter()
+                    .map(Bytes::from)
+                    .map(Value::BulkString)
+                    .collect();
+
+                scan_reply(cursor, keys)
+            }
+            RedisCommand::RandomKey => db
+                .random_key()
+                .map_or(Value::NullString, |key| Value::BulkString(Bytes::from(key))),
+            RedisCommand::Keys(glob) => {
+                let keys = db
+                    .keys(&glob)
+                    .into_iter()
+                    .map(Bytes::from)
+                    .map(Value::BulkString)
+                    .collect();
+
+                Value::Array(keys)
+            }
+            RedisCommand::ObjectEncoding(key) => {
+                if let Some(encoding) = db.object_encoding(&key) {
+                    Value::BulkString(Bytes::from(encoding))
+                } else {
+                    Value::NullString
+                }
+            }
+            RedisCommand::ObjectRefCount(key) => {
+                if db.exists(&[key]) == 1 {
+                    Value::Integer(1)
+                } else {
+                    Value::NullString
+                }
+            }
+            RedisCommand::ObjectIdleTime(key) => match db.idle_time(&key) {
+                Some(seconds) => Value::Integer(seconds as i64),
+                None => Value::NullString,
+            },
+            RedisCommand::MemoryUsage(key) => match db.memory_usage(&key) {
+                Some(bytes) => Value::Integer(bytes as i64),
+                None => Value::NullString,
+            },
+            RedisCommand::DebugSleep(duration) => {
+                tokio::time::sleep(duration).await;
+
+                Value::SimpleString(String::from("OK"))
+            }
+            RedisCommand::DebugJmap | RedisCommand::DebugSetActiveExpire => {
+                Value::SimpleString(String::from("OK"))
+            }
+            RedisCommand::DebugObject(key) => {
+                if let Some(encoding) = db.object_encoding(&key) {
+                    // There are no pointers or LRU clocks to report
+                    Value::SimpleString(format!(
+                        "Value at:0x0 refcount:1 encoding:{encoding} serializedlength:0 lru:0 \
+                         lru_seconds_idle:0"
+                    ))
+                } else {
+                    no_such_key_error()
+                }
+            }
+            RedisCommand::LatencyHistory(event) => {
+                let samples = db
+                    .latency()
+                    .history(&event)
+                    .into_iter()
+                    .map(|sample| {
+                        Value::Array(vec![
+                            Value::Integer(sample.timestamp as i64),
+                            Value::Integer(sample.latency as i64),
+                        ])
+                    })
+                    .collect();
+
+                Value::Array(samples)
+            }
+            RedisCommand::LatencyLatest => {
+                let events = db
+                    .latency()
+                    .latest()
+                    .into_iter()
+                    .map(|(event, latest, max)| {
+                        Value::Array(vec![
+                            Value::BulkString(Bytes::from(event)),
+                            Value::Integer(latest.timestamp as i64),
+                            Value::Integer(latest.latency as i64),
+                            Value::Integer(max as i64),
+                        ])
+                    })
+                    .collect();
+
+                Value::Array(events)
+            }
+            RedisCommand::LatencyReset(events) => {
+                let count = db.latency().reset(&events);
+
+                Value::Integer(count as i64)
+            }
+            RedisCommand::AclWhoAmI => {
+                // Connections are always authenticated as the default user
+                Value::BulkString(Bytes::from(DEFAULT_USER))
+            }
+            RedisCommand::AclList => {
+                let users = db
+                    .acl()
+                    .users()
+                    .map(|user| Value::BulkString(Bytes::from(user.describe())))
+                    .collect();
+
+                Value::Array(users)
+            }
+            RedisCommand::AclCat => {
+                let categories = acl::CATEGORIES
+                    .iter()
+                    .map(|category| Value::BulkString(Bytes::from(*category)))
+                    .collect();
+
+                Value::Array(categories)
+            }
+            RedisCommand::AclGetUser(name) => {
+                if let Some(user) = db.acl().user(&name) {
+                    user.to_value()
+                } else {
+                    Value::NullString
+                }
             }
+            RedisCommand::FunctionList => Value::Array(Vec::new()),
+            RedisCommand::FunctionDump => Value::NullString,
+            RedisCommand::FCall => Value::Error(RedisError {
+                message: String::from("ERR Function not found"),
+            }),
         }
     }
 }
+
+pub struct CommandParser {
+    buffer: VecDeque<Value>,
+}
+
+impl CommandParser {
+    pub fn new(buffer: Vec<Value>) -> Self {
+        Self {
+            buffer: VecDeque::from(buffer),
+        }
+    }
+
+    fn peek(&self) -> Option<&Value> {
+        self.buffer.front()
+    }
+
+    /// Returns the option the next argument names, ignoring case.
+    ///
+    /// This does not allocate, unlike uppercasing the argument first.
+    fn peek_option(&self, options: &[&'static str]) -> Option<&'static str> {
+        let argument = match self.peek() {
+            Some(Value::BulkString(bytes)) => &bytes[..],
+            Some(Value::SimpleString(string)) => string.as_bytes(),
+            _ => return None,
+        };
+
+        options
+            .iter()
+            .copied()
+            .find(|option| argument.eq_ignore_ascii_case(option.as_bytes()))
+    }
+
+    fn skip(&mut self) {
+        self.buffer.pop_front();
+    }
+
+    /// Takes the next argument as is, which may be any binary data.
+    fn expect_bytes(&mut self) -> Result<Bytes, ParseError> {
+        match self.buffer.pop_front() {
+            Some(Value::BulkString(bytes)) => Ok(bytes),
+            Some(Value::SimpleString(string)) => Ok(Bytes::from(string)),
+            _ => Err(ParseError::ExpectedString),
+        }
+    }
+
+    fn expect_string(&mut self) -> Result<String, ParseError> {
+        let bytes = self.expect_bytes()?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| ParseError::InvalidUtf8)
+    }
+
+    fn expect_integer(&mut self) -> Result<i64, ParseError> {
+        match self.buffer.pop_front() {
+            Some(Value::Integer(integer)) => Ok(integer),
+            // Clients send all arguments as strings
+            Some(Value::BulkString(bytes)) => {
+                parse_bytes(&bytes).ok_or(ParseError::ExpectedInteger)
+            }
+            Some(Value::SimpleString(string)) => {
+                string.parse().map_err(|_| ParseError::ExpectedInteger)
+            }
+            _ => Err(ParseError::ExpectedInteger),
+        }
+    }
+
+    /// Takes an expire time, which has to be positive.
+    /// See [`expire_timeout`] for the units.
+    fn expect_expire_time(
+        &mut self,
+        command: &'static str,
+        in_seconds: bool,
+        absolute: bool,
+    ) -> Result<Duration, ParseError> {
+        match self.expect_integer()? {
+            integer if integer > 0 => expire_timeout(integer, in_seconds, absolute)
+                .ok_or(ParseError::InvalidExpireTime(command)),
+            _ => Err(ParseError::InvalidExpireTime(command)),
+        }
+    }
+
+    /// Takes a longitude and a latitude that can be encoded as a geohash.
+    fn expect_position(&mut self) -> Result<(f64, f64), ParseError> {
+        let longitude = self.expect_float()?;
+        let latitude = self.expect_float()?;
+
+        if geo::is_valid(longitude, latitude) {
+            Ok((longitude, latitude))
+        } else {
+            Err(ParseError::InvalidCoordinates {
+                longitude,
+                latitude,
+            })
+        }
+    }
+
+    /// Takes a unit of distance, returning its length in meters.
+    fn expect_unit(&mut self) -> Result<f64, ParseError> {
+        let unit = self.expect_string()?;
+
+        geo::parse_unit(&unit).ok_or(ParseError::UnsupportedUnit)
+    }
+
+    fn expect_bit_offset(&mut self) -> Result<usize, ParseError> {
+        match self.expect_integer() {
+            Ok(offset) if (0..=MAX_BIT_OFFSET).contains(&offset) => Ok(offset as usize),
+            _ => Err(ParseError::InvalidBitOffset),
+        }
+    }
+
+    fn expect_float(&mut self) -> Result<f64, ParseError> {
+        let float: f64 = match self.buffer.pop_front() {
+            Some(Value::BulkString(bytes)) => {
+                parse_bytes(&bytes).ok_or(ParseError::ExpectedFloat)?
+            }
+            Some(Value::SimpleString(string)) => {
+                string.parse().map_err(|_| ParseError::ExpectedFloat)?
+            }
+            Some(Value::Integer(integer)) => integer as f64,
+            _ => return Err(ParseError::ExpectedFloat),
+        };
+
+        if float.is_nan() {
+            Err(ParseError::ExpectedFloat)
+        } else {
+            Ok(float)
+        }
+    }
+
+    /// Takes all remaining string arguments.
+    fn expect_remaining_strings(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut strings = Vec::with_capacity(self.buffer.len());
+
+        while !self.buffer.is_empty() {
+            strings.push(self.expect_string()?);
+        }
+
+        Ok(strings)
+    }
+
+    /// Takes all remaining arguments as key and value pairs.
+    fn expect_remaining_pairs(
+        &mut self,
+        command: &'static str,
+    ) -> Result<Vec<(String, Bytes)>, ParseError> {
+        if self.buffer.is_empty() || !self.buffer.len().is_multiple_of(2) {
+            return Err(ParseError::WrongArity(command));
+        }
+
+        let mut pairs = Vec::with_capacity(self.buffer.len() / 2);
+
+        while !self.buffer.is_empty() {
+            let key = self.expect_string()?;
+            let value = self.expect_bytes()?;
+
+            pairs.push((key, value));
+        }
+
+        Ok(pairs)
+    }
+
+    /// Takes all remaining arguments as field and value pairs.
+    fn expect_remaining_string_pairs(
+        &mut self,
+        command: &'static str,
+    ) -> Result<Vec<(String, String)>, ParseError> {
+        if self.buffer.is_empty() || !self.buffer.len().is_multiple_of(2) {
+            return Err(ParseError::WrongArity(command));
+        }
+
+        let mut pairs = Vec::with_capacity(self.buffer.len() / 2);
+
+        while !self.buffer.is_empty() {
+            let field = self.expect_string()?;
+            let value = self.expect_string()?;
+
+            pairs.push((field, value));
+        }
+
+        Ok(pairs)
+    }
+
+    /// Takes the optional `COUNT n` argument, treating negative counts as 0.
+    fn expect_count_option(&mut self) -> Result<Option<usize>, ParseError> {
+        if self.peek_option(&["COUNT"]).is_some() {
+            self.skip();
+            let count = self.expect_integer()?;
+
+            Ok(Some(count.max(0) as usize))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn expect_cursor(&mut self) -> Result<u64, ParseError> {
+        let cursor = self.expect_bytes().map_err(|_| ParseError::InvalidCursor)?;
+
+        parse_bytes(&cursor).ok_or(ParseError::InvalidCursor)
+    }
+
+    /// Takes the remaining `MATCH pattern` and `COUNT n` arguments of the
+    /// scan commands. The count defaults to 10 like in Redis.
+    fn expect_scan_options(&mut self) -> Result<(Option<String>, usize), ParseError> {
+        let mut pattern = None;
+        let mut count = 10;
+
+        while let Some(option) = self.peek_option(&["MATCH", "COUNT"]) {
+            self.skip();
+
+            if option == "MATCH" {
+                pattern = Some(self.expect_string()?);
+            } else {
+                count = self.expect_integer()?;
+
+                if count < 1 {
+                    return Err(ParseError::Syntax);
+                }
+            }
+        }
+
+        if !self.buffer.is_empty() {
+            return Err(ParseError::Syntax);
+        }
+
+        Ok((pattern, count as usize))
+    }
+
+    /// Takes the optional `MAXLEN [=|~] n` or `MINID [=|~] id` argument.
+    /// Approximate trimming is exact here.
+    fn expect_trim_option(&mut self) -> Result<Option<TrimStrategy>, ParseError> {
+        let option = match self.peek_option(&["MAXLEN", "MINID"]) {
+            Some(option) => option,
+            None => return Ok(None),
+        };
+        self.skip();
+
+        if self.peek_option(&["=", "~"]).is_some() {
+            self.skip();
+        }
+
+        let strategy = if option == "MAXLEN" {
+            let max_len = self.expect_integer()?;
+
+            if max_len < 0 {
+                return Err(ParseError::NegativeMaxLen);
+            }
+
+            TrimStrategy::MaxLen(max_len as usize)
+        } else {
+            let min_id =
+                StreamId::parse(&self.expect_string()?, 0).ok_or(ParseError::InvalidStreamId)?;
+
+            TrimStrategy::MinId(min_id)
+        };
+
+        Ok(Some(strategy))
+    }
+
+    /// Takes the `STREAMS key... id...` arguments of `XREAD` and
+    /// `XREADGROUP`. The special ID is `None`.
+    fn expect_streams(
+        &mut self,
+        command: &'static str,
+        special_id: &str,
+    ) -> Result<Vec<(String, Option<StreamId>)>, ParseError> {
+        if self.peek_option(&["STREAMS"]).is_none() {
+            return Err(ParseError::Syntax);
+        }
+        self.skip();
+
+        let mut arguments = self.expect_remaining_strings()?;
+
+        if arguments.is_empty() || !arguments.len().is_multiple_of(2) {
+            return Err(ParseError::UnbalancedStreams(command));
+        }
+
+        let ids = arguments.split_off(arguments.len() / 2);
+
+        arguments
+            .into_iter()
+            .zip(ids)
+            .map(|(key, id)| {
+                if id == special_id {
+                    Ok((key, None))
+                } else {
+                    let id = StreamId::parse(&id, 0).ok_or(ParseError::InvalidStreamId)?;
+
+                    Ok((key, Some(id)))
+                }
+            })
+            .collect()
+    }
+
+    pub fn parse(mut self) -> Result<RedisCommand, ParseError> {
+        let argc = self.buffer.len();
+        let mut command_name = self.expect_string()?;
+        command_name.make_ascii_uppercase();
+
+        check_arity(&command_name, argc)?;
+
+        // Some commands might have a subcommand
+        if command_name == "COMMAND" {
+            if let Ok(mut subcommand) = self.expect_string() {
+                subcommand.make_ascii_uppercase();
+
+                command_name.push(' ');
+                command_name.push_str(&subcommand);
+            }
+        } else if matches!(
+            command_name.as_str(),
+            "CLIENT"
+                | "CONFIG"
+                | "ACL"
+                | "DEBUG"
+                | "FUNCTION"
+                | "LATENCY"
+                | "MEMORY"
+                | "OBJECT"
+                | "XGROUP"
+        ) {
+            let mut subcommand = self.expect_string()?;
+            subcommand.make_ascii_uppercase();
+            command_name.push(' ');
+            command_name.push_str(&subcommand);
+        }
+
+        if command_name.contains(' ') {
+            check_arity(&command_name.replace(' ', "|"), argc)?;
+        }
+
+        // Now parse the arguments
+        match command_name.as_str() {
+            "COMMAND" => Ok(RedisCommand::Command),
+            "COMMAND INFO" => {
+                let command_names = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::CommandInfo(command_names))
+            }
+            "COMMAND DOCS" => {
+                let command_names = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::CommandDocs(command_names))
+            }
+            "COMMAND COUNT" => Ok(RedisCommand::CommandCount),
+            "COMMAND LIST" => Ok(RedisCommand::CommandList),
+            "CONFIG GET" => {
+                let parameter_globs = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::ConfigGet(parameter_globs))
+            }
+            "CONFIG SET" => {
+                let parameters = self.expect_remaining_string_pairs("config|set")?;
+
+                Ok(RedisCommand::ConfigSet(parameters))
+            }
+            "AUTH" => {
+                let first = self.expect_string()?;
+
+                let (username, password) = match self.expect_string() {
+                    Ok(password) => (Some(first), password),
+                    Err(_) => (None, first),
+                };
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                Ok(RedisCommand::Auth { username, password })
+            }
+            "HELLO" => {
+                let version = if self.buffer.is_empty() {
+                    None
+                } else {
+                    Some(self.expect_integer()?)
+                };
+
+                let mut auth = None;
+                let mut name = None;
+
+                while !self.buffer.is_empty() {
+                    let option = self
+                        .peek_option(&["AUTH", "SETNAME"])
+                        .ok_or(ParseError::Syntax)?;
+                    self.skip();
+
+                    if option == "AUTH" {
+                        let username = self.expect_string()?;
+                        let password = self.expect_string()?;
+
+                        auth = Some((username, password));
+                    } else {
+                        name = Some(self.expect_string()?);
+                    }
+                }
+
+                Ok(RedisCommand::Hello {
+                    version,
+                    auth,
+                    name,
+                })
+            }
+            "PING" => {
+                let message = self.expect_bytes().ok();
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::WrongArity("ping"));
+                }
+
+                Ok(RedisCommand::Ping(message))
+            }
+            "ECHO" => {
+                let message = self.expect_bytes()?;
+
+                Ok(RedisCommand::Echo(message))
+            }
+            "TIME" => Ok(RedisCommand::Time),
+            "LASTSAVE" => Ok(RedisCommand::LastSave),
+            "LOLWUT" => {
+                let version = if self.peek_option(&["VERSION"]).is_some() {
+                    self.skip();
+                    Some(self.expect_integer()?)
+                } else {
+                    None
+                };
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                Ok(RedisCommand::Lolwut(version))
+            }
+            "SELECT" => {
+                let index = self.expect_integer()?;
+
+                Ok(RedisCommand::Select(index))
+            }
+            "MULTI" => Ok(RedisCommand::Multi),
+            "EXEC" => Ok(RedisCommand::Exec),
+            "DISCARD" => Ok(RedisCommand::Discard),
+            "WATCH" => {
+                let keys = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::Watch(keys))
+            }
+            "UNWATCH" => Ok(RedisCommand::Unwatch),
+            "RESET" => Ok(RedisCommand::Reset),
+            "SUBSCRIBE" => {
+                let channels = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::Subscribe(channels))
+            }
+            "UNSUBSCRIBE" => {
+                let channels = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::Unsubscribe(channels))
+            }
+            "PUBLISH" => {
+                let channel = self.expect_string()?;
+                let message = self.expect_bytes()?;
+
+                Ok(RedisCommand::Publish { channel, message })
+            }
+            "GET" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::Get(key))
+            }
+            "GETDEL" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::GetDel(key))
+            }
+            "GETEX" => {
+                let key = self.expect_string()?;
+
+                let behaviour = match self.peek_option(&["EX", "PX", "EXAT", "PXAT", "PERSIST"]) {
+                    Some(option @ ("EX" | "PX" | "EXAT" | "PXAT")) => {
+                        self.skip();
+
+                        // Absolute times in the past expire the key right
+                        // away
+                        GetExBehaviour::Expire(self.expect_expire_time(
+                            "getex",
+                            matches!(option, "EX" | "EXAT"),
+                            matches!(option, "EXAT" | "PXAT"),
+                        )?)
+                    }
+                    Some("PERSIST") => {
+                        self.skip();
+
+                        GetExBehaviour::Persist
+                    }
+                    _ => GetExBehaviour::Keep,
+                };
+
+                // Only a single option is allowed
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                Ok(RedisCommand::GetEx { key, behaviour })
+            }
+            "MGET" => {
+                let keys = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::MGet(keys))
+            }
+            "SET" => {
+                let key = self.expect_string()?;
+                let value = self.expect_bytes()?;
+
+                let mut behaviour = SetBehaviour::Force;
+                let mut return_old = false;
+                let mut expiry = None;
+                let mut keep_ttl = false;
+
+                // The options may come in any order
+                while !self.buffer.is_empty() {
+                    let option = self
+                        .peek_option(&["NX", "XX", "GET", "EX", "PX", "EXAT", "PXAT", "KEEPTTL"])
+                        .ok_or(ParseError::Syntax)?;
+                    self.skip();
+
+                    match option {
+                        "NX" if matches!(behaviour, SetBehaviour::OnlyIfExists) => {
+                            return Err(ParseError::ConflictingOptions);
+                        }
+                        "XX" if matches!(behaviour, SetBehaviour::OnlyIfNotExists) => {
+                            return Err(ParseError::ConflictingOptions);
+                        }
+                        "NX" => behaviour = SetBehaviour::OnlyIfNotExists,
+                        "XX" => behaviour = SetBehaviour::OnlyIfExists,
+                        "GET" => return_old = true,
+                        _ if expiry.is_some() || keep_ttl => {
+                            return Err(ParseError::ConflictingOptions);
+                        }
+                        "KEEPTTL" => keep_ttl = true,
+                        _ => {
+                            // Absolute times in the past are a zero expiry,
+                            // which removes the key right away
+                            expiry = Some(self.expect_expire_time(
+                                "set",
+                                matches!(option, "EX" | "EXAT"),
+                                matches!(option, "EXAT" | "PXAT"),
+                            )?);
+                        }
+                    }
+                }
+
+                Ok(RedisCommand::Set {
+                    key,
+                    value,
+                    expiry,
+                    behaviour,
+                    return_old,
+                    keep_ttl,
+                })
+            }
+            "SETEX" | "PSETEX" => {
+                let command = if command_name == "SETEX" {
+                    "setex"
+                } else {
+                    "psetex"
+                };
+
+                let key = self.expect_string()?;
+                let expiry = self.expect_expire_time(command, command_name == "SETEX", false)?;
+                let value = self.expect_bytes()?;
+
+                Ok(RedisCommand::Set {
+                    key,
+                    value,
+                    expiry: Some(expiry),
+                    behaviour: SetBehaviour::Force,
+                    return_old: false,
+                    keep_ttl: false,
+                })
+            }
+            "GETSET" => {
+                let key = self.expect_string()?;
+                let value = self.expect_bytes()?;
+
+                Ok(RedisCommand::GetSet { key, value })
+            }
+            "SETNX" => {
+                let key = self.expect_string()?;
+                let value = self.expect_bytes()?;
+
+                Ok(RedisCommand::SetNx { key, value })
+            }
+            "MSET" => {
+                let pairs = self.expect_remaining_pairs("mset")?;
+
+                Ok(RedisCommand::MSet(pairs))
+            }
+            "MSETNX" => {
+                let pairs = self.expect_remaining_pairs("msetnx")?;
+
+                Ok(RedisCommand::MSetNx(pairs))
+            }
+            "DEL" => {
+                let keys = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::Del(keys))
+            }
+            "UNLINK" => {
+                let keys = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::Unlink(keys))
+            }
+            "RENAME" => {
+                let src = self.expect_string()?;
+                let dst = self.expect_string()?;
+
+                Ok(RedisCommand::Rename { src, dst })
+            }
+            "RENAMENX" => {
+                let src = self.expect_string()?;
+                let dst = self.expect_string()?;
+
+                Ok(RedisCommand::RenameNx { src, dst })
+            }
+            "COPY" => {
+                let src = self.expect_string()?;
+                let dst = self.expect_string()?;
+
+                let replace = self.peek_option(&["REPLACE"]).is_some();
+
+                if replace {
+                    self.skip();
+                }
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                Ok(RedisCommand::Copy { src, dst, replace })
+            }
+            "DUMP" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::Dump(key))
+            }
+            "RESTORE" => {
+                let key = self.expect_string()?;
+                let ttl = match self.expect_integer()? {
+                    0 => None,
+                    millis if millis > 0 => Some(Duration::from_millis(millis as u64)),
+                    _ => return Err(ParseError::InvalidTtl),
+                };
+                let value =
+                    dump::deserialize(&self.expect_bytes()?).ok_or(ParseError::BadDataFormat)?;
+
+                let replace = self.peek_option(&["REPLACE"]).is_some();
+
+                if replace {
+                    self.skip();
+                }
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                Ok(RedisCommand::Restore {
+                    key,
+                    ttl,
+                    value,
+                    replace,
+                })
+            }
+            "EXISTS" => {
+                let keys = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::Exists(keys))
+            }
+            "TOUCH" => {
+                let keys = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::Touch(keys))
+            }
+            "TTL" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::Ttl(key))
+            }
+            "PERSIST" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::Persist(key))
+            }
+            "PTTL" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::Pttl(key))
+            }
+            "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" => {
+                let key = self.expect_string()?;
+                let time = self.expect_integer()?;
+
+                let (command, in_seconds, absolute) = match command_name.as_str() {
+                    "EXPIRE" => ("expire", true, false),
+                    "PEXPIRE" => ("pexpire", false, false),
+                    "EXPIREAT" => ("expireat", true, true),
+                    _ => ("pexpireat", false, true),
+                };
+
+                // Times in the past are a zero timeout, which removes the key
+                // right away
+                let timeout = expire_timeout(time, in_seconds, absolute)
+                    .ok_or(ParseError::InvalidExpireTime(command))?;
+
+                let behaviour = match self.peek_option(&["NX", "XX", "GT", "LT"]) {
+                    Some("NX") => {
+                        self.skip();
+                        ExpireBehaviour::OnlyIfNoExpiry
+                    }
+                    Some("XX") => {
+                        self.skip();
+                        ExpireBehaviour::OnlyIfExpiry
+                    }
+                    Some("GT") => {
+                        self.skip();
+                        ExpireBehaviour::OnlyIfGreater
+                    }
+                    Some("LT") => {
+                        self.skip();
+                        ExpireBehaviour::OnlyIfLess
+                    }
+                    _ => ExpireBehaviour::Force,
+                };
+
+                Ok(RedisCommand::Expire {
+                    key,
+                    timeout,
+                    behaviour,
+                })
+            }
+            "INCR" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::Incr(key))
+            }
+            "DECR" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::Decr(key))
+            }
+            "INCRBY" => {
+                let key = self.expect_string()?;
+                let increment = self.expect_integer()?;
+
+                Ok(RedisCommand::IncrBy { key, increment })
+            }
+            "DECRBY" => {
+                let key = self.expect_string()?;
+                let decrement = self.expect_integer()?;
+
+                Ok(RedisCommand::DecrBy { key, decrement })
+            }
+            "INCRBYFLOAT" => {
+                let key = self.expect_string()?;
+                let increment = self.expect_float()?;
+
+                Ok(RedisCommand::IncrByFloat { key, increment })
+            }
+            "APPEND" => {
+                let key = self.expect_string()?;
+                let value = self.expect_bytes()?;
+
+                Ok(RedisCommand::Append { key, value })
+            }
+            "SETRANGE" => {
+                let key = self.expect_string()?;
+                let offset = match self.expect_integer()? {
+                    offset if offset >= 0 => offset as usize,
+                    _ => return Err(ParseError::OffsetOutOfRange),
+                };
+                let value = self.expect_bytes()?;
+
+                Ok(RedisCommand::SetRange { key, offset, value })
+            }
+            "STRLEN" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::Strlen(key))
+            }
+            "SETBIT" => {
+                let key = self.expect_string()?;
+                let offset = self.expect_bit_offset()?;
+                let bit = match self.expect_integer() {
+                    Ok(0) => false,
+                    Ok(1) => true,
+                    _ => return Err(ParseError::InvalidBit),
+                };
+
+                Ok(RedisCommand::SetBit { key, offset, bit })
+            }
+            "GETBIT" => {
+                let key = self.expect_string()?;
+                let offset = self.expect_bit_offset()?;
+
+                Ok(RedisCommand::GetBit { key, offset })
+            }
+            "BITCOUNT" => {
+                let key = self.expect_string()?;
+
+                let range = if self.buffer.is_empty() {
+                    None
+                } else {
+                    let start = self.expect_integer()?;
+                    let end = self.expect_integer().map_err(|_| ParseError::Syntax)?;
+
+                    let in_bits = match self.peek_option(&["BYTE", "BIT"]) {
+                        Some(unit) => {
+                            self.skip();
+                            unit == "BIT"
+                        }
+                        None => false,
+                    };
+
+                    Some(BitRange {
+                        start,
+                        end,
+                        in_bits,
+                    })
+                };
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                Ok(RedisCommand::BitCount { key, range })
+            }
+            "LPUSH" => {
+                let key = self.expect_string()?;
+                let mut elements = vec![self.expect_string()?];
+                elements.extend(self.expect_remaining_strings()?);
+
+                Ok(RedisCommand::LPush { key, elements })
+            }
+            "RPUSH" => {
+                let key = self.expect_string()?;
+                let mut elements = vec![self.expect_string()?];
+                elements.extend(self.expect_remaining_strings()?);
+
+                Ok(RedisCommand::RPush { key, elements })
+            }
+            "LPOP" | "RPOP" => {
+                let key = self.expect_string()?;
+                let count = if self.buffer.is_empty() {
+                    None
+                } else {
+                    let count = self.expect_integer()?;
+
+                    if count < 0 {
+                        return Err(ParseError::NegativeCount);
+                    }
+
+                    Some(count as usize)
+                };
+
+                if command_name == "LPOP" {
+                    Ok(RedisCommand::LPop { key, count })
+                } else {
+                    Ok(RedisCommand::RPop { key, count })
+                }
+            }
+            "BLPOP" | "BRPOP" => {
+                let mut keys = self.expect_remaining_strings()?;
+                // The arity guarantees a key and the timeout
+                let timeout = keys.pop().unwrap();
+                let seconds: f64 = timeout.parse().map_err(|_| ParseError::InvalidTimeout)?;
+
+                if seconds < 0.0 {
+                    return Err(ParseError::NegativeTimeout);
+                }
+
+                // 0 waits forever
+                let timeout =
+                    Duration::try_from_secs_f64(seconds).map_err(|_| ParseError::InvalidTimeout)?;
+                let timeout = Some(timeout).filter(|timeout| !timeout.is_zero());
+
+                if command_name == "BLPOP" {
+                    Ok(RedisCommand::BLPop { keys, timeout })
+                } else {
+                    Ok(RedisCommand::BRPop { keys, timeout })
+                }
+            }
+            "LLEN" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::LLen(key))
+            }
+            "LRANGE" => {
+                let key = self.expect_string()?;
+                let start = self.expect_integer()?;
+                let stop = self.expect_integer()?;
+
+                Ok(RedisCommand::LRange { key, start, stop })
+            }
+            "HSET" => {
+                let key = self.expect_string()?;
+                let pairs = self.expect_remaining_string_pairs("hset")?;
+
+                Ok(RedisCommand::HSet { key, pairs })
+            }
+            "HGET" => {
+                let key = self.expect_string()?;
+                let field = self.expect_string()?;
+
+                Ok(RedisCommand::HGet { key, field })
+            }
+            "HDEL" => {
+                let key = self.expect_string()?;
+                let mut fields = vec![self.expect_string()?];
+                fields.extend(self.expect_remaining_strings()?);
+
+                Ok(RedisCommand::HDel { key, fields })
+            }
+            "HGETALL" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::HGetAll(key))
+            }
+            "HSCAN" => {
+                let key = self.expect_string()?;
+                let cursor = self.expect_cursor()?;
+                let (pattern, count) = self.expect_scan_options()?;
+
+                Ok(RedisCommand::HScan {
+                    key,
+                    cursor,
+                    pattern,
+                    count,
+                })
+            }
+            "SADD" => {
+                let key = self.expect_string()?;
+                let mut members = vec![self.expect_string()?];
+                members.extend(self.expect_remaining_strings()?);
+
+                Ok(RedisCommand::SAdd { key, members })
+            }
+            "SREM" => {
+                let key = self.expect_string()?;
+                let mut members = vec![self.expect_string()?];
+                members.extend(self.expect_remaining_strings()?);
+
+                Ok(RedisCommand::SRem { key, members })
+            }
+            "SISMEMBER" => {
+                let key = self.expect_string()?;
+                let member = self.expect_string()?;
+
+                Ok(RedisCommand::SIsMember { key, member })
+            }
+            "SMEMBERS" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::SMembers(key))
+            }
+            "SSCAN" => {
+                let key = self.expect_string()?;
+                let cursor = self.expect_cursor()?;
+                let (pattern, count) = self.expect_scan_options()?;
+
+                Ok(RedisCommand::SScan {
+                    key,
+                    cursor,
+                    pattern,
+                    count,
+                })
+            }
+            "SCARD" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::SCard(key))
+            }
+            "SPOP" => {
+                let key = self.expect_string()?;
+                let count = if self.buffer.is_empty() {
+                    None
+                } else {
+                    let count = self.expect_integer()?;
+
+                    if count < 0 {
+                        return Err(ParseError::NegativeCount);
+                    }
+
+                    Some(count as usize)
+                };
+
+                Ok(RedisCommand::SPop { key, count })
+            }
+            "ZADD" => {
+                let key = self.expect_string()?;
+
+                let mut condition = AddCondition::Always;
+                let mut update = ScoreUpdate::Always;
+                let mut count_changed = false;
+
+                while let Some(option) = self.peek_option(&["NX", "XX", "GT", "LT", "CH"]) {
+                    self.skip();
+
+                    match option {
+                        "NX" if matches!(condition, AddCondition::OnlyExisting) => {
+                            return Err(ParseError::IncompatibleNxXx)
+                        }
+                        "XX" if matches!(condition, AddCondition::OnlyNew) => {
+                            return Err(ParseError::IncompatibleNxXx)
+                        }
+                        "GT" if matches!(update, ScoreUpdate::OnlyLess) => {
+                            return Err(ParseError::IncompatibleGtLtNx)
+                        }
+                        "LT" if matches!(update, ScoreUpdate::OnlyGreater) => {
+                            return Err(ParseError::IncompatibleGtLtNx)
+                        }
+                        "NX" => condition = AddCondition::OnlyNew,
+                        "XX" => condition = AddCondition::OnlyExisting,
+                        "GT" => update = ScoreUpdate::OnlyGreater,
+                        "LT" => update = ScoreUpdate::OnlyLess,
+                        _ => count_changed = true,
+                    }
+                }
+
+                // GT and LT only apply to existing members
+                if matches!(condition, AddCondition::OnlyNew)
+                    && !matches!(update, ScoreUpdate::Always)
+                {
+                    return Err(ParseError::IncompatibleGtLtNx);
+                }
+
+                if self.buffer.is_empty() || !self.buffer.len().is_multiple_of(2) {
+                    return Err(ParseError::Syntax);
+                }
+
+                let mut members = Vec::with_capacity(self.buffer.len() / 2);
+
+                while !self.buffer.is_empty() {
+                    let score = self.expect_float()?;
+                    let member = self.expect_string()?;
+
+                    members.push((score, member));
+                }
+
+                Ok(RedisCommand::ZAdd {
+                    key,
+                    members,
+                    condition,
+                    update,
+                    count_changed,
+                })
+            }
+            "ZSCORE" => {
+                let key = self.expect_string()?;
+                let member = self.expect_string()?;
+
+                Ok(RedisCommand::ZScore { key, member })
+            }
+            "ZCARD" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::ZCard(key))
+            }
+            "ZRANGE" => {
+                let key = self.expect_string()?;
+                let start = self.expect_integer()?;
+                let stop = self.expect_integer()?;
+
+                let mut reverse = false;
+                let mut with_scores = false;
+
+                while let Some(option) = self.peek_option(&["REV", "WITHSCORES"]) {
+                    self.skip();
+
+                    match option {
+                        "REV" => reverse = true,
+                        _ => with_scores = true,
+                    }
+                }
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                Ok(RedisCommand::ZRange {
+                    key,
+                    start,
+                    stop,
+                    reverse,
+                    with_scores,
+                })
+            }
+            "ZRANGEBYSCORE" => {
+                let key = self.expect_string()?;
+                let min = zset::parse_bound(&self.expect_string()?)
+                    .ok_or(ParseError::InvalidScoreBound)?;
+                let max = zset::parse_bound(&self.expect_string()?)
+                    .ok_or(ParseError::InvalidScoreBound)?;
+
+                let mut with_scores = false;
+                let mut offset = 0;
+                let mut count = None;
+
+                while let Some(option) = self.peek_option(&["WITHSCORES", "LIMIT"]) {
+                    self.skip();
+
+                    if option == "WITHSCORES" {
+                        with_scores = true;
+                        continue;
+                    }
+
+                    let limit_offset = self.expect_integer()?;
+                    let limit_count = self.expect_integer()?;
+
+                    // A negative offset selects nothing and a negative count
+                    // everything after the offset
+                    if limit_offset < 0 {
+                        count = Some(0);
+                    } else {
+                        offset = limit_offset as usize;
+                        count = (limit_count >= 0).then_some(limit_count as usize);
+                    }
+                }
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                Ok(RedisCommand::ZRangeByScore {
+                    key,
+                    min,
+                    max,
+                    with_scores,
+                    offset,
+                    count,
+                })
+            }
+            "ZINCRBY" => {
+                let key = self.expect_string()?;
+                let increment = self.expect_float()?;
+                let member = self.expect_string()?;
+
+                Ok(RedisCommand::ZIncrBy {
+                    key,
+                    increment,
+                    member,
+                })
+            }
+            "GEOADD" => {
+                let key = self.expect_string()?;
+
+                let mut condition = AddCondition::Always;
+                let mut count_changed = false;
+
+                while let Some(option) = self.peek_option(&["NX", "XX", "CH"]) {
+                    self.skip();
+
+                    match option {
+                        "NX" if matches!(condition, AddCondition::OnlyExisting) => {
+                            return Err(ParseError::IncompatibleNxXx)
+                        }
+                        "XX" if matches!(condition, AddCondition::OnlyNew) => {
+                            return Err(ParseError::IncompatibleNxXx)
+                        }
+                        "NX" => condition = AddCondition::OnlyNew,
+                        "XX" => condition = AddCondition::OnlyExisting,
+                        _ => count_changed = true,
+                    }
+                }
+
+                if self.buffer.is_empty() || !self.buffer.len().is_multiple_of(3) {
+                    return Err(ParseError::Syntax);
+                }
+
+                let mut members = Vec::with_capacity(self.buffer.len() / 3);
+
+                while !self.buffer.is_empty() {
+                    let (longitude, latitude) = self.expect_position()?;
+                    let member = self.expect_string()?;
+
+                    members.push((geo::encode(longitude, latitude) as f64, member));
+                }
+
+                // The geohashes are the scores of a sorted set
+                Ok(RedisCommand::ZAdd {
+                    key,
+                    members,
+                    condition,
+                    update: ScoreUpdate::Always,
+                    count_changed,
+                })
+            }
+            "GEOPOS" => {
+                let key = self.expect_string()?;
+                let members = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::GeoPos { key, members })
+            }
+            "GEODIST" => {
+                let key = self.expect_string()?;
+                let from = self.expect_string()?;
+                let to = self.expect_string()?;
+
+                let unit = if self.buffer.is_empty() {
+                    1.0
+                } else {
+                    self.expect_unit()?
+                };
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                Ok(RedisCommand::GeoDist {
+                    key,
+                    from,
+                    to,
+                    unit,
+                })
+            }
+            "GEOSEARCH" => {
+                let key = self.expect_string()?;
+
+                let mut center = None;
+                let mut shape = None;
+                let mut unit = 1.0;
+                let mut order = None;
+                let mut count = None;
+                let mut any = false;
+                let mut with_coord = false;
+                let mut with_dist = false;
+                let mut with_hash = false;
+
+                while !self.buffer.is_empty() {
+                    let option = self
+                        .peek_option(&[
+                            "FROMMEMBER",
+                            "FROMLONLAT",
+                            "BYRADIUS",
+                            "BYBOX",
+                            "ASC",
+                            "DESC",
+                            "COUNT",
+                            "WITHCOORD",
+                            "WITHDIST",
+                            "WITHHASH",
+                        ])
+                        .ok_or(ParseError::Syntax)?;
+                    self.skip();
+
+                    match option {
+                        "FROMMEMBER" | "FROMLONLAT" if center.is_some() => {
+                            return Err(ParseError::GeoSearchCenter);
+                        }
+                        "BYRADIUS" | "BYBOX" if shape.is_some() => {
+                            return Err(ParseError::GeoSearchShape);
+                        }
+                        "FROMMEMBER" => center = Some(Center::Member(self.expect_string()?)),
+                        "FROMLONLAT" => {
+                            let (longitude, latitude) = self.expect_position()?;
+
+                            center = Some(Center::Position {
+                                longitude,
+                                latitude,
+                            });
+                        }
+                        "BYRADIUS" => {
+                            let radius = self.expect_float()?;
+
+                            if radius < 0.0 {
+                                return Err(ParseError::NegativeRadius);
+                            }
+
+                            unit = self.expect_unit()?;
+                            shape = Some(Shape::Radius(radius * unit));
+                        }
+                        "BYBOX" => {
+                            let width = self.expect_float()?;
+                            let height = self.expect_float()?;
+
+                            if width < 0.0 || height < 0.0 {
+                                return Err(ParseError::NegativeBoxSize);
+                            }
+
+                            unit = self.expect_unit()?;
+                            shape = Some(Shape::Box {
+                                width: width * unit,
+                                height: height * unit,
+                            });
+                        }
+                        "ASC" => order = Some(Order::Ascending),
+                        "DESC" => order = Some(Order::Descending),
+                        "COUNT" => {
+                            match self.expect_integer()? {
+                                limit if limit > 0 => count = Some(limit as usize),
+                                _ => return Err(ParseError::CountNotPositive),
+                            }
+
+                            if self.peek_option(&["ANY"]).is_some() {
+                                self.skip();
+                                any = true;
+                            }
+                        }
+                        "WITHCOORD" => with_coord = true,
+                        "WITHDIST" => with_dist = true,
+                        _ => with_hash = true,
+                    }
+                }
+
+                let center = center.ok_or(ParseError::GeoSearchCenter)?;
+                let shape = shape.ok_or(ParseError::GeoSearchShape)?;
+
+                Ok(RedisCommand::GeoSearch {
+                    key,
+                    center,
+                    shape,
+                    unit,
+                    order,
+                    count,
+                    any,
+                    with_coord,
+                    with_dist,
+                    with_hash,
+                })
+            }
+            "XADD" => {
+                let key = self.expect_string()?;
+                let trim = self.expect_trim_option()?;
+                let id =
+                    IdSpec::parse(&self.expect_string()?).ok_or(ParseError::InvalidStreamId)?;
+                let fields = self.expect_remaining_string_pairs("xadd")?;
+
+                Ok(RedisCommand::XAdd {
+                    key,
+                    trim,
+                    id,
+                    fields,
+                })
+            }
+            "XTRIM" => {
+                let key = self.expect_string()?;
+                let strategy = self.expect_trim_option()?.ok_or(ParseError::Syntax)?;
+
+                Ok(RedisCommand::XTrim { key, strategy })
+            }
+            "XLEN" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::XLen(key))
+            }
+            "XRANGE" => {
+                let key = self.expect_string()?;
+                let start = stream::parse_bound(&self.expect_string()?, 0)
+                    .ok_or(ParseError::InvalidStreamId)?;
+                let end = stream::parse_bound(&self.expect_string()?, u64::MAX)
+                    .ok_or(ParseError::InvalidStreamId)?;
+                let count = self.expect_count_option()?;
+
+                Ok(RedisCommand::XRange {
+                    key,
+                    start,
+                    end,
+                    count,
+                })
+            }
+            "XREAD" => {
+                let mut count = None;
+                let mut block = None;
+
+                while let Some(option) = self.peek_option(&["COUNT", "BLOCK"]) {
+                    if option == "COUNT" {
+                        count = self.expect_count_option()?;
+                    } else {
+                        self.skip();
+                        let millis = self.expect_integer()?;
+
+                        if millis < 0 {
+                            return Err(ParseError::NegativeTimeout);
+                        }
+
+                        block = Some(Duration::from_millis(millis as u64));
+                    }
+                }
+
+                let streams = self.expect_streams("xread", "$")?;
+
+                Ok(RedisCommand::XRead {
+                    streams,
+                    count,
+                    block,
+                })
+            }
+            "XGROUP CREATE" => {
+                let key = self.expect_string()?;
+                let group = self.expect_string()?;
+                let id = match self.expect_string()?.as_str() {
+                    "$" => None,
+                    id => Some(StreamId::parse(id, 0).ok_or(ParseError::InvalidStreamId)?),
+                };
+
+                let mkstream = if self.peek_option(&["MKSTREAM"]).is_some() {
+                    self.skip();
+                    true
+                } else {
+                    false
+                };
+
+                Ok(RedisCommand::XGroupCreate {
+                    key,
+                    group,
+                    id,
+                    mkstream,
+                })
+            }
+            "XREADGROUP" => {
+                if self.peek_option(&["GROUP"]).is_none() {
+                    return Err(ParseError::Syntax);
+                }
+                self.skip();
+
+                let group = self.expect_string()?;
+                let consumer = self.expect_string()?;
+
+                let mut count = None;
+                let mut noack = false;
+
+                while let Some(option) = self.peek_option(&["COUNT", "NOACK"]) {
+                    if option == "COUNT" {
+                        count = self.expect_count_option()?;
+                    } else {
+                        self.skip();
+                        noack = true;
+                    }
+                }
+
+                let streams = self.expect_streams("xreadgroup", ">")?;
+
+                Ok(RedisCommand::XReadGroup {
+                    group,
+                    consumer,
+                    streams,
+                    count,
+                    noack,
+                })
+            }
+            "XACK" => {
+                let key = self.expect_string()?;
+                let group = self.expect_string()?;
+
+                let mut ids = vec![self.expect_string()?];
+                ids.extend(self.expect_remaining_strings()?);
+
+                let ids = ids
+                    .iter()
+                    .map(|id| StreamId::parse(id, 0).ok_or(ParseError::InvalidStreamId))
+                    .collect::<Result<_, _>>()?;
+
+                Ok(RedisCommand::XAck { key, group, ids })
+            }
+            "XPENDING" => {
+                let key = self.expect_string()?;
+                let group = self.expect_string()?;
+
+                let filter = if self.buffer.is_empty() {
+                    None
+                } else {
+                    let min_idle = if self.peek_option(&["IDLE"]).is_some() {
+                        self.skip();
+                        let millis = self.expect_integer()?;
+
+                        Duration::from_millis(millis.max(0) as u64)
+                    } else {
+                        Duration::ZERO
+                    };
+
+                    let start = stream::parse_bound(&self.expect_string()?, 0)
+                        .ok_or(ParseError::InvalidStreamId)?;
+                    let end = stream::parse_bound(&self.expect_string()?, u64::MAX)
+                        .ok_or(ParseError::InvalidStreamId)?;
+                    let count = self.expect_integer()?.max(0) as usize;
+                    let consumer = self.expect_string().ok();
+
+                    Some(PendingFilter {
+                        start,
+                        end,
+                        count: Some(count),
+                        consumer,
+                        min_idle,
+                    })
+                };
+
+                Ok(RedisCommand::XPending { key, group, filter })
+            }
+            "TYPE" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::Type(key))
+            }
+            "KEYS" => {
+                let glob = self.expect_string()?;
+
+                Ok(RedisCommand::Keys(glob))
+            }
+            "SCAN" => {
+                let cursor = self.expect_cursor()?;
+                let (pattern, count) = self.expect_scan_options()?;
+
+                Ok(RedisCommand::Scan {
+                    cursor,
+                    pattern,
+                    count,
+                })
+            }
+            "RANDOMKEY" => Ok(RedisCommand::RandomKey),
+            "DBSIZE" => Ok(RedisCommand::DbSize),
+            "WAIT" => {
+                self.expect_integer()?;
+
+                if self.expect_integer()? < 0 {
+                    return Err(ParseError::NegativeTimeout);
+                }
+
+                Ok(RedisCommand::Wait)
+            }
+            "CLIENT ID" => Ok(RedisCommand::ClientId),
+            "CLIENT GETNAME" => Ok(RedisCommand::ClientGetName),
+            "CLIENT INFO" => Ok(RedisCommand::ClientInfo),
+            "CLIENT SETNAME" => {
+                let name = self.expect_string()?;
+
+                Ok(RedisCommand::ClientSetName(name))
+            }
+            "INFO" => {
+                let section = self.expect_string().ok();
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                Ok(RedisCommand::Info(section))
+            }
+            "FLUSHDB" | "FLUSHALL" => {
+                // Flushing is always synchronous
+                if self.peek_option(&["ASYNC", "SYNC"]).is_some() {
+                    self.skip();
+                }
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                if command_name == "FLUSHDB" {
+                    Ok(RedisCommand::FlushDb)
+                } else {
+                    Ok(RedisCommand::FlushAll)
+                }
+            }
+            "OBJECT ENCODING" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::ObjectEncoding(key))
+            }
+            "OBJECT REFCOUNT" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::ObjectRefCount(key))
+            }
+            "OBJECT IDLETIME" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::ObjectIdleTime(key))
+            }
+            "MEMORY USAGE" => {
+                let key = self.expect_string()?;
+
+                // The estimate always looks at every element
+                if self.peek_option(&["SAMPLES"]).is_some() {
+                    self.skip();
+                    self.expect_integer()?;
+                }
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                Ok(RedisCommand::MemoryUsage(key))
+            }
+            "DEBUG SLEEP" => {
+                let seconds = self.expect_float()?;
+                let duration = Duration::try_from_secs_f64(seconds.max(0.0))
+                    .map_err(|_| ParseError::ExpectedFloat)?;
+
+                Ok(RedisCommand::DebugSleep(duration))
+            }
+            "DEBUG JMAP" => Ok(RedisCommand::DebugJmap),
+            "DEBUG SET-ACTIVE-EXPIRE" => {
+                self.expect_integer()?;
+
+                Ok(RedisCommand::DebugSetActiveExpire)
+            }
+            "DEBUG OBJECT" => {
+                let key = self.expect_string()?;
+
+                Ok(RedisCommand::DebugObject(key))
+            }
+            "LATENCY HISTORY" => {
+                let event = self.expect_string()?;
+
+                Ok(RedisCommand::LatencyHistory(event))
+            }
+            "LATENCY LATEST" => Ok(RedisCommand::LatencyLatest),
+            "LATENCY RESET" => {
+                let events = self.expect_remaining_strings()?;
+
+                Ok(RedisCommand::LatencyReset(events))
+            }
+            "ACL WHOAMI" => Ok(RedisCommand::AclWhoAmI),
+            "ACL LIST" => Ok(RedisCommand::AclList),
+            "ACL CAT" => Ok(RedisCommand::AclCat),
+            "ACL GETUSER" => {
+                let name = self.expect_string()?;
+
+                Ok(RedisCommand::AclGetUser(name))
+            }
+            "FUNCTION LIST" => {
+                // The options only filter the libraries
+                if self.peek_option(&["LIBRARYNAME"]).is_some() {
+                    self.skip();
+                    self.expect_string()?;
+                }
+
+                if self.peek_option(&["WITHCODE"]).is_some() {
+                    self.skip();
+                }
+
+                if !self.buffer.is_empty() {
+                    return Err(ParseError::Syntax);
+                }
+
+                Ok(RedisCommand::FunctionList)
+            }
+            "FUNCTION DUMP" => Ok(RedisCommand::FunctionDump),
+            "FCALL" | "FCALL_RO" => {
+                self.expect_string()?;
+                let numkeys = self.expect_integer()?;
+
+                if numkeys < 0 {
+                    return Err(ParseError::NegativeNumKeys);
+                }
+
+                if numkeys as usize > self.buffer.len() {
+                    return Err(ParseError::TooManyKeys);
+                }
+
+                Ok(RedisCommand::FCall)
+            }
+            cmd => {
+                if let Some((command, subcommand)) = cmd.split_once(' ') {
+                    return Err(ParseError::UnknownSubcommand {
+                        command: command.to_string(),
+                        subcommand: subcommand.to_string(),
+                    });
+                }
+
+                Err(ParseError::UnknownCommand(cmd.to_string()))
+            }
+        }
+    }
+}
+
+/// Checks a password for a user, which is the default user without a user
+/// name. The default user is nopass without a password configured.
+fn check_credentials(db: &Db, username: Option<&str>, password: &str) -> Result<(), Value> {
+    let requirepass = db.config().value("requirepass");
+
+    let username_matches = username.is_none_or(|username| username == DEFAULT_USER);
+    let password_matches = requirepass.is_empty() || password == requirepass;
+
+    if username_matches && password_matches {
+        Ok(())
+    } else {
+        Err(Value::Error(RedisError {
+            message: String::from("WRONGPASS invalid username-password pair or user is disabled."),
+        }))
+    }
+}
+
+/// Checks a client name, since names show up in space separated lists.
+fn check_client_name(name: &str) -> Result<(), Value> {
+    if name.bytes().any(|byte| !(b'!'..=b'~').contains(&byte)) {
+        Err(Value::Error(RedisError {
+            message: String::from(
+                "ERR Client names cannot contain spaces, newlines or special characters.",
+            ),
+        }))
+    } else {
+        Ok(())
+    }
+}
+
+/// Converts an expire time in seconds or milliseconds, relative to now or a
+/// Unix time, to the timeout from now. Like Redis, times whose milliseconds
+/// or Unix time overflow are rejected. Times in the past are a zero timeout.
+fn expire_timeout(time: i64, in_seconds: bool, absolute: bool) -> Option<Duration> {
+    let millis = if in_seconds {
+        time.checked_mul(1000)?
+    } else {
+        time
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64;
+    let at = if absolute {
+        millis
+    } else {
+        millis.checked_add(now)?
+    };
+
+    let timeout = Duration::from_millis(at.saturating_sub(now).max(0) as u64);
+
+    Instant::now().checked_add(timeout).map(|_| timeout)
+}
+
+/// Checks the number of arguments, including the command name, against the
+/// command table. Unknown commands are left to the parser.
+fn check_arity(name: &str, argc: usize) -> Result<(), ParseError> {
+    match command_table::lookup(name) {
+        Some(spec) if !spec.accepts(argc) => Err(ParseError::WrongArity(spec.name)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+fn parse(args: &[&str]) -> Result<RedisCommand, ParseError> {
+    let buffer = args
+        .iter()
+        .map(|arg| Value::BulkString(Bytes::copy_from_slice(arg.as_bytes())))
+        .collect();
+
+    CommandParser::new(buffer).parse()
+}
+
+#[tokio::test]
+async fn acl_whoami_works() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let reply = parse(&["ACL", "WHOAMI"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    assert_eq!(reply, Value::BulkString(Bytes::from("default")));
+
+    let reply = parse(&["acl", "getuser", "default"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    assert!(matches!(reply, Value::Array(fields) if fields.len() == 12));
+}
+
+#[test]
+fn acl_unknown_subcommand_errors() {
+    let err = parse(&["ACL", "foo"]).err().unwrap();
+
+    assert_eq!(
+        err.to_string(),
+        "ERR unknown subcommand 'FOO'. Try ACL HELP."
+    );
+}
+
+#[test]
+fn missing_arguments_error() {
+    let test_data: &[(&[&str], &str)] = &[
+        (&["GET"], "get"),
+        (&["get", "a", "b"], "get"),
+        (&["SET", "a"], "set"),
+        (&["SET"], "set"),
+        (&["EXPIRE", "a"], "expire"),
+        (&["CONFIG"], "config"),
+        (&["CONFIG", "GET"], "config|get"),
+        (&["COMMAND", "COUNT", "a"], "command|count"),
+        (&["HSET", "a", "b", "c", "d"], "hset"),
+    ];
+
+    for (args, name) in test_data {
+        let err = parse(args).err().unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            format!("ERR wrong number of arguments for '{name}' command"),
+            "{args:?}"
+        );
+    }
+}
+
+#[test]
+fn invalid_integers_error() {
+    for args in [
+        &["INCRBY", "a", "abc"][..],
+        &["EXPIRE", "a", "abc"],
+        &["LRANGE", "a", "a", "b"],
+        &["SELECT", "x"],
+    ] {
+        let err = parse(args).err().unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            "ERR value is not an integer or out of range",
+            "{args:?}"
+        );
+    }
+}
+
+#[test]
+fn unknown_command_errors() {
+    let err = parse(&["FOO", "bar"]).err().unwrap();
+
+    assert_eq!(err.to_string(), "ERR unknown command 'FOO'");
+
+    // Every command in the table is dispatched
+    for spec in COMMANDS {
+        let name = spec.name.to_ascii_uppercase();
+
+        assert!(
+            !matches!(parse(&[&name]), Err(ParseError::UnknownCommand(_))),
+            "{name}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn config_get_matches_globs() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["CONFIG", "GET", "maxmemory*"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Map(vec![
+            (
+                Value::BulkString(Bytes::from("maxmemory")),
+                Value::BulkString(Bytes::from("0")),
+            ),
+            (
+                Value::BulkString(Bytes::from("maxmemory-policy")),
+                Value::BulkString(Bytes::from("noeviction")),
+            ),
+        ])
+    );
+
+    let reply = parse(&["CONFIG", "SET", "maxmemory", "1024"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+
+    // Parameters matching several patterns are only returned once
+    let reply = parse(&["CONFIG", "GET", "MAXMEMORY", "max*ory"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Map(vec![(
+            Value::BulkString(Bytes::from("maxmemory")),
+            Value::BulkString(Bytes::from("1024")),
+        )])
+    );
+
+    let reply = parse(&["CONFIG", "SET", "missing", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from(
+                "ERR Unknown option or number of arguments for CONFIG SET - 'missing'"
+            ),
+        })
+    );
+}
+
+#[tokio::test]
+async fn config_set_changes_latency_monitor_threshold() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    // Disabled by default
+    db.latency().record("command", Duration::from_millis(500));
+    assert!(db.latency().history("command").is_empty());
+
+    let reply = parse(&["CONFIG", "SET", "latency-monitor-threshold", "100"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+
+    let reply = parse(&["CONFIG", "GET", "latency-monitor-threshold"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Map(vec![(
+            Value::BulkString(Bytes::from("latency-monitor-threshold")),
+            Value::BulkString(Bytes::from("100")),
+        )])
+    );
+
+    db.latency().record("command", Duration::from_millis(50));
+    assert!(db.latency().history("command").is_empty());
+
+    db.latency().record("command", Duration::from_millis(150));
+    assert_eq!(db.latency().history("command").len(), 1);
+}
+
+#[tokio::test]
+async fn ping_works() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["PING"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, Value::SimpleString(String::from("PONG")));
+
+    let reply = parse(&["ping", "hello world"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("hello world")));
+
+    let err = parse(&["PING", "a", "b"]).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "ERR wrong number of arguments for 'ping' command"
+    );
+}
+
+#[tokio::test]
+async fn echo_works() {
+    use crate::proto::RedisProtocol;
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let message = "héllo wörld ✓ 🦀";
+
+    let reply = parse(&["ECHO", message])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let mut buffer = BytesMut::new();
+    RedisProtocol::default().encode(reply, &mut buffer).unwrap();
+    assert_eq!(
+        RedisProtocol::default().decode(&mut buffer).unwrap(),
+        Some(Value::BulkString(Bytes::from(message)))
+    );
+
+    let err = parse(&["ECHO"]).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "ERR wrong number of arguments for 'echo' command"
+    );
+}
+
+#[tokio::test]
+async fn hello_negotiates_protocol() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let field = |reply: &Value, name: &str| match reply {
+        Value::Map(pairs) => pairs
+            .iter()
+            .find(|(key, _)| *key == Value::BulkString(Bytes::from(name.to_owned())))
+            .map(|(_, value)| value.clone())
+            .unwrap(),
+        _ => panic!("reply is not a map"),
+    };
+
+    let reply = parse(&["HELLO"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(field(&reply, "proto"), Value::Integer(2));
+    assert_eq!(field(&reply, "id"), Value::Integer(connection.id() as i64));
+
+    let reply = parse(&["HELLO", "3"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(field(&reply, "proto"), Value::Integer(3));
+    assert_eq!(connection.protocol(), ProtocolVersion::Resp3);
+
+    let reply = parse(&["HELLO", "4"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("NOPROTO unsupported protocol version"),
+        })
+    );
+    assert_eq!(connection.protocol(), ProtocolVersion::Resp3);
+}
+
+#[tokio::test]
+async fn hello_authenticates_and_sets_name() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    db.config()
+        .set(vec![(
+            String::from("requirepass"),
+            String::from("correctpass"),
+        )])
+        .unwrap();
+
+    let reply = parse(&["HELLO", "3"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(reply, Value::Error(error) if error.message.starts_with("NOAUTH ")));
+
+    // A failed AUTH leaves the protocol and name alone
+    let reply = parse(&["HELLO", "3", "AUTH", "default", "wrongpass", "SETNAME", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("WRONGPASS invalid username-password pair or user is disabled."),
+        })
+    );
+    assert_eq!(connection.protocol(), ProtocolVersion::Resp2);
+    assert_eq!(connection.name(), None);
+    assert!(!connection.is_authenticated());
+
+    let reply = parse(&[
+        "HELLO",
+        "3",
+        "AUTH",
+        "default",
+        "correctpass",
+        "SETNAME",
+        "a",
+    ])
+    .unwrap()
+    .apply(&db, &connection)
+    .await;
+    match reply {
+        Value::Map(pairs) => {
+            assert!(pairs.contains(&(
+                Value::BulkString(Bytes::from("server")),
+                Value::BulkString(Bytes::from("redis"))
+            )));
+            assert!(pairs.contains(&(Value::BulkString(Bytes::from("proto")), Value::Integer(3))));
+        }
+        _ => panic!("reply is not a map"),
+    }
+    assert_eq!(connection.protocol(), ProtocolVersion::Resp3);
+    assert_eq!(connection.name(), Some(String::from("a")));
+    assert!(connection.is_authenticated());
+
+    assert!(matches!(
+        parse(&["HELLO", "3", "AUTH", "default"]),
+        Err(ParseError::ExpectedString)
+    ));
+    assert!(matches!(
+        parse(&["HELLO", "3", "EVERYWHERE"]),
+        Err(ParseError::Syntax)
+    ));
+}
+
+#[tokio::test]
+async fn auth_is_required_with_requirepass() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    db.config()
+        .set(vec![(String::from("requirepass"), String::from("secret"))])
+        .unwrap();
+
+    let reply = parse(&["GET", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("NOAUTH Authentication required."),
+        })
+    );
+
+    let reply = parse(&["PING"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, Value::SimpleString(String::from("PONG")));
+
+    let reply = parse(&["AUTH", "wrong"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("WRONGPASS invalid username-password pair or user is disabled."),
+        })
+    );
+    assert!(!connection.is_authenticated());
+
+    let reply = parse(&["AUTH", "default", "secret"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+
+    let reply = parse(&["GET", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::NullString);
+
+    // Other connections still have to authenticate
+    let reply = parse(&["GET", "key"])
+        .unwrap()
+        .apply(&db, &ConnectionState::new())
+        .await;
+    assert!(matches!(reply, Value::Error(_)));
+}
+
+#[tokio::test]
+async fn multi_exec_works() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let ok = || Value::SimpleString(String::from("OK"));
+    let queued = || Value::SimpleString(String::from("QUEUED"));
+
+    let reply = parse(&["EXEC"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("ERR EXEC without MULTI"),
+        })
+    );
+
+    let reply = parse(&["MULTI"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, ok());
+    let reply = parse(&["SET", "key", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, queued());
+    let reply = parse(&["INCR", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, queued());
+
+    // Nothing runs before EXEC
+    assert_eq!(db.get("key").unwrap(), None);
+
+    let reply = parse(&["EXEC"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, Value::Array(vec![ok(), Value::Integer(2)]));
+
+    parse(&["MULTI"]).unwrap().apply(&db, &connection).await;
+    parse(&["DEL", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["DISCARD"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, ok());
+    assert_eq!(db.get("key").unwrap(), Some(Bytes::from("2")));
+}
+
+#[tokio::test]
+async fn watch_aborts_exec_after_write() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let other = ConnectionState::new();
+
+    for command in [&["WATCH", "key"][..], &["MULTI"], &["SET", "key", "1"]] {
+        parse(command).unwrap().apply(&db, &connection).await;
+    }
+
+    parse(&["SET", "key", "2"])
+        .unwrap()
+        .apply(&db, &other)
+        .await;
+
+    let reply = parse(&["EXEC"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, Value::NullArray);
+    assert_eq!(db.get("key").unwrap(), Some(Bytes::from("2")));
+
+    // EXEC unwatched the key
+    for command in [&["MULTI"][..], &["SET", "key", "1"]] {
+        parse(command).unwrap().apply(&db, &connection).await;
+    }
+
+    let reply = parse(&["EXEC"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(
+        reply,
+        Value::Array(vec![Value::SimpleString(String::from("OK"))])
+    );
+
+    // Removing a watched key counts as a write, reading it does not
+    for command in [&["WATCH", "key"][..], &["GET", "key"], &["MULTI"]] {
+        parse(command).unwrap().apply(&db, &connection).await;
+    }
+
+    parse(&["DEL", "key"]).unwrap().apply(&db, &other).await;
+
+    let reply = parse(&["EXEC"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, Value::NullArray);
+}
+
+#[tokio::test]
+async fn reset_clears_connection_state() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    for command in [
+        &["HELLO", "3"][..],
+        &["CLIENT", "SETNAME", "pooled"],
+        &["SUBSCRIBE", "channel"],
+        &["WATCH", "key"],
+        &["MULTI"],
+        &["SET", "key", "1"],
+    ] {
+        parse(command).unwrap().apply(&db, &connection).await;
+    }
+    connection.take_pending_replies();
+
+    let reply = parse(&["RESET"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, Value::SimpleString(String::from("RESET")));
+
+    assert!(!connection.in_transaction());
+    assert!(connection.channels().is_empty());
+    assert_eq!(connection.name(), None);
+    assert_eq!(connection.protocol(), ProtocolVersion::Resp2);
+    assert_eq!(db.pubsub().publish("channel", Bytes::from("message")), 0);
+
+    // Commands run right away again instead of being queued
+    let reply = parse(&["SET", "key", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+}
+
+#[tokio::test]
+async fn set_replies_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let ok = Value::SimpleString(String::from("OK"));
+
+    // Missing key
+    let reply = parse(&["SET", "a", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, ok);
+
+    let reply = parse(&["SET", "b", "1", "GET"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::NullString);
+
+    // Existing key
+    let reply = parse(&["SET", "a", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, ok);
+
+    let reply = parse(&["SET", "a", "3", "GET"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("2")));
+}
+
+#[tokio::test]
+async fn set_not_performed_replies_nil() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["SET", "a", "1", "XX"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::NullString);
+    assert_eq!(db.get("a"), Ok(None));
+
+    let reply = parse(&["SET", "a", "1", "NX"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+
+    let reply = parse(&["SET", "a", "2", "NX"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::NullString);
+    assert_eq!(db.get("a"), Ok(Some(Bytes::from("1"))));
+}
+
+#[tokio::test]
+async fn setex_and_psetex_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["SETEX", "a", "100", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+    assert_eq!(db.get("a"), Ok(Some(Bytes::from("1"))));
+    assert!((99..=100).contains(&db.ttl("a")));
+
+    let reply = parse(&["PSETEX", "b", "100000", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+    assert!((99_000..=100_000).contains(&db.pttl("b")));
+
+    let test_data = [
+        (&["SETEX", "a", "0", "1"][..], "setex"),
+        (&["SETEX", "a", "-1", "1"], "setex"),
+        (&["PSETEX", "a", "0", "1"], "psetex"),
+        // The milliseconds overflow, or the time from now does
+        (&["SETEX", "a", "9223372036854775807", "2"], "setex"),
+        (&["PSETEX", "a", "9223372036854775807", "2"], "psetex"),
+    ];
+
+    for (args, command) in test_data {
+        let err = parse(args).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("ERR invalid expire time in '{command}' command")
+        );
+    }
+
+    assert_eq!(db.get("a"), Ok(Some(Bytes::from("1"))));
+}
+
+#[tokio::test]
+async fn set_with_expiry_in_the_past_removes_key() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let ok = Value::SimpleString(String::from("OK"));
+
+    let reply = parse(&["SET", "a", "1", "EXAT", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, ok);
+    assert_eq!(db.exists(&[String::from("a")]), 0);
+
+    parse(&["SET", "a", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["SET", "a", "2", "GET", "PXAT", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("1")));
+    assert_eq!(db.exists(&[String::from("a")]), 0);
+
+    let err = parse(&["SET", "a", "1", "EX", "0"]).err().unwrap();
+    assert_eq!(err.to_string(), "ERR invalid expire time in 'set' command");
+}
+
+#[tokio::test]
+async fn set_options_come_in_any_order() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "key", "old", "EX", "100"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["SET", "key", "new", "KEEPTTL", "GET"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("old")));
+    assert!(db.ttl("key") > 0);
+
+    let reply = parse(&["SET", "key", "newer", "GET", "EX", "50", "XX"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("new")));
+    assert!(db.ttl("key") <= 50);
+
+    for conflicting in [
+        &["SET", "key", "value", "EX", "5", "KEEPTTL"][..],
+        &["SET", "key", "value", "KEEPTTL", "PX", "5"],
+        &["SET", "key", "value", "EX", "5", "PX", "5"],
+        &["SET", "key", "value", "NX", "XX"],
+    ] {
+        let err = parse(conflicting).err().unwrap();
+        assert!(
+            matches!(err, ParseError::ConflictingOptions),
+            "{conflicting:?}"
+        );
+        assert_eq!(err.to_string(), "ERR syntax error");
+    }
+
+    assert!(matches!(
+        parse(&["SET", "key", "value", "EVERYWHERE"]),
+        Err(ParseError::Syntax)
+    ));
+}
+
+#[test]
+fn set_rejects_overflowing_expire_times() {
+    for option in ["EX", "PX", "EXAT"] {
+        let err = parse(&["SET", "key", "value", option, "9223372036854775807"])
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "ERR invalid expire time in 'set' command",
+            "{option}"
+        );
+    }
+
+    assert!(parse(&["SET", "key", "value", "PXAT", "9223372036854775807"]).is_ok());
+}
+
+#[tokio::test]
+async fn setbit_extends_string() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["SETBIT", "bits", "23", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+    assert_eq!(db.get("bits"), Ok(Some(Bytes::from_static(&[0, 0, 1]))));
+
+    let reply = parse(&["SETBIT", "bits", "23", "0"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    let reply = parse(&["GETBIT", "bits", "23"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+
+    // Clearing bits never shrinks the string
+    assert_eq!(db.strlen("bits"), Ok(3));
+
+    assert!(matches!(
+        parse(&["SETBIT", "bits", "-1", "1"]),
+        Err(ParseError::InvalidBitOffset)
+    ));
+    assert!(matches!(
+        parse(&["SETBIT", "bits", "0", "2"]),
+        Err(ParseError::InvalidBit)
+    ));
+}
+
+#[tokio::test]
+async fn setrange_overwrites_and_pads() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "key", "Hello World"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["SETRANGE", "key", "6", "Redis"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(11));
+    assert_eq!(db.get("key"), Ok(Some(Bytes::from("Hello Redis"))));
+
+    let reply = parse(&["SETRANGE", "padded", "3", "!"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(4));
+    assert_eq!(db.get("padded"), Ok(Some(Bytes::from_static(b"\0\0\0!"))));
+
+    // Empty values leave the key alone
+    let reply = parse(&["SETRANGE", "key", "100", ""])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(11));
+    assert_eq!(db.strlen("key"), Ok(11));
+
+    assert!(matches!(
+        parse(&["SETRANGE", "key", "-1", "value"]),
+        Err(ParseError::OffsetOutOfRange)
+    ));
+}
+
+#[tokio::test]
+async fn strings_cannot_grow_past_proto_max_bulk_len() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let too_long = Value::Error(RedisError {
+        message: String::from("ERR string exceeds maximum allowed size (proto-max-bulk-len)"),
+    });
+
+    db.config()
+        .set(vec![(
+            String::from("proto-max-bulk-len"),
+            String::from("8"),
+        )])
+        .unwrap();
+
+    parse(&["SET", "key", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["APPEND", "key", "!!!!"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, too_long);
+    assert_eq!(db.get("key"), Ok(Some(Bytes::from("value"))));
+
+    let reply = parse(&["APPEND", "key", "!!!"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(8));
+
+    let reply = parse(&["SETRANGE", "key", "6", "???"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, too_long);
+    assert_eq!(db.get("key"), Ok(Some(Bytes::from("value!!!"))));
+
+    let reply = parse(&["SETBIT", "key", "64", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("ERR bit offset is not an integer or out of range"),
+        })
+    );
+    assert_eq!(db.strlen("key"), Ok(8));
+}
+
+#[tokio::test]
+async fn bitcount_counts_ranges() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "key", "foobar"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let test_data = [
+        (&["BITCOUNT", "key"][..], 26),
+        (&["BITCOUNT", "key", "0", "0"], 4),
+        (&["BITCOUNT", "key", "1", "1"], 6),
+        (&["BITCOUNT", "key", "-2", "-1"], 7),
+        (&["BITCOUNT", "key", "3", "1"], 0),
+        (&["BITCOUNT", "key", "5", "30", "BIT"], 17),
+    ];
+
+    for (args, count) in test_data {
+        let reply = parse(args).unwrap().apply(&db, &connection).await;
+        assert_eq!(reply, Value::Integer(count), "{args:?}");
+    }
+}
+
+#[tokio::test]
+async fn unlink_removes_keys() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["MSET", "a", "1", "b", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    parse(&["RPUSH", "list", "x", "y", "z"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["UNLINK", "a", "list", "missing", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(2));
+    assert_eq!(db.exists(&[String::from("a"), String::from("list")]), 0);
+    assert_eq!(db.get("b"), Ok(Some(Bytes::from("2"))));
+}
+
+#[tokio::test]
+async fn setnx_replies_with_integers() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["SETNX", "a", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    let reply = parse(&["SETNX", "a", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+    assert_eq!(db.get("a"), Ok(Some(Bytes::from("1"))));
+}
+
+#[tokio::test]
+async fn maxmemory_bounds_used_memory() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    db.config()
+        .set(vec![
+            (String::from("maxmemory"), String::from("10000")),
+            (
+                String::from("maxmemory-policy"),
+                String::from("allkeys-random"),
+            ),
+        ])
+        .unwrap();
+
+    let value = "x".repeat(100);
+
+    for i in 0..1000 {
+        let key = format!("key:{i}");
+        let reply = parse(&["SET", &key, &value])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+        assert_eq!(reply, Value::SimpleString(String::from("OK")));
+
+        // Eviction runs before the write, so the last write may exceed it
+        assert!(db.used_memory() <= 10000 + 200);
+    }
+
+    assert!(db.len() > 0 && db.len() < 1000);
+
+    db.config()
+        .set(vec![
+            (String::from("maxmemory"), String::from("1000")),
+            (String::from("maxmemory-policy"), String::from("noeviction")),
+        ])
+        .unwrap();
+
+    let reply = parse(&["SET", "another", &value])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("OOM command not allowed when used memory > 'maxmemory'."),
+        })
+    );
+}
+
+#[tokio::test]
+async fn missing_key_replies_work() {
+    let no_such_key = Value::Error(RedisError {
+        message: String::from("ERR no such key"),
+    });
+
+    let test_data = [
+        (&["GET", "missing"][..], Value::NullString),
+        (
+            &["HSCAN", "missing", "0"],
+            Value::Array(vec![
+                Value::BulkString(Bytes::from("0")),
+                Value::Array(Vec::new()),
+            ]),
+        ),
+        (
+            &["SSCAN", "missing", "0"],
+            Value::Array(vec![
+                Value::BulkString(Bytes::from("0")),
+                Value::Array(Vec::new()),
+            ]),
+        ),
+        (
+            &["WATCH", "missing"],
+            Value::SimpleString(String::from("OK")),
+        ),
+        (&["GETDEL", "missing"], Value::NullString),
+        (&["GETEX", "missing", "PERSIST"], Value::NullString),
+        (&["LPOP", "missing"], Value::NullString),
+        (&["RPOP", "missing", "2"], Value::NullArray),
+        (&["LLEN", "missing"], Value::Integer(0)),
+        (&["LRANGE", "missing", "0", "-1"], Value::Array(Vec::new())),
+        (&["HGET", "missing", "field"], Value::NullString),
+        (&["HDEL", "missing", "field"], Value::Integer(0)),
+        (&["HGETALL", "missing"], Value::Map(Vec::new())),
+        (&["SREM", "missing", "member"], Value::Integer(0)),
+        (&["SISMEMBER", "missing", "member"], Value::Integer(0)),
+        (&["SMEMBERS", "missing"], Value::Array(Vec::new())),
+        (&["SCARD", "missing"], Value::Integer(0)),
+        (&["ZADD", "missing", "XX", "1", "a"], Value::Integer(0)),
+        (
+            &["GEOADD", "missing", "XX", "1", "2", "a"],
+            Value::Integer(0),
+        ),
+        (
+            &["GEOPOS", "missing", "a"],
+            Value::Array(vec![Value::NullArray]),
+        ),
+        (&["GEODIST", "missing", "a", "b"], Value::NullString),
+        (
+            &[
+                "GEOSEARCH",
+                "missing",
+                "FROMMEMBER",
+                "a",
+                "BYRADIUS",
+                "1",
+                "m",
+            ],
+            Value::Array(Vec::new()),
+        ),
+        (&["ZSCORE", "missing", "a"], Value::NullString),
+        (&["ZCARD", "missing"], Value::Integer(0)),
+        (&["ZRANGE", "missing", "0", "-1"], Value::Array(Vec::new())),
+        (&["DUMP", "missing"], Value::NullString),
+        (
+            &["ZRANGEBYSCORE", "missing", "-inf", "+inf"],
+            Value::Array(Vec::new()),
+        ),
+        (&["ZINCRBY", "missing", "1.5", "a"], Value::Double(1.5)),
+        (&["SPOP", "missing"], Value::NullString),
+        (&["SPOP", "missing", "2"], Value::Array(Vec::new())),
+        (&["XLEN", "missing"], Value::Integer(0)),
+        (&["XTRIM", "missing", "MAXLEN", "0"], Value::Integer(0)),
+        (&["XRANGE", "missing", "-", "+"], Value::Array(Vec::new())),
+        (&["XREAD", "STREAMS", "missing", "0"], Value::NullArray),
+        (&["XACK", "missing", "group", "0-1"], Value::Integer(0)),
+        (&["MGET", "missing"], Value::Array(vec![Value::NullString])),
+        (&["SET", "missing", "value", "XX"], Value::NullString),
+        (&["SETNX", "missing", "value"], Value::Integer(1)),
+        (&["GETSET", "missing", "value"], Value::NullString),
+        (&["DEL", "missing"], Value::Integer(0)),
+        (&["RENAME", "missing", "other"], no_such_key.clone()),
+        (&["RENAMENX", "missing", "other"], no_such_key.clone()),
+        (&["OBJECT", "REFCOUNT", "missing"], Value::NullString),
+        (&["OBJECT", "IDLETIME", "missing"], Value::NullString),
+        (&["EXISTS", "missing"], Value::Integer(0)),
+        (&["GETBIT", "missing", "7"], Value::Integer(0)),
+        (&["BITCOUNT", "missing"], Value::Integer(0)),
+        (&["TOUCH", "missing"], Value::Integer(0)),
+        (&["UNLINK", "missing"], Value::Integer(0)),
+        (&["TTL", "missing"], Value::Integer(-2)),
+        (&["PTTL", "missing"], Value::Integer(-2)),
+        (&["EXPIRE", "missing", "10"], Value::Integer(0)),
+        (&["PEXPIRE", "missing", "10"], Value::Integer(0)),
+        (&["EXPIREAT", "missing", "10"], Value::Integer(0)),
+        (&["PEXPIREAT", "missing", "10"], Value::Integer(0)),
+        (&["PERSIST", "missing"], Value::Integer(0)),
+        (&["KEYS", "missing"], Value::Array(Vec::new())),
+        (
+            &["TYPE", "missing"],
+            Value::SimpleString(String::from("none")),
+        ),
+        (&["DECR", "missing"], Value::Integer(-1)),
+        (&["APPEND", "missing", "value"], Value::Integer(5)),
+        (&["SETRANGE", "missing", "0", ""], Value::Integer(0)),
+        (&["OBJECT", "ENCODING", "missing"], Value::NullString),
+        (&["COPY", "missing", "dst"], Value::Integer(0)),
+        (&["MEMORY", "USAGE", "missing"], Value::NullString),
+        (&["DEBUG", "OBJECT", "missing"], no_such_key),
+        (&["STRLEN", "missing"], Value::Integer(0)),
+    ];
+
+    for (args, expected) in test_data {
+        // Some commands create the key, so every one gets a fresh database
+        let db = Db::new();
+        let connection = ConnectionState::new();
+        let reply = parse(args).unwrap().apply(&db, &connection).await;
+
+        assert_eq!(reply, expected, "{args:?}");
+    }
+}
+
+#[tokio::test]
+async fn exists_counts_duplicates() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "a", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["EXISTS", "a", "a", "b"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    assert_eq!(reply, Value::Integer(2));
+}
+
+#[tokio::test]
+async fn incr_by_float_works() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["INCRBYFLOAT", "k", "3.0e3"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("3000")));
+
+    let reply = parse(&["INCRBYFLOAT", "k", "0.5"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("3000.5")));
+
+    connection.set_protocol(ProtocolVersion::Resp3);
+
+    let reply = parse(&["INCRBYFLOAT", "k", "0.25"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Double(3000.75));
+
+    let err = parse(&["INCRBYFLOAT", "k", "nan"]).err().unwrap();
+    assert_eq!(err.to_string(), "ERR value is not a valid float");
+}
+
+#[tokio::test]
+async fn incr_by_and_decr_by_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["INCRBY", "k", "10"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(10));
+
+    let reply = parse(&["DECRBY", "k", "15"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(-5));
+
+    let reply = parse(&["DECRBY", "k", &i64::MIN.to_string()])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(reply, Value::Error(_)));
+}
+
+#[tokio::test]
+async fn mget_preserves_order() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "a", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    parse(&["SET", "c", "3"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["MGET", "a", "b", "c", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    assert_eq!(
+        reply,
+        Value::Array(vec![
+            Value::BulkString(Bytes::from("1")),
+            Value::NullString,
+            Value::BulkString(Bytes::from("3")),
+            Value::BulkString(Bytes::from("1")),
+        ])
+    );
+}
+
+#[test]
+fn variadic_round_trip_works() {
+    use crate::proto::RedisProtocol;
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    // Deterministic xorshift so failures are reproducible
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    for _ in 0..200 {
+        // Variadic commands need at least one key
+        let count = 1 + (next() % 32) as usize;
+        let keys: Vec<String> = (0..count)
+            .map(|_| {
+                let length = (next() % 24) as usize;
+                (0..length)
+                    .map(|_| char::from(b' ' + (next() % 95) as u8))
+                    .collect()
+            })
+            .collect();
+
+        for name in ["DEL", "MGET", "EXISTS"] {
+            let mut args = vec![Value::BulkString(Bytes::from(name))];
+            args.extend(keys.iter().cloned().map(Bytes::from).map(Value::BulkString));
+
+            let mut buffer = BytesMut::new();
+            RedisProtocol::default()
+                .encode(Value::Array(args), &mut buffer)
+                .unwrap();
+
+            let args = match RedisProtocol::default().decode(&mut buffer) {
+                Ok(Some(Value::Array(args))) => args,
+                other => panic!("unexpected decode result: {other:?}"),
+            };
+
+            match CommandParser::new(args).parse() {
+                Ok(
+                    RedisCommand::Del(parsed)
+                    | RedisCommand::MGet(parsed)
+                    | RedisCommand::Exists(parsed),
+                ) => assert_eq!(parsed, keys),
+                _ => panic!("{name} did not round trip"),
+            }
+        }
+    }
+}
+
+#[test]
+fn options_ignore_case() {
+    let command = parse(&["set", "k", "v", "nx", "get", "px", "100"]).unwrap();
+
+    assert!(matches!(
+        command,
+        RedisCommand::Set {
+            behaviour: SetBehaviour::OnlyIfNotExists,
+            return_old: true,
+            expiry: Some(expiry),
+            ..
+        } if expiry == Duration::from_millis(100)
+    ));
+}
+
+#[tokio::test]
+async fn mset_works() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["MSET", "a", "1", "b", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+
+    let reply = parse(&["MGET", "a", "b"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Array(vec![
+            Value::BulkString(Bytes::from("1")),
+            Value::BulkString(Bytes::from("2")),
+        ])
+    );
+
+    let err = parse(&["MSET", "a", "1", "b"]).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "ERR wrong number of arguments for 'mset' command"
+    );
+}
+
+#[tokio::test]
+async fn msetnx_aborts_if_any_key_exists() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["MSETNX", "a", "1", "b", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    let reply = parse(&["MSETNX", "c", "3", "b", "4", "d", "5"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+
+    let reply = parse(&["MGET", "b", "c", "d"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Array(vec![
+            Value::BulkString(Bytes::from("2")),
+            Value::NullString,
+            Value::NullString,
+        ])
+    );
+}
+
+#[tokio::test]
+async fn object_encoding_of_strings_works() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let embstr = Value::BulkString(Bytes::from("embstr"));
+    let raw = Value::BulkString(Bytes::from("raw"));
+
+    parse(&["SET", "short", "0123456789"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["OBJECT", "ENCODING", "short"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, embstr);
+
+    parse(&["SET", "long", &"x".repeat(50)])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["OBJECT", "ENCODING", "long"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, raw);
+
+    // APPEND always makes it raw, even if it stays short
+    parse(&["APPEND", "short", "!"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["OBJECT", "ENCODING", "short"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, raw);
+
+    // Setting a new value starts over
+    parse(&["SET", "short", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["OBJECT", "ENCODING", "short"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, embstr);
+
+    let test_data = [
+        ("12345", "int"),
+        ("-42", "int"),
+        ("9223372036854775807", "int"),
+        ("9223372036854775808", "embstr"),
+        ("+1", "embstr"),
+        ("-0", "embstr"),
+        ("1.5", "embstr"),
+    ];
+
+    for (value, encoding) in test_data {
+        parse(&["SET", "number", value])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+        let reply = parse(&["OBJECT", "ENCODING", "number"])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+        assert_eq!(reply, Value::BulkString(Bytes::from(encoding)), "{value}");
+    }
+
+    // INCR stores integers, APPEND makes them raw
+    parse(&["INCR", "counter"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["OBJECT", "ENCODING", "counter"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("int")));
+
+    parse(&["APPEND", "counter", "0"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["OBJECT", "ENCODING", "counter"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, raw);
+}
+
+#[tokio::test]
+async fn object_idletime_counts_from_last_access() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "key", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    parse(&["GET", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["OBJECT", "IDLETIME", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+
+    let reply = parse(&["OBJECT", "REFCOUNT", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+}
+
+#[tokio::test]
+async fn memory_usage_grows_with_value() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let mut usages = Vec::new();
+
+    for (key, value) in [("short", "x"), ("long", &"x".repeat(100))] {
+        parse(&["SET", key, value])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+
+        match parse(&["MEMORY", "USAGE", key, "SAMPLES", "5"])
+            .unwrap()
+            .apply(&db, &connection)
+            .await
+        {
+            Value::Integer(bytes) => usages.push(bytes),
+            reply => panic!("unexpected reply {reply:?}"),
+        }
+    }
+
+    assert!(usages[1] > usages[0]);
+}
+
+#[tokio::test]
+async fn expired_lists_free_their_memory() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let before = db.used_memory();
+
+    let reply = parse(&["RPUSH", "list", "a", "b", "c"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(3));
+    assert!(db.used_memory() > before);
+
+    let reply = parse(&["PEXPIRE", "list", "20"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    // Removed by the expiration task rather than by a read
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(db.used_memory(), before);
+    assert_eq!(db.exists(&[String::from("list")]), 0);
+}
+
+#[tokio::test]
+async fn list_encoding_follows_list_max_listpack_size() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let encoding = |encoding: &'static str| Value::BulkString(Bytes::from(encoding));
+
+    parse(&["RPUSH", "list", "a", "b", "c"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["OBJECT", "ENCODING", "list"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, encoding("listpack"));
+
+    for (size, expected) in [("2", "quicklist"), ("3", "listpack"), ("-1", "listpack")] {
+        parse(&["CONFIG", "SET", "list-max-listpack-size", size])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+
+        let reply = parse(&["OBJECT", "ENCODING", "list"])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+        assert_eq!(reply, encoding(expected), "{size}");
+    }
+
+    // Over 4 KB with -1
+    parse(&["RPUSH", "list", &"x".repeat(5000)])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["OBJECT", "ENCODING", "list"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, encoding("quicklist"));
+
+    let reply = parse(&["CONFIG", "GET", "list-max-listpack-size"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Map(vec![(
+            Value::BulkString(Bytes::from("list-max-listpack-size")),
+            Value::BulkString(Bytes::from("-1"))
+        )])
+    );
+}
+
+#[tokio::test]
+async fn strlen_counts_bytes() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "ascii", "hello"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    parse(&["SET", "multibyte", "héllo wörld 🦀"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["STRLEN", "ascii"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(5));
+
+    // 13 characters, but é, ö and the crab take up 8 bytes together
+    let reply = parse(&["STRLEN", "multibyte"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(18));
+}
+
+#[tokio::test]
+async fn getex_persist_clears_ttl() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "key", "value", "EX", "100"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["GETEX", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("value")));
+    assert!(matches!(db.ttl("key"), 99 | 100));
+
+    let reply = parse(&["GETEX", "key", "PERSIST"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("value")));
+    assert_eq!(db.ttl("key"), -1);
+}
+
+#[tokio::test]
+async fn getex_exat_in_the_past_removes_key() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "key", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["GETEX", "key", "EXAT", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("value")));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(db.get("key"), Ok(None));
+}
+
+#[test]
+fn getex_rejects_invalid_expire_times() {
+    for (option, time) in [
+        ("EX", "0"),
+        ("EX", "-1"),
+        ("PX", "0"),
+        ("PX", "-1"),
+        ("EXAT", "0"),
+        ("PXAT", "-1"),
+        ("EX", "9223372036854775807"),
+    ] {
+        let err = parse(&["GETEX", "key", option, time]).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "ERR invalid expire time in 'getex' command",
+            "{option} {time}"
+        );
+    }
+}
+
+#[test]
+fn getex_rejects_extra_arguments() {
+    for args in [
+        &["GETEX", "key", "EX", "10", "PERSIST"][..],
+        &["GETEX", "key", "EX", "10", "PX", "5"],
+        &["GETEX", "key", "PERSIST", "PERSIST"],
+        &["GETEX", "key", "foo"],
+    ] {
+        let err = parse(args).err().unwrap();
+        assert_eq!(err.to_string(), "ERR syntax error", "{args:?}");
+    }
+}
+
+#[tokio::test]
+async fn xrange_bounds_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    for id in ["1-1", "1-2", "2-0", "3-5"] {
+        let reply = parse(&["XADD", "stream", id, "field", "value"])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+        assert_eq!(reply, Value::BulkString(Bytes::from(id)));
+    }
+
+    let ids = |reply: Value| match reply {
+        Value::Array(entries) => entries
+            .into_iter()
+            .map(|entry| match entry {
+                Value::Array(mut parts) => parts.remove(0),
+                _ => panic!("entry is not an array"),
+            })
+            .collect::<Vec<_>>(),
+        _ => panic!("reply is not an array"),
+    };
+    let expected = |expected: &[&str]| {
+        expected
+            .iter()
+            .map(|id| Value::BulkString(Bytes::from(id.to_string())))
+            .collect::<Vec<_>>()
+    };
+
+    let test_data = [
+        (&["-", "+"][..], &["1-1", "1-2", "2-0", "3-5"][..]),
+        (&["1", "1"], &["1-1", "1-2"]),
+        (&["1-2", "2"], &["1-2", "2-0"]),
+        (&["(1-2", "+"], &["2-0", "3-5"]),
+        (&["-", "(3-5"], &["1-1", "1-2", "2-0"]),
+        (&["(2-0", "(2-0"], &[]),
+        (&["3", "1"], &[]),
+        (&["-", "+", "COUNT", "2"], &["1-1", "1-2"]),
+    ];
+
+    for (arguments, expected_ids) in test_data {
+        let mut command = vec!["XRANGE", "stream"];
+        command.extend_from_slice(arguments);
+
+        let reply = parse(&command).unwrap().apply(&db, &connection).await;
+        assert_eq!(ids(reply), expected(expected_ids), "{command:?}");
+    }
+
+    let err = parse(&["XRANGE", "stream", "x", "+"]).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "ERR Invalid stream ID specified as stream command argument"
+    );
+
+    let reply = parse(&["XADD", "stream", "3-5", "field", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(reply, Value::Error(_)));
+
+    let reply = parse(&["XLEN", "stream"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(4));
+}
+
+#[tokio::test]
+async fn xread_returns_newer_entries() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    for (key, id) in [("a", "1-1"), ("a", "1-2"), ("b", "5-0")] {
+        parse(&["XADD", key, id, "field", "value"])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+    }
+
+    let reply = parse(&["XREAD", "COUNT", "1", "STREAMS", "a", "b", "1-1", "$"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let entry = Value::Array(vec![
+        Value::BulkString(Bytes::from("1-2")),
+        Value::Array(vec![
+            Value::BulkString(Bytes::from("field")),
+            Value::BulkString(Bytes::from("value")),
+        ]),
+    ]);
+    assert_eq!(
+        reply,
+        Value::Array(vec![Value::Array(vec![
+            Value::BulkString(Bytes::from("a")),
+            Value::Array(vec![entry]),
+        ])])
+    );
+
+    let err = parse(&["XREAD", "STREAMS", "a", "b", "0"]).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."
+    );
+
+    let reply = parse(&["GET", "a"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from(
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            ),
+        })
+    );
+}
+
+#[tokio::test]
+async fn consumer_groups_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["XGROUP", "CREATE", "stream", "group", "$"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(reply, Value::Error(_)));
+
+    let reply = parse(&["XGROUP", "CREATE", "stream", "group", "$", "MKSTREAM"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+
+    for id in ["1-0", "2-0"] {
+        parse(&["XADD", "stream", id, "field", "value"])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+    }
+
+    let read = |consumer: &'static str| {
+        let db = db.clone();
+
+        async move {
+            let command = [
+                "XREADGROUP",
+                "GROUP",
+                "group",
+                consumer,
+                "COUNT",
+                "1",
+                "STREAMS",
+                "stream",
+                ">",
+            ];
+
+            match parse(&command)
+                .unwrap()
+                .apply(&db, &ConnectionState::new())
+                .await
+            {
+                Value::Array(mut streams) => match streams.remove(0) {
+                    Value::Array(mut stream) => match stream.remove(1) {
+                        Value::Array(mut entries) => match entries.remove(0) {
+                            Value::Array(mut entry) => entry.remove(0),
+                            _ => panic!("entry is not an array"),
+                        },
+                        _ => panic!("entries are not an array"),
+                    },
+                    _ => panic!("stream is not an array"),
+                },
+                reply => reply,
+            }
+        }
+    };
+
+    assert_eq!(read("alice").await, Value::BulkString(Bytes::from("1-0")));
+    assert_eq!(read("bob").await, Value::BulkString(Bytes::from("2-0")));
+    assert_eq!(read("alice").await, Value::NullArray);
+
+    let reply = parse(&["XPENDING", "stream", "group"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let consumer = |name: &str| {
+        Value::Array(vec![
+            Value::BulkString(Bytes::from(name.to_string())),
+            Value::BulkString(Bytes::from("1")),
+        ])
+    };
+    assert_eq!(
+        reply,
+        Value::Array(vec![
+            Value::Integer(2),
+            Value::BulkString(Bytes::from("1-0")),
+            Value::BulkString(Bytes::from("2-0")),
+            Value::Array(vec![consumer("alice"), consumer("bob")]),
+        ])
+    );
+
+    let reply = parse(&["XACK", "stream", "group", "1-0", "1-0"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    let reply = parse(&["XPENDING", "stream", "group", "-", "+", "10", "alice"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Array(Vec::new()));
+
+    let reply = parse(&["XPENDING", "stream", "group", "-", "+", "10"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(&reply, Value::Array(pending) if pending.len() == 1));
+}
+
+#[tokio::test]
+async fn scan_works() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "key", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["SCAN", "0", "MATCH", "k*", "COUNT", "100"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Array(vec![
+            Value::BulkString(Bytes::from("0")),
+            Value::Array(vec![Value::BulkString(Bytes::from("key"))]),
+        ])
+    );
+
+    let err = parse(&["SCAN", "-1"]).err().unwrap();
+    assert_eq!(err.to_string(), "ERR invalid cursor");
+
+    let err = parse(&["SCAN", "0", "COUNT", "0"]).err().unwrap();
+    assert_eq!(err.to_string(), "ERR syntax error");
+}
+
+#[tokio::test]
+async fn dbsize_counts_keys() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["DBSIZE"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, Value::Integer(0));
+
+    parse(&["MSET", "a", "1", "b", "2", "c", "3"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    parse(&["SADD", "set", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["DBSIZE"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, Value::Integer(4));
+
+    parse(&["DEL", "a", "set", "missing"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["DBSIZE"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, Value::Integer(2));
+}
+
+#[tokio::test]
+async fn info_works() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["MSET", "a", "1", "b", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    parse(&["SET", "c", "3", "EX", "100"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let Value::BulkString(info) = parse(&["INFO"]).unwrap().apply(&db, &connection).await else {
+        panic!("INFO did not reply with a bulk string");
+    };
+    let info = String::from_utf8(info.to_vec()).unwrap();
+
+    let Value::Integer(keys) = parse(&["DBSIZE"]).unwrap().apply(&db, &connection).await else {
+        panic!("DBSIZE did not reply with an integer");
+    };
+    let keyspace = info
+        .lines()
+        .find_map(|line| line.strip_prefix("db0:keys="))
+        .unwrap();
+    assert_eq!(keyspace, format!("{keys},expires=1,avg_ttl=0"));
+
+    for header in ["# Server", "# Clients", "# Memory", "# Keyspace"] {
+        assert!(info.lines().any(|line| line == header), "{header}");
+    }
+
+    // Sections can be picked, ignoring case
+    let Value::BulkString(info) = parse(&["INFO", "CLIENTS"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await
+    else {
+        panic!("INFO did not reply with a bulk string");
+    };
+    assert_eq!(&info[..], b"# Clients\r\nconnected_clients:0\r\n");
+
+    let reply = parse(&["INFO", "missing"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::new()));
+}
+
+#[tokio::test]
+async fn select_only_allows_database_zero() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["SELECT", "0"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+    assert_eq!(connection.db_index(), 0);
+
+    let reply = parse(&["SELECT", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("ERR DB index is out of range"),
+        })
+    );
+    assert_eq!(connection.db_index(), 0);
+
+    // The selected database is reported to the client
+    let reply = parse(&["CLIENT", "INFO"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::BulkString(Bytes::from(format!(
+            "id={} name= db=0 sub=0 resp=2\n",
+            connection.id()
+        )))
+    );
+}
+
+#[tokio::test]
+async fn client_name_works() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["CLIENT", "GETNAME"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::NullString);
+
+    let reply = parse(&["CLIENT", "SETNAME", "worker"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+
+    let reply = parse(&["CLIENT", "GETNAME"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("worker")));
+
+    let reply = parse(&["CLIENT", "SETNAME", "two words"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(reply, Value::Error(_)));
+
+    // An empty name removes it
+    parse(&["CLIENT", "SETNAME", ""])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["CLIENT", "GETNAME"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::NullString);
+}
+
+#[tokio::test]
+async fn flushdb_removes_keys_and_expirations() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "a", "1", "PX", "50"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    parse(&["RPUSH", "b", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["FLUSHDB"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+    assert_eq!(db.keys("*"), Vec::<String>::new());
+
+    // The old expiration must neither fire for the new key nor panic
+    parse(&["SET", "a", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(db.get("a"), Ok(Some(Bytes::from("2"))));
+
+    let reply = parse(&["FLUSHALL", "async"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+    assert_eq!(db.keys("*"), Vec::<String>::new());
+
+    assert!(matches!(
+        parse(&["FLUSHDB", "LATER"]),
+        Err(ParseError::Syntax)
+    ));
+}
+
+#[tokio::test]
+async fn persist_removes_ttl() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "key", "value", "PX", "50"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["PERSIST", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    let reply = parse(&["TTL", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(-1));
+
+    let reply = parse(&["PERSIST", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+
+    // The cancelled expiration must not remove the key anymore
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(db.get("key"), Ok(Some(Bytes::from("value"))));
+}
+
+#[tokio::test]
+async fn pexpire_and_expireat_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let unix_secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    parse(&["SET", "key", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["PEXPIRE", "key", "5000"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+    assert!(matches!(db.pttl("key"), 4900..=5000));
+
+    let at = (unix_secs + 100).to_string();
+    let reply = parse(&["EXPIREAT", "key", &at])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+    assert!(matches!(db.ttl("key"), 98..=100));
+
+    // The behaviour flags work like for EXPIRE
+    let at = ((unix_secs + 200) * 1000).to_string();
+    let reply = parse(&["PEXPIREAT", "key", &at, "NX"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+
+    let reply = parse(&["PEXPIREAT", "key", &at, "GT"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+    assert!(matches!(db.ttl("key"), 198..=200));
+
+    // Times in the past remove the key
+    let reply = parse(&["EXPIREAT", "key", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+    assert_eq!(db.get("key"), Ok(None));
+}
+
+#[tokio::test]
+async fn far_future_expire_times_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    for key in ["far", "near"] {
+        parse(&["SET", key, "value"])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+    }
+
+    // Past the longest timeout of the delay queue in the expiration tasks
+    let reply = parse(&["PEXPIREAT", "far", "9999999999999"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+    assert!(db.ttl("far") > 8_000_000_000);
+
+    let reply = parse(&["PEXPIRE", "near", "20"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(db.get("near"), Ok(None));
+    assert_eq!(db.get("far"), Ok(Some(Bytes::from("value"))));
+
+    for (command, time) in [
+        ("EXPIRE", "9223372036854775807"),
+        ("PEXPIRE", "9223372036854775807"),
+        ("EXPIREAT", "9223372036854775807"),
+    ] {
+        let err = parse(&[command, "far", time]).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "ERR invalid expire time in '{}' command",
+                command.to_lowercase()
+            )
+        );
+    }
+}
+
+#[tokio::test]
+async fn rename_keeps_ttl() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let ok = Value::SimpleString(String::from("OK"));
+
+    parse(&["SET", "a", "1", "EX", "100"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["RENAME", "a", "b"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, ok);
+    assert_eq!(db.get("a"), Ok(None));
+    assert!(matches!(db.ttl("b"), 99 | 100));
+
+    // Renaming a key to itself is a no-op
+    let reply = parse(&["RENAME", "b", "b"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, ok);
+    let reply = parse(&["RENAMENX", "b", "b"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+
+    parse(&["SET", "c", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["RENAMENX", "b", "c"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+
+    let reply = parse(&["RENAME", "c", "b"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, ok);
+    assert_eq!(db.ttl("b"), -1);
+    assert_eq!(db.get("b"), Ok(Some(Bytes::from("2"))));
+
+    parse(&["SET", "d", "3", "PX", "50"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["RENAMENX", "d", "e"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    // The expiration follows the key to its new name
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(db.exists(&[String::from("d"), String::from("e")]), 0);
+    assert_eq!(db.get("b"), Ok(Some(Bytes::from("2"))));
+}
+
+#[tokio::test]
+async fn stream_trimming_works() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    for seq in 1..=5 {
+        let id = format!("1-{seq}");
+
+        parse(&["XADD", "stream", "MAXLEN", "3", &id, "field", "value"])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+    }
+
+    let reply = parse(&["XLEN", "stream"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(3));
+
+    let reply = parse(&["XRANGE", "stream", "-", "+", "COUNT", "1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(
+        reply,
+        Value::Array(entries) if entries[0] == Value::Array(vec![
+            Value::BulkString(Bytes::from("1-3")),
+            Value::Array(vec![
+                Value::BulkString(Bytes::from("field")),
+                Value::BulkString(Bytes::from("value")),
+            ]),
+        ])
+    ));
+
+    let reply = parse(&["XTRIM", "stream", "MINID", "~", "1-5"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(2));
+
+    let reply = parse(&["XTRIM", "stream", "MAXLEN", "=", "0"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    let err = parse(&["XTRIM", "stream", "MAXLEN", "-1"]).err().unwrap();
+    assert_eq!(err.to_string(), "ERR The MAXLEN argument must be >= 0.");
+}
+
+#[tokio::test]
+async fn type_names_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "string", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    parse(&["XADD", "stream", "*", "field", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    parse(&["RPUSH", "list", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    parse(&["HSET", "hash", "field", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    parse(&["SADD", "set", "member"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let test_data = [
+        ("string", "string"),
+        ("list", "list"),
+        ("hash", "hash"),
+        ("set", "set"),
+        ("stream", "stream"),
+        ("missing", "none"),
+    ];
+
+    for (key, expected) in test_data {
+        let reply = parse(&["TYPE", key]).unwrap().apply(&db, &connection).await;
+        assert_eq!(reply, Value::SimpleString(String::from(expected)));
+    }
+}
+
+#[tokio::test]
+async fn xread_block_waits_for_new_entries() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["XADD", "stream", "1-0", "field", "old"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reader = tokio::spawn({
+        let db = db.clone();
+
+        async move {
+            parse(&["XREAD", "BLOCK", "0", "STREAMS", "stream", "$"])
+                .unwrap()
+                .apply(&db, &ConnectionState::new())
+                .await
+        }
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    parse(&["XADD", "stream", "2-0", "field", "new"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = tokio::time::timeout(Duration::from_secs(1), reader)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let entry = Value::Array(vec![
+        Value::BulkString(Bytes::from("2-0")),
+        Value::Array(vec![
+            Value::BulkString(Bytes::from("field")),
+            Value::BulkString(Bytes::from("new")),
+        ]),
+    ]);
+    assert_eq!(
+        reply,
+        Value::Array(vec![Value::Array(vec![
+            Value::BulkString(Bytes::from("stream")),
+            Value::Array(vec![entry]),
+        ])])
+    );
+
+    let reply = parse(&["XREAD", "BLOCK", "50", "STREAMS", "stream", "$"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::NullArray);
+}
+
+#[tokio::test]
+async fn blpop_waits_for_pushes() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let blpop = |db: &Db| {
+        let db = db.clone();
+
+        tokio::spawn(async move {
+            parse(&["BLPOP", "missing", "list", "1"])
+                .unwrap()
+                .apply(&db, &ConnectionState::new())
+                .await
+        })
+    };
+
+    // The client that blocked first is served first
+    let first = blpop(&db);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let second = blpop(&db);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    parse(&["RPUSH", "list", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = first.await.unwrap();
+    assert_eq!(
+        reply,
+        Value::Array(vec![
+            Value::BulkString(Bytes::from("list")),
+            Value::BulkString(Bytes::from("a")),
+        ])
+    );
+
+    parse(&["RPUSH", "list", "b"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = second.await.unwrap();
+    assert_eq!(
+        reply,
+        Value::Array(vec![
+            Value::BulkString(Bytes::from("list")),
+            Value::BulkString(Bytes::from("b")),
+        ])
+    );
+
+    // Pushing several elements serves several clients
+    let clients: Vec<_> = (0..2).map(|_| blpop(&db)).collect();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    parse(&["RPUSH", "list", "c", "d"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    for client in clients {
+        let reply = client.await.unwrap();
+        assert!(matches!(reply, Value::Array(_)), "{reply:?}");
+    }
+
+    let reply = parse(&["BRPOP", "list", "0.05"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::NullArray);
+
+    let err = parse(&["BLPOP", "list", "-1"]).err().unwrap();
+    assert_eq!(err.to_string(), "ERR timeout is negative");
+
+    let err = parse(&["BLPOP", "list", "soon"]).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "ERR timeout is not a float or out of range"
+    );
+}
+
+#[tokio::test]
+async fn command_info_reports_arity() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["COMMAND", "INFO", "get", "missing"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    match reply {
+        Value::Array(commands) => {
+            assert!(matches!(
+                &commands[0],
+                Value::Array(info) if info[0] == Value::BulkString(Bytes::from("get"))
+                    && info[1] == Value::Integer(2)
+            ));
+            assert_eq!(commands[1], Value::NullArray);
+        }
+        _ => panic!("reply is not an array"),
+    }
+
+    // Without arguments the whole table is returned
+    let reply = parse(&["COMMAND"]).unwrap().apply(&db, &connection).await;
+    assert!(matches!(reply, Value::Array(commands) if commands.len() == COMMANDS.len()));
+}
+
+#[tokio::test]
+async fn command_count_matches_list() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let count = parse(&["COMMAND", "COUNT"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let list = parse(&["COMMAND", "LIST"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    match (count, list) {
+        (Value::Integer(count), Value::Array(names)) => {
+            assert_eq!(count as usize, names.len());
+            assert!(names.contains(&Value::BulkString(Bytes::from("get"))));
+        }
+        replies => panic!("unexpected replies {replies:?}"),
+    }
+}
+
+#[tokio::test]
+async fn lrange_negative_indices_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["RPUSH", "list", "b", "c"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(2));
+    let reply = parse(&["LPUSH", "list", "a", "z"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(4));
+
+    let test_data = [
+        ("0", "-1", &["z", "a", "b", "c"][..]),
+        ("-2", "-1", &["b", "c"]),
+        ("-100", "1", &["z", "a"]),
+        ("2", "100", &["b", "c"]),
+        ("-1", "-2", &[]),
+        ("4", "5", &[]),
+    ];
+
+    for (start, stop, expected) in test_data {
+        let reply = parse(&["LRANGE", "list", start, stop])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+        let expected = expected
+            .iter()
+            .map(|element| Value::BulkString(Bytes::from(element.to_string())))
+            .collect();
+
+        assert_eq!(reply, Value::Array(expected), "LRANGE {start} {stop}");
+    }
+
+    let reply = parse(&["LLEN", "list"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(4));
+
+    parse(&["SET", "string", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    for command in [
+        &["LPUSH", "string", "a"][..],
+        &["RPUSH", "string", "a"],
+        &["LLEN", "string"],
+        &["LRANGE", "string", "0", "-1"],
+        &["GET", "list"],
+    ] {
+        let reply = parse(command).unwrap().apply(&db, &connection).await;
+        assert!(
+            matches!(&reply, Value::Error(err) if err.message.starts_with("WRONGTYPE")),
+            "{command:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn pop_removes_empty_lists() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let bulk = |element: &str| Value::BulkString(Bytes::from(element.to_string()));
+
+    parse(&["RPUSH", "list", "a", "b", "c", "d"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["LPOP", "list"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, bulk("a"));
+
+    let reply = parse(&["RPOP", "list", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Array(vec![bulk("d"), bulk("c")]));
+
+    let reply = parse(&["LPOP", "list", "0"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Array(Vec::new()));
+
+    // Popping more elements than present returns the rest
+    let reply = parse(&["LPOP", "list", "10"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Array(vec![bulk("b")]));
+
+    let reply = parse(&["EXISTS", "list"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+
+    let reply = parse(&["LPOP", "list", "10"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::NullArray);
+
+    let err = parse(&["LPOP", "list", "-1"]).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "ERR value is out of range, must be positive"
+    );
+}
+
+#[tokio::test]
+async fn hashes_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let bulk = |string: &str| Value::BulkString(Bytes::from(string.to_string()));
+
+    let reply = parse(&["HSET", "hash", "a", "1", "b", "2", "c", "3"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(3));
+
+    // Overwriting an existing field does not count as a new field
+    let reply = parse(&["HSET", "hash", "a", "4", "d", "5"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    let reply = parse(&["HGET", "hash", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, bulk("4"));
+    let reply = parse(&["HGET", "hash", "missing"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::NullString);
+
+    let first = parse(&["HGETALL", "hash"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let second = parse(&["HGETALL", "hash"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(first, second);
+
+    let mut pairs = match first {
+        Value::Map(pairs) => pairs,
+        _ => panic!("reply is not a map"),
+    };
+    pairs.sort_by_key(|(field, _)| format!("{field:?}"));
+    assert_eq!(
+        pairs,
+        [
+            (bulk("a"), bulk("4")),
+            (bulk("b"), bulk("2")),
+            (bulk("c"), bulk("3")),
+            (bulk("d"), bulk("5")),
+        ]
+    );
+
+    let reply = parse(&["HDEL", "hash", "a", "b", "missing"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(2));
+
+    let reply = parse(&["HDEL", "hash", "c", "d"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(2));
+    assert_eq!(db.exists(&[String::from("hash")]), 0);
+
+    let err = parse(&["HSET", "hash", "a"]).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "ERR wrong number of arguments for 'hset' command"
+    );
+
+    parse(&["SET", "string", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["HGET", "string", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(&reply, Value::Error(err) if err.message.starts_with("WRONGTYPE")));
+}
+
+#[tokio::test]
+async fn sets_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["SADD", "set", "a", "b", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(2));
+
+    // Existing members are not counted again
+    let reply = parse(&["SADD", "set", "b", "c"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    let reply = parse(&["SISMEMBER", "set", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+    let reply = parse(&["SISMEMBER", "set", "z"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+
+    let mut members = match parse(&["SMEMBERS", "set"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await
+    {
+        Value::Array(members) => members,
+        _ => panic!("reply is not an array"),
+    };
+    members.sort_by_key(|member| format!("{member:?}"));
+    assert_eq!(
+        members,
+        ["a", "b", "c"].map(|member| Value::BulkString(Bytes::from(member.to_string())))
+    );
+
+    let reply = parse(&["SREM", "set", "a", "z"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    // Removing the last members removes the key
+    let reply = parse(&["SREM", "set", "b", "c"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(2));
+    assert_eq!(db.exists(&[String::from("set")]), 0);
+
+    parse(&["SET", "string", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["SADD", "string", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(&reply, Value::Error(err) if err.message.starts_with("WRONGTYPE")));
+}
+
+#[tokio::test]
+async fn zadd_updates_scores() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["ZADD", "zset", "1", "a", "2", "b"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(2));
+
+    let reply = parse(&["ZADD", "zset", "CH", "3", "a", "2", "b", "4", "c"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(2));
+
+    let reply = parse(&["ZADD", "zset", "NX", "5", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+
+    let reply = parse(&["ZADD", "zset", "XX", "5", "d"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+
+    parse(&["ZADD", "zset", "GT", "1", "a", "5", "b"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    parse(&["ZADD", "zset", "LT", "10", "c"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    for (member, score) in [("a", 3.0), ("b", 5.0), ("c", 4.0)] {
+        let reply = parse(&["ZSCORE", "zset", member])
+            .unwrap()
+            .apply(&db, &connection)
+            .await;
+        assert_eq!(reply, Value::Double(score));
+    }
+
+    let reply = parse(&["ZSCORE", "zset", "d"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::NullString);
+
+    let reply = parse(&["ZCARD", "zset"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(3));
+
+    assert!(matches!(
+        parse(&["ZADD", "zset", "NX", "XX", "1", "a"]),
+        Err(ParseError::IncompatibleNxXx)
+    ));
+    assert!(matches!(
+        parse(&["ZADD", "zset", "NX", "GT", "1", "a"]),
+        Err(ParseError::IncompatibleGtLtNx)
+    ));
+    assert!(matches!(
+        parse(&["ZADD", "zset", "1", "a", "2"]),
+        Err(ParseError::Syntax)
+    ));
+    assert!(matches!(
+        parse(&["ZADD", "zset", "nan", "a"]),
+        Err(ParseError::ExpectedFloat)
+    ));
+
+    parse(&["SET", "string", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["ZADD", "string", "1", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(&reply, Value::Error(err) if err.message.starts_with("WRONGTYPE")));
+}
+
+#[tokio::test]
+async fn zrange_orders_by_score_then_member() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["ZADD", "zset", "2", "c", "1", "b", "2", "a", "-1.5", "d"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["ZRANGE", "zset", "0", "-1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Array(
+            ["d", "b", "a", "c"]
+                .into_iter()
+                .map(|member| Value::BulkString(Bytes::from(member)))
+                .collect()
+        )
+    );
+
+    let reply = parse(&["ZRANGE", "zset", "1", "2", "WITHSCORES"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Array(vec![
+            Value::BulkString(Bytes::from("b")),
+            Value::Double(1.0),
+            Value::BulkString(Bytes::from("a")),
+            Value::Double(2.0),
+        ])
+    );
+
+    let reply = parse(&["ZRANGE", "zset", "0", "1", "REV", "WITHSCORES"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Array(vec![
+            Value::BulkString(Bytes::from("c")),
+            Value::Double(2.0),
+            Value::BulkString(Bytes::from("a")),
+            Value::Double(2.0),
+        ])
+    );
+
+    let reply = parse(&["ZRANGE", "zset", "5", "10"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Array(Vec::new()));
+
+    assert!(matches!(
+        parse(&["ZRANGE", "zset", "0", "1", "LIMIT"]),
+        Err(ParseError::Syntax)
+    ));
+}
+
+#[tokio::test]
+async fn spop_removes_members() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SADD", "set", "a", "b", "c", "d"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["SPOP", "set"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(reply, Value::BulkString(_)));
+
+    let reply = parse(&["SPOP", "set", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(&reply, Value::Array(members) if members.len() == 2));
+
+    let reply = parse(&["SCARD", "set"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(1));
+
+    parse(&["SADD", "set", "e", "f"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    // A count larger than the set pops all members
+    let reply = parse(&["SPOP", "set", "10"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(&reply, Value::Array(members) if members.len() == 3));
+    assert_eq!(db.exists(&[String::from("set")]), 0);
+}
+
+#[tokio::test]
+async fn binary_values_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let value = Bytes::from_static(b"\xff\x00binary\xfe");
+
+    let buffer = vec![
+        Value::BulkString(Bytes::from("SET")),
+        Value::BulkString(Bytes::from("key")),
+        Value::BulkString(value.clone()),
+    ];
+    CommandParser::new(buffer)
+        .parse()
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["GET", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(value));
+
+    // Keys have to be valid UTF-8 for now
+    let buffer = vec![
+        Value::BulkString(Bytes::from("GET")),
+        Value::BulkString(Bytes::from_static(b"\xff")),
+    ];
+    assert!(matches!(
+        CommandParser::new(buffer).parse(),
+        Err(ParseError::InvalidUtf8)
+    ));
+}
+
+#[tokio::test]
+async fn wait_replies_without_replicas() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["WAIT", "1", "0"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(0));
+
+    assert!(matches!(
+        parse(&["WAIT", "1", "-1"]),
+        Err(ParseError::NegativeTimeout)
+    ));
+}
+
+#[tokio::test]
+async fn debug_sleep_works() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let start = std::time::Instant::now();
+    let reply = parse(&["DEBUG", "SLEEP", "0.1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let elapsed = start.elapsed();
+
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+    assert!(elapsed >= Duration::from_millis(100), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+}
+
+#[tokio::test]
+async fn functions_are_never_found() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["FUNCTION", "LIST", "WITHCODE"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Array(Vec::new()));
+
+    let reply = parse(&["FCALL", "myfunc", "1", "key", "arg"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("ERR Function not found"),
+        })
+    );
+
+    assert!(matches!(
+        parse(&["FCALL", "myfunc", "2", "key"]),
+        Err(ParseError::TooManyKeys)
+    ));
+}
+
+#[tokio::test]
+async fn zrangebyscore_handles_bounds_and_limit() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&[
+        "ZADD", "zset", "1", "a", "2", "b", "2", "c", "3", "d", "-inf", "e",
+    ])
+    .unwrap()
+    .apply(&db, &connection)
+    .await;
+
+    let members = |reply: Value| match reply {
+        Value::Array(elements) => elements
+            .into_iter()
+            .map(|element| match element {
+                Value::BulkString(bytes) => String::from_utf8(bytes.to_vec()).unwrap(),
+                Value::Double(score) => score.to_string(),
+                _ => panic!("unexpected element"),
+            })
+            .collect::<Vec<_>>(),
+        _ => panic!("unexpected reply {reply:?}"),
+    };
+
+    let test_data: [(&[&str], &[&str]); 7] = [
+        (&["-inf", "+inf"], &["e", "a", "b", "c", "d"]),
+        (&["1", "2"], &["a", "b", "c"]),
+        (&["(1", "2"], &["b", "c"]),
+        (&["1", "(2"], &["a"]),
+        (&["(-inf", "(1"], &[]),
+        (&["-inf", "inf", "LIMIT", "1", "2"], &["a", "b"]),
+        (
+            &["2", "+inf", "WITHSCORES", "LIMIT", "1", "-1"],
+            &["c", "2", "d", "3"],
+        ),
+    ];
+
+    for (arguments, expected) in test_data {
+        let mut command = vec!["ZRANGEBYSCORE", "zset"];
+        command.extend_from_slice(arguments);
+
+        let reply = parse(&command).unwrap().apply(&db, &connection).await;
+        assert_eq!(members(reply), expected, "{command:?}");
+    }
+
+    let reply = parse(&["ZRANGEBYSCORE", "zset", "-inf", "+inf", "LIMIT", "-1", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Array(Vec::new()));
+
+    assert!(matches!(
+        parse(&["ZRANGEBYSCORE", "zset", "(x", "1"]),
+        Err(ParseError::InvalidScoreBound)
+    ));
+    assert!(matches!(
+        parse(&["ZRANGEBYSCORE", "zset", "0", "1", "LIMIT", "1"]),
+        Err(ParseError::ExpectedInteger)
+    ));
+}
+
+#[tokio::test]
+async fn zincrby_moves_members() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["ZADD", "zset", "1", "a", "2", "b"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["ZINCRBY", "zset", "2.5", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Double(3.5));
+
+    let reply = parse(&["ZINCRBY", "zset", "-1", "c"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Double(-1.0));
+
+    let reply = parse(&["ZRANGE", "zset", "0", "-1"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Array(
+            ["c", "b", "a"]
+                .into_iter()
+                .map(|member| Value::BulkString(Bytes::from(member)))
+                .collect()
+        )
+    );
+
+    parse(&["ZINCRBY", "zset", "+inf", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["ZINCRBY", "zset", "-inf", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("ERR resulting score is not a number (NaN)"),
+        })
+    );
+}
+
+#[tokio::test]
+async fn geo_commands_work() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+    let bulk = |string: &str| Value::BulkString(Bytes::from(string.to_owned()));
+
+    let reply = parse(&[
+        "GEOADD",
+        "Sicily",
+        "13.361389",
+        "38.115556",
+        "Palermo",
+        "15.087269",
+        "37.502669",
+        "Catania",
+    ])
+    .unwrap()
+    .apply(&db, &connection)
+    .await;
+    assert_eq!(reply, Value::Integer(2));
+
+    // The scores are the same as in Redis
+    assert_eq!(db.zscore("Sicily", "Palermo"), Ok(Some(3479099956230698.0)));
+
+    let reply = parse(&["GEODIST", "Sicily", "Palermo", "Catania"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let Value::BulkString(distance) = reply else {
+        panic!("unexpected reply {reply:?}");
+    };
+    let distance: f64 = std::str::from_utf8(&distance).unwrap().parse().unwrap();
+    assert!((distance - 166274.1516).abs() < 0.01, "{distance}");
+
+    let reply = parse(&["GEODIST", "Sicily", "Palermo", "Catania", "KM"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, bulk("166.2742"));
+
+    let reply = parse(&["GEOPOS", "Sicily", "Palermo", "Atlantis"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let Value::Array(positions) = reply else {
+        panic!("unexpected reply {reply:?}");
+    };
+    assert_eq!(positions[1], Value::NullArray);
+    let Value::Array(coordinates) = &positions[0] else {
+        panic!("unexpected position {:?}", positions[0]);
+    };
+    let coordinates: Vec<f64> = coordinates
+        .iter()
+        .map(|coordinate| match coordinate {
+            Value::Double(coordinate) => *coordinate,
+            _ => panic!("unexpected coordinate {coordinate:?}"),
+        })
+        .collect();
+    assert!((coordinates[0] - 13.361389).abs() < 1e-5);
+    assert!((coordinates[1] - 38.115556).abs() < 1e-5);
+
+    let reply = parse(&[
+        "GEOSEARCH",
+        "Sicily",
+        "FROMLONLAT",
+        "15",
+        "37",
+        "BYRADIUS",
+        "200",
+        "km",
+        "ASC",
+        "WITHDIST",
+    ])
+    .unwrap()
+    .apply(&db, &connection)
+    .await;
+    assert_eq!(
+        reply,
+        Value::Array(vec![
+            Value::Array(vec![bulk("Catania"), bulk("56.4413")]),
+            Value::Array(vec![bulk("Palermo"), bulk("190.4424")]),
+        ])
+    );
+
+    let reply = parse(&[
+        "GEOSEARCH",
+        "Sicily",
+        "FROMLONLAT",
+        "15",
+        "37",
+        "BYRADIUS",
+        "100",
+        "km",
+    ])
+    .unwrap()
+    .apply(&db, &connection)
+    .await;
+    assert_eq!(reply, Value::Array(vec![bulk("Catania")]));
+
+    let reply = parse(&[
+        "GEOSEARCH",
+        "Sicily",
+        "FROMMEMBER",
+        "Palermo",
+        "BYBOX",
+        "400",
+        "400",
+        "km",
+        "DESC",
+        "COUNT",
+        "1",
+    ])
+    .unwrap()
+    .apply(&db, &connection)
+    .await;
+    assert_eq!(reply, Value::Array(vec![bulk("Catania")]));
+
+    let reply = parse(&[
+        "GEOSEARCH",
+        "Sicily",
+        "FROMMEMBER",
+        "Atlantis",
+        "BYRADIUS",
+        "1",
+        "m",
+    ])
+    .unwrap()
+    .apply(&db, &connection)
+    .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("ERR could not decode requested zset member"),
+        })
+    );
+
+    let err = parse(&["GEOADD", "Sicily", "0", "90", "Pole"])
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "ERR invalid longitude,latitude pair 0.000000,90.000000"
+    );
+    assert!(matches!(
+        parse(&["GEODIST", "Sicily", "Palermo", "Catania", "parsecs"]),
+        Err(ParseError::UnsupportedUnit)
+    ));
+    assert!(matches!(
+        parse(&[
+            "GEOSEARCH",
+            "Sicily",
+            "BYRADIUS",
+            "1",
+            "m",
+            "ASC",
+            "WITHDIST"
+        ]),
+        Err(ParseError::GeoSearchCenter)
+    ));
+    assert!(matches!(
+        parse(&[
+            "GEOSEARCH",
+            "Sicily",
+            "FROMLONLAT",
+            "15",
+            "37",
+            "ASC",
+            "WITHDIST"
+        ]),
+        Err(ParseError::GeoSearchShape)
+    ));
+
+    // RESP3 clients get distances as doubles
+    connection.set_protocol(ProtocolVersion::Resp3);
+
+    let reply = parse(&["GEODIST", "Sicily", "Palermo", "Catania", "KM"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Double(166.2742));
+}
+
+#[tokio::test]
+async fn dump_and_restore_round_trip() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["HSET", "hash", "a", "1", "b", "2"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let Value::BulkString(payload) = parse(&["DUMP", "hash"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await
+    else {
+        panic!("DUMP did not return a payload");
+    };
+
+    let restore = |key: &'static str, ttl: &'static str, replace: bool| {
+        let mut arguments = vec![
+            Value::BulkString(Bytes::from("RESTORE")),
+            Value::BulkString(Bytes::from(key)),
+            Value::BulkString(Bytes::from(ttl)),
+            Value::BulkString(payload.clone()),
+        ];
+
+        if replace {
+            arguments.push(Value::BulkString(Bytes::from("REPLACE")));
+        }
+
+        CommandParser::new(arguments).parse()
+    };
+
+    let reply = restore("copy", "0", false)
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::SimpleString(String::from("OK")));
+
+    let reply = parse(&["HGET", "copy", "b"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("2")));
+
+    let reply = parse(&["TTL", "copy"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(-1));
+
+    let reply = restore("copy", "0", false)
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("BUSYKEY Target key name already exists."),
+        })
+    );
+
+    restore("copy", "10000", true)
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["PTTL", "copy"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(reply, Value::Integer(ttl) if ttl > 9000 && ttl <= 10000));
+
+    assert!(matches!(
+        restore("other", "-1", false),
+        Err(ParseError::InvalidTtl)
+    ));
+
+    // Flipping any byte breaks the checksum
+    let mut corrupted = payload.to_vec();
+    corrupted[1] ^= 1;
+
+    assert!(matches!(
+        CommandParser::new(vec![
+            Value::BulkString(Bytes::from("RESTORE")),
+            Value::BulkString(Bytes::from("other")),
+            Value::BulkString(Bytes::from("0")),
+            Value::BulkString(Bytes::from(corrupted)),
+        ])
+        .parse(),
+        Err(ParseError::BadDataFormat)
+    ));
+}
+
+#[tokio::test]
+async fn lpush_on_string_is_wrongtype() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "string", "value"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["LPUSH", "string", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from(
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            ),
+        })
+    );
+
+    // The rejected write leaves the string alone
+    let reply = parse(&["GET", "string"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("value")));
+}
+
+#[tokio::test]
+async fn getset_clears_ttl() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    parse(&["SET", "key", "old", "EX", "100"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+
+    let reply = parse(&["GETSET", "key", "new"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("old")));
+
+    let reply = parse(&["TTL", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::Integer(-1));
+
+    let reply = parse(&["GET", "key"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert_eq!(reply, Value::BulkString(Bytes::from("new")));
+
+    parse(&["LPUSH", "list", "a"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    let reply = parse(&["GETSET", "list", "new"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(&reply, Value::Error(err) if err.message.starts_with("WRONGTYPE")));
+}
+
+#[tokio::test]
+async fn lolwut_replies_with_version() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["LOLWUT"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(
+        reply,
+        Value::BulkString(Bytes::from(format!("Redis ver. {REDIS_VERSION}\n")))
+    );
+
+    let reply = parse(&["LOLWUT", "VERSION", "5"])
+        .unwrap()
+        .apply(&db, &connection)
+        .await;
+    assert!(matches!(
+        reply,
+        Value::BulkString(text) if text.starts_with(b"LOLWUT version 5")
+            && text.ends_with(format!("Redis ver. {REDIS_VERSION}\n").as_bytes())
+    ));
+
+    assert!(matches!(
+        parse(&["LOLWUT", "VERSION"]),
+        Err(ParseError::ExpectedInteger)
+    ));
+}
+
+#[tokio::test]
+async fn time_is_close_to_the_clock() {
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let reply = parse(&["TIME"]).unwrap().apply(&db, &connection).await;
+    let Value::Array(elements) = reply else {
+        panic!("TIME did not return an array");
+    };
+
+    let integers: Vec<u64> = elements
+        .iter()
+        .map(|element| match element {
+            Value::BulkString(bytes) => parse_bytes(bytes).unwrap(),
+            _ => panic!("unexpected element {element}"),
+        })
+        .collect();
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    assert_eq!(integers.len(), 2);
+    assert!(now.abs_diff(integers[0]) <= 2, "{integers:?}");
+    assert!(integers[1] < 1_000_000);
+}
+
+#[tokio::test]
+async fn lastsave_is_start_time_without_saves() {
+    let before = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let db = Db::new();
+    let connection = ConnectionState::new();
+
+    let after = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let reply = parse(&["LASTSAVE"]).unwrap().apply(&db, &connection).await;
+    assert!(matches!(reply, Value::Integer(time) if (before..=after).contains(&time)));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn exec_runs_exclusively() {
+    let db = Db::new();
+
+    // Increments with check-and-set transactions, which would lose
+    // increments of the other clients if they could run between checking
+    // the watched key and running the queued SET
+    let transactions: Vec<_> = (0..4)
+        .map(|_| {
+            let db = db.clone();
+
+            tokio::spawn(async move {
+                let connection = ConnectionState::new();
+
+                for _ in 0..200 {
+                    loop {
+                        parse(&["WATCH", "counter"])
+                            .unwrap()
+                            .apply(&db, &connection)
+                            .await;
+
+                        let value = match parse(&["GET", "counter"])
+                            .unwrap()
+                            .apply(&db, &connection)
+                            .await
+                        {
+                            Value::BulkString(value) => {
+                                std::str::from_utf8(&value).unwrap().parse().unwrap()
+                            }
+                            _ => 0,
+                        };
+                        let next = (value + 1_i64).to_string();
+
+                        for command in [&["MULTI"][..], &["SET", "counter", &next]] {
+                            parse(command).unwrap().apply(&db, &connection).await;
+                        }
+
+                        let reply = parse(&["EXEC"]).unwrap().apply(&db, &connection).await;
+
+                        if reply != Value::NullArray {
+                            break;
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let increments: Vec<_> = (0..4)
+        .map(|_| {
+            let db = db.clone();
+
+            tokio::spawn(async move {
+                let connection = ConnectionState::new();
+
+                for _ in 0..200 {
+                    parse(&["INCR", "counter"])
+                        .unwrap()
+                        .apply(&db, &connection)
+                        .await;
+                }
+            })
+        })
+        .collect();
+
+    for task in transactions.into_iter().chain(increments) {
+        task.await.unwrap();
+    }
+
+    assert_eq!(db.get("counter").unwrap(), Some(Bytes::from("1600")));
+
+    // Nothing could push while EXEC runs, so blocking commands do not wait
+    let connection = ConnectionState::new();
+
+    for command in [&["MULTI"][..], &["BLPOP", "list", "0"]] {
+        parse(command).unwrap().apply(&db, &connection).await;
+    }
+
+    let reply = parse(&["EXEC"]).unwrap().apply(&db, &connection).await;
+    assert_eq!(reply, Value::Array(vec![Value::NullArray]));
+}
@@ -0,0 +1,314 @@
+/// The metadata of the supported commands as reported by `COMMAND`.
+///
+/// Every command `CommandParser::parse` dispatches must be listed here.
+///
+/// The arity counts the command name itself. A negative arity means the
+/// command takes at least that many arguments, a positive one exactly that
+/// many.
+use bytes::Bytes;
+
+use crate::proto::Value;
+
+pub struct CommandSpec {
+    /// The lowercase name, `container|subcommand` for subcommands.
+    pub name: &'static str,
+    pub arity: i64,
+    /// The position of the first key argument, 0 if there is none or the key
+    /// positions can only be found by parsing the arguments.
+    pub first_key: i64,
+    /// The position of the last key argument, negative to count from the
+    /// end.
+    pub last_key: i64,
+    /// The distance between key arguments.
+    pub step: i64,
+    pub subcommands: &'static [CommandSpec],
+}
+
+const fn command(
+    name: &'static str,
+    arity: i64,
+    first_key: i64,
+    last_key: i64,
+    step: i64,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        first_key,
+        last_key,
+        step,
+        subcommands: &[],
+    }
+}
+
+const fn container(name: &'static str, subcommands: &'static [CommandSpec]) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity: -2,
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        subcommands,
+    }
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        arity: -1,
+        ..container(
+            "command",
+            &[
+                command("command|count", 2, 0, 0, 0),
+                command("command|docs", -2, 0, 0, 0),
+                command("command|info", -2, 0, 0, 0),
+                command("command|list", -2, 0, 0, 0),
+            ],
+        )
+    },
+    container(
+        "config",
+        &[
+            command("config|get", -3, 0, 0, 0),
+            command("config|set", -4, 0, 0, 0),
+        ],
+    ),
+    command("auth", -2, 0, 0, 0),
+    command("hello", -1, 0, 0, 0),
+    command("ping", -1, 0, 0, 0),
+    command("lolwut", -1, 0, 0, 0),
+    command("time", 1, 0, 0, 0),
+    command("lastsave", 1, 0, 0, 0),
+    command("echo", 2, 0, 0, 0),
+    command("select", 2, 0, 0, 0),
+    command("multi", 1, 0, 0, 0),
+    command("exec", 1, 0, 0, 0),
+    command("discard", 1, 0, 0, 0),
+    command("watch", -2, 1, -1, 1),
+    command("unwatch", 1, 0, 0, 0),
+    command("reset", 1, 0, 0, 0),
+    command("subscribe", -2, 0, 0, 0),
+    command("unsubscribe", -1, 0, 0, 0),
+    command("publish", 3, 0, 0, 0),
+    command("get", 2, 1, 1, 1),
+    command("getdel", 2, 1, 1, 1),
+    command("getex", -2, 1, 1, 1),
+    command("mget", -2, 1, -1, 1),
+    command("set", -3, 1, 1, 1),
+    command("setex", 4, 1, 1, 1),
+    command("psetex", 4, 1, 1, 1),
+    command("setnx", 3, 1, 1, 1),
+    command("getset", 3, 1, 1, 1),
+    command("mset", -3, 1, -1, 2),
+    command("msetnx", -3, 1, -1, 2),
+    command("del", -2, 1, -1, 1),
+    command("unlink", -2, 1, -1, 1),
+    command("rename", 3, 1, 2, 1),
+    command("renamenx", 3, 1, 2, 1),
+    command("copy", -3, 1, 2, 1),
+    command("dump", 2, 1, 1, 1),
+    command("restore", -4, 1, 1, 1),
+    command("exists", -2, 1, -1, 1),
+    command("touch", -2, 1, -1, 1),
+    command("ttl", 2, 1, 1, 1),
+    command("pttl", 2, 1, 1, 1),
+    command("expire", -3, 1, 1, 1),
+    command("pexpire", -3, 1, 1, 1),
+    command("expireat", -3, 1, 1, 1),
+    command("pexpireat", -3, 1, 1, 1),
+    command("persist", 2, 1, 1, 1),
+    command("incr", 2, 1, 1, 1),
+    command("decr", 2, 1, 1, 1),
+    command("incrby", 3, 1, 1, 1),
+    command("decrby", 3, 1, 1, 1),
+    command("incrbyfloat", 3, 1, 1, 1),
+    command("append", 3, 1, 1, 1),
+    command("setrange", 4, 1, 1, 1),
+    command("strlen", 2, 1, 1, 1),
+    command("setbit", 4, 1, 1, 1),
+    command("getbit", 3, 1, 1, 1),
+    command("bitcount", -2, 1, 1, 1),
+    command("lpush", -3, 1, 1, 1),
+    command("rpush", -3, 1, 1, 1),
+    command("lpop", -2, 1, 1, 1),
+    command("rpop", -2, 1, 1, 1),
+    command("blpop", -3, 1, -2, 1),
+    command("brpop", -3, 1, -2, 1),
+    command("llen", 2, 1, 1, 1),
+    command("lrange", 4, 1, 1, 1),
+    command("hset", -4, 1, 1, 1),
+    command("hget", 3, 1, 1, 1),
+    command("hdel", -3, 1, 1, 1),
+    command("hgetall", 2, 1, 1, 1),
+    command("hscan", -3, 1, 1, 1),
+    command("sadd", -3, 1, 1, 1),
+    command("srem", -3, 1, 1, 1),
+    command("sismember", 3, 1, 1, 1),
+    command("smembers", 2, 1, 1, 1),
+    command("sscan", -3, 1, 1, 1),
+    command("scard", 2, 1, 1, 1),
+    command("spop", -2, 1, 1, 1),
+    command("zadd", -4, 1, 1, 1),
+    command("zscore", 3, 1, 1, 1),
+    command("zcard", 2, 1, 1, 1),
+    command("zrange", -4, 1, 1, 1),
+    command("zrangebyscore", -4, 1, 1, 1),
+    command("zincrby", 4, 1, 1, 1),
+    command("geoadd", -5, 1, 1, 1),
+    command("geopos", -2, 1, 1, 1),
+    command("geodist", -4, 1, 1, 1),
+    command("geosearch", -7, 1, 1, 1),
+    command("xadd", -5, 1, 1, 1),
+    command("xtrim", -4, 1, 1, 1),
+    command("xlen", 2, 1, 1, 1),
+    command("xrange", -4, 1, 1, 1),
+    command("xread", -4, 0, 0, 0),
+    container("xgroup", &[command("xgroup|create", -5, 2, 2, 1)]),
+    command("xreadgroup", -7, 0, 0, 0),
+    command("xack", -4, 1, 1, 1),
+    command("xpending", -3, 1, 1, 1),
+    command("type", 2, 1, 1, 1),
+    command("keys", 2, 0, 0, 0),
+    command("scan", -2, 0, 0, 0),
+    command("randomkey", 1, 0, 0, 0),
+    command("dbsize", 1, 0, 0, 0),
+    command("wait", 3, 0, 0, 0),
+    command("info", -1, 0, 0, 0),
+    container(
+        "client",
+        &[
+            command("client|id", 2, 0, 0, 0),
+            command("client|getname", 2, 0, 0, 0),
+            command("client|info", 2, 0, 0, 0),
+            command("client|setname", 3, 0, 0, 0),
+        ],
+    ),
+    command("flushdb", -1, 0, 0, 0),
+    command("flushall", -1, 0, 0, 0),
+    container(
+        "object",
+        &[
+            command("object|encoding", 3, 2, 2, 1),
+            command("object|refcount", 3, 2, 2, 1),
+            command("object|idletime", 3, 2, 2, 1),
+        ],
+    ),
+    container("memory", &[command("memory|usage", -3, 2, 2, 1)]),
+    container(
+        "debug",
+        &[
+            command("debug|sleep", 3, 0, 0, 0),
+            command("debug|jmap", 2, 0, 0, 0),
+            command("debug|set-active-expire", 3, 0, 0, 0),
+            command("debug|object", 3, 2, 2, 1),
+        ],
+    ),
+    container(
+        "latency",
+        &[
+            command("latency|history", 3, 0, 0, 0),
+            command("latency|latest", 2, 0, 0, 0),
+            command("latency|reset", -2, 0, 0, 0),
+        ],
+    ),
+    container(
+        "acl",
+        &[
+            command("acl|whoami", 2, 0, 0, 0),
+            command("acl|list", 2, 0, 0, 0),
+            command("acl|cat", -2, 0, 0, 0),
+            command("acl|getuser", 3, 0, 0, 0),
+        ],
+    ),
+    container(
+        "function",
+        &[
+            command("function|list", -2, 0, 0, 0),
+            command("function|dump", 2, 0, 0, 0),
+        ],
+    ),
+    command("fcall", -3, 0, 0, 0),
+    command("fcall_ro", -3, 0, 0, 0),
+];
+
+impl CommandSpec {
+    /// Whether the command can be called with argc arguments, counting the
+    /// command name.
+    pub fn accepts(&self, argc: usize) -> bool {
+        if self.arity < 0 {
+            argc as i64 >= -self.arity
+        } else {
+            argc as i64 == self.arity
+        }
+    }
+
+    /// The reply format of `COMMAND INFO`.
+    pub fn to_value(&self) -> Value {
+        let subcommands = self
+            .subcommands
+            .iter()
+            .map(|subcommand| subcommand.to_value())
+            .collect();
+
+        Value::Array(vec![
+            Value::BulkString(Bytes::from(self.name)),
+            Value::Integer(self.arity),
+            // Flags
+            Value::Array(Vec::new()),
+            Value::Integer(self.first_key),
+            Value::Integer(self.last_key),
+            Value::Integer(self.step),
+            // ACL categories
+            Value::Array(Vec::new()),
+            // Tips
+            Value::Array(Vec::new()),
+            // Key specifications
+            Value::Array(Vec::new()),
+            Value::Array(subcommands),
+        ])
+    }
+}
+
+/// Looks up a command or a `container|subcommand` ignoring case.
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    let name = name.to_ascii_lowercase();
+
+    let (container, subcommand) = match name.split_once('|') {
+        Some((container, _)) => (container, Some(name.as_str())),
+        None => (name.as_str(), None),
+    };
+
+    let spec = COMMANDS.iter().find(|spec| spec.name == container)?;
+
+    match subcommand {
+        Some(subcommand) => spec.subcommands.iter().find(|spec| spec.name == subcommand),
+        None => Some(spec),
+    }
+}
+
+#[test]
+fn command_arities_match_redis() {
+    let test_data = [
+        ("command", -1),
+        ("get", 2),
+        ("set", -3),
+        ("del", -2),
+        ("mget", -2),
+        ("exists", -2),
+        ("mset", -3),
+        ("rename", 3),
+        ("incrby", 3),
+        ("expire", -3),
+        ("xadd", -5),
+        ("xread", -4),
+        ("config", -2),
+        ("CONFIG|GET", -3),
+        ("object|encoding", 3),
+    ];
+
+    for (name, arity) in test_data {
+        assert_eq!(lookup(name).unwrap().arity, arity, "{name}");
+    }
+
+    assert!(lookup("missing").is_none());
+    assert!(lookup("get|missing").is_none());
+}
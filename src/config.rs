@@ -0,0 +1,317 @@
+/// The server configuration as exposed by `CONFIG GET` and `CONFIG SET`.
+///
+/// Most parameters are only stored for now, changing them does not affect the
+/// behaviour of the server yet.
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{connection::MESSAGE_CAPACITY, glob::Glob, proto::DEFAULT_MAX_BULK_LEN, pubsub};
+
+/// The supported parameters and their defaults, the same as in Redis. Only
+/// pubsub-message-capacity has no equivalent in Redis, which limits the bytes
+/// instead with client-output-buffer-limit.
+const DEFAULTS: &[(&str, &str)] = &[
+    ("appendonly", "no"),
+    ("latency-monitor-threshold", "0"),
+    ("list-max-listpack-size", "-2"),
+    ("maxmemory", "0"),
+    ("maxmemory-policy", "noeviction"),
+    ("notify-keyspace-events", ""),
+    ("proto-max-bulk-len", "536870912"),
+    ("pubsub-message-capacity", "1024"),
+    ("requirepass", ""),
+    ("save", "3600 1 300 100 60 10000"),
+    ("timeout", "0"),
+];
+
+const MAXMEMORY_POLICIES: &[&str] = &[
+    "volatile-lru",
+    "allkeys-lru",
+    "volatile-lfu",
+    "allkeys-lfu",
+    "volatile-random",
+    "allkeys-random",
+    "volatile-ttl",
+    "noeviction",
+];
+
+/// How large lists encoded as a single listpack may be, see
+/// [`Config::list_max_listpack_size`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListpackLimit {
+    Entries(usize),
+    Bytes(usize),
+}
+
+impl ListpackLimit {
+    /// Converts a `list-max-listpack-size`. Positive values are a number of
+    /// entries, -1 to -5 a size of 4 KB to 64 KB like in Redis. Smaller
+    /// values are treated as -5.
+    pub fn from_config(value: i32) -> Self {
+        match value {
+            0.. => Self::Entries(value as usize),
+            _ => Self::Bytes(4096 << (value.unsigned_abs().min(5) - 1)),
+        }
+    }
+
+    /// Whether the list fits into a single listpack.
+    pub fn allows(self, list: &VecDeque<String>) -> bool {
+        match self {
+            Self::Entries(entries) => list.len() <= entries,
+            Self::Bytes(bytes) => list.iter().map(String::len).sum::<usize>() <= bytes,
+        }
+    }
+}
+
+pub struct Config {
+    parameters: Mutex<BTreeMap<&'static str, String>>,
+    /// The parsed notify-keyspace-events, which is checked on every write.
+    keyspace_events: AtomicU32,
+    /// The parsed maxmemory, which is checked on every write.
+    maxmemory: AtomicU64,
+    /// The parsed proto-max-bulk-len, which is checked on every write that
+    /// grows a string.
+    proto_max_bulk_len: AtomicU64,
+}
+
+/// Returns why the value is invalid for the parameter, if it is.
+fn validate(name: &str, value: &str) -> Option<&'static str> {
+    match name {
+        "appendonly" if !matches!(value, "yes" | "no") => Some("argument must be 'yes' or 'no'"),
+        "latency-monitor-threshold" | "maxmemory" | "proto-max-bulk-len" | "timeout"
+            if value.parse::<u64>().is_err() =>
+        {
+            Some("argument couldn't be parsed into an integer")
+        }
+        "pubsub-message-capacity" if !matches!(value.parse::<usize>(), Ok(1..)) => {
+            Some("argument must be greater than 0")
+        }
+        "list-max-listpack-size" if value.parse::<i32>().is_err() => {
+            Some("argument couldn't be parsed into an integer")
+        }
+        "maxmemory-policy" if !MAXMEMORY_POLICIES.contains(&value) => {
+            Some("argument(s) must be one of the following: volatile-lru, allkeys-lru, volatile-lfu, allkeys-lfu, volatile-random, allkeys-random, volatile-ttl, noeviction")
+        }
+        "notify-keyspace-events" if pubsub::parse_keyspace_events(value).is_none() => {
+            Some("Invalid event class character. Use 'Ag$lshzxeKEt'.")
+        }
+        "save" if value.split_whitespace().any(|part| part.parse::<u64>().is_err()) => {
+            Some("Invalid save parameters")
+        }
+        _ => None,
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        let parameters = DEFAULTS
+            .iter()
+            .map(|(name, value)| (*name, String::from(*value)))
+            .collect();
+
+        Self {
+            parameters: Mutex::new(parameters),
+            keyspace_events: AtomicU32::new(0),
+            maxmemory: AtomicU64::new(0),
+            proto_max_bulk_len: AtomicU64::new(DEFAULT_MAX_BULK_LEN as u64),
+        }
+    }
+
+    /// Returns the parameters matching any of the glob-style patterns,
+    /// ignoring case.
+    pub fn get(&self, patterns: &[String]) -> Vec<(&'static str, String)> {
+        let globs: Vec<Glob> = patterns
+            .iter()
+            .map(|pattern| Glob::new(pattern.to_ascii_lowercase().as_bytes()))
+            .collect();
+
+        self.parameters
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, _)| globs.iter().any(|glob| glob.matches(name.as_bytes())))
+            .map(|(name, value)| (*name, value.clone()))
+            .collect()
+    }
+
+    /// Returns the value of a supported parameter.
+    pub fn value(&self, name: &str) -> String {
+        self.parameters.lock().unwrap()[name].clone()
+    }
+
+    /// The keyspace event flags, see [`pubsub::parse_keyspace_events`].
+    pub fn keyspace_events(&self) -> u32 {
+        self.keyspace_events.load(Ordering::Relaxed)
+    }
+
+    /// The memory limit in bytes, 0 if there is none.
+    pub fn maxmemory(&self) -> u64 {
+        self.maxmemory.load(Ordering::Relaxed)
+    }
+
+    /// The minimum latency in milliseconds the latency monitor records, see
+    /// [`LatencyMonitor`](crate::latency::LatencyMonitor).
+    pub fn latency_monitor_threshold(&self) -> u64 {
+        self.value("latency-monitor-threshold")
+            .parse()
+            .unwrap_or_default()
+    }
+
+    /// The largest lists encoded as a single listpack.
+    pub fn list_max_listpack_size(&self) -> ListpackLimit {
+        let value = self.value("list-max-listpack-size").parse().unwrap_or(-2);
+
+        ListpackLimit::from_config(value)
+    }
+
+    /// How many pub/sub messages can wait to be written to a new connection,
+    /// see [`ConnectionState::with_message_capacity`](crate::connection::ConnectionState::with_message_capacity).
+    pub fn pubsub_message_capacity(&self) -> usize {
+        self.value("pubsub-message-capacity")
+            .parse()
+            .unwrap_or(MESSAGE_CAPACITY)
+    }
+
+    /// The longest string in bytes.
+    pub fn proto_max_bulk_len(&self) -> u64 {
+        self.proto_max_bulk_len.load(Ordering::Relaxed)
+    }
+
+    /// Sets all parameters or none of them if any name or value is invalid.
+    ///
+    /// Returns the error to reply with otherwise.
+    pub fn set(&self, pairs: Vec<(String, String)>) -> Result<(), String> {
+        let mut parameters = self.parameters.lock().unwrap();
+        let mut updates = Vec::with_capacity(pairs.len());
+
+        for (name, value) in pairs {
+            let name = name.to_ascii_lowercase();
+
+            let Some((&name, _)) = parameters.get_key_value(name.as_str()) else {
+                return Err(format!(
+                    "ERR Unknown option or number of arguments for CONFIG SET - '{name}'"
+                ));
+            };
+
+            if let Some(reason) = validate(name, &value) {
+                return Err(format!(
+                    "ERR CONFIG SET failed (possibly related to argument '{name}') - {reason}"
+                ));
+            }
+
+            updates.push((name, value));
+        }
+
+        for (name, value) in updates {
+            if name == "notify-keyspace-events" {
+                let flags = pubsub::parse_keyspace_events(&value).unwrap_or_default();
+                self.keyspace_events.store(flags, Ordering::Relaxed);
+            } else if name == "maxmemory" {
+                self.maxmemory
+                    .store(value.parse().unwrap_or_default(), Ordering::Relaxed);
+            } else if name == "proto-max-bulk-len" {
+                self.proto_max_bulk_len
+                    .store(value.parse().unwrap_or_default(), Ordering::Relaxed);
+            }
+
+            parameters.insert(name, value);
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn config_set_is_atomic() {
+    let config = Config::new();
+
+    let err = config
+        .set(vec![
+            (String::from("timeout"), String::from("10")),
+            (String::from("maxmemory"), String::from("lots")),
+        ])
+        .unwrap_err();
+
+    assert_eq!(
+        err,
+        "ERR CONFIG SET failed (possibly related to argument 'maxmemory') - argument couldn't be parsed into an integer"
+    );
+    assert_eq!(
+        config.get(&[String::from("timeout")]),
+        vec![("timeout", String::from("0"))]
+    );
+
+    config
+        .set(vec![(String::from("TIMEOUT"), String::from("10"))])
+        .unwrap();
+    assert_eq!(
+        config.get(&[String::from("timeout")]),
+        vec![("timeout", String::from("10"))]
+    );
+}
+
+#[test]
+fn list_max_listpack_size_converts() {
+    let test_data = [
+        (-1, ListpackLimit::Bytes(4096)),
+        (-2, ListpackLimit::Bytes(8192)),
+        (-3, ListpackLimit::Bytes(16384)),
+        (-4, ListpackLimit::Bytes(32768)),
+        (-5, ListpackLimit::Bytes(65536)),
+        (-100, ListpackLimit::Bytes(65536)),
+        (0, ListpackLimit::Entries(0)),
+        (128, ListpackLimit::Entries(128)),
+    ];
+
+    for (value, expected) in test_data {
+        assert_eq!(ListpackLimit::from_config(value), expected, "{value}");
+    }
+
+    let config = Config::new();
+    assert_eq!(config.list_max_listpack_size(), ListpackLimit::Bytes(8192));
+
+    config
+        .set(vec![(
+            String::from("list-max-listpack-size"),
+            String::from("5"),
+        )])
+        .unwrap();
+    assert_eq!(config.list_max_listpack_size(), ListpackLimit::Entries(5));
+
+    assert!(config
+        .set(vec![(
+            String::from("list-max-listpack-size"),
+            String::from("big"),
+        )])
+        .is_err());
+}
+
+#[test]
+fn pubsub_message_capacity_works() {
+    let config = Config::new();
+    assert_eq!(config.pubsub_message_capacity(), MESSAGE_CAPACITY);
+
+    let err = config
+        .set(vec![(
+            String::from("pubsub-message-capacity"),
+            String::from("0"),
+        )])
+        .unwrap_err();
+    assert_eq!(
+        err,
+        "ERR CONFIG SET failed (possibly related to argument 'pubsub-message-capacity') - argument must be greater than 0"
+    );
+
+    config
+        .set(vec![(
+            String::from("pubsub-message-capacity"),
+            String::from("16"),
+        )])
+        .unwrap();
+    assert_eq!(config.pubsub_message_capacity(), 16);
+}
@@ -0,0 +1,271 @@
+/// The state of a single client connection.
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    Notify,
+};
+
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    cmd::RedisCommand,
+    proto::{ProtocolVersion, Value},
+};
+
+/// The id of the next connection, starting at 1 like in Redis.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// How many messages can wait to be written to a connection by default, see
+/// [`ConnectionState::with_message_capacity`].
+pub const MESSAGE_CAPACITY: usize = 1024;
+
+/// Sends messages to a connection without waiting for it to catch up.
+#[derive(Clone)]
+pub struct MessageSender {
+    sender: mpsc::Sender<Value>,
+    /// Notified when a message did not fit into the channel.
+    overflowed: Arc<Notify>,
+}
+
+impl MessageSender {
+    /// Returns whether the message was queued. Messages to a connection that
+    /// is too far behind are dropped and the connection is closed.
+    pub fn send(&self, message: Value) -> bool {
+        match self.sender.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.overflowed.notify_one();
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+/// The commands queued after `MULTI`.
+pub struct Transaction {
+    pub commands: Vec<RedisCommand>,
+    /// Whether a command could not be queued, which aborts `EXEC`.
+    pub failed: bool,
+}
+
+pub struct ConnectionState {
+    id: u64,
+    /// The name set with `CLIENT SETNAME`.
+    name: Mutex<Option<String>>,
+    /// Whether the client negotiated RESP3 with `HELLO 3`.
+    resp3: AtomicBool,
+    /// Whether the client sent the right password with `AUTH`.
+    authenticated: AtomicBool,
+    /// The database chosen with `SELECT`.
+    db_index: AtomicUsize,
+    /// The transaction started with `MULTI`, if any.
+    transaction: Mutex<Option<Transaction>>,
+    /// The keys passed to `WATCH` and their versions at that time.
+    watched: Mutex<Vec<(String, u64)>>,
+    /// The pub/sub channels the client subscribed to.
+    channels: Mutex<BTreeSet<String>>,
+    /// Sends values to the client outside of replies, like pub/sub messages.
+    messages: MessageSender,
+    /// Taken by the connection loop that writes the messages.
+    receiver: Mutex<Option<mpsc::Receiver<Value>>>,
+    /// Replies that are written before the reply of the running command.
+    pending: Mutex<Vec<Value>>,
+}
+
+impl ConnectionState {
+    /// Creates the state of a connection with the default capacity, the
+    /// server uses the configured one.
+    #[cfg(test)]
+    pub fn new() -> Self {
+        Self::with_message_capacity(MESSAGE_CAPACITY)
+    }
+
+    /// Creates the state of a connection that can have the given number of
+    /// messages waiting to be written. A client that falls further behind
+    /// is disconnected, like with the pubsub class of
+    /// client-output-buffer-limit in Redis.
+    pub fn with_message_capacity(capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let messages = MessageSender {
+            sender,
+            overflowed: Arc::new(Notify::new()),
+        };
+
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name: Mutex::new(None),
+            resp3: AtomicBool::new(false),
+            authenticated: AtomicBool::new(false),
+            db_index: AtomicUsize::new(0),
+            transaction: Mutex::new(None),
+            watched: Mutex::new(Vec::new()),
+            channels: Mutex::new(BTreeSet::new()),
+            messages,
+            receiver: Mutex::new(Some(receiver)),
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn name(&self) -> Option<String> {
+        self.name.lock().unwrap().clone()
+    }
+
+    pub fn set_name(&self, name: Option<String>) {
+        *self.name.lock().unwrap() = name;
+    }
+
+    pub fn protocol(&self) -> ProtocolVersion {
+        if self.resp3.load(Ordering::Relaxed) {
+            ProtocolVersion::Resp3
+        } else {
+            ProtocolVersion::Resp2
+        }
+    }
+
+    pub fn set_protocol(&self, version: ProtocolVersion) {
+        self.resp3
+            .store(version == ProtocolVersion::Resp3, Ordering::Relaxed);
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated.load(Ordering::Relaxed)
+    }
+
+    pub fn authenticate(&self) {
+        self.authenticated.store(true, Ordering::Relaxed);
+    }
+
+    pub fn db_index(&self) -> usize {
+        self.db_index.load(Ordering::Relaxed)
+    }
+
+    pub fn select(&self, index: usize) {
+        self.db_index.store(index, Ordering::Relaxed);
+    }
+
+    /// Starts a transaction.
+    ///
+    /// Returns false if one was already started.
+    pub fn begin_transaction(&self) -> bool {
+        let mut transaction = self.transaction.lock().unwrap();
+
+        if transaction.is_some() {
+            return false;
+        }
+
+        *transaction = Some(Transaction {
+            commands: Vec::new(),
+            failed: false,
+        });
+
+        true
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.lock().unwrap().is_some()
+    }
+
+    /// Queues the command in the started transaction.
+    pub fn queue(&self, command: RedisCommand) {
+        if let Some(transaction) = self.transaction.lock().unwrap().as_mut() {
+            transaction.commands.push(command);
+        }
+    }
+
+    /// Makes the started transaction, if any, fail on `EXEC`.
+    pub fn fail_transaction(&self) {
+        if let Some(transaction) = self.transaction.lock().unwrap().as_mut() {
+            transaction.failed = true;
+        }
+    }
+
+    /// Ends the started transaction and returns it.
+    pub fn take_transaction(&self) -> Option<Transaction> {
+        self.transaction.lock().unwrap().take()
+    }
+
+    pub fn watch(&self, key: String, version: u64) {
+        self.watched.lock().unwrap().push((key, version));
+    }
+
+    /// Stops watching all keys and returns them with their versions.
+    pub fn take_watched(&self) -> Vec<(String, u64)> {
+        std::mem::take(&mut self.watched.lock().unwrap())
+    }
+
+    pub fn messages(&self) -> &MessageSender {
+        &self.messages
+    }
+
+    /// Returns the receiving end of the messages, only once.
+    pub fn take_receiver(&self) -> Option<mpsc::Receiver<Value>> {
+        self.receiver.lock().unwrap().take()
+    }
+
+    /// Resolves once a message was dropped because the client fell behind.
+    pub async fn overflowed(&self) {
+        self.messages.overflowed.notified().await;
+    }
+
+    /// Queues a reply to write before the reply of the running command.
+    pub fn push_reply(&self, reply: Value) {
+        self.pending.lock().unwrap().push(reply);
+    }
+
+    /// Takes the replies queued by the last command.
+    pub fn take_pending_replies(&self) -> Vec<Value> {
+        std::mem::take(&mut self.pending.lock().unwrap())
+    }
+
+    /// Adds the channel to the subscriptions.
+    ///
+    /// Returns the number of subscriptions afterwards.
+    pub fn subscribe(&self, channel: String) -> usize {
+        let mut channels = self.channels.lock().unwrap();
+        channels.insert(channel);
+
+        channels.len()
+    }
+
+    /// Removes the channel from the subscriptions.
+    ///
+    /// Returns the number of subscriptions afterwards.
+    pub fn unsubscribe(&self, channel: &str) -> usize {
+        let mut channels = self.channels.lock().unwrap();
+        channels.remove(channel);
+
+        channels.len()
+    }
+
+    /// The subscribed channels in alphabetical order.
+    pub fn channels(&self) -> Vec<String> {
+        self.channels.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Puts the connection back into the state of a new one for `RESET`,
+    /// except for its id. The client has to authenticate again.
+    ///
+    /// Returns the channels it was subscribed to.
+    pub fn reset(&self) -> Vec<String> {
+        self.set_name(None);
+        self.set_protocol(ProtocolVersion::Resp2);
+        self.authenticated.store(false, Ordering::Relaxed);
+        self.select(0);
+        self.take_transaction();
+        self.take_watched();
+
+        std::mem::take(&mut *self.channels.lock().unwrap())
+            .into_iter()
+            .collect()
+    }
+}
@@ -1,14 +1,43 @@
-use dashmap::{mapref::entry::Entry as MapEntry, DashMap};
-use futures_util::StreamExt;
-use tokio::sync::{mpsc, oneshot};
+use bytes::{Bytes, BytesMut};
+use dashmap::{
+    mapref::{entry::Entry as MapEntry, one::MappedRef},
+    DashMap, SharedValue,
+};
+use futures_util::{future::select_all, StreamExt};
+use log::warn;
+use tokio::sync::{mpsc, Notify, RwLock, RwLockReadGuard};
 use tokio_util::time::{delay_queue::Key, DelayQueue};
 
 use std::{
-    sync::Arc,
-    time::{Duration, Instant},
+    collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque},
+    future::Future,
+    hash::{Hash, Hasher},
+    num::NonZeroUsize,
+    ops::Bound,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, LazyLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{cmd::SetBehaviour, proto::Value};
+use crate::{
+    acl::Acl,
+    cmd::{BitRange, ExpireBehaviour, GetExBehaviour, SetBehaviour},
+    config::{Config, ListpackLimit},
+    dump,
+    geo::{self, Center, Neighbor, Shape},
+    glob::Glob,
+    latency::LatencyMonitor,
+    object::{element_size, Object},
+    proto::{parse_bytes, RedisError, Value},
+    pubsub::{self, PubSub},
+    rand::Rng,
+    stream::{
+        self, IdSpec, PendingFilter, PendingInfo, Stream, StreamEntry, StreamId, TrimStrategy,
+    },
+    zset::{member_size, AddCondition, AddOutcome, ScoreUpdate, SortedSet},
+};
 
 #[derive(Clone)]
 pub struct Db {
@@ -18,61 +47,422 @@ pub struct Db {
 struct DbInner {
     /// The key-value data store.
     entries: DashMap<String, Entry>,
-    /// Notifies the expiration task.
-    background_task: mpsc::UnboundedSender<ExpirationUpdate>,
+    /// Notifies the expiration tasks, which each own the expirations of
+    /// some of the shards, see [`Db::expiration_task_of`].
+    expiration_tasks: Vec<mpsc::UnboundedSender<ExpirationUpdate>>,
+    /// The ID of the next expiration, see [`Entry::expiration_id`].
+    next_expiration_id: AtomicU64,
+    /// The users and their permissions.
+    acl: Acl,
+    /// The parameters of CONFIG GET and CONFIG SET.
+    config: Config,
+    /// Records latency spikes.
+    latency: LatencyMonitor,
+    /// Wakes the clients blocked on a key when it is written to.
+    blocked: DashMap<String, Arc<Notify>>,
+    /// Held exclusively by EXEC and shared by all other commands, see
+    /// [`Db::run_exclusively`].
+    transaction_lock: RwLock<()>,
+    /// The subscribers of pub/sub channels.
+    pubsub: PubSub,
+    /// When the database was created, for the uptime in INFO.
+    started: Instant,
+    /// The Unix time in seconds of the last save. There is no persistence
+    /// yet, so this is when the database was created, like in Redis before
+    /// the first save.
+    last_save: u64,
+    /// The number of open client connections.
+    clients: AtomicUsize,
+    /// The approximate bytes all entries take, see [`Entry::size`].
+    used_memory: AtomicUsize,
 }
 
+/// Identifies a pending expiration, see [`Entry::expiration_id`].
+#[derive(Clone, Copy)]
+struct ExpirationId {
+    /// The index of the expiration task that owns it.
+    task: usize,
+    id: u64,
+}
+
+/// Changes to the expirations of an expiration task. The IDs are chosen by
+/// the db, so that inserting does not have to wait for the delay queue key.
 #[derive(Debug)]
 enum ExpirationUpdate {
     Remove {
-        key: Key,
+        id: u64,
     },
     Reset {
-        key: Key,
-        timeout: Duration,
+        id: u64,
+        expires_at: Instant,
     },
     Insert {
+        id: u64,
         value: String,
-        timeout: Duration,
-        return_key: oneshot::Sender<Key>,
+        expires_at: Instant,
     },
+    /// Drops all pending expirations.
+    Clear,
+}
+
+/// The result of [`Db::set`].
+pub enum SetOutcome {
+    /// The set behaviour prevented the write.
+    NotPerformed,
+    /// The key did not exist before.
+    Inserted,
+    /// The key existed and held this value.
+    Replaced(Object),
+}
+
+/// A key that may be evicted, see [`Db::evict_if_needed`].
+struct EvictionCandidate {
+    key: String,
+    last_access: u64,
+    expires_at: Option<Instant>,
+}
+
+/// Counts towards the connected clients while it is alive, see
+/// [`Db::client_connected`].
+pub struct ConnectedClient {
+    db: Db,
+}
+
+impl Drop for ConnectedClient {
+    fn drop(&mut self) {
+        self.db.inner.clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn wrong_type_error() -> Value {
+    Value::Error(RedisError {
+        message: String::from("WRONGTYPE Operation against a key holding the wrong kind of value"),
+    })
+}
+
+fn string_too_long_error() -> Value {
+    Value::Error(RedisError {
+        message: String::from("ERR string exceeds maximum allowed size (proto-max-bulk-len)"),
+    })
+}
+
+/// Strings up to this length are encoded as embstr by Redis.
+const EMBSTR_SIZE_LIMIT: usize = 44;
+
+/// Hashes with up to this many fields are encoded as listpack by Redis.
+const HASH_LISTPACK_ENTRIES: usize = 128;
+
+/// Hashes with fields and values up to this length are encoded as listpack
+/// by Redis.
+const HASH_LISTPACK_VALUE: usize = 64;
+
+/// Sets of integers up to this size are encoded as intset by Redis.
+const SET_INTSET_ENTRIES: usize = 512;
+
+/// Sets with up to this many members are encoded as listpack by Redis.
+const SET_LISTPACK_ENTRIES: usize = 128;
+
+/// Sets with members up to this length are encoded as listpack by Redis.
+const SET_LISTPACK_VALUE: usize = 64;
+
+/// Sorted sets with up to this many members are encoded as listpack by
+/// Redis.
+const ZSET_LISTPACK_ENTRIES: usize = 128;
+
+/// Sorted sets with members up to this length are encoded as listpack by
+/// Redis.
+const ZSET_LISTPACK_VALUE: usize = 64;
+
+/// The approximate bytes every key takes besides its name and value.
+const KEY_OVERHEAD: usize = 64;
+
+/// How many keys are compared to pick the one to evict, like the default
+/// maxmemory-samples of Redis.
+const EVICTION_SAMPLES: usize = 5;
+
+/// The start of the clock the last access times count from.
+static LRU_CLOCK_START: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// The current time of the clock for last access times, in milliseconds.
+fn lru_clock() -> u64 {
+    LRU_CLOCK_START.elapsed().as_millis() as u64
+}
+
+/// A value of a specific type borrowed from the entries, see
+/// [`Db::get_typed`].
+type TypedRef<'a, T> = MappedRef<'a, String, Entry, T>;
+
+tokio::task_local! {
+    /// Set while a command runs exclusively, see [`Db::run_exclusively`].
+    static EXCLUSIVE: ();
 }
 
+/// The version of the next write. Versions are unique, so a key that was
+/// removed and created again has a different version than before.
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+
 struct Entry {
-    /// TODO: Consider storing the bytes instead
-    value: Value,
+    value: Object,
     expires_at: Option<Instant>,
-    expiration_key: Option<Key>,
+    /// Identifies the pending expiration in its expiration task.
+    expiration_id: Option<ExpirationId>,
+    /// Whether the string was modified in place, which makes Redis encode
+    /// it as raw regardless of its length.
+    forced_raw: bool,
+    /// Changes on every write, see [`Db::version`].
+    version: u64,
+    /// The bytes accounted for in the used memory, including the key.
+    size: usize,
+    /// When the key was last read or written to, see [`lru_clock`].
+    last_access: AtomicU64,
+}
+
+impl Entry {
+    fn new(value: Object) -> Self {
+        Self {
+            value,
+            expires_at: None,
+            expiration_id: None,
+            forced_raw: false,
+            version: NEXT_VERSION.fetch_add(1, Ordering::Relaxed),
+            size: 0,
+            last_access: AtomicU64::new(lru_clock()),
+        }
+    }
+
+    /// Marks the entry as accessed for the LRU eviction.
+    fn access(&self) {
+        self.last_access.store(lru_clock(), Ordering::Relaxed);
+    }
+
+    /// Whether the expiry passed, even if the key was not removed yet.
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Instant::now())
+    }
+
+    /// Marks the entry as written to.
+    fn touch(&mut self) {
+        self.version = NEXT_VERSION.fetch_add(1, Ordering::Relaxed);
+        self.access();
+    }
+
+    /// The encoding Redis would report for the value, with lists up to the
+    /// limit encoded as a single listpack.
+    fn encoding(&self, list_limit: ListpackLimit) -> &'static str {
+        match &self.value {
+            Object::String(_) if self.forced_raw => "raw",
+            // Only integers that format back to the same string, so no
+            // leading zeros or plus signs
+            Object::String(bytes)
+                if bytes.len() <= 20
+                    && parse_bytes::<i64>(bytes)
+                        .is_some_and(|integer| integer.to_string().as_bytes() == &bytes[..]) =>
+            {
+                "int"
+            }
+            Object::String(bytes) if bytes.len() <= EMBSTR_SIZE_LIMIT => "embstr",
+            Object::String(_) => "raw",
+            Object::List(list) if list_limit.allows(list) => "listpack",
+            Object::List(_) => "quicklist",
+            Object::Hash(hash)
+                if hash.len() <= HASH_LISTPACK_ENTRIES
+                    && hash.iter().all(|(field, value)| {
+                        field.len() <= HASH_LISTPACK_VALUE && value.len() <= HASH_LISTPACK_VALUE
+                    }) =>
+            {
+                "listpack"
+            }
+            Object::Hash(_) => "hashtable",
+            Object::Set(set)
+                if set.len() <= SET_INTSET_ENTRIES
+                    && set.iter().all(|member| member.parse::<i64>().is_ok()) =>
+            {
+                "intset"
+            }
+            Object::Set(set)
+                if set.len() <= SET_LISTPACK_ENTRIES
+                    && set.iter().all(|member| member.len() <= SET_LISTPACK_VALUE) =>
+            {
+                "listpack"
+            }
+            Object::Set(_) => "hashtable",
+            Object::Stream(_) => "stream",
+            Object::SortedSet(zset)
+                if zset.len() <= ZSET_LISTPACK_ENTRIES
+                    && zset
+                        .members()
+                        .all(|member| member.len() <= ZSET_LISTPACK_VALUE) =>
+            {
+                "listpack"
+            }
+            Object::SortedSet(_) => "skiplist",
+        }
+    }
+}
+
+/// Returns about count of the elements in the order of their hashes,
+/// starting at the cursor, and the cursor to continue with or 0 once all
+/// elements were scanned. Works like [`Db::scan`] within a single value.
+fn scan_elements<'a, T>(
+    elements: impl Iterator<Item = (&'a String, T)>,
+    cursor: u64,
+    pattern: Option<&str>,
+    count: usize,
+) -> (u64, Vec<T>) {
+    let mut hashed: Vec<(u64, &String, T)> = elements
+        .map(|(name, element)| {
+            // The default keys are fixed, so the order is the same on
+            // every call
+            let mut hasher = DefaultHasher::new();
+            name.hash(&mut hasher);
+
+            (hasher.finish(), name, element)
+        })
+        .filter(|(hash, _, _)| *hash >= cursor)
+        .collect();
+    hashed.sort_unstable_by_key(|(hash, _, _)| *hash);
+
+    // Elements with the same hash are never split across calls
+    let mut end = count.min(hashed.len());
+    while end > 0 && end < hashed.len() && hashed[end].0 == hashed[end - 1].0 {
+        end += 1;
+    }
+
+    let next_cursor = if end < hashed.len() {
+        hashed[end - 1].0.wrapping_add(1)
+    } else {
+        0
+    };
+
+    let glob = pattern.map(|pattern| Glob::new(pattern.as_bytes()));
+    let elements = hashed
+        .into_iter()
+        .take(end)
+        .filter(|(_, name, _)| {
+            glob.as_ref()
+                .is_none_or(|glob| glob.matches(name.as_bytes()))
+        })
+        .map(|(_, _, element)| element)
+        .collect();
+
+    (next_cursor, elements)
+}
+
+/// How often the active expire cycle runs, 10 times per second like in Redis.
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many keys of every shard the active expire cycle looks at.
+const ACTIVE_EXPIRE_SAMPLES: usize = 20;
+
+/// Removes expired keys the expiration tasks missed, like the active expire
+/// cycle of Redis. Every run looks at a few keys of every shard, starting at
+/// a random one.
+async fn active_expire_task(db: Db) {
+    let mut rng = Rng::new();
+
+    loop {
+        tokio::time::sleep(ACTIVE_EXPIRE_INTERVAL).await;
+
+        let mut expired = Vec::new();
+
+        for shard in db.inner.entries.shards() {
+            let shard = shard.read();
+
+            if shard.is_empty() {
+                continue;
+            }
+
+            let start = rng.below(shard.len());
+
+            expired.extend(
+                shard
+                    .iter()
+                    .chain(shard.iter())
+                    .skip(start)
+                    .take(ACTIVE_EXPIRE_SAMPLES.min(shard.len()))
+                    .filter(|(_, entry)| entry.get().is_expired())
+                    .map(|(key, _)| key.clone()),
+            );
+        }
+
+        for key in expired {
+            db.remove_expired(&key);
+        }
+    }
+}
+
+/// The longest timeout the delay queue is given. It panics for timeouts
+/// above 2^36 - 1 ms counted from the last time it fired, so expirations
+/// further away are queued for at most half of that and queued again when
+/// they fire.
+const MAX_QUEUED_TIMEOUT: Duration = Duration::from_millis(1 << 35);
+
+/// How often the heartbeat fires, which bounds how long ago the delay queue
+/// last fired.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The ID of the heartbeat in the delay queue, which the db never hands out.
+const HEARTBEAT_ID: u64 = u64::MAX;
+
+/// The timeout to queue an expiration at expires_at with.
+fn queued_timeout(expires_at: Instant) -> Duration {
+    expires_at
+        .saturating_duration_since(Instant::now())
+        .min(MAX_QUEUED_TIMEOUT)
 }
 
 async fn expiration_task(
-    mut queue: DelayQueue<String>,
+    mut queue: DelayQueue<(u64, String)>,
     mut rx: mpsc::UnboundedReceiver<ExpirationUpdate>,
     db: Db,
 ) {
-    let mut has_items = !queue.is_empty();
+    // The updates arrive in order, so an expiration is always inserted
+    // before it is reset or removed
+    let mut keys: HashMap<u64, (Key, Instant)> = HashMap::new();
+
+    // The heartbeat keeps the queue from ever being empty
+    queue.insert((HEARTBEAT_ID, String::new()), HEARTBEAT_INTERVAL);
 
     loop {
         tokio::select! {
-            // Only poll this branch if the queue has items
-            // else we would instantly always resolve to None
-            // and block
-            Some(item) = queue.next(), if has_items => {
-                db.remove_raw(item.get_ref());
-                has_items = !queue.is_empty();
+            Some(item) = queue.next() => {
+                let (id, key) = item.into_inner();
+
+                if id == HEARTBEAT_ID {
+                    queue.insert((id, key), HEARTBEAT_INTERVAL);
+                } else if let Some(&(_, expires_at)) = keys.get(&id) {
+                    if expires_at > Instant::now() {
+                        // Queued for less than the full timeout
+                        let slot = queue.insert((id, key), queued_timeout(expires_at));
+                        keys.insert(id, (slot, expires_at));
+                    } else {
+                        keys.remove(&id);
+                        db.remove_raw(&key);
+                    }
+                }
             },
             Some(update) = rx.recv() => {
                 match update {
-                    ExpirationUpdate::Remove { key } => {
-                        queue.remove(&key);
+                    ExpirationUpdate::Remove { id } => {
+                        if let Some((slot, _)) = keys.remove(&id) {
+                            queue.remove(&slot);
+                        }
                     },
-                    ExpirationUpdate::Reset { key, timeout } => {
-                        queue.reset(&key, timeout);
+                    ExpirationUpdate::Reset { id, expires_at } => {
+                        if let Some((slot, queued_expires_at)) = keys.get_mut(&id) {
+                            queue.reset(slot, queued_timeout(expires_at));
+                            *queued_expires_at = expires_at;
+                        }
                     },
-                    ExpirationUpdate::Insert { value, timeout, return_key } => {
-                        let key = queue.insert(value, timeout);
-                        return_key.send(key).unwrap();
-                        has_items = true;
+                    ExpirationUpdate::Insert { id, value, expires_at } => {
+                        let slot = queue.insert((id, value), queued_timeout(expires_at));
+                        keys.insert(id, (slot, expires_at));
+                    }
+                    ExpirationUpdate::Clear => {
+                        queue.clear();
+                        keys.clear();
+                        queue.insert((HEARTBEAT_ID, String::new()), HEARTBEAT_INTERVAL);
                     }
                 }
             }
@@ -82,33 +472,455 @@ async fn expiration_task(
 
 impl Db {
     pub fn new() -> Self {
-        let (background_task, background_receive) = mpsc::unbounded_channel();
+        let tasks = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let (expiration_tasks, expiration_receivers): (Vec<_>, Vec<_>) =
+            (0..tasks).map(|_| mpsc::unbounded_channel()).unzip();
 
         let inner = Arc::new(DbInner {
             entries: DashMap::new(),
-            background_task,
+            expiration_tasks,
+            next_expiration_id: AtomicU64::new(0),
+            acl: Acl::new(),
+            config: Config::new(),
+            latency: LatencyMonitor::new(0),
+            blocked: DashMap::new(),
+            transaction_lock: RwLock::new(()),
+            pubsub: PubSub::new(),
+            started: Instant::now(),
+            last_save: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            clients: AtomicUsize::new(0),
+            used_memory: AtomicUsize::new(0),
         });
         let db = Self { inner };
 
-        let expirations = DelayQueue::new();
+        for receiver in expiration_receivers {
+            tokio::spawn(expiration_task(DelayQueue::new(), receiver, db.clone()));
+        }
+
+        tokio::spawn(active_expire_task(db.clone()));
 
-        tokio::spawn(expiration_task(expirations, background_receive, db.clone()));
+        let watched = db.clone();
+        tokio::spawn(async move { watched.latency().watchdog().await });
 
         db
     }
 
-    pub fn get(&self, key: &str) -> Option<Value> {
-        self.inner.entries.get(key).map(|entry| entry.value.clone())
+    pub fn acl(&self) -> &Acl {
+        &self.inner.acl
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.inner.config
+    }
+
+    pub fn latency(&self) -> &LatencyMonitor {
+        &self.inner.latency
+    }
+
+    /// Sets configuration parameters like [`Config::set`] and applies those
+    /// that are not read from the configuration when used.
+    pub fn config_set(&self, pairs: Vec<(String, String)>) -> Result<(), String> {
+        self.inner.config.set(pairs)?;
+
+        self.inner
+            .latency
+            .set_threshold(self.inner.config.latency_monitor_threshold());
+
+        Ok(())
+    }
+
+    pub fn pubsub(&self) -> &PubSub {
+        &self.inner.pubsub
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.inner.started.elapsed()
+    }
+
+    /// The Unix time in seconds of the last save, see
+    /// [`DbInner::last_save`].
+    pub fn last_save(&self) -> u64 {
+        self.inner.last_save
+    }
+
+    pub fn connected_clients(&self) -> usize {
+        self.inner.clients.load(Ordering::Relaxed)
+    }
+
+    /// Counts a client as connected until the returned guard is dropped.
+    pub fn client_connected(&self) -> ConnectedClient {
+        self.inner.clients.fetch_add(1, Ordering::Relaxed);
+
+        ConnectedClient { db: self.clone() }
+    }
+
+    /// Runs the future while no other command runs, like EXEC between
+    /// checking the watched keys and running the queued commands.
+    pub async fn run_exclusively<T>(&self, future: impl Future<Output = T>) -> T {
+        let _exclusive = self.inner.transaction_lock.write().await;
+
+        EXCLUSIVE.scope((), future).await
+    }
+
+    /// Waits until no command runs exclusively and keeps any from starting
+    /// until the guard is dropped. Commands that already run exclusively
+    /// need no guard.
+    pub async fn lock_shared(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        if EXCLUSIVE.try_with(|_| ()).is_ok() {
+            return None;
+        }
+
+        Some(self.inner.transaction_lock.read().await)
+    }
+
+    /// Runs the operation until it produces a result, waiting for writes to
+    /// any of the keys in between. Gives up with `None` once the timeout
+    /// elapsed, without a timeout it waits forever. Like in Redis, commands
+    /// that run exclusively never wait, since nothing could write the keys.
+    pub async fn block_on_keys<T>(
+        &self,
+        keys: &[String],
+        timeout: Option<Duration>,
+        mut operation: impl FnMut() -> Option<T>,
+    ) -> Option<T> {
+        let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+
+        let notifiers: Vec<Arc<Notify>> = keys
+            .iter()
+            .map(|key| {
+                self.inner
+                    .blocked
+                    .entry(key.clone())
+                    .or_insert_with(|| Arc::new(Notify::new()))
+                    .clone()
+            })
+            .collect();
+
+        let result = loop {
+            // The futures are created before running the operation so that
+            // no write in between is missed
+            let notified = notifiers
+                .iter()
+                .map(|notifier| Box::pin(notifier.notified()));
+            let woken = select_all(notified);
+
+            // Only while running the operation, so that waiting does not
+            // hold up commands that run exclusively
+            let shared = self.lock_shared().await;
+
+            if let Some(result) = operation() {
+                break Some(result);
+            }
+
+            if shared.is_none() {
+                break None;
+            }
+
+            drop(shared);
+
+            if let Some(deadline) = deadline {
+                if tokio::time::timeout_at(deadline, woken).await.is_err() {
+                    break None;
+                }
+            } else {
+                woken.await;
+            }
+        };
+
+        drop(notifiers);
+
+        for key in keys {
+            self.inner
+                .blocked
+                .remove_if(key, |_, notifier| Arc::strong_count(notifier) == 1);
+        }
+
+        result
+    }
+
+    /// Publishes a keyspace event if notify-keyspace-events enables it.
+    fn notify_event(&self, class: u32, event: &str, key: &str) {
+        let flags = self.inner.config.keyspace_events();
+
+        if flags != 0 {
+            self.inner
+                .pubsub
+                .notify_keyspace_event(flags, class, event, key);
+        }
+    }
+
+    /// Wakes the clients blocked on key.
+    fn notify_key(&self, key: &str) {
+        if let Some(notifier) = self.inner.blocked.get(key) {
+            notifier.notify_waiters();
+        }
+    }
+
+    /// Wakes the client that blocked on key first, for writes only one of
+    /// them can consume.
+    fn wake_one(&self, key: &str) {
+        if let Some(notifier) = self.inner.blocked.get(key) {
+            notifier.notify_one();
+        }
+    }
+
+    /// The approximate bytes all keys and values take.
+    pub fn used_memory(&self) -> usize {
+        self.inner.used_memory.load(Ordering::Relaxed)
+    }
+
+    /// Creates the entry of a new key and accounts for its memory.
+    fn new_entry(&self, key: &str, value: Object) -> Entry {
+        let mut entry = Entry::new(value);
+        entry.size = KEY_OVERHEAD + key.len() + entry.value.size_estimate();
+
+        self.inner
+            .used_memory
+            .fetch_add(entry.size, Ordering::Relaxed);
+
+        entry
+    }
+
+    /// Accounts for the bytes removed from and added to the value of an
+    /// entry in place.
+    fn resize_entry(&self, entry: &mut Entry, removed: usize, added: usize) {
+        entry.size = entry.size + added - removed;
+
+        self.inner.used_memory.fetch_add(added, Ordering::Relaxed);
+        self.inner.used_memory.fetch_sub(removed, Ordering::Relaxed);
+    }
+
+    /// Counts the memory of an entry again from scratch, for changes that
+    /// are hard to track in place.
+    fn recount_entry(&self, key: &str, entry: &mut Entry) {
+        let size = KEY_OVERHEAD + key.len() + entry.value.size_estimate();
+
+        self.resize_entry(entry, entry.size, size);
+    }
+
+    /// Stops accounting for the memory of a removed entry.
+    fn forget_entry(&self, entry: &Entry) {
+        self.inner
+            .used_memory
+            .fetch_sub(entry.size, Ordering::Relaxed);
+    }
+
+    /// Evicts keys according to maxmemory-policy until the used memory is
+    /// below maxmemory again.
+    ///
+    /// Returns the error to reply with if not enough keys can be evicted.
+    pub fn evict_if_needed(&self) -> Result<(), Value> {
+        let maxmemory = self.inner.config.maxmemory() as usize;
+
+        if maxmemory == 0 || self.used_memory() <= maxmemory {
+            return Ok(());
+        }
+
+        let policy = self.inner.config.value("maxmemory-policy");
+        let mut rng = Rng::new();
+
+        while self.used_memory() > maxmemory {
+            // LFU is approximated by LRU, there are no access counters
+            let victim = match policy.as_str() {
+                "allkeys-random" => self.eviction_candidates(&mut rng, 1, false).pop(),
+                "volatile-random" => self.eviction_candidates(&mut rng, 1, true).pop(),
+                "allkeys-lru" | "allkeys-lfu" => self
+                    .eviction_candidates(&mut rng, EVICTION_SAMPLES, false)
+                    .into_iter()
+                    .min_by_key(|candidate| candidate.last_access),
+                "volatile-lru" | "volatile-lfu" => self
+                    .eviction_candidates(&mut rng, EVICTION_SAMPLES, true)
+                    .into_iter()
+                    .min_by_key(|candidate| candidate.last_access),
+                "volatile-ttl" => self
+                    .eviction_candidates(&mut rng, EVICTION_SAMPLES, true)
+                    .into_iter()
+                    .min_by_key(|candidate| candidate.expires_at),
+                _ => None,
+            };
+
+            let Some(EvictionCandidate { key, .. }) = victim else {
+                return Err(Value::Error(RedisError {
+                    message: String::from(
+                        "OOM command not allowed when used memory > 'maxmemory'.",
+                    ),
+                }));
+            };
+
+            if let Some((key, entry)) = self.inner.entries.remove(&key) {
+                self.forget_entry(&entry);
+                self.cancel_expiration(&entry);
+                self.notify_event(pubsub::EVICTED, "evicted", &key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Looks at up to count keys, starting at a random one. Only keys with an
+    /// expiry are considered if volatile is set.
+    fn eviction_candidates(
+        &self,
+        rng: &mut Rng,
+        count: usize,
+        volatile: bool,
+    ) -> Vec<EvictionCandidate> {
+        let shards = self.inner.entries.shards();
+        let first_shard = rng.below(shards.len());
+        let mut candidates = Vec::with_capacity(count);
+
+        for index in 0..shards.len() {
+            let shard = shards[(first_shard + index) % shards.len()].read();
+
+            if shard.is_empty() {
+                continue;
+            }
+
+            let start = rng.below(shard.len());
+
+            candidates.extend(
+                shard
+                    .iter()
+                    .chain(shard.iter())
+                    .skip(start)
+                    .take(shard.len())
+                    .filter(|(_, entry)| !volatile || entry.get().expires_at.is_some())
+                    .take(count - candidates.len())
+                    .map(|(key, entry)| EvictionCandidate {
+                        key: key.clone(),
+                        last_access: entry.get().last_access.load(Ordering::Relaxed),
+                        expires_at: entry.get().expires_at,
+                    }),
+            );
+
+            if candidates.len() == count {
+                break;
+            }
+        }
+
+        candidates
+    }
+
+    /// Fetches the value at key as the type that view selects, like
+    /// [`Object::as_string`], and marks it as accessed.
+    ///
+    /// Returns `None` if the key is missing and the WRONGTYPE error to reply
+    /// with if it holds another type.
+    fn get_typed<T>(
+        &self,
+        key: &str,
+        view: impl FnOnce(&Object) -> Option<&T>,
+    ) -> Result<Option<TypedRef<'_, T>>, Value> {
+        self.expire_if_needed(key);
+
+        let Some(entry) = self.inner.entries.get(key) else {
+            return Ok(None);
+        };
+
+        entry.access();
+
+        entry
+            .try_map(|entry| view(&entry.value))
+            .map(Some)
+            .map_err(|_| wrong_type_error())
+    }
+
+    /// Returns the string stored at key or the error to reply with if the
+    /// key holds another type.
+    pub fn get(&self, key: &str) -> Result<Option<Bytes>, Value> {
+        let value = self.get_typed(key, Object::as_string)?;
+
+        Ok(value.map(|value| value.clone()))
+    }
+
+    /// Returns the values of all keys in order, `None` for missing keys and
+    /// keys not holding a string.
+    pub fn mget(&self, keys: &[String]) -> Vec<Option<Bytes>> {
+        keys.iter()
+            .map(|key| self.get(key).ok().flatten())
+            .collect()
+    }
+
+    /// Sets all keys to their values, discarding any previous expiry.
+    pub fn mset(&self, pairs: Vec<(String, Bytes)>) {
+        self.set_many(pairs, false);
+    }
+
+    /// Sets all keys to their values only if none of them exists.
+    ///
+    /// Returns whether the keys were set.
+    pub fn msetnx(&self, pairs: Vec<(String, Bytes)>) -> bool {
+        self.set_many(pairs, true)
+    }
+
+    fn set_many(&self, pairs: Vec<(String, Bytes)>, only_if_none_exist: bool) -> bool {
+        for (key, _) in &pairs {
+            self.expire_if_needed(key);
+        }
+
+        let entries = &self.inner.entries;
+
+        let shard_indices: Vec<usize> = pairs
+            .iter()
+            .map(|(key, _)| entries.determine_map(key.as_str()))
+            .collect();
+
+        // All involved shards are locked up front to make the existence check
+        // and the writes atomic. Locking them in ascending order prevents
+        // deadlocks with other writes to multiple keys.
+        let mut locked_indices = shard_indices.clone();
+        locked_indices.sort_unstable();
+        locked_indices.dedup();
+
+        let mut shards: Vec<_> = locked_indices
+            .iter()
+            .map(|idx| entries.shards()[*idx].write())
+            .collect();
+
+        let shard_position = |idx: &usize| locked_indices.binary_search(idx).unwrap();
+
+        if only_if_none_exist {
+            let any_exists = pairs
+                .iter()
+                .zip(&shard_indices)
+                .any(|((key, _), idx)| shards[shard_position(idx)].contains_key(key));
+
+            if any_exists {
+                return false;
+            }
+        }
+
+        for ((key, value), idx) in pairs.into_iter().zip(&shard_indices) {
+            let entry = self.new_entry(&key, Object::String(value));
+            self.notify_event(pubsub::STRING, "set", &key);
+
+            let old = shards[shard_position(idx)].insert(key, SharedValue::new(entry));
+
+            if let Some(old) = old {
+                let old = old.into_inner();
+
+                self.forget_entry(&old);
+                self.cancel_expiration(&old);
+            }
+        }
+
+        true
     }
 
-    pub async fn set(
+    pub fn set(
         &self,
         key: String,
-        value: Value,
+        value: Bytes,
         expire: Option<Duration>,
         behaviour: SetBehaviour,
         keep_ttl: bool,
-    ) -> Option<Value> {
+        return_old: bool,
+    ) -> Result<SetOutcome, Value> {
+        self.expire_if_needed(&key);
+
         let map_entry = self.inner.entries.entry(key);
         let should_insert = match behaviour {
             SetBehaviour::Force => true,
@@ -116,91 +928,2362 @@ impl Db {
             SetBehaviour::OnlyIfNotExists => matches!(map_entry, MapEntry::Vacant(_)),
         };
 
+        if return_old {
+            if let MapEntry::Occupied(occupied_entry) = &map_entry {
+                if occupied_entry.get().value.as_string().is_none() {
+                    return Err(wrong_type_error());
+                }
+            }
+        }
+
+        // Like in Redis, an expiry in the past removes the key instead
+        if should_insert && !keep_ttl && expire.is_some_and(|expire| expire.is_zero()) {
+            return match map_entry {
+                MapEntry::Occupied(occupied_entry) => {
+                    let (key, entry) = occupied_entry.remove_entry();
+
+                    self.forget_entry(&entry);
+                    self.cancel_expiration(&entry);
+                    self.notify_event(pubsub::GENERIC, "del", &key);
+
+                    Ok(SetOutcome::Replaced(entry.value))
+                }
+                MapEntry::Vacant(_) => Ok(SetOutcome::Inserted),
+            };
+        }
+
         if should_insert {
             match map_entry {
                 MapEntry::Occupied(mut occupied_entry) => {
-                    let old = occupied_entry.get_mut();
+                    let entry = occupied_entry.get_mut();
+                    let added = value.len();
+                    let prev = std::mem::replace(&mut entry.value, Object::String(value));
+                    entry.forced_raw = false;
+                    self.resize_entry(entry, prev.size_estimate(), added);
+                    entry.touch();
 
-                    let prev = std::mem::replace(&mut old.value, value);
+                    self.notify_event(pubsub::STRING, "set", occupied_entry.key());
 
+                    // Like in Redis, writing a key drops its expiry unless it
+                    // is kept or replaced
                     if !keep_ttl {
                         if let Some(expiration) = expire {
-                            old.expires_at = Some(Instant::now() + expiration);
-
-                            self.inner
-                                .background_task
-                                .send(ExpirationUpdate::Reset {
-                                    key: old.expiration_key.unwrap(),
-                                    timeout: expiration,
-                                })
-                                .unwrap();
+                            let key = occupied_entry.key().clone();
+
+                            self.schedule_expiration(key, occupied_entry.get_mut(), expiration);
+                        } else if occupied_entry.get().expires_at.is_some() {
+                            self.clear_expiration(occupied_entry.get_mut());
                         }
                     }
 
-                    Some(prev)
+                    Ok(SetOutcome::Replaced(prev))
                 }
                 MapEntry::Vacant(vacant_entry) => {
-                    let entry = if let Some(expiration) = expire {
-                        let (tx, rx) = oneshot::channel();
-                        self.inner
-                            .background_task
-                            .send(ExpirationUpdate::Insert {
-                                value: vacant_entry.key().clone(),
-                                timeout: expiration,
-                                return_key: tx,
-                            })
-                            .unwrap();
-                        let expiration_key = rx.await.unwrap();
-
-                        Entry {
-                            value,
-                            expires_at: Some(Instant::now() + expiration),
-                            expiration_key: Some(expiration_key),
-                        }
-                    } else {
-                        Entry {
-                            value,
-                            expires_at: None,
-                            expiration_key: None,
-                        }
-                    };
+                    let mut entry = self.new_entry(vacant_entry.key(), Object::String(value));
+
+                    self.notify_event(pubsub::STRING, "set", vacant_entry.key());
+
+                    if let Some(expiration) = expire {
+                        let key = vacant_entry.key().clone();
+
+                        self.schedule_expiration(key, &mut entry, expiration);
+                    }
 
                     vacant_entry.insert(entry);
 
-                    Some(Value::NullString)
+                    Ok(SetOutcome::Inserted)
                 }
             }
         } else {
-            None
+            Ok(SetOutcome::NotPerformed)
         }
     }
 
-    pub fn remove(&self, keys: Vec<String>) -> usize {
-        let mut count = 0;
+    /// Makes an existing key expire at the given instant if the behaviour
+    /// allows it. Like in Redis, an instant that already passed removes the
+    /// key instead.
+    ///
+    /// Returns whether the expiry was applied.
+    pub fn set_expiry(&self, key: &str, at: Instant, behaviour: ExpireBehaviour) -> bool {
+        self.expire_if_needed(key);
 
-        for key in keys {
-            if let Some((_, entry)) = self.inner.entries.remove(&key) {
-                count += 1;
+        let mut entry = match self.inner.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => return false,
+        };
 
-                if let Some(expiration_key) = entry.expiration_key {
-                    self.inner
-                        .background_task
-                        .send(ExpirationUpdate::Remove {
-                            key: expiration_key,
-                        })
-                        .unwrap();
-                }
-            };
+        // Keys without an expiry are treated as having an infinite TTL
+        let should_expire = match behaviour {
+            ExpireBehaviour::Force => true,
+            ExpireBehaviour::OnlyIfNoExpiry => entry.expires_at.is_none(),
+            ExpireBehaviour::OnlyIfExpiry => entry.expires_at.is_some(),
+            ExpireBehaviour::OnlyIfGreater => {
+                entry.expires_at.is_some_and(|expires_at| at > expires_at)
+            }
+            ExpireBehaviour::OnlyIfLess => {
+                entry.expires_at.is_none_or(|expires_at| at < expires_at)
+            }
+        };
+
+        if !should_expire {
+            return false;
         }
 
-        count
+        let now = Instant::now();
+
+        if at <= now {
+            drop(entry);
+            self.remove(vec![key.to_string()]);
+        } else {
+            self.schedule_expiration(key.to_string(), &mut entry, at - now);
+            self.notify_event(pubsub::GENERIC, "expire", key);
+        }
+
+        true
+    }
+
+    /// The index of the expiration task that expires key. All keys of a
+    /// shard share the same task.
+    fn expiration_task_of(&self, key: &str) -> usize {
+        self.inner.entries.determine_map(key) % self.inner.expiration_tasks.len()
+    }
+
+    /// Sends an update to an expiration task. The tasks only stop with the
+    /// runtime, and the active expire cycle still removes expired keys then.
+    fn update_expirations(&self, task: usize, update: ExpirationUpdate) {
+        if let Err(err) = self.inner.expiration_tasks[task].send(update) {
+            warn!("Expiration task {task} stopped, dropping {:?}", err.0);
+        }
+    }
+
+    /// Expires an entry after the timeout, either by resetting its existing
+    /// delay queue slot or by inserting a new one.
+    fn schedule_expiration(&self, key: String, entry: &mut Entry, timeout: Duration) {
+        // The commands reject expire times this far away
+        let Some(expires_at) = Instant::now().checked_add(timeout) else {
+            self.clear_expiration(entry);
+            return;
+        };
+
+        if let Some(ExpirationId { task, id }) = entry.expiration_id {
+            self.update_expirations(task, ExpirationUpdate::Reset { id, expires_at });
+        } else {
+            let task = self.expiration_task_of(&key);
+            let id = self
+                .inner
+                .next_expiration_id
+                .fetch_add(1, Ordering::Relaxed);
+
+            self.update_expirations(
+                task,
+                ExpirationUpdate::Insert {
+                    id,
+                    value: key,
+                    expires_at,
+                },
+            );
+
+            entry.expiration_id = Some(ExpirationId { task, id });
+        }
+
+        entry.expires_at = Some(expires_at);
+        entry.touch();
+    }
+
+    /// Removes the pending expiration of an entry that was removed.
+    fn cancel_expiration(&self, entry: &Entry) {
+        if let Some(ExpirationId { task, id }) = entry.expiration_id {
+            self.update_expirations(task, ExpirationUpdate::Remove { id });
+        }
+    }
+
+    /// Removes the expiry of an entry.
+    fn clear_expiration(&self, entry: &mut Entry) {
+        self.cancel_expiration(entry);
+
+        entry.expires_at = None;
+        entry.expiration_id = None;
+        entry.touch();
+    }
+
+    /// Removes the expiry of key.
+    ///
+    /// Returns 1 if the key had an expiry, 0 otherwise.
+    pub fn persist(&self, key: &str) -> i64 {
+        self.expire_if_needed(key);
+
+        match self.inner.entries.get_mut(key) {
+            Some(mut entry) if entry.expires_at.is_some() => {
+                self.clear_expiration(&mut entry);
+                self.notify_event(pubsub::GENERIC, "persist", key);
+
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    /// Returns the string stored at key and changes its TTL.
+    pub fn getex(&self, key: &str, behaviour: GetExBehaviour) -> Result<Option<Bytes>, Value> {
+        self.expire_if_needed(key);
+
+        let mut entry = match self.inner.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        let value = entry
+            .value
+            .as_string()
+            .cloned()
+            .ok_or_else(wrong_type_error)?;
+
+        match behaviour {
+            GetExBehaviour::Keep => {}
+            GetExBehaviour::Expire(timeout) => {
+                self.schedule_expiration(key.to_string(), &mut entry, timeout);
+            }
+            GetExBehaviour::Persist => self.clear_expiration(&mut entry),
+        }
+
+        Ok(Some(value))
+    }
+
+    /// Removes the key and returns its string.
+    pub fn get_del(&self, key: &str) -> Result<Option<Bytes>, Value> {
+        self.expire_if_needed(key);
+
+        let removed = self
+            .inner
+            .entries
+            .remove_if(key, |_, entry| entry.value.as_string().is_some());
+
+        match removed {
+            Some((_, entry)) => {
+                self.forget_entry(&entry);
+                self.cancel_expiration(&entry);
+                self.notify_event(pubsub::GENERIC, "del", key);
+
+                Ok(entry.value.into_string())
+            }
+            None if self.inner.entries.contains_key(key) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn remove(&self, keys: Vec<String>) -> usize {
+        self.remove_entries(keys).len()
+    }
+
+    /// Removes the keys like [`Db::remove`], but drops the values on a
+    /// blocking thread so that freeing large values does not hold up the
+    /// reply.
+    pub fn unlink(&self, keys: Vec<String>) -> usize {
+        let removed = self.remove_entries(keys);
+        let count = removed.len();
+
+        if count > 0 {
+            tokio::task::spawn_blocking(move || drop(removed));
+        }
+
+        count
+    }
+
+    /// Removes the keys and returns their entries.
+    fn remove_entries(&self, keys: Vec<String>) -> Vec<Entry> {
+        let mut removed = Vec::new();
+
+        for key in keys {
+            self.expire_if_needed(&key);
+
+            if let Some((_, entry)) = self.inner.entries.remove(&key) {
+                self.forget_entry(&entry);
+                self.cancel_expiration(&entry);
+                self.notify_event(pubsub::GENERIC, "del", &key);
+
+                removed.push(entry);
+            };
+        }
+
+        removed
+    }
+
+    /// Renames src to dst, overwriting dst unless only_if_missing is set.
+    /// The expiry of src moves along with it.
+    ///
+    /// Returns whether the key was renamed or the error to reply with.
+    pub fn rename(&self, src: &str, dst: String, only_if_missing: bool) -> Result<bool, Value> {
+        self.expire_if_needed(src);
+        self.expire_if_needed(&dst);
+
+        let entries = &self.inner.entries;
+
+        let src_index = entries.determine_map(src);
+        let dst_index = entries.determine_map(dst.as_str());
+
+        // Both shards are locked in ascending order like in set_many to make
+        // the move atomic
+        let mut locked_indices = vec![src_index, dst_index];
+        locked_indices.sort_unstable();
+        locked_indices.dedup();
+
+        let mut shards: Vec<_> = locked_indices
+            .iter()
+            .map(|idx| entries.shards()[*idx].write())
+            .collect();
+
+        let shard_position = |idx: &usize| locked_indices.binary_search(idx).unwrap();
+
+        if !shards[shard_position(&src_index)].contains_key(src) {
+            return Err(Value::Error(RedisError {
+                message: String::from("ERR no such key"),
+            }));
+        }
+
+        if src == dst {
+            return Ok(!only_if_missing);
+        }
+
+        if only_if_missing && shards[shard_position(&dst_index)].contains_key(&dst) {
+            return Ok(false);
+        }
+
+        let mut entry = shards[shard_position(&src_index)]
+            .remove(src)
+            .unwrap()
+            .into_inner();
+
+        // The delay queue refers to the old name
+        if let Some(expires_at) = entry.expires_at {
+            self.cancel_expiration(&entry);
+            entry.expiration_id = None;
+
+            let timeout = expires_at.saturating_duration_since(Instant::now());
+            self.schedule_expiration(dst.clone(), &mut entry, timeout);
+        }
+
+        self.resize_entry(&mut entry, src.len(), dst.len());
+
+        let old = shards[shard_position(&dst_index)].insert(dst.clone(), SharedValue::new(entry));
+
+        if let Some(old) = old {
+            let old = old.into_inner();
+
+            self.forget_entry(&old);
+            self.cancel_expiration(&old);
+        }
+
+        self.notify_event(pubsub::GENERIC, "rename_from", src);
+        self.notify_event(pubsub::GENERIC, "rename_to", &dst);
+
+        Ok(true)
+    }
+
+    /// Copies the value of src to dst, overwriting dst only if replace is
+    /// set. The copy expires at the same time as src but independently of
+    /// it.
+    ///
+    /// Returns whether the key was copied or the error to reply with.
+    pub fn copy(&self, src: &str, dst: String, replace: bool) -> Result<bool, Value> {
+        self.expire_if_needed(src);
+        self.expire_if_needed(&dst);
+
+        if src == dst {
+            return Err(Value::Error(RedisError {
+                message: String::from("ERR source and destination objects are the same"),
+            }));
+        }
+
+        let entries = &self.inner.entries;
+
+        let src_index = entries.determine_map(src);
+        let dst_index = entries.determine_map(dst.as_str());
+
+        // Both shards are locked in ascending order like in rename to make
+        // the copy atomic
+        let mut locked_indices = vec![src_index, dst_index];
+        locked_indices.sort_unstable();
+        locked_indices.dedup();
+
+        let mut shards: Vec<_> = locked_indices
+            .iter()
+            .map(|idx| entries.shards()[*idx].write())
+            .collect();
+
+        let shard_position = |idx: &usize| locked_indices.binary_search(idx).unwrap();
+
+        let Some(src_entry) = shards[shard_position(&src_index)].get(src) else {
+            return Ok(false);
+        };
+        let src_entry = src_entry.get();
+
+        if !replace && shards[shard_position(&dst_index)].contains_key(&dst) {
+            return Ok(false);
+        }
+
+        let mut entry = self.new_entry(&dst, src_entry.value.clone());
+        entry.forced_raw = src_entry.forced_raw;
+
+        // The copy gets its own delay queue slot, so that expiring or
+        // persisting one key does not affect the other
+        if let Some(expires_at) = src_entry.expires_at {
+            let timeout = expires_at.saturating_duration_since(Instant::now());
+            self.schedule_expiration(dst.clone(), &mut entry, timeout);
+        }
+
+        let old = shards[shard_position(&dst_index)].insert(dst.clone(), SharedValue::new(entry));
+
+        if let Some(old) = old {
+            let old = old.into_inner();
+
+            self.forget_entry(&old);
+            self.cancel_expiration(&old);
+        }
+
+        self.notify_event(pubsub::GENERIC, "copy_to", &dst);
+
+        Ok(true)
+    }
+
+    /// Serializes the value at key, see [`dump::serialize`].
+    pub fn dump(&self, key: &str) -> Option<Bytes> {
+        self.expire_if_needed(key);
+
+        self.inner
+            .entries
+            .get(key)
+            .map(|entry| dump::serialize(&entry.value))
+    }
+
+    /// Stores a value restored from a `DUMP` payload at key, expiring after
+    /// ttl if given. Existing keys are only overwritten with replace.
+    pub fn restore(
+        &self,
+        key: String,
+        value: Object,
+        ttl: Option<Duration>,
+        replace: bool,
+    ) -> Result<(), Value> {
+        self.expire_if_needed(&key);
+
+        let map_entry = self.inner.entries.entry(key);
+
+        if !replace && matches!(map_entry, MapEntry::Occupied(_)) {
+            return Err(Value::Error(RedisError {
+                message: String::from("BUSYKEY Target key name already exists."),
+            }));
+        }
+
+        let key = map_entry.key().clone();
+        let mut entry = self.new_entry(&key, value);
+
+        if let Some(ttl) = ttl {
+            self.schedule_expiration(key.clone(), &mut entry, ttl);
+        }
+
+        match map_entry {
+            MapEntry::Occupied(mut occupied_entry) => {
+                let old = occupied_entry.insert(entry);
+
+                self.forget_entry(&old);
+                self.cancel_expiration(&old);
+            }
+            MapEntry::Vacant(vacant_entry) => {
+                vacant_entry.insert(entry);
+            }
+        }
+
+        self.notify_event(pubsub::GENERIC, "restore", &key);
+
+        Ok(())
+    }
+
+    /// Returns the version of key, which changes on every write to it and is
+    /// 0 while the key is missing.
+    pub fn version(&self, key: &str) -> u64 {
+        self.inner.entries.get(key).map_or(0, |entry| entry.version)
+    }
+
+    /// Counts how many of the keys exist, counting duplicates repeatedly.
+    pub fn exists(&self, keys: &[String]) -> i64 {
+        keys.iter()
+            .filter(|key| {
+                self.expire_if_needed(key);
+                self.inner.entries.contains_key(key.as_str())
+            })
+            .count() as i64
+    }
+
+    /// Counts how many of the keys exist like [`Db::exists`] and marks them
+    /// as accessed for the LRU eviction.
+    pub fn touch(&self, keys: &[String]) -> i64 {
+        keys.iter()
+            .filter(|key| {
+                self.expire_if_needed(key);
+
+                self.inner
+                    .entries
+                    .get(key.as_str())
+                    .map(|entry| entry.access())
+                    .is_some()
+            })
+            .count() as i64
+    }
+
+    /// Adds delta to the integer stored at key, treating a missing key as 0.
+    ///
+    /// Returns the new value or the error to reply with.
+    pub fn incr_by(&self, key: String, delta: i64) -> Result<i64, Value> {
+        self.expire_if_needed(&key);
+
+        match self.inner.entries.entry(key) {
+            MapEntry::Occupied(mut occupied_entry) => {
+                let entry = occupied_entry.get_mut();
+
+                let current: Option<i64> = match &entry.value {
+                    Object::String(bytes) => parse_bytes(bytes),
+                    _ => return Err(wrong_type_error()),
+                };
+
+                let current = current.ok_or_else(|| {
+                    Value::Error(RedisError {
+                        message: String::from("ERR value is not an integer or out of range"),
+                    })
+                })?;
+
+                let new = current.checked_add(delta).ok_or_else(|| {
+                    Value::Error(RedisError {
+                        message: String::from("ERR increment or decrement would overflow"),
+                    })
+                })?;
+
+                let value = Bytes::from(new.to_string());
+                self.resize_entry(entry, entry.value.size_estimate(), value.len());
+                entry.value = Object::String(value);
+                entry.forced_raw = false;
+                entry.touch();
+
+                self.notify_event(pubsub::STRING, "incrby", occupied_entry.key());
+
+                Ok(new)
+            }
+            MapEntry::Vacant(vacant_entry) => {
+                self.notify_event(pubsub::STRING, "incrby", vacant_entry.key());
+
+                let value = Object::String(Bytes::from(delta.to_string()));
+                let entry = self.new_entry(vacant_entry.key(), value);
+                vacant_entry.insert(entry);
+
+                Ok(delta)
+            }
+        }
+    }
+
+    /// Adds the increment to the float stored at key, treating a missing key
+    /// as 0.
+    ///
+    /// Returns the new value formatted like it is stored or the error to
+    /// reply with.
+    pub fn incr_by_float(&self, key: String, increment: f64) -> Result<String, Value> {
+        self.expire_if_needed(&key);
+
+        let map_entry = self.inner.entries.entry(key);
+
+        let current = match &map_entry {
+            MapEntry::Occupied(occupied_entry) => match &occupied_entry.get().value {
+                Object::String(bytes) => parse_bytes(bytes),
+                _ => return Err(wrong_type_error()),
+            },
+            MapEntry::Vacant(_) => Some(0.0),
+        };
+
+        let current = current
+            .filter(|current: &f64| !current.is_nan())
+            .ok_or_else(|| {
+                Value::Error(RedisError {
+                    message: String::from("ERR value is not a valid float"),
+                })
+            })?;
+
+        let new = current + increment;
+
+        if !new.is_finite() {
+            return Err(Value::Error(RedisError {
+                message: String::from("ERR increment would produce NaN or Infinity"),
+            }));
+        }
+
+        // Display uses the shortest representation and never an exponent
+        let formatted = new.to_string();
+        let value = Object::String(Bytes::from(formatted.clone()));
+
+        self.notify_event(pubsub::STRING, "incrbyfloat", map_entry.key());
+
+        match map_entry {
+            MapEntry::Occupied(mut occupied_entry) => {
+                let entry = occupied_entry.get_mut();
+                self.resize_entry(entry, entry.value.size_estimate(), value.size_estimate());
+                entry.value = value;
+                entry.forced_raw = false;
+                entry.touch();
+            }
+            MapEntry::Vacant(vacant_entry) => {
+                let entry = self.new_entry(vacant_entry.key(), value);
+                vacant_entry.insert(entry);
+            }
+        }
+
+        Ok(formatted)
+    }
+
+    /// Appends to the string stored at key, creating it if missing.
+    ///
+    /// Returns the new length or the error to reply with.
+    pub fn append(&self, key: String, value: Bytes) -> Result<i64, Value> {
+        self.expire_if_needed(&key);
+
+        match self.inner.entries.entry(key) {
+            MapEntry::Occupied(mut occupied_entry) => {
+                let entry = occupied_entry.get_mut();
+
+                let current = match &entry.value {
+                    Object::String(current) => current,
+                    _ => return Err(wrong_type_error()),
+                };
+
+                self.check_string_len(current.len() + value.len())?;
+
+                let mut appended = BytesMut::with_capacity(current.len() + value.len());
+                appended.extend_from_slice(current);
+                appended.extend_from_slice(&value);
+                let length = appended.len() as i64;
+
+                self.resize_entry(entry, 0, value.len());
+                entry.value = Object::String(appended.freeze());
+                entry.forced_raw = true;
+                entry.touch();
+
+                self.notify_event(pubsub::STRING, "append", occupied_entry.key());
+
+                Ok(length)
+            }
+            MapEntry::Vacant(vacant_entry) => {
+                let length = value.len() as i64;
+
+                self.notify_event(pubsub::STRING, "append", vacant_entry.key());
+
+                let entry = self.new_entry(vacant_entry.key(), Object::String(value));
+                vacant_entry.insert(entry);
+
+                Ok(length)
+            }
+        }
+    }
+
+    /// Checks that a string may grow to len bytes, see `proto-max-bulk-len`.
+    fn check_string_len(&self, len: usize) -> Result<(), Value> {
+        if len as u64 > self.inner.config.proto_max_bulk_len() {
+            Err(string_too_long_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Overwrites the string at key from offset on with value, padding it
+    /// with zero bytes if it is shorter. Nothing is written for an empty
+    /// value.
+    ///
+    /// Returns the length of the string afterwards.
+    pub fn setrange(&self, key: String, offset: usize, value: Bytes) -> Result<i64, Value> {
+        self.expire_if_needed(&key);
+
+        let end = offset.saturating_add(value.len());
+
+        let update = |current: &[u8]| {
+            let mut bytes = BytesMut::from(current);
+
+            if bytes.len() < end {
+                bytes.resize(end, 0);
+            }
+
+            bytes[offset..end].copy_from_slice(&value);
+
+            bytes.freeze()
+        };
+
+        let length = match self.inner.entries.entry(key.clone()) {
+            MapEntry::Occupied(mut occupied_entry) => {
+                let entry = occupied_entry.get_mut();
+
+                let current = match &entry.value {
+                    Object::String(current) => current,
+                    _ => return Err(wrong_type_error()),
+                };
+
+                if value.is_empty() {
+                    return Ok(current.len() as i64);
+                }
+
+                self.check_string_len(end)?;
+
+                let bytes = update(current);
+                let length = bytes.len();
+
+                self.resize_entry(entry, entry.value.size_estimate(), length);
+                entry.value = Object::String(bytes);
+                entry.forced_raw = true;
+                entry.touch();
+
+                length
+            }
+            MapEntry::Vacant(vacant_entry) => {
+                if value.is_empty() {
+                    return Ok(0);
+                }
+
+                self.check_string_len(end)?;
+
+                let bytes = update(&[]);
+                let length = bytes.len();
+
+                let mut entry = self.new_entry(vacant_entry.key(), Object::String(bytes));
+                entry.forced_raw = true;
+                vacant_entry.insert(entry);
+
+                length
+            }
+        };
+
+        self.notify_event(pubsub::STRING, "setrange", &key);
+
+        Ok(length as i64)
+    }
+
+    /// Returns the length in bytes of the string stored at key, 0 if missing.
+    pub fn strlen(&self, key: &str) -> Result<i64, Value> {
+        let bytes = self.get_typed(key, Object::as_string)?;
+
+        Ok(bytes.map_or(0, |bytes| bytes.len() as i64))
+    }
+
+    /// Sets or clears the bit at offset in the string at key, growing the
+    /// string with zero bytes as needed. Bits are counted from the most
+    /// significant bit of the first byte.
+    ///
+    /// Returns the previous bit or the error to reply with.
+    pub fn setbit(&self, key: String, offset: usize, bit: bool) -> Result<bool, Value> {
+        self.expire_if_needed(&key);
+
+        let index = offset / 8;
+
+        // The offset is limited by the longest string, like in Redis
+        if index as u64 >= self.inner.config.proto_max_bulk_len() {
+            return Err(Value::Error(RedisError {
+                message: String::from("ERR bit offset is not an integer or out of range"),
+            }));
+        }
+        let mask = 0x80 >> (offset % 8);
+
+        let update = |current: &[u8]| {
+            let mut bytes = BytesMut::from(current);
+
+            if bytes.len() <= index {
+                bytes.resize(index + 1, 0);
+            }
+
+            let previous = bytes[index] & mask != 0;
+
+            if bit {
+                bytes[index] |= mask;
+            } else {
+                bytes[index] &= !mask;
+            }
+
+            (bytes.freeze(), previous)
+        };
+
+        let previous = match self.inner.entries.entry(key.clone()) {
+            MapEntry::Occupied(mut occupied_entry) => {
+                let entry = occupied_entry.get_mut();
+
+                let (bytes, previous) = match &entry.value {
+                    Object::String(current) => update(current),
+                    _ => return Err(wrong_type_error()),
+                };
+
+                self.resize_entry(entry, entry.value.size_estimate(), bytes.len());
+                entry.value = Object::String(bytes);
+                entry.forced_raw = true;
+                entry.touch();
+
+                previous
+            }
+            MapEntry::Vacant(vacant_entry) => {
+                let (bytes, previous) = update(&[]);
+
+                let mut entry = self.new_entry(vacant_entry.key(), Object::String(bytes));
+                entry.forced_raw = true;
+                vacant_entry.insert(entry);
+
+                previous
+            }
+        };
+
+        self.notify_event(pubsub::STRING, "setbit", &key);
+
+        Ok(previous)
+    }
+
+    /// Returns the bit at offset in the string at key, 0 past its end or if
+    /// the key is missing.
+    pub fn getbit(&self, key: &str, offset: usize) -> Result<bool, Value> {
+        let bytes = self.get_typed(key, Object::as_string)?;
+
+        Ok(bytes.is_some_and(|bytes| {
+            bytes
+                .get(offset / 8)
+                .is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0)
+        }))
+    }
+
+    /// Counts the set bits in the string at key, optionally only within a
+    /// range of bytes or bits. Negative indices count from the end.
+    pub fn bitcount(&self, key: &str, range: Option<BitRange>) -> Result<i64, Value> {
+        let Some(bytes) = self.get_typed(key, Object::as_string)? else {
+            return Ok(0);
+        };
+
+        let Some(range) = range else {
+            return Ok(bytes.iter().map(|byte| byte.count_ones() as i64).sum());
+        };
+
+        let length = if range.in_bits {
+            bytes.len() as i64 * 8
+        } else {
+            bytes.len() as i64
+        };
+        let start = if range.start < 0 {
+            length + range.start
+        } else {
+            range.start
+        }
+        .max(0);
+        let end = if range.end < 0 {
+            length + range.end
+        } else {
+            range.end
+        }
+        .min(length - 1);
+
+        if start > end {
+            return Ok(0);
+        }
+
+        let (start, end) = (start as usize, end as usize);
+
+        let count = if range.in_bits {
+            (start..=end)
+                .filter(|bit| bytes[bit / 8] & (0x80 >> (bit % 8)) != 0)
+                .count() as i64
+        } else {
+            bytes[start..=end]
+                .iter()
+                .map(|byte| byte.count_ones() as i64)
+                .sum()
+        };
+
+        Ok(count)
+    }
+
+    /// Returns the type name of the value at key, `none` if missing.
+    pub fn type_of(&self, key: &str) -> &'static str {
+        self.expire_if_needed(key);
+
+        self.inner
+            .entries
+            .get(key)
+            .map_or("none", |entry| entry.value.type_name())
+    }
+
+    /// Returns the encoding Redis would use for the value at key.
+    pub fn object_encoding(&self, key: &str) -> Option<&'static str> {
+        self.expire_if_needed(key);
+
+        let list_limit = self.inner.config.list_max_listpack_size();
+
+        self.inner
+            .entries
+            .get(key)
+            .map(|entry| entry.encoding(list_limit))
+    }
+
+    /// Returns the seconds since key was last read or written to, without
+    /// counting this as an access.
+    pub fn idle_time(&self, key: &str) -> Option<u64> {
+        self.expire_if_needed(key);
+
+        self.inner.entries.get(key).map(|entry| {
+            let last_access = entry.last_access.load(Ordering::Relaxed);
+
+            lru_clock().saturating_sub(last_access) / 1000
+        })
+    }
+
+    /// Returns the approximate bytes the key and its value take, see
+    /// [`Object::size_estimate`].
+    pub fn memory_usage(&self, key: &str) -> Option<usize> {
+        self.expire_if_needed(key);
+
+        self.inner
+            .entries
+            .get(key)
+            .map(|entry| KEY_OVERHEAD + key.len() + entry.value.size_estimate())
+    }
+
+    /// Pushes the elements one after another to the head of the list at
+    /// key, creating it if missing.
+    ///
+    /// Returns the new length or the error to reply with.
+    pub fn lpush(&self, key: String, elements: Vec<String>) -> Result<i64, Value> {
+        self.push(key, elements, true)
+    }
+
+    /// Pushes the elements to the tail of the list at key, creating it if
+    /// missing.
+    ///
+    /// Returns the new length or the error to reply with.
+    pub fn rpush(&self, key: String, elements: Vec<String>) -> Result<i64, Value> {
+        self.push(key, elements, false)
+    }
+
+    fn push(&self, key: String, elements: Vec<String>, to_head: bool) -> Result<i64, Value> {
+        self.expire_if_needed(&key);
+
+        let added = elements.iter().map(|element| element_size(element)).sum();
+
+        let extend = |list: &mut VecDeque<String>| {
+            for element in elements {
+                if to_head {
+                    list.push_front(element);
+                } else {
+                    list.push_back(element);
+                }
+            }
+
+            list.len() as i64
+        };
+
+        let length = match self.inner.entries.entry(key.clone()) {
+            MapEntry::Occupied(mut occupied_entry) => {
+                let entry = occupied_entry.get_mut();
+
+                let length = match &mut entry.value {
+                    Object::List(list) => extend(list),
+                    _ => return Err(wrong_type_error()),
+                };
+
+                self.resize_entry(entry, 0, added);
+                entry.touch();
+
+                length
+            }
+            MapEntry::Vacant(vacant_entry) => {
+                let mut list = VecDeque::new();
+                let length = extend(&mut list);
+
+                let entry = self.new_entry(vacant_entry.key(), Object::List(list));
+                vacant_entry.insert(entry);
+
+                length
+            }
+        };
+
+        let event = if to_head { "lpush" } else { "rpush" };
+        self.notify_event(pubsub::LIST, event, &key);
+        self.wake_one(&key);
+
+        Ok(length)
+    }
+
+    /// Removes up to count elements from the head of the list at key.
+    /// Empty lists are removed.
+    ///
+    /// Returns `None` if the key is missing.
+    pub fn lpop(&self, key: &str, count: usize) -> Result<Option<Vec<String>>, Value> {
+        self.pop(key, count, true)
+    }
+
+    /// Removes up to count elements from the tail of the list at key.
+    /// Empty lists are removed.
+    ///
+    /// Returns `None` if the key is missing.
+    pub fn rpop(&self, key: &str, count: usize) -> Result<Option<Vec<String>>, Value> {
+        self.pop(key, count, false)
+    }
+
+    fn pop(&self, key: &str, count: usize, from_head: bool) -> Result<Option<Vec<String>>, Value> {
+        self.expire_if_needed(key);
+
+        let mut occupied_entry = match self.inner.entries.entry(key.to_string()) {
+            MapEntry::Occupied(occupied_entry) => occupied_entry,
+            MapEntry::Vacant(_) => return Ok(None),
+        };
+
+        let list = match &mut occupied_entry.get_mut().value {
+            Object::List(list) => list,
+            _ => return Err(wrong_type_error()),
+        };
+
+        let count = count.min(list.len());
+        let elements: Vec<String> = if from_head {
+            list.drain(..count).collect()
+        } else {
+            list.drain(list.len() - count..).rev().collect()
+        };
+
+        if count > 0 {
+            let event = if from_head { "lpop" } else { "rpop" };
+            self.notify_event(pubsub::LIST, event, key);
+        }
+
+        if list.is_empty() {
+            let entry = occupied_entry.remove();
+
+            self.forget_entry(&entry);
+            self.cancel_expiration(&entry);
+            self.notify_event(pubsub::GENERIC, "del", key);
+        } else if count > 0 {
+            let removed = elements.iter().map(|element| element_size(element)).sum();
+
+            let entry = occupied_entry.get_mut();
+            self.resize_entry(entry, removed, 0);
+            entry.touch();
+        }
+
+        Ok(Some(elements))
+    }
+
+    /// Pops an element from the first non-empty list of the keys, waiting
+    /// for one to be pushed if all of them are empty. Clients are served in
+    /// the order they started waiting.
+    ///
+    /// Returns the key and the element, `None` on timeout or the error to
+    /// reply with.
+    pub async fn blocking_pop(
+        &self,
+        keys: &[String],
+        timeout: Option<Duration>,
+        from_head: bool,
+    ) -> Result<Option<(String, String)>, Value> {
+        let result = self
+            .block_on_keys(keys, timeout, || {
+                for key in keys {
+                    match self.pop(key, 1, from_head) {
+                        Ok(Some(mut elements)) => {
+                            // Pushing several elements wakes a single client,
+                            // which passes it on to the next one
+                            if self.inner.entries.contains_key(key) {
+                                self.wake_one(key);
+                            }
+
+                            return Some(Ok((key.clone(), elements.remove(0))));
+                        }
+                        Ok(None) => {}
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+
+                None
+            })
+            .await;
+
+        result.transpose()
+    }
+
+    /// Returns the length of the list at key, 0 if missing.
+    pub fn llen(&self, key: &str) -> Result<i64, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::List(list)) => Ok(list.len() as i64),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns the elements of the list at key between the inclusive
+    /// indices, which count from the tail if negative.
+    pub fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<String>, Value> {
+        self.expire_if_needed(key);
+
+        let entry = self.inner.entries.get(key);
+
+        let list = match entry.as_deref().map(|entry| &entry.value) {
+            Some(Object::List(list)) => list,
+            Some(_) => return Err(wrong_type_error()),
+            None => return Ok(Vec::new()),
+        };
+
+        let length = list.len() as i64;
+        let start = if start < 0 { length + start } else { start }.max(0);
+        let stop = if stop < 0 { length + stop } else { stop }.min(length - 1);
+
+        if start > stop {
+            return Ok(Vec::new());
+        }
+
+        let elements = list
+            .range(start as usize..=stop as usize)
+            .cloned()
+            .collect();
+
+        Ok(elements)
+    }
+
+    /// Sets the fields of the hash at key, creating it if missing.
+    ///
+    /// Returns the number of fields that were added rather than updated.
+    pub fn hset(&self, key: String, pairs: Vec<(String, String)>) -> Result<i64, Value> {
+        self.expire_if_needed(&key);
+
+        let mut occupied_entry = match self.inner.entries.entry(key) {
+            MapEntry::Occupied(occupied_entry) => occupied_entry,
+            MapEntry::Vacant(vacant_entry) => {
+                let hash: HashMap<String, String> = pairs.into_iter().collect();
+                let added = hash.len() as i64;
+
+                self.notify_event(pubsub::HASH, "hset", vacant_entry.key());
+
+                let entry = self.new_entry(vacant_entry.key(), Object::Hash(hash));
+                vacant_entry.insert(entry);
+
+                return Ok(added);
+            }
+        };
+
+        let entry = occupied_entry.get_mut();
+
+        let hash = match &mut entry.value {
+            Object::Hash(hash) => hash,
+            _ => return Err(wrong_type_error()),
+        };
+
+        let mut added = 0;
+        let mut removed_size = 0;
+        let mut added_size = 0;
+
+        for (field, value) in pairs {
+            let field_size = element_size(&field);
+            added_size += field_size + element_size(&value);
+
+            match hash.insert(field, value) {
+                Some(old) => removed_size += field_size + element_size(&old),
+                None => added += 1,
+            }
+        }
+
+        self.resize_entry(entry, removed_size, added_size);
+        entry.touch();
+        self.notify_event(pubsub::HASH, "hset", occupied_entry.key());
+
+        Ok(added)
+    }
+
+    /// Returns the value of the field in the hash at key.
+    pub fn hget(&self, key: &str, field: &str) -> Result<Option<String>, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::Hash(hash)) => Ok(hash.get(field).cloned()),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the fields from the hash at key. Empty hashes are removed.
+    ///
+    /// Returns the number of removed fields.
+    pub fn hdel(&self, key: &str, fields: &[String]) -> Result<i64, Value> {
+        self.expire_if_needed(key);
+
+        let mut occupied_entry = match self.inner.entries.entry(key.to_string()) {
+            MapEntry::Occupied(occupied_entry) => occupied_entry,
+            MapEntry::Vacant(_) => return Ok(0),
+        };
+
+        let hash = match &mut occupied_entry.get_mut().value {
+            Object::Hash(hash) => hash,
+            _ => return Err(wrong_type_error()),
+        };
+
+        let mut removed = 0;
+        let mut removed_size = 0;
+
+        for field in fields {
+            if let Some(value) = hash.remove(field.as_str()) {
+                removed += 1;
+                removed_size += element_size(field) + element_size(&value);
+            }
+        }
+
+        if removed > 0 {
+            self.notify_event(pubsub::HASH, "hdel", key);
+        }
+
+        if hash.is_empty() {
+            let entry = occupied_entry.remove();
+
+            self.forget_entry(&entry);
+            self.cancel_expiration(&entry);
+            self.notify_event(pubsub::GENERIC, "del", key);
+        } else if removed > 0 {
+            let entry = occupied_entry.get_mut();
+            self.resize_entry(entry, removed_size, 0);
+            entry.touch();
+        }
+
+        Ok(removed as i64)
+    }
+
+    /// Returns all fields and values of the hash at key.
+    pub fn hgetall(&self, key: &str) -> Result<Vec<(String, String)>, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::Hash(hash)) => Ok(hash
+                .iter()
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect()),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Scans the fields of the hash at key, see [`scan_elements`].
+    pub fn hscan(
+        &self,
+        key: &str,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(u64, Vec<(String, String)>), Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::Hash(hash)) => {
+                let pairs = hash
+                    .iter()
+                    .map(|(field, value)| (field, (field.clone(), value.clone())));
+
+                Ok(scan_elements(pairs, cursor, pattern, count))
+            }
+            Some(_) => Err(wrong_type_error()),
+            None => Ok((0, Vec::new())),
+        }
+    }
+
+    /// Adds the members to the set at key, creating it if missing.
+    ///
+    /// Returns the number of members that were not in the set yet.
+    pub fn sadd(&self, key: String, members: Vec<String>) -> Result<i64, Value> {
+        self.expire_if_needed(&key);
+
+        let mut occupied_entry = match self.inner.entries.entry(key) {
+            MapEntry::Occupied(occupied_entry) => occupied_entry,
+            MapEntry::Vacant(vacant_entry) => {
+                let set: HashSet<String> = members.into_iter().collect();
+                let added = set.len() as i64;
+
+                self.notify_event(pubsub::SET, "sadd", vacant_entry.key());
+
+                let entry = self.new_entry(vacant_entry.key(), Object::Set(set));
+                vacant_entry.insert(entry);
+
+                return Ok(added);
+            }
+        };
+
+        let entry = occupied_entry.get_mut();
+
+        let set = match &mut entry.value {
+            Object::Set(set) => set,
+            _ => return Err(wrong_type_error()),
+        };
+
+        let mut added = 0;
+        let mut added_size = 0;
+
+        for member in members {
+            let size = element_size(&member);
+
+            if set.insert(member) {
+                added += 1;
+                added_size += size;
+            }
+        }
+
+        if added > 0 {
+            self.resize_entry(entry, 0, added_size);
+            entry.touch();
+            self.notify_event(pubsub::SET, "sadd", occupied_entry.key());
+        }
+
+        Ok(added)
+    }
+
+    /// Removes the members from the set at key. Empty sets are removed.
+    ///
+    /// Returns the number of removed members.
+    pub fn srem(&self, key: &str, members: &[String]) -> Result<i64, Value> {
+        self.expire_if_needed(key);
+
+        let mut occupied_entry = match self.inner.entries.entry(key.to_string()) {
+            MapEntry::Occupied(occupied_entry) => occupied_entry,
+            MapEntry::Vacant(_) => return Ok(0),
+        };
+
+        let set = match &mut occupied_entry.get_mut().value {
+            Object::Set(set) => set,
+            _ => return Err(wrong_type_error()),
+        };
+
+        let removed: Vec<&String> = members
+            .iter()
+            .filter(|member| set.remove(member.as_str()))
+            .collect();
+
+        if !removed.is_empty() {
+            self.notify_event(pubsub::SET, "srem", key);
+        }
+
+        if set.is_empty() {
+            let entry = occupied_entry.remove();
+
+            self.forget_entry(&entry);
+            self.cancel_expiration(&entry);
+            self.notify_event(pubsub::GENERIC, "del", key);
+        } else if !removed.is_empty() {
+            let removed_size = removed.iter().map(|member| element_size(member)).sum();
+
+            let entry = occupied_entry.get_mut();
+            self.resize_entry(entry, removed_size, 0);
+            entry.touch();
+        }
+
+        Ok(removed.len() as i64)
+    }
+
+    /// Returns whether the member is in the set at key.
+    pub fn sismember(&self, key: &str, member: &str) -> Result<bool, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::Set(set)) => Ok(set.contains(member)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the number of members of the set at key, 0 if missing.
+    pub fn scard(&self, key: &str) -> Result<i64, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::Set(set)) => Ok(set.len() as i64),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(0),
+        }
+    }
+
+    /// Removes up to count random members from the set at key. Empty sets
+    /// are removed.
+    ///
+    /// Returns `None` if the key is missing.
+    pub fn spop(&self, key: &str, count: usize) -> Result<Option<Vec<String>>, Value> {
+        self.expire_if_needed(key);
+
+        let mut occupied_entry = match self.inner.entries.entry(key.to_string()) {
+            MapEntry::Occupied(occupied_entry) => occupied_entry,
+            MapEntry::Vacant(_) => return Ok(None),
+        };
+
+        let set = match &mut occupied_entry.get_mut().value {
+            Object::Set(set) => set,
+            _ => return Err(wrong_type_error()),
+        };
+
+        let popped: Vec<String> = if count >= set.len() {
+            set.drain().collect()
+        } else {
+            // A partial Fisher-Yates shuffle picks the members
+            let mut members: Vec<&String> = set.iter().collect();
+            let mut rng = Rng::new();
+
+            for i in 0..count {
+                let j = i + rng.below(members.len() - i);
+                members.swap(i, j);
+            }
+
+            let popped: Vec<String> = members[..count]
+                .iter()
+                .map(|member| (*member).clone())
+                .collect();
+
+            for member in &popped {
+                set.remove(member);
+            }
+
+            popped
+        };
+
+        if !popped.is_empty() {
+            self.notify_event(pubsub::SET, "spop", key);
+        }
+
+        if set.is_empty() {
+            let entry = occupied_entry.remove();
+
+            self.forget_entry(&entry);
+            self.cancel_expiration(&entry);
+            self.notify_event(pubsub::GENERIC, "del", key);
+        } else if !popped.is_empty() {
+            let removed_size = popped.iter().map(|member| element_size(member)).sum();
+
+            let entry = occupied_entry.get_mut();
+            self.resize_entry(entry, removed_size, 0);
+            entry.touch();
+        }
+
+        Ok(Some(popped))
+    }
+
+    /// Returns all members of the set at key.
+    pub fn smembers(&self, key: &str) -> Result<Vec<String>, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::Set(set)) => Ok(set.iter().cloned().collect()),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Scans the members of the set at key, see [`scan_elements`].
+    pub fn sscan(
+        &self,
+        key: &str,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(u64, Vec<String>), Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::Set(set)) => {
+                let members = set.iter().map(|member| (member, member.clone()));
+
+                Ok(scan_elements(members, cursor, pattern, count))
+            }
+            Some(_) => Err(wrong_type_error()),
+            None => Ok((0, Vec::new())),
+        }
+    }
+
+    /// Adds the members with their scores to the sorted set at key, creating
+    /// it if missing unless only existing members are updated.
+    ///
+    /// Returns the number of added members and the number of members whose
+    /// score changed.
+    pub fn zadd(
+        &self,
+        key: String,
+        members: Vec<(f64, String)>,
+        condition: AddCondition,
+        update: ScoreUpdate,
+    ) -> Result<(i64, i64), Value> {
+        self.expire_if_needed(&key);
+
+        let mut occupied_entry = match self.inner.entries.entry(key) {
+            MapEntry::Occupied(occupied_entry) => occupied_entry,
+            MapEntry::Vacant(_) if matches!(condition, AddCondition::OnlyExisting) => {
+                return Ok((0, 0));
+            }
+            MapEntry::Vacant(vacant_entry) => {
+                let mut zset = SortedSet::new();
+
+                for (score, member) in members {
+                    zset.add(member, score, condition, update);
+                }
+
+                let added = zset.len() as i64;
+
+                self.notify_event(pubsub::ZSET, "zadd", vacant_entry.key());
+
+                let entry = self.new_entry(vacant_entry.key(), Object::SortedSet(zset));
+                vacant_entry.insert(entry);
+
+                return Ok((added, 0));
+            }
+        };
+
+        let entry = occupied_entry.get_mut();
+
+        let zset = match &mut entry.value {
+            Object::SortedSet(zset) => zset,
+            _ => return Err(wrong_type_error()),
+        };
+
+        let mut added = 0;
+        let mut updated = 0;
+        let mut added_size = 0;
+
+        for (score, member) in members {
+            let size = member_size(&member);
+
+            match zset.add(member, score, condition, update) {
+                AddOutcome::Added => {
+                    added += 1;
+                    added_size += size;
+                }
+                AddOutcome::Updated => updated += 1,
+                AddOutcome::Unchanged => {}
+            }
+        }
+
+        if added > 0 || updated > 0 {
+            self.resize_entry(entry, 0, added_size);
+            entry.touch();
+            self.notify_event(pubsub::ZSET, "zadd", occupied_entry.key());
+        }
+
+        Ok((added, updated))
+    }
+
+    /// Returns the score of the member of the sorted set at key.
+    pub fn zscore(&self, key: &str, member: &str) -> Result<Option<f64>, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::SortedSet(zset)) => Ok(zset.score(member)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the scores of the members of the sorted set at key, none for
+    /// missing members.
+    pub fn zmscore(&self, key: &str, members: &[String]) -> Result<Vec<Option<f64>>, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::SortedSet(zset)) => {
+                Ok(members.iter().map(|member| zset.score(member)).collect())
+            }
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(vec![None; members.len()]),
+        }
+    }
+
+    /// Returns the members of the geospatial index at key within the shape
+    /// around the center, in the order of their geohashes. Every member is
+    /// checked, rather than only those of the geohash cells around the
+    /// center.
+    pub fn geosearch(
+        &self,
+        key: &str,
+        center: &Center,
+        shape: Shape,
+    ) -> Result<Vec<Neighbor>, Value> {
+        self.expire_if_needed(key);
+
+        let entry = self.inner.entries.get(key);
+
+        let zset = match entry.as_deref().map(|entry| &entry.value) {
+            Some(Object::SortedSet(zset)) => zset,
+            Some(_) => return Err(wrong_type_error()),
+            None => return Ok(Vec::new()),
+        };
+
+        let center = match center {
+            Center::Member(member) => match zset.score(member) {
+                Some(score) => geo::decode(score as u64),
+                None => {
+                    return Err(Value::Error(RedisError {
+                        message: String::from("ERR could not decode requested zset member"),
+                    }))
+                }
+            },
+            Center::Position {
+                longitude,
+                latitude,
+            } => (*longitude, *latitude),
+        };
+
+        let neighbors = zset
+            .iter()
+            .filter_map(|(member, score)| {
+                let hash = score as u64;
+                let distance = shape.distance_within(center, geo::decode(hash))?;
+
+                Some(Neighbor {
+                    member: member.clone(),
+                    hash,
+                    distance,
+                })
+            })
+            .collect();
+
+        Ok(neighbors)
+    }
+
+    /// Returns the number of members of the sorted set at key, 0 if missing.
+    pub fn zcard(&self, key: &str) -> Result<i64, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::SortedSet(zset)) => Ok(zset.len() as i64),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns the members and their scores of the sorted set at key between
+    /// the ranks start and stop, both inclusive and counted from the highest
+    /// score if reversed. Negative ranks count from the end.
+    pub fn zrange(
+        &self,
+        key: &str,
+        start: i64,
+        stop: i64,
+        reverse: bool,
+    ) -> Result<Vec<(String, f64)>, Value> {
+        self.expire_if_needed(key);
+
+        let entry = self.inner.entries.get(key);
+
+        let zset = match entry.as_deref().map(|entry| &entry.value) {
+            Some(Object::SortedSet(zset)) => zset,
+            Some(_) => return Err(wrong_type_error()),
+            None => return Ok(Vec::new()),
+        };
+
+        let length = zset.len() as i64;
+        let start = if start < 0 { length + start } else { start }.max(0);
+        let stop = if stop < 0 { length + stop } else { stop }.min(length - 1);
+
+        if start > stop {
+            return Ok(Vec::new());
+        }
+
+        let members: Box<dyn Iterator<Item = (&String, f64)>> = if reverse {
+            Box::new(zset.iter().rev())
+        } else {
+            Box::new(zset.iter())
+        };
+
+        let members = members
+            .skip(start as usize)
+            .take((stop - start + 1) as usize)
+            .map(|(member, score)| (member.clone(), score))
+            .collect();
+
+        Ok(members)
+    }
+
+    /// Returns the members and their scores of the sorted set at key within
+    /// the bounds, skipping offset members and returning at most count.
+    pub fn zrangebyscore(
+        &self,
+        key: &str,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        offset: usize,
+        count: Option<usize>,
+    ) -> Result<Vec<(String, f64)>, Value> {
+        self.expire_if_needed(key);
+
+        let entry = self.inner.entries.get(key);
+
+        let zset = match entry.as_deref().map(|entry| &entry.value) {
+            Some(Object::SortedSet(zset)) => zset,
+            Some(_) => return Err(wrong_type_error()),
+            None => return Ok(Vec::new()),
+        };
+
+        let members = zset
+            .range_by_score(min, max)
+            .skip(offset)
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(member, score)| (member.clone(), score))
+            .collect();
+
+        Ok(members)
+    }
+
+    /// Adds the increment to the score of the member of the sorted set at
+    /// key. Missing members and keys start at 0.
+    ///
+    /// Returns the new score or the error to reply with.
+    pub fn zincrby(&self, key: String, increment: f64, member: String) -> Result<f64, Value> {
+        self.expire_if_needed(&key);
+
+        let mut occupied_entry = match self.inner.entries.entry(key) {
+            MapEntry::Occupied(occupied_entry) => occupied_entry,
+            MapEntry::Vacant(vacant_entry) => {
+                let mut zset = SortedSet::new();
+                zset.add(member, increment, AddCondition::Always, ScoreUpdate::Always);
+
+                self.notify_event(pubsub::ZSET, "zincr", vacant_entry.key());
+
+                let entry = self.new_entry(vacant_entry.key(), Object::SortedSet(zset));
+                vacant_entry.insert(entry);
+
+                return Ok(increment);
+            }
+        };
+
+        let entry = occupied_entry.get_mut();
+
+        let zset = match &mut entry.value {
+            Object::SortedSet(zset) => zset,
+            _ => return Err(wrong_type_error()),
+        };
+
+        // Adding opposite infinities is the only way to end up with NaN
+        let score = zset.score(&member).unwrap_or(0.0) + increment;
+
+        if score.is_nan() {
+            return Err(Value::Error(RedisError {
+                message: String::from("ERR resulting score is not a number (NaN)"),
+            }));
+        }
+
+        let size = member_size(&member);
+
+        if zset.add(member, score, AddCondition::Always, ScoreUpdate::Always) == AddOutcome::Added {
+            self.resize_entry(entry, 0, size);
+        }
+
+        entry.touch();
+        self.notify_event(pubsub::ZSET, "zincr", occupied_entry.key());
+
+        Ok(score)
+    }
+
+    /// Appends an entry to the stream at key, creating it if missing, and
+    /// trims the stream afterwards.
+    ///
+    /// Returns the ID of the new entry or the error to reply with.
+    pub fn xadd(
+        &self,
+        key: String,
+        id: IdSpec,
+        fields: Vec<(String, String)>,
+        trim: Option<TrimStrategy>,
+    ) -> Result<StreamId, Value> {
+        self.expire_if_needed(&key);
+
+        let added = stream::entry_size(&fields);
+        let trimmed = trim.is_some();
+
+        let add = |stream: &mut Stream| {
+            let id = stream.add(id, fields)?;
+
+            if let Some(strategy) = &trim {
+                stream.trim(strategy);
+            }
+
+            Ok(id)
+        };
+
+        let result = match self.inner.entries.entry(key.clone()) {
+            MapEntry::Occupied(mut occupied_entry) => {
+                let entry = occupied_entry.get_mut();
+
+                let result = match &mut entry.value {
+                    Object::Stream(stream) => add(stream),
+                    _ => Err(wrong_type_error()),
+                };
+
+                if result.is_ok() {
+                    if trimmed {
+                        self.recount_entry(&key, entry);
+                    } else {
+                        self.resize_entry(entry, 0, added);
+                    }
+
+                    entry.touch();
+                }
+
+                result
+            }
+            MapEntry::Vacant(vacant_entry) => {
+                let mut stream = Stream::new();
+                let id = add(&mut stream)?;
+
+                let entry = self.new_entry(vacant_entry.key(), Object::Stream(stream));
+                vacant_entry.insert(entry);
+
+                Ok(id)
+            }
+        };
+
+        if result.is_ok() {
+            self.notify_event(pubsub::STREAM, "xadd", &key);
+            self.notify_key(&key);
+        }
+
+        result
+    }
+
+    /// Trims the stream at key.
+    ///
+    /// Returns the number of removed entries, 0 if the key is missing.
+    pub fn xtrim(&self, key: &str, strategy: &TrimStrategy) -> Result<i64, Value> {
+        self.expire_if_needed(key);
+
+        let mut entry = match self.inner.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => return Ok(0),
+        };
+
+        let removed = match &mut entry.value {
+            Object::Stream(stream) => stream.trim(strategy),
+            _ => return Err(wrong_type_error()),
+        };
+
+        if removed > 0 {
+            self.recount_entry(key, &mut entry);
+            entry.touch();
+            self.notify_event(pubsub::STREAM, "xtrim", key);
+        }
+
+        Ok(removed as i64)
+    }
+
+    /// Returns the number of entries in the stream at key, 0 if missing.
+    pub fn xlen(&self, key: &str) -> Result<i64, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::Stream(stream)) => Ok(stream.len() as i64),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns the entries of the stream at key between start and end.
+    pub fn xrange(
+        &self,
+        key: &str,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::Stream(stream)) => Ok(stream.range(start, end, count)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Replaces the `$` IDs with the ID of the last entry of each stream, so
+    /// that a blocking read only returns entries added after it started.
+    pub fn resolve_last_ids(
+        &self,
+        streams: Vec<(String, Option<StreamId>)>,
+    ) -> Vec<(String, Option<StreamId>)> {
+        streams
+            .into_iter()
+            .map(|(key, id)| {
+                self.expire_if_needed(&key);
+
+                let id = id.or_else(|| {
+                    match self
+                        .inner
+                        .entries
+                        .get(&key)
+                        .as_deref()
+                        .map(|entry| &entry.value)
+                    {
+                        Some(Object::Stream(stream)) => Some(stream.last_id()),
+                        // Wrong types are reported by the read itself
+                        Some(_) => None,
+                        None => Some(StreamId::MIN),
+                    }
+                });
+
+                (key, id)
+            })
+            .collect()
+    }
+
+    /// Returns the entries after the given ID of each stream, skipping
+    /// streams without any. An ID of `None` stands for the last entry.
+    pub fn xread(
+        &self,
+        streams: &[(String, Option<StreamId>)],
+        count: Option<usize>,
+    ) -> Result<Vec<(String, Vec<StreamEntry>)>, Value> {
+        let mut results = Vec::new();
+
+        for (key, id) in streams {
+            self.expire_if_needed(key);
+
+            let entry = match self.inner.entries.get(key) {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            let stream = match &entry.value {
+                Object::Stream(stream) => stream,
+                _ => return Err(wrong_type_error()),
+            };
+
+            let after = id.unwrap_or_else(|| stream.last_id());
+            let entries = stream.range(Bound::Excluded(after), Bound::Unbounded, count);
+
+            if !entries.is_empty() {
+                results.push((key.clone(), entries));
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Creates a consumer group on the stream at key. With mkstream a missing
+    /// stream is created empty.
+    pub fn xgroup_create(
+        &self,
+        key: String,
+        group: String,
+        id: Option<StreamId>,
+        mkstream: bool,
+    ) -> Result<(), Value> {
+        self.expire_if_needed(&key);
+
+        match self.inner.entries.entry(key) {
+            MapEntry::Occupied(mut occupied_entry) => {
+                let entry = occupied_entry.get_mut();
+
+                match &mut entry.value {
+                    Object::Stream(stream) => stream.create_group(group, id)?,
+                    _ => return Err(wrong_type_error()),
+                }
+
+                entry.touch();
+
+                Ok(())
+            }
+            MapEntry::Vacant(vacant_entry) if mkstream => {
+                let mut stream = Stream::new();
+                stream.create_group(group, id)?;
+
+                let entry = self.new_entry(vacant_entry.key(), Object::Stream(stream));
+                vacant_entry.insert(entry);
+
+                Ok(())
+            }
+            MapEntry::Vacant(_) => Err(Value::Error(RedisError {
+                message: String::from("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically."),
+            })),
+        }
+    }
+
+    /// Reads entries of each stream for a consumer of the group, see
+    /// [`Stream::read_group`]. Streams without new entries are skipped.
+    pub fn xreadgroup(
+        &self,
+        group: &str,
+        consumer: &str,
+        streams: &[(String, Option<StreamId>)],
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Vec<(String, Vec<StreamEntry>)>, Value> {
+        let no_group_error = |key: &str| {
+            Value::Error(RedisError {
+                message: format!("NOGROUP No such key '{key}' or consumer group '{group}' in XREADGROUP with GROUP option"),
+            })
+        };
+
+        // All groups are checked before reading from any of them
+        for (key, _) in streams {
+            self.expire_if_needed(key);
+
+            match self
+                .inner
+                .entries
+                .get(key)
+                .as_deref()
+                .map(|entry| &entry.value)
+            {
+                Some(Object::Stream(stream)) if stream.has_group(group) => {}
+                Some(Object::Stream(_)) | None => return Err(no_group_error(key)),
+                Some(_) => return Err(wrong_type_error()),
+            }
+        }
+
+        let mut results = Vec::new();
+
+        for (key, id) in streams {
+            let mut entry = match self.inner.entries.get_mut(key) {
+                Some(entry) => entry,
+                None => return Err(no_group_error(key)),
+            };
+
+            let entries = match &mut entry.value {
+                Object::Stream(stream) => stream.read_group(group, consumer, *id, count, noack),
+                _ => return Err(wrong_type_error()),
+            };
+            let entries = entries.ok_or_else(|| no_group_error(key))?;
+
+            // Pending entries are always reported, even if there are none
+            if id.is_some() || !entries.is_empty() {
+                results.push((key.clone(), entries));
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Acknowledges entries of the group on the stream at key.
+    ///
+    /// Returns how many entries were pending, 0 if the key is missing.
+    pub fn xack(&self, key: &str, group: &str, ids: &[StreamId]) -> Result<i64, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get_mut(key)
+            .as_deref_mut()
+            .map(|entry| &mut entry.value)
+        {
+            Some(Object::Stream(stream)) => Ok(stream.ack(group, ids) as i64),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns the pending entries of the group on the stream at key.
+    pub fn xpending(
+        &self,
+        key: &str,
+        group: &str,
+        filter: &PendingFilter,
+    ) -> Result<Vec<PendingInfo>, Value> {
+        self.expire_if_needed(key);
+
+        match self
+            .inner
+            .entries
+            .get(key)
+            .as_deref()
+            .map(|entry| &entry.value)
+        {
+            Some(Object::Stream(stream)) => stream.pending(group, filter),
+            Some(_) => return Err(wrong_type_error()),
+            None => None,
+        }
+        .ok_or_else(|| {
+            Value::Error(RedisError {
+                message: format!("NOGROUP No such key '{key}' or consumer group '{group}'"),
+            })
+        })
+    }
+
+    /// Returns all keys matching the glob-style pattern.
+    pub fn keys(&self, pattern: &str) -> Vec<String> {
+        // Iterating holds the shard locks, so only collect the keys
+        // and match them afterwards
+        let keys: Vec<String> = self
+            .inner
+            .entries
+            .iter()
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        let glob = Glob::new(pattern.as_bytes());
+
+        keys.into_iter()
+            .filter(|key| glob.matches(key.as_bytes()))
+            .collect()
+    }
+
+    /// Returns about count keys matching the glob-style pattern, starting at
+    /// the cursor, and the cursor to continue with or 0 once all keys were
+    /// scanned.
+    ///
+    /// Keys are scanned in the order of their hashes rotated so that the
+    /// shard index is in the top bits. The cursor is the next hash to scan, so
+    /// keys that exist during the whole scan are returned exactly once even
+    /// if others are added or removed in between.
+    pub fn scan(&self, cursor: u64, pattern: Option<&str>, count: usize) -> (u64, Vec<String>) {
+        let entries = &self.inner.entries;
+        let order = |key: &String| (entries.hash_usize(key) as u64).rotate_left(7);
+
+        let shards = entries.shards();
+        let shard_bits = shards.len().trailing_zeros();
+        let first_shard = cursor.checked_shr(u64::BITS - shard_bits).unwrap_or(0) as usize;
+
+        let mut scanned: Vec<(u64, String)> = Vec::with_capacity(count);
+
+        for shard in &shards[first_shard..] {
+            let mut keys: Vec<(u64, String)> = shard
+                .read()
+                .keys()
+                .map(|key| (order(key), key))
+                .filter(|(hash, _)| *hash >= cursor)
+                .map(|(hash, key)| (hash, key.clone()))
+                .collect();
+            keys.sort_unstable();
+
+            for (hash, key) in keys {
+                // Keys with the same hash are never split across calls
+                let done =
+                    scanned.len() >= count && scanned.last().is_some_and(|(last, _)| *last != hash);
+
+                if done {
+                    break;
+                }
+
+                scanned.push((hash, key));
+            }
+
+            if scanned.len() >= count {
+                break;
+            }
+        }
+
+        let next_cursor = match scanned.last() {
+            Some((hash, _)) if scanned.len() >= count => hash.wrapping_add(1),
+            _ => 0,
+        };
+
+        let glob = pattern.map(|pattern| Glob::new(pattern.as_bytes()));
+        let keys = scanned
+            .into_iter()
+            .map(|(_, key)| key)
+            .filter(|key| {
+                glob.as_ref()
+                    .is_none_or(|glob| glob.matches(key.as_bytes()))
+            })
+            .filter(|key| {
+                self.expire_if_needed(key);
+                entries.contains_key(key)
+            })
+            .collect();
+
+        (next_cursor, keys)
+    }
+
+    /// Returns a uniformly random key, `None` if there are none.
+    pub fn random_key(&self) -> Option<String> {
+        // Expired keys are removed and another one is picked, up to a limit
+        // in case most keys expired
+        for _ in 0..100 {
+            let key = self.random_key_including_expired()?;
+            self.expire_if_needed(&key);
+
+            if self.inner.entries.contains_key(&key) {
+                return Some(key);
+            }
+        }
+
+        None
+    }
+
+    fn random_key_including_expired(&self) -> Option<String> {
+        // Read locks are taken in ascending order like write locks in
+        // set_many, so the shard sizes stay consistent with the total
+        let shards: Vec<_> = self
+            .inner
+            .entries
+            .shards()
+            .iter()
+            .map(|shard| shard.read())
+            .collect();
+
+        let total = shards.iter().map(|shard| shard.len()).sum();
+
+        if total == 0 {
+            return None;
+        }
+
+        let mut idx = Rng::new().below(total);
+
+        for shard in &shards {
+            if idx < shard.len() {
+                return shard.keys().nth(idx).cloned();
+            }
+
+            idx -= shard.len();
+        }
+
+        None
+    }
+
+    /// Returns the number of keys, counted across all shards.
+    pub fn len(&self) -> usize {
+        self.inner.entries.len()
+    }
+
+    /// Returns the number of keys with an expiration.
+    pub fn expires_count(&self) -> usize {
+        self.inner
+            .entries
+            .iter()
+            .filter(|entry| entry.expires_at.is_some())
+            .count()
+    }
+
+    /// Removes all keys and their pending expirations.
+    pub fn flush(&self) {
+        // Expirations are scheduled while holding the shard lock of the key,
+        // so holding all of them orders the clear after every expiration of
+        // a removed key and before any of a new one
+        let mut shards: Vec<_> = self
+            .inner
+            .entries
+            .shards()
+            .iter()
+            .map(|shard| shard.write())
+            .collect();
+
+        for shard in &mut shards {
+            shard.clear();
+        }
+
+        self.inner.used_memory.store(0, Ordering::Relaxed);
+
+        for task in 0..self.inner.expiration_tasks.len() {
+            self.update_expirations(task, ExpirationUpdate::Clear);
+        }
     }
 
+    /// Removes key once its timer fired. A key that was written in the
+    /// meantime might not have expired anymore, so it is kept then.
     pub fn remove_raw(&self, key: &str) {
-        self.inner.entries.remove(key);
+        if let Some((_, entry)) = self
+            .inner
+            .entries
+            .remove_if(key, |_, entry| entry.is_expired())
+        {
+            self.forget_entry(&entry);
+            self.notify_event(pubsub::EXPIRED, "expired", key);
+        }
+    }
+
+    /// Removes key if it expired but was not removed yet, so that every
+    /// command treats it as missing like in Redis.
+    fn expire_if_needed(&self, key: &str) {
+        if self
+            .inner
+            .entries
+            .get(key)
+            .is_some_and(|entry| entry.is_expired())
+        {
+            self.remove_expired(key);
+        }
+    }
+
+    /// Removes key if it expired, along with its pending timer.
+    fn remove_expired(&self, key: &str) {
+        if let Some((_, entry)) = self
+            .inner
+            .entries
+            .remove_if(key, |_, entry| entry.is_expired())
+        {
+            self.forget_entry(&entry);
+            self.cancel_expiration(&entry);
+            self.notify_event(pubsub::EXPIRED, "expired", key);
+        }
     }
 
     pub fn ttl(&self, key: &str) -> i64 {
+        self.expire_if_needed(key);
+
         if let Some(value) = self.inner.entries.get(key) {
             if let Some(expiration) = value.expires_at {
                 let remaining = expiration.checked_duration_since(Instant::now());
@@ -220,6 +3303,8 @@ impl Db {
     }
 
     pub fn pttl(&self, key: &str) -> i64 {
+        self.expire_if_needed(key);
+
         if let Some(value) = self.inner.entries.get(key) {
             if let Some(expiration) = value.expires_at {
                 let remaining = expiration.checked_duration_since(Instant::now());
@@ -238,3 +3323,612 @@ impl Db {
         }
     }
 }
+
+#[tokio::test]
+async fn expired_keys_without_timer_are_removed() {
+    let db = Db::new();
+
+    // Expired, but the expiration task does not know about it
+    let mut entry = Entry::new(Object::String(Bytes::from("value")));
+    entry.expires_at = Some(Instant::now());
+    db.inner.entries.insert(String::from("key"), entry);
+
+    assert_eq!(db.get("key"), Ok(None));
+
+    tokio::time::sleep(ACTIVE_EXPIRE_INTERVAL * 3).await;
+
+    assert!(!db.inner.entries.contains_key("key"));
+}
+
+#[tokio::test]
+async fn expired_keys_are_missing_before_removal() {
+    let db = Db::new();
+
+    // Without a timer, so only reading the key can notice the expiry
+    for key in ["a", "b", "c"] {
+        let mut entry = Entry::new(Object::String(Bytes::from("1")));
+        entry.expires_at = Some(Instant::now() + Duration::from_millis(20));
+        db.inner.entries.insert(String::from(key), entry);
+    }
+
+    assert_eq!(db.get("a"), Ok(Some(Bytes::from("1"))));
+
+    tokio::time::sleep(Duration::from_millis(30)).await;
+
+    assert_eq!(db.get("a"), Ok(None));
+    assert_eq!(db.exists(&[String::from("b")]), 0);
+    assert_eq!(db.ttl("c"), -2);
+    assert!(db.keys("*").is_empty());
+    assert_eq!(db.len(), 0);
+}
+
+#[cfg(test)]
+fn in_secs(seconds: u64) -> Instant {
+    Instant::now() + Duration::from_secs(seconds)
+}
+
+#[tokio::test]
+async fn expire_missing_key_works() {
+    let db = Db::new();
+
+    assert!(!db.set_expiry("missing", in_secs(10), ExpireBehaviour::Force));
+    assert_eq!(db.ttl("missing"), -2);
+}
+
+#[tokio::test]
+async fn expire_behaviours_work() {
+    let db = Db::new();
+    let value = Bytes::from("value");
+
+    db.set(
+        String::from("key"),
+        value,
+        None,
+        SetBehaviour::Force,
+        false,
+        false,
+    )
+    .unwrap();
+
+    // Without an expiry the TTL is infinite
+    assert!(!db.set_expiry("key", in_secs(100), ExpireBehaviour::OnlyIfExpiry));
+    assert!(!db.set_expiry("key", in_secs(100), ExpireBehaviour::OnlyIfGreater));
+    assert_eq!(db.ttl("key"), -1);
+
+    // This inserts a new expiration
+    assert!(db.set_expiry("key", in_secs(100), ExpireBehaviour::OnlyIfNoExpiry));
+    assert!(matches!(db.ttl("key"), 99 | 100));
+    assert!(!db.set_expiry("key", in_secs(200), ExpireBehaviour::OnlyIfNoExpiry));
+
+    // These reset the existing expiration
+    assert!(!db.set_expiry("key", in_secs(200), ExpireBehaviour::OnlyIfLess));
+    assert!(db.set_expiry("key", in_secs(50), ExpireBehaviour::OnlyIfLess));
+    assert!(matches!(db.ttl("key"), 49 | 50));
+    assert!(!db.set_expiry("key", in_secs(10), ExpireBehaviour::OnlyIfGreater));
+    assert!(db.set_expiry("key", in_secs(300), ExpireBehaviour::OnlyIfGreater));
+    assert!(matches!(db.ttl("key"), 299 | 300));
+    assert!(db.set_expiry("key", in_secs(400), ExpireBehaviour::OnlyIfExpiry));
+    assert!(matches!(db.ttl("key"), 399 | 400));
+    assert!(db.set_expiry("key", in_secs(20), ExpireBehaviour::Force));
+    assert!(matches!(db.ttl("key"), 19 | 20));
+}
+
+#[tokio::test]
+async fn expire_less_without_expiry_works() {
+    let db = Db::new();
+    let value = Bytes::from("value");
+
+    db.set(
+        String::from("key"),
+        value,
+        None,
+        SetBehaviour::Force,
+        false,
+        false,
+    )
+    .unwrap();
+
+    assert!(db.set_expiry("key", in_secs(0), ExpireBehaviour::OnlyIfLess));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(db.get("key"), Ok(None));
+}
+
+#[tokio::test]
+async fn keys_works() {
+    let db = Db::new();
+
+    for key in ["user:1", "user:2", "user:10", "session:1"] {
+        let value = Bytes::from("value");
+
+        db.set(
+            key.to_string(),
+            value,
+            None,
+            SetBehaviour::Force,
+            false,
+            false,
+        )
+        .unwrap();
+    }
+
+    let mut keys = db.keys("user:?");
+    keys.sort();
+
+    assert_eq!(keys, ["user:1", "user:2"]);
+    assert_eq!(db.keys("*").len(), 4);
+    assert_eq!(db.keys("session:*"), ["session:1"]);
+    assert!(db.keys("nothing*").is_empty());
+}
+
+#[tokio::test]
+async fn incr_by_works() {
+    let db = Db::new();
+
+    assert_eq!(db.incr_by(String::from("counter"), 1), Ok(1));
+    assert_eq!(db.incr_by(String::from("counter"), 1), Ok(2));
+    assert_eq!(db.incr_by(String::from("counter"), -5), Ok(-3));
+    assert_eq!(db.get("counter"), Ok(Some(Bytes::from("-3"))));
+
+    let value = Bytes::from("10");
+    db.set(
+        String::from("existing"),
+        value,
+        None,
+        SetBehaviour::Force,
+        false,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(db.incr_by(String::from("existing"), 1), Ok(11));
+}
+
+#[tokio::test]
+async fn incr_by_errors() {
+    let db = Db::new();
+
+    let value = Bytes::from(i64::MAX.to_string());
+    db.set(
+        String::from("max"),
+        value,
+        None,
+        SetBehaviour::Force,
+        false,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(
+        db.incr_by(String::from("max"), 1),
+        Err(Value::Error(RedisError {
+            message: String::from("ERR increment or decrement would overflow")
+        }))
+    );
+    assert_eq!(db.get("max"), Ok(Some(Bytes::from(i64::MAX.to_string()))));
+
+    let value = Bytes::from("abc");
+    db.set(
+        String::from("abc"),
+        value,
+        None,
+        SetBehaviour::Force,
+        false,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(
+        db.incr_by(String::from("abc"), 1),
+        Err(Value::Error(RedisError {
+            message: String::from("ERR value is not an integer or out of range")
+        }))
+    );
+}
+
+#[tokio::test]
+async fn append_works() {
+    let db = Db::new();
+
+    assert_eq!(db.append(String::from("key"), Bytes::from("Hello")), Ok(5));
+    assert_eq!(
+        db.append(String::from("key"), Bytes::from(" World")),
+        Ok(11)
+    );
+    assert_eq!(db.get("key"), Ok(Some(Bytes::from("Hello World"))));
+
+    db.rpush(String::from("list"), vec![String::from("value")])
+        .unwrap();
+
+    assert_eq!(
+        db.append(String::from("list"), Bytes::from("value")),
+        Err(wrong_type_error())
+    );
+}
+
+#[tokio::test]
+async fn get_does_not_copy_values() {
+    let db = Db::new();
+    let value = Bytes::from(vec![b'x'; 1024 * 1024]);
+
+    db.set(
+        String::from("key"),
+        value.clone(),
+        None,
+        SetBehaviour::Force,
+        false,
+        false,
+    )
+    .unwrap();
+
+    // Both reads share the buffer the value was set with
+    for _ in 0..2 {
+        let read = db.get("key").unwrap().unwrap();
+
+        assert_eq!(read.as_ptr(), value.as_ptr());
+    }
+}
+
+#[tokio::test]
+async fn get_del_cancels_expiration() {
+    let db = Db::new();
+    let value = Bytes::from("value");
+
+    db.set(
+        String::from("key"),
+        value.clone(),
+        Some(Duration::from_millis(50)),
+        SetBehaviour::Force,
+        false,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(db.get_del("key"), Ok(Some(value.clone())));
+    assert_eq!(db.get("key"), Ok(None));
+
+    // A lingering timer would remove the new key
+    db.set(
+        String::from("key"),
+        value.clone(),
+        None,
+        SetBehaviour::Force,
+        false,
+        false,
+    )
+    .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(db.get("key"), Ok(Some(value)));
+}
+
+#[tokio::test]
+async fn touch_updates_access_time() {
+    let db = Db::new();
+
+    db.mset(vec![(String::from("key"), Bytes::from("value"))]);
+    let last_access = || {
+        db.inner
+            .entries
+            .get("key")
+            .unwrap()
+            .last_access
+            .load(Ordering::Relaxed)
+    };
+    let before = last_access();
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let keys = [
+        String::from("key"),
+        String::from("missing"),
+        String::from("key"),
+    ];
+    assert_eq!(db.touch(&keys), 2);
+    assert!(last_access() > before);
+}
+
+#[tokio::test]
+async fn copies_expire_independently() {
+    let db = Db::new();
+    let value = Bytes::from("value");
+
+    db.set(
+        String::from("src"),
+        value.clone(),
+        Some(Duration::from_millis(50)),
+        SetBehaviour::Force,
+        false,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(db.copy("src", String::from("expiring"), false), Ok(true));
+    assert_eq!(db.copy("src", String::from("kept"), false), Ok(true));
+    assert_eq!(db.copy("src", String::from("kept"), false), Ok(false));
+    assert!(db.pttl("kept") > 0);
+
+    // Removing the expiry of one copy keeps the others expiring
+    assert_eq!(db.persist("kept"), 1);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(db.get("src"), Ok(None));
+    assert_eq!(db.get("expiring"), Ok(None));
+    assert_eq!(db.get("kept"), Ok(Some(value)));
+}
+
+#[tokio::test]
+async fn expired_keyspace_event_is_published() {
+    let db = Db::new();
+    let connection = crate::connection::ConnectionState::new();
+    let mut rx = connection.take_receiver().unwrap();
+
+    db.config()
+        .set(vec![(
+            String::from("notify-keyspace-events"),
+            String::from("Ex"),
+        )])
+        .unwrap();
+
+    for channel in ["__keyevent@0__:expired", "__keyevent@0__:set"] {
+        db.pubsub().subscribe(
+            String::from(channel),
+            connection.id(),
+            connection.messages().clone(),
+        );
+    }
+
+    db.set(
+        String::from("key"),
+        Bytes::from("value"),
+        Some(Duration::from_millis(50)),
+        SetBehaviour::Force,
+        false,
+        false,
+    )
+    .unwrap();
+
+    // Only the expired class is enabled, so SET did not publish anything
+    let message = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        message,
+        Value::Push(vec![
+            Value::BulkString(Bytes::from("message")),
+            Value::BulkString(Bytes::from("__keyevent@0__:expired")),
+            Value::BulkString(Bytes::from("key")),
+        ])
+    );
+    assert_eq!(db.get("key"), Ok(None));
+}
+
+#[tokio::test]
+async fn scan_returns_every_key_once() {
+    let db = Db::new();
+    let keys: Vec<(String, Bytes)> = (0..1000)
+        .map(|idx| (format!("key:{idx}"), Bytes::from("value")))
+        .collect();
+    db.mset(keys);
+
+    let mut scanned = Vec::new();
+    let mut cursor = 0;
+    let mut calls = 0;
+
+    loop {
+        let (next_cursor, keys) = db.scan(cursor, None, 7);
+        scanned.extend(keys);
+        calls += 1;
+
+        if next_cursor == 0 {
+            break;
+        }
+
+        cursor = next_cursor;
+    }
+
+    scanned.sort();
+    let mut expected: Vec<String> = (0..1000).map(|idx| format!("key:{idx}")).collect();
+    expected.sort();
+
+    assert_eq!(scanned, expected);
+    assert!(calls > 1000 / 7);
+
+    let (cursor, keys) = db.scan(0, Some("key:99*"), 2000);
+    assert_eq!(cursor, 0);
+    assert_eq!(keys.len(), 11);
+}
+
+#[tokio::test]
+async fn hscan_and_sscan_return_every_element_once() {
+    let db = Db::new();
+    let pairs: Vec<(String, String)> = (0..300)
+        .map(|idx| (format!("field:{idx}"), idx.to_string()))
+        .collect();
+    db.hset(String::from("hash"), pairs.clone()).unwrap();
+    db.sadd(
+        String::from("set"),
+        pairs.iter().map(|(field, _)| field.clone()).collect(),
+    )
+    .unwrap();
+
+    let mut scanned = Vec::new();
+    let mut cursor = 0;
+
+    loop {
+        let (next_cursor, pairs) = db.hscan("hash", cursor, None, 10).unwrap();
+        assert!(pairs.len() <= 11);
+        scanned.extend(pairs);
+
+        if next_cursor == 0 {
+            break;
+        }
+
+        cursor = next_cursor;
+    }
+
+    scanned.sort();
+    let mut expected = pairs.clone();
+    expected.sort();
+    assert_eq!(scanned, expected);
+
+    let mut scanned = Vec::new();
+    let mut cursor = 0;
+
+    loop {
+        let (next_cursor, members) = db.sscan("set", cursor, Some("*:1?"), 25).unwrap();
+        scanned.extend(members);
+
+        if next_cursor == 0 {
+            break;
+        }
+
+        cursor = next_cursor;
+    }
+
+    scanned.sort();
+    let mut expected: Vec<String> = (10..20).map(|idx| format!("field:{idx}")).collect();
+    expected.sort();
+    assert_eq!(scanned, expected);
+
+    assert_eq!(db.sscan("missing", 0, None, 10), Ok((0, Vec::new())));
+    assert_eq!(db.hscan("set", 0, None, 10), Err(wrong_type_error()));
+}
+
+#[tokio::test]
+async fn random_key_exists() {
+    let db = Db::new();
+
+    assert_eq!(db.random_key(), None);
+
+    let keys: Vec<(String, Bytes)> = (0..100)
+        .map(|idx| (format!("key:{idx}"), Bytes::from("value")))
+        .collect();
+    db.mset(keys);
+
+    let mut seen = HashSet::new();
+
+    for _ in 0..1000 {
+        let key = db.random_key().unwrap();
+        assert_eq!(db.exists(std::slice::from_ref(&key)), 1);
+        seen.insert(key);
+    }
+
+    // Not a proof of uniformity, but every key shows up eventually
+    assert!(seen.len() > 90);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_expiring_sets_finish() {
+    let db = Db::new();
+
+    let writers: Vec<_> = (0..8)
+        .map(|writer| {
+            let db = db.clone();
+
+            tokio::spawn(async move {
+                for i in 0..1000 {
+                    db.set(
+                        format!("{writer}:{i}"),
+                        Bytes::from("value"),
+                        Some(Duration::from_millis(20)),
+                        SetBehaviour::Force,
+                        false,
+                        false,
+                    )
+                    .unwrap();
+                }
+            })
+        })
+        .collect();
+
+    let started = Instant::now();
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        for writer in writers {
+            writer.await.unwrap();
+        }
+    })
+    .await
+    .expect("expiring SETs did not finish");
+
+    // Without waiting for the expiration task, 8000 inserts are far below
+    // this on any machine
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    // The expiration tasks remove the keys without any reads
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(db.used_memory(), 0);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn keys_of_all_shards_expire() {
+    let db = Db::new();
+
+    // Enough keys to land in every shard and thus on every expiration task
+    let keys: Vec<String> = (0..5000).map(|i| format!("key:{i}")).collect();
+
+    for key in &keys {
+        db.set(
+            key.clone(),
+            Bytes::from("value"),
+            Some(Duration::from_millis(20)),
+            SetBehaviour::Force,
+            false,
+            false,
+        )
+        .unwrap();
+    }
+
+    let tasks: HashSet<usize> = keys.iter().map(|key| db.expiration_task_of(key)).collect();
+    assert_eq!(tasks.len(), db.inner.expiration_tasks.len());
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    assert_eq!(db.used_memory(), 0);
+    assert_eq!(db.exists(&keys), 0);
+}
+
+#[tokio::test]
+async fn multi_year_ttls_keep_expirations_working() {
+    let db = Db::new();
+
+    // Beyond the longest timeout of the delay queue
+    let far = Duration::from_secs(100_000_000);
+    assert_eq!(queued_timeout(Instant::now() + far), MAX_QUEUED_TIMEOUT);
+
+    db.set(
+        String::from("far"),
+        Bytes::from("value"),
+        Some(far),
+        SetBehaviour::Force,
+        false,
+        false,
+    )
+    .unwrap();
+
+    // A key on the same expiration task still expires
+    let task = db.expiration_task_of("far");
+    let near = (0..)
+        .map(|i| format!("near:{i}"))
+        .find(|key| db.expiration_task_of(key) == task)
+        .unwrap();
+
+    db.set(
+        near.clone(),
+        Bytes::from("value"),
+        Some(Duration::from_millis(20)),
+        SetBehaviour::Force,
+        false,
+        false,
+    )
+    .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(db.exists(&[near]), 0);
+    assert_eq!(db.ttl("far"), 99_999_999);
+}
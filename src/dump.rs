@@ -0,0 +1,266 @@
+/// The serialization format of `DUMP` and `RESTORE`.
+///
+/// A payload is the type of the value and its contents, followed by the
+/// format version and a CRC-64 of everything before the checksum. Integers
+/// are little endian and strings, like all collections, are prefixed with
+/// their length.
+use bytes::Bytes;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{
+    object::Object,
+    stream::{Stream, StreamId},
+    zset::{AddCondition, ScoreUpdate, SortedSet},
+};
+
+/// The version of the format, which `RESTORE` requires to match.
+const VERSION: u16 = 1;
+
+const STRING: u8 = 0;
+const LIST: u8 = 1;
+const HASH: u8 = 2;
+const SET: u8 = 3;
+const SORTED_SET: u8 = 4;
+const STREAM: u8 = 5;
+
+/// The reflected polynomial of CRC-64/Jones, which Redis uses as well.
+const CRC64_POLYNOMIAL: u64 = 0x95ac_9329_ac4b_c9b5;
+
+fn crc64(bytes: &[u8]) -> u64 {
+    let mut crc = 0;
+
+    for byte in bytes {
+        crc ^= u64::from(*byte);
+
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC64_POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// Writes the parts of a payload.
+#[derive(Default)]
+pub struct Encoder {
+    buffer: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn put_u64(&mut self, integer: u64) {
+        self.buffer.extend_from_slice(&integer.to_le_bytes());
+    }
+
+    pub fn put_len(&mut self, len: usize) {
+        self.put_u64(len as u64);
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.put_len(bytes.len());
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn put_f64(&mut self, float: f64) {
+        self.put_u64(float.to_bits());
+    }
+
+    pub fn put_stream_id(&mut self, id: StreamId) {
+        self.put_u64(id.ms);
+        self.put_u64(id.seq);
+    }
+}
+
+/// Reads the parts of a payload. All methods return `None` once the payload
+/// ends early or is malformed.
+pub struct Decoder<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        if count > self.input.len() {
+            return None;
+        }
+
+        let (taken, rest) = self.input.split_at(count);
+        self.input = rest;
+
+        Some(taken)
+    }
+
+    pub fn get_u64(&mut self) -> Option<u64> {
+        let bytes = self.take(8)?;
+
+        Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Reads a length, which cannot exceed the rest of the payload since
+    /// every counted part takes at least a byte.
+    pub fn get_len(&mut self) -> Option<usize> {
+        let len = usize::try_from(self.get_u64()?).ok()?;
+
+        (len <= self.input.len()).then_some(len)
+    }
+
+    pub fn get_bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.get_len()?;
+
+        self.take(len)
+    }
+
+    pub fn get_string(&mut self) -> Option<String> {
+        String::from_utf8(self.get_bytes()?.to_vec()).ok()
+    }
+
+    pub fn get_f64(&mut self) -> Option<f64> {
+        Some(f64::from_bits(self.get_u64()?))
+    }
+
+    pub fn get_stream_id(&mut self) -> Option<StreamId> {
+        let ms = self.get_u64()?;
+        let seq = self.get_u64()?;
+
+        Some(StreamId { ms, seq })
+    }
+}
+
+/// Serializes the value into a `DUMP` payload.
+pub fn serialize(object: &Object) -> Bytes {
+    let mut encoder = Encoder::default();
+
+    match object {
+        Object::String(value) => {
+            encoder.buffer.push(STRING);
+            encoder.put_bytes(value);
+        }
+        Object::List(list) => {
+            encoder.buffer.push(LIST);
+            encoder.put_len(list.len());
+
+            for element in list {
+                encoder.put_bytes(element.as_bytes());
+            }
+        }
+        Object::Hash(hash) => {
+            encoder.buffer.push(HASH);
+            encoder.put_len(hash.len());
+
+            for (field, value) in hash {
+                encoder.put_bytes(field.as_bytes());
+                encoder.put_bytes(value.as_bytes());
+            }
+        }
+        Object::Set(set) => {
+            encoder.buffer.push(SET);
+            encoder.put_len(set.len());
+
+            for member in set {
+                encoder.put_bytes(member.as_bytes());
+            }
+        }
+        Object::SortedSet(zset) => {
+            encoder.buffer.push(SORTED_SET);
+            encoder.put_len(zset.len());
+
+            for (member, score) in zset.iter() {
+                encoder.put_bytes(member.as_bytes());
+                encoder.put_f64(score);
+            }
+        }
+        Object::Stream(stream) => {
+            encoder.buffer.push(STREAM);
+            stream.serialize(&mut encoder);
+        }
+    }
+
+    encoder.buffer.extend_from_slice(&VERSION.to_le_bytes());
+
+    let checksum = crc64(&encoder.buffer);
+    encoder.put_u64(checksum);
+
+    Bytes::from(encoder.buffer)
+}
+
+/// Deserializes a `DUMP` payload, returning `None` if it was not created by
+/// [`serialize`] with this version of the format.
+pub fn deserialize(payload: &[u8]) -> Option<Object> {
+    let (contents, checksum) = payload.split_at_checked(payload.len().checked_sub(8)?)?;
+
+    if crc64(contents).to_le_bytes() != checksum {
+        return None;
+    }
+
+    let (contents, version) = contents.split_at_checked(contents.len().checked_sub(2)?)?;
+
+    if version != VERSION.to_le_bytes() {
+        return None;
+    }
+
+    let (kind, contents) = contents.split_first()?;
+    let mut decoder = Decoder { input: contents };
+
+    let object = match *kind {
+        STRING => Object::String(Bytes::copy_from_slice(decoder.get_bytes()?)),
+        LIST => {
+            let len = decoder.get_len()?;
+            let mut list = VecDeque::new();
+
+            for _ in 0..len {
+                list.push_back(decoder.get_string()?);
+            }
+
+            Object::List(list)
+        }
+        HASH => {
+            let len = decoder.get_len()?;
+            let mut hash = HashMap::new();
+
+            for _ in 0..len {
+                let field = decoder.get_string()?;
+                let value = decoder.get_string()?;
+
+                hash.insert(field, value);
+            }
+
+            Object::Hash(hash)
+        }
+        SET => {
+            let len = decoder.get_len()?;
+            let mut set = HashSet::new();
+
+            for _ in 0..len {
+                set.insert(decoder.get_string()?);
+            }
+
+            Object::Set(set)
+        }
+        SORTED_SET => {
+            let len = decoder.get_len()?;
+            let mut zset = SortedSet::new();
+
+            for _ in 0..len {
+                let member = decoder.get_string()?;
+                let score = decoder.get_f64().filter(|score| !score.is_nan())?;
+
+                zset.add(member, score, AddCondition::Always, ScoreUpdate::Always);
+            }
+
+            Object::SortedSet(zset)
+        }
+        STREAM => Object::Stream(Stream::deserialize(&mut decoder)?),
+        _ => return None,
+    };
+
+    // Trailing bytes mean the payload is not what it claims to be
+    decoder.input.is_empty().then_some(object)
+}
+
+#[test]
+fn crc64_matches_redis() {
+    assert_eq!(crc64(b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+}
@@ -0,0 +1,157 @@
+/// Geospatial indexes, which are sorted sets with positions as scores.
+///
+/// Positions are encoded like in Redis, so that the scores are compatible:
+/// The longitude and latitude are scaled to 26 bits each and interleaved
+/// into a 52-bit geohash, with the latitude in the even bits. Distances are
+/// great circle distances from the haversine formula.
+use std::ops::RangeInclusive;
+
+/// The latitudes that can be encoded, the limits of Web Mercator.
+const LATITUDES: RangeInclusive<f64> = -85.05112878..=85.05112878;
+
+const LONGITUDES: RangeInclusive<f64> = -180.0..=180.0;
+
+/// The bits of each coordinate in a geohash.
+const STEP: u32 = 26;
+
+/// The earth radius in meters Redis uses for distances.
+const EARTH_RADIUS: f64 = 6372797.560856;
+
+/// Where `GEOSEARCH` searches from.
+pub enum Center {
+    Member(String),
+    Position { longitude: f64, latitude: f64 },
+}
+
+/// How `GEOSEARCH` sorts by distance.
+#[derive(Clone, Copy)]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+/// The area `GEOSEARCH` searches, in meters.
+#[derive(Clone, Copy)]
+pub enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+/// A member `GEOSEARCH` found, see [`Db::geosearch`](crate::db::Db::geosearch).
+pub struct Neighbor {
+    pub member: String,
+    pub hash: u64,
+    /// The distance from the center in meters.
+    pub distance: f64,
+}
+
+/// Whether the position can be encoded.
+pub fn is_valid(longitude: f64, latitude: f64) -> bool {
+    LONGITUDES.contains(&longitude) && LATITUDES.contains(&latitude)
+}
+
+/// Parses a unit of distance into its length in meters, ignoring case.
+pub fn parse_unit(unit: &str) -> Option<f64> {
+    match unit.to_ascii_lowercase().as_str() {
+        "m" => Some(1.0),
+        "km" => Some(1000.0),
+        "ft" => Some(0.3048),
+        "mi" => Some(1609.34),
+        _ => None,
+    }
+}
+
+/// Moves the bits apart, so that every other bit is zero.
+fn spread(bits: u32) -> u64 {
+    let mut bits = u64::from(bits);
+
+    bits = (bits | bits << 16) & 0x0000_ffff_0000_ffff;
+    bits = (bits | bits << 8) & 0x00ff_00ff_00ff_00ff;
+    bits = (bits | bits << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    bits = (bits | bits << 2) & 0x3333_3333_3333_3333;
+
+    (bits | bits << 1) & 0x5555_5555_5555_5555
+}
+
+/// Moves the even bits together, the inverse of [`spread`].
+fn squash(bits: u64) -> u32 {
+    let mut bits = bits & 0x5555_5555_5555_5555;
+
+    bits = (bits | bits >> 1) & 0x3333_3333_3333_3333;
+    bits = (bits | bits >> 2) & 0x0f0f_0f0f_0f0f_0f0f;
+    bits = (bits | bits >> 4) & 0x00ff_00ff_00ff_00ff;
+    bits = (bits | bits >> 8) & 0x0000_ffff_0000_ffff;
+
+    (bits | bits >> 16) as u32
+}
+
+/// Encodes a valid position into its geohash.
+pub fn encode(longitude: f64, latitude: f64) -> u64 {
+    let scale = |value: f64, range: RangeInclusive<f64>| {
+        let (min, max) = range.into_inner();
+
+        ((value - min) / (max - min) * f64::from(1 << STEP)) as u32
+    };
+
+    spread(scale(latitude, LATITUDES)) | spread(scale(longitude, LONGITUDES)) << 1
+}
+
+/// Decodes a geohash into the longitude and latitude of the center of its
+/// cell.
+pub fn decode(hash: u64) -> (f64, f64) {
+    let center = |bits: u32, range: RangeInclusive<f64>| {
+        let (min, max) = range.into_inner();
+        let cells = f64::from(1 << STEP);
+        let cell_min = min + f64::from(bits) / cells * (max - min);
+        let cell_max = min + (f64::from(bits) + 1.0) / cells * (max - min);
+
+        ((cell_min + cell_max) / 2.0).clamp(min, max)
+    };
+
+    (
+        center(squash(hash >> 1), LONGITUDES),
+        center(squash(hash), LATITUDES),
+    )
+}
+
+/// The distance between two positions in meters.
+pub fn distance(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (from_longitude, from_latitude) = (from.0.to_radians(), from.1.to_radians());
+    let (to_longitude, to_latitude) = (to.0.to_radians(), to.1.to_radians());
+
+    let u = ((to_latitude - from_latitude) / 2.0).sin();
+    let v = ((to_longitude - from_longitude) / 2.0).sin();
+    let a = u * u + from_latitude.cos() * to_latitude.cos() * v * v;
+
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+impl Shape {
+    /// The distance of the position from the center if it is within the
+    /// shape around the center. Like in Redis, the width of a box is
+    /// measured at the latitude of the position.
+    pub fn distance_within(self, center: (f64, f64), position: (f64, f64)) -> Option<f64> {
+        match self {
+            Self::Radius(radius) => Some(distance(center, position)).filter(|d| *d <= radius),
+            Self::Box { width, height } => {
+                let latitude_distance =
+                    EARTH_RADIUS * (position.1.to_radians() - center.1.to_radians()).abs();
+                let longitude_distance = distance((center.0, position.1), position);
+
+                (latitude_distance <= height / 2.0 && longitude_distance <= width / 2.0)
+                    .then(|| distance(center, position))
+            }
+        }
+    }
+}
+
+#[test]
+fn geohashes_match_redis() {
+    // The scores of the examples in the Redis documentation
+    assert_eq!(encode(13.361389, 38.115556), 3479099956230698);
+    assert_eq!(encode(15.087269, 37.502669), 3479447370796909);
+
+    let (longitude, latitude) = decode(3479099956230698);
+    assert!((longitude - 13.361389).abs() < 1e-5);
+    assert!((latitude - 38.115556).abs() < 1e-5);
+}
@@ -0,0 +1,355 @@
+/// Redis-style glob matching as used by KEYS.
+///
+/// Supports `*`, `?`, character classes like `[a-z]` or `[^abc]` and escaping
+/// with `\`, mirroring `stringmatchlen` in Redis. Patterns are compiled once
+/// into a [`Glob`] so that matching many strings does not parse them again.
+#[derive(Debug)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, PartialEq)]
+enum ClassItem {
+    Byte(u8),
+    /// An inclusive range with the bounds in order.
+    Range(u8, u8),
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Literal(u8),
+    /// `?`
+    AnyByte,
+    /// `*`, consecutive stars are merged.
+    AnyBytes,
+    Class {
+        negate: bool,
+        items: Vec<ClassItem>,
+    },
+}
+
+impl Token {
+    /// Whether the token matches a single byte, never true for `*`.
+    fn matches(&self, byte: u8) -> bool {
+        match self {
+            Self::Literal(literal) => *literal == byte,
+            Self::AnyByte => true,
+            Self::AnyBytes => false,
+            Self::Class { negate, items } => {
+                let matched = items.iter().any(|item| match item {
+                    ClassItem::Byte(class_byte) => *class_byte == byte,
+                    ClassItem::Range(start, end) => (*start..=*end).contains(&byte),
+                });
+
+                matched != *negate
+            }
+        }
+    }
+}
+
+impl Glob {
+    pub fn new(pattern: &[u8]) -> Self {
+        let mut tokens = Vec::new();
+        let mut p = 0;
+
+        while p < pattern.len() {
+            let token = match pattern[p] {
+                b'*' => {
+                    if tokens.last() == Some(&Token::AnyBytes) {
+                        p += 1;
+                        continue;
+                    }
+
+                    Token::AnyBytes
+                }
+                b'?' => Token::AnyByte,
+                b'[' => {
+                    p += 1;
+
+                    let negate = pattern.get(p) == Some(&b'^');
+
+                    if negate {
+                        p += 1;
+                    }
+
+                    let mut items = Vec::new();
+
+                    // An unterminated class is closed by the end of the pattern
+                    while p < pattern.len() && pattern[p] != b']' {
+                        if pattern[p] == b'\\' && p + 1 < pattern.len() {
+                            p += 1;
+                            items.push(ClassItem::Byte(pattern[p]));
+                        } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' {
+                            let (start, end) = (pattern[p], pattern[p + 2]);
+                            items.push(ClassItem::Range(start.min(end), start.max(end)));
+                            p += 2;
+                        } else {
+                            items.push(ClassItem::Byte(pattern[p]));
+                        }
+
+                        p += 1;
+                    }
+
+                    Token::Class { negate, items }
+                }
+                b'\\' if p + 1 < pattern.len() => {
+                    p += 1;
+
+                    Token::Literal(pattern[p])
+                }
+                c => Token::Literal(c),
+            };
+
+            tokens.push(token);
+            p += 1;
+        }
+
+        Self { tokens }
+    }
+
+    pub fn matches(&self, string: &[u8]) -> bool {
+        let mut t = 0;
+        let mut s = 0;
+        // Where to continue after the last star if the rest does not match,
+        // the star then consumes one more byte
+        let mut backtrack = None;
+
+        while s < string.len() {
+            match self.tokens.get(t) {
+                Some(Token::AnyBytes) => {
+                    t += 1;
+                    backtrack = Some((t, s));
+                    continue;
+                }
+                Some(token) if token.matches(string[s]) => {
+                    t += 1;
+                    s += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            match backtrack {
+                Some((star_t, star_s)) => {
+                    t = star_t;
+                    s = star_s + 1;
+                    backtrack = Some((star_t, s));
+                }
+                None => return false,
+            }
+        }
+
+        // Trailing stars also match an empty rest
+        self.tokens[t..]
+            .iter()
+            .all(|token| *token == Token::AnyBytes)
+    }
+}
+
+/// The straightforward matcher the compiled glob is checked against.
+#[cfg(test)]
+fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let mut p = 0;
+    let mut s = 0;
+
+    while p < pattern.len() && s < string.len() {
+        match pattern[p] {
+            b'*' => {
+                // Consecutive stars are the same as one star
+                while pattern.get(p + 1) == Some(&b'*') {
+                    p += 1;
+                }
+
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+
+                return (s..string.len()).any(|start| matches(&pattern[p + 1..], &string[start..]));
+            }
+            b'?' => {
+                s += 1;
+            }
+            b'[' => {
+                p += 1;
+
+                let negate = pattern.get(p) == Some(&b'^');
+
+                if negate {
+                    p += 1;
+                }
+
+                let mut matched = false;
+
+                loop {
+                    match pattern.get(p) {
+                        Some(b'\\') if p + 1 < pattern.len() => {
+                            p += 1;
+
+                            if pattern[p] == string[s] {
+                                matched = true;
+                            }
+                        }
+                        Some(b']') => break,
+                        None => {
+                            // Unterminated class, the end of the pattern closes it
+                            p -= 1;
+                            break;
+                        }
+                        Some(&start) if p + 2 < pattern.len() && pattern[p + 1] == b'-' => {
+                            let end = pattern[p + 2];
+                            let range = if start <= end {
+                                start..=end
+                            } else {
+                                end..=start
+                            };
+
+                            if range.contains(&string[s]) {
+                                matched = true;
+                            }
+
+                            p += 2;
+                        }
+                        Some(&c) => {
+                            if c == string[s] {
+                                matched = true;
+                            }
+                        }
+                    }
+
+                    p += 1;
+                }
+
+                if matched == negate {
+                    return false;
+                }
+
+                s += 1;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+
+                if pattern[p] != string[s] {
+                    return false;
+                }
+
+                s += 1;
+            }
+            c => {
+                if c != string[s] {
+                    return false;
+                }
+
+                s += 1;
+            }
+        }
+
+        p += 1;
+    }
+
+    // Trailing stars also match an empty rest
+    while pattern.get(p) == Some(&b'*') {
+        p += 1;
+    }
+
+    p == pattern.len() && s == string.len()
+}
+
+#[test]
+fn glob_matches_works() {
+    let test_data: &[(&str, &str, bool)] = &[
+        ("*", "", true),
+        ("*", "anything", true),
+        ("**", "anything", true),
+        ("user:*", "user:1000", true),
+        ("user:*", "user:", true),
+        ("user:*", "session:1000", false),
+        ("*:1000", "user:1000", true),
+        ("h?llo", "hello", true),
+        ("h?llo", "hallo", true),
+        ("h?llo", "hllo", false),
+        ("h*llo", "hllo", true),
+        ("h*llo", "heeeello", true),
+        ("h[ae]llo", "hello", true),
+        ("h[ae]llo", "hallo", true),
+        ("h[ae]llo", "hillo", false),
+        ("h[^e]llo", "hallo", true),
+        ("h[^e]llo", "hello", false),
+        ("h[a-b]llo", "hbllo", true),
+        ("h[b-a]llo", "hallo", true),
+        ("h[a-b]llo", "hcllo", false),
+        ("h\\*llo", "h*llo", true),
+        ("h\\*llo", "hello", false),
+        ("h[\\]]llo", "h]llo", true),
+        ("h[ab", "ha", true),
+        ("hello", "hello", true),
+        ("hello", "hello!", false),
+        ("hello!", "hello", false),
+    ];
+
+    for (pattern, string, expected) in test_data {
+        assert_eq!(
+            matches(pattern.as_bytes(), string.as_bytes()),
+            *expected,
+            "{pattern} against {string}"
+        );
+    }
+}
+
+#[test]
+fn glob_compiled_matches_naive() {
+    let patterns = [
+        "*",
+        "**",
+        "?",
+        "a*",
+        "*a",
+        "*a*",
+        "a*b*c",
+        "*?*",
+        "a?c",
+        "[abc]*",
+        "[^abc]?",
+        "[a-c][x-z]",
+        "[c-a]*",
+        "[\\]a]",
+        "[a-]",
+        "\\*a",
+        "a\\",
+        "[ab",
+        "[^",
+        "*[",
+        "a*a*a*b",
+        "",
+    ];
+    let strings = [
+        "",
+        "a",
+        "b",
+        "ab",
+        "abc",
+        "aab",
+        "aaab",
+        "abcabc",
+        "xyz",
+        "]",
+        "-",
+        "*a",
+        "a\\",
+        "[",
+        "cz",
+        "aaaaaaaaab",
+        "aaaaaaaaa",
+    ];
+
+    for pattern in patterns {
+        let glob = Glob::new(pattern.as_bytes());
+
+        for string in strings {
+            assert_eq!(
+                glob.matches(string.as_bytes()),
+                matches(pattern.as_bytes(), string.as_bytes()),
+                "{pattern} against {string}"
+            );
+        }
+    }
+}
@@ -0,0 +1,109 @@
+/// The report of the `INFO` command.
+///
+/// Sections are rendered like in Redis, a `# Section` header followed by
+/// `field:value` lines, with an empty line between sections.
+use std::fmt::Write;
+
+use crate::{cmd::REDIS_VERSION, db::Db};
+
+/// Appends the fields of a section.
+type RenderSection = fn(&Db, &mut String);
+
+const SECTIONS: &[(&str, RenderSection)] = &[
+    ("server", server),
+    ("clients", clients),
+    ("memory", memory),
+    ("keyspace", keyspace),
+];
+
+/// Renders the section with the given name, ignoring case, or all sections.
+///
+/// Unknown sections render as an empty string, like in Redis.
+pub fn render(db: &Db, section: Option<&str>) -> String {
+    let section = section.map(str::to_ascii_lowercase);
+    let all = matches!(
+        section.as_deref(),
+        None | Some("all" | "default" | "everything")
+    );
+
+    let mut info = String::new();
+
+    for (name, render_section) in SECTIONS {
+        if !all && section.as_deref() != Some(*name) {
+            continue;
+        }
+
+        if !info.is_empty() {
+            info.push_str("\r\n");
+        }
+
+        let mut title = name.to_string();
+        title[..1].make_ascii_uppercase();
+        let _ = write!(info, "# {title}\r\n");
+
+        render_section(db, &mut info);
+    }
+
+    info
+}
+
+fn server(db: &Db, info: &mut String) {
+    let uptime = db.uptime().as_secs();
+
+    let _ = write!(
+        info,
+        "redis_version:{REDIS_VERSION}\r\n\
+         redis_mode:standalone\r\n\
+         arch_bits:{}\r\n\
+         process_id:{}\r\n\
+         uptime_in_seconds:{uptime}\r\n\
+         uptime_in_days:{}\r\n",
+        usize::BITS,
+        std::process::id(),
+        uptime / 86400,
+    );
+}
+
+fn clients(db: &Db, info: &mut String) {
+    let _ = write!(info, "connected_clients:{}\r\n", db.connected_clients());
+}
+
+fn memory(db: &Db, info: &mut String) {
+    let config = db.config();
+
+    let _ = write!(
+        info,
+        "used_memory:{}\r\n\
+         used_memory_rss:{}\r\n\
+         maxmemory:{}\r\n\
+         maxmemory_policy:{}\r\n",
+        db.used_memory(),
+        resident_set_size(),
+        config.value("maxmemory"),
+        config.value("maxmemory-policy"),
+    );
+}
+
+fn keyspace(db: &Db, info: &mut String) {
+    let keys = db.len();
+
+    // Like in Redis, empty databases are left out
+    if keys != 0 {
+        let _ = write!(
+            info,
+            "db0:keys={keys},expires={},avg_ttl=0\r\n",
+            db.expires_count()
+        );
+    }
+}
+
+/// The resident set size of the process in bytes, 0 if it is unknown.
+fn resident_set_size() -> u64 {
+    let pages = std::fs::read_to_string("/proc/self/statm")
+        .ok()
+        .and_then(|statm| statm.split_whitespace().nth(1)?.parse::<u64>().ok())
+        .unwrap_or_default();
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+
+    pages * page_size.max(0) as u64
+}
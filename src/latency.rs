@@ -0,0 +1,216 @@
+/// Latency monitoring, modeled after the Redis latency monitor.
+///
+/// A watchdog task wakes up periodically and records an event whenever it was
+/// woken up later than the threshold allows, which means the worker it runs on
+/// was blocked in the meantime.
+use log::warn;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How often the watchdog checks for stalls.
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many samples are kept per event, the same as in Redis.
+const MAX_SAMPLES: usize = 160;
+
+/// The event recorded for stalls detected by the watchdog.
+pub const EVENT_LOOP_EVENT: &str = "event-loop";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    /// Unix timestamp in seconds.
+    pub timestamp: u64,
+    /// Latency in milliseconds.
+    pub latency: u64,
+}
+
+struct Event {
+    samples: VecDeque<Sample>,
+    max: u64,
+}
+
+pub struct LatencyMonitor {
+    /// The minimum latency in milliseconds that is recorded, 0 disables the
+    /// monitor.
+    threshold: AtomicU64,
+    events: Mutex<HashMap<String, Event>>,
+}
+
+impl LatencyMonitor {
+    pub fn new(threshold: u64) -> Self {
+        Self {
+            threshold: AtomicU64::new(threshold),
+            events: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn set_threshold(&self, threshold: u64) {
+        self.threshold.store(threshold, Ordering::Relaxed);
+    }
+
+    /// Records a latency for the event if it exceeds the threshold.
+    pub fn record(&self, event: &str, latency: Duration) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        self.record_at(event, latency, timestamp);
+    }
+
+    /// Records a latency that happened at the Unix timestamp in seconds.
+    fn record_at(&self, event: &str, latency: Duration, timestamp: u64) {
+        let threshold = self.threshold.load(Ordering::Relaxed);
+        let latency = latency.as_millis() as u64;
+
+        if threshold == 0 || latency < threshold {
+            return;
+        }
+
+        let mut events = self.events.lock().unwrap();
+        let event = events.entry(event.to_string()).or_insert_with(|| Event {
+            samples: VecDeque::with_capacity(MAX_SAMPLES),
+            max: 0,
+        });
+
+        event.max = event.max.max(latency);
+
+        // Samples within the same second are merged, keeping the maximum
+        match event.samples.back_mut() {
+            Some(last) if last.timestamp == timestamp => {
+                last.latency = last.latency.max(latency);
+            }
+            _ => {
+                if event.samples.len() == MAX_SAMPLES {
+                    event.samples.pop_front();
+                }
+
+                event.samples.push_back(Sample { timestamp, latency });
+            }
+        }
+    }
+
+    /// All samples of an event, oldest first.
+    pub fn history(&self, event: &str) -> Vec<Sample> {
+        let events = self.events.lock().unwrap();
+
+        events
+            .get(event)
+            .map(|event| event.samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// The latest sample and all-time maximum of every event.
+    pub fn latest(&self) -> Vec<(String, Sample, u64)> {
+        let events = self.events.lock().unwrap();
+
+        events
+            .iter()
+            .filter_map(|(name, event)| {
+                let latest = event.samples.back()?;
+
+                Some((name.clone(), *latest, event.max))
+            })
+            .collect()
+    }
+
+    /// Resets the given events, or all of them if none are given.
+    ///
+    /// Returns the number of events that were reset.
+    pub fn reset(&self, names: &[String]) -> usize {
+        let mut events = self.events.lock().unwrap();
+
+        if names.is_empty() {
+            let count = events.len();
+            events.clear();
+
+            count
+        } else {
+            names
+                .iter()
+                .filter(|name| events.remove(name.as_str()).is_some())
+                .count()
+        }
+    }
+
+    /// Periodically checks whether the worker this runs on was stalled.
+    ///
+    /// This only notices stalls of the worker the task is currently
+    /// scheduled on.
+    pub async fn watchdog(&self) {
+        loop {
+            let before = Instant::now();
+
+            tokio::time::sleep(WATCHDOG_INTERVAL).await;
+
+            let stall = before.elapsed().saturating_sub(WATCHDOG_INTERVAL);
+            let threshold = Duration::from_millis(self.threshold.load(Ordering::Relaxed));
+
+            if !threshold.is_zero() && stall >= threshold {
+                warn!("Event loop stalled for {}ms", stall.as_millis());
+
+                self.record(EVENT_LOOP_EVENT, stall);
+            }
+        }
+    }
+}
+
+#[test]
+fn latency_record_works() {
+    let monitor = LatencyMonitor::new(0);
+
+    monitor.record("command", Duration::from_millis(500));
+    assert!(monitor.history("command").is_empty());
+
+    monitor.set_threshold(100);
+    monitor.record("command", Duration::from_millis(50));
+    assert!(monitor.history("command").is_empty());
+
+    monitor.record_at("command", Duration::from_millis(150), 1000);
+    monitor.record_at("command", Duration::from_millis(120), 1000);
+
+    // Both fell into the same second
+    assert_eq!(
+        monitor.history("command"),
+        vec![Sample {
+            timestamp: 1000,
+            latency: 150
+        }]
+    );
+
+    monitor.record_at("command", Duration::from_millis(110), 1001);
+    assert_eq!(monitor.history("command").len(), 2);
+    assert_eq!(monitor.latest()[0].1.latency, 110);
+    assert_eq!(monitor.latest()[0].2, 150);
+
+    assert_eq!(monitor.reset(&[String::from("other")]), 0);
+    assert_eq!(monitor.reset(&[]), 1);
+    assert!(monitor.latest().is_empty());
+}
+
+#[tokio::test]
+async fn latency_watchdog_records_stall() {
+    use std::sync::Arc;
+
+    let monitor = Arc::new(LatencyMonitor::new(50));
+
+    let watched = monitor.clone();
+    tokio::spawn(async move { watched.watchdog().await });
+
+    // Let the watchdog start sleeping, then block the only worker
+    tokio::time::sleep(WATCHDOG_INTERVAL / 2).await;
+    std::thread::sleep(Duration::from_millis(300));
+    tokio::time::sleep(WATCHDOG_INTERVAL).await;
+
+    let history = monitor.history(EVENT_LOOP_EVENT);
+
+    assert_eq!(history.len(), 1);
+    assert!(history[0].latency >= 50);
+}
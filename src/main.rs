@@ -1,99 +1,396 @@
-use futures_util::{SinkExt, StreamExt};
-use libc::{c_int, sighandler_t, signal, SIGINT, SIGTERM};
-use log::info;
+use futures_util::{FutureExt, SinkExt, StreamExt};
+use libc::{
+    c_int, fcntl, pipe, sighandler_t, signal, write, FD_CLOEXEC, F_GETFL, F_SETFD, F_SETFL,
+    O_NONBLOCK, SIGINT, SIGTERM,
+};
+use log::{debug, error, info, log_enabled, warn, Level};
 use tokio::{
+    io::unix::AsyncFd,
     net::{TcpListener, TcpStream},
-    sync::mpsc,
+    sync::{mpsc, watch, Notify},
 };
 use tokio_util::codec::Decoder;
 
 use std::{
-    env, io,
+    env,
+    fs::File,
+    io::{self, Read},
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    os::unix::io::FromRawFd,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc,
+    },
 };
 
 use crate::{
     cmd::CommandParser,
+    connection::ConnectionState,
     db::Db,
-    proto::{RedisError, RedisProtocol, Value},
+    proto::{ProtocolVersion, RedisError, RedisProtocol, Value, DEFAULT_MAX_BULK_LEN},
 };
 
+mod acl;
 mod cmd;
+mod command_table;
+mod config;
+mod connection;
 mod db;
+mod dump;
+mod geo;
+mod glob;
+mod info;
+mod latency;
+mod object;
 mod proto;
+mod pubsub;
+mod rand;
+mod stream;
+mod zset;
+
+/// The write end of the pipe through which the signal handler wakes up the
+/// server.
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
 
-async fn run() -> Result<(), io::Error> {
+async fn run(signals: File) -> Result<(), io::Error> {
     info!("Initializing database");
 
     let db = Db::new();
 
-    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 6379);
+    if let Ok(threshold) = env::var("XYLON_LATENCY_MONITOR_THRESHOLD") {
+        if let Err(message) =
+            db.config_set(vec![(String::from("latency-monitor-threshold"), threshold)])
+        {
+            error!("{message}");
+            std::process::exit(1);
+        }
+    }
 
-    let listener = TcpListener::bind(addr).await?;
+    if let Some(password) = requirepass(env::args()).or_else(|| env::var("XYLON_REQUIREPASS").ok())
+    {
+        if let Err(message) = db.config_set(vec![(String::from("requirepass"), password)]) {
+            error!("{message}");
+            std::process::exit(1);
+        }
+    }
+
+    let bind_address = env::var("XYLON_BIND").ok();
+    let port = env::var("XYLON_PORT").ok();
+
+    let addr = match listen_addr(bind_address.as_deref(), port.as_deref()) {
+        Ok(addr) => addr,
+        Err(message) => {
+            error!("{message}");
+            std::process::exit(1);
+        }
+    };
+
+    let listener = match bind(addr).await {
+        Ok(listener) => listener,
+        Err(message) => {
+            error!("{message}");
+            std::process::exit(1);
+        }
+    };
 
     info!("Listening on {addr}");
 
-    while let Ok((stream, client_addr)) = listener.accept().await {
-        info!("Client connected from {client_addr}");
+    let shutdown = Arc::new(Notify::new());
+    let notifier = shutdown.clone();
 
-        tokio::spawn(handle(stream, db.clone()));
-    }
+    tokio::spawn(async move {
+        match wait_for_signal(signals).await {
+            Ok(()) => {
+                info!("Received shutdown signal");
+                notifier.notify_one();
+            }
+            Err(err) => error!("Failed to wait for signals: {err}"),
+        }
+    });
+
+    serve(listener, db, shutdown).await;
+
+    info!("Shut down");
 
     Ok(())
 }
 
-async fn handle(stream: TcpStream, db: Db) -> Result<(), io::Error> {
-    let stream = RedisProtocol.framed(stream);
-    let (mut sink, mut stream) = stream.split();
-    let (tx, mut rx) = mpsc::unbounded_channel();
+/// Accepts connections until shutdown is notified, then waits for the
+/// connections to reply to the commands they already received.
+async fn serve(listener: TcpListener, db: Db, shutdown: Arc<Notify>) {
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    // Every connection holds a sender, so receiving fails once all of them
+    // are done
+    let (done_tx, mut done_rx) = mpsc::channel::<()>(1);
 
-    tokio::spawn(async move {
-        while let Some(item) = rx.recv().await {
-            if sink.send(item).await.is_err() {
-                break;
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, client_addr) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(_) => break,
+                };
+
+                info!("Client connected from {client_addr}");
+
+                let db = db.clone();
+                let shutdown = shutdown_rx.clone();
+                let done = done_tx.clone();
+
+                tokio::spawn(async move {
+                    let _ = handle(stream, db, shutdown).await;
+                    drop(done);
+                });
+            }
+            _ = shutdown.notified() => break,
+        }
+    }
+
+    drop(listener);
+
+    let _ = shutdown_tx.send(true);
+    drop(done_tx);
+    let _ = done_rx.recv().await;
+}
+
+/// Returns the password passed with `--requirepass`, if any.
+fn requirepass(mut args: impl Iterator<Item = String>) -> Option<String> {
+    args.find(|arg| arg == "--requirepass")?;
+    args.next()
+}
+
+/// Builds the address to listen on, by default 0.0.0.0:6379 like Redis.
+fn listen_addr(bind_address: Option<&str>, port: Option<&str>) -> Result<SocketAddr, String> {
+    let ip = match bind_address {
+        Some(bind_address) => bind_address
+            .parse()
+            .map_err(|_| format!("Invalid bind address '{bind_address}'"))?,
+        None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    };
+
+    let port = match port {
+        Some(port) => match port.parse::<u16>() {
+            Ok(port) if port != 0 => port,
+            _ => {
+                return Err(format!(
+                    "Invalid port '{port}', must be between 1 and 65535"
+                ))
+            }
+        },
+        None => 6379,
+    };
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Binds the listener, describing failures like Redis does.
+async fn bind(addr: SocketAddr) -> Result<TcpListener, String> {
+    TcpListener::bind(addr).await.map_err(|err| {
+        // Strip the " (os error 98)" suffix
+        let err = err.to_string();
+        let reason = err.split(" (os error").next().unwrap_or_default();
+
+        format!("Could not create server TCP listening socket {addr}: bind: {reason}")
+    })
+}
+
+/// Runs a command received from a client and returns the reply, or `None`
+/// if a blocking command was still waiting when the server shut down.
+async fn execute(
+    item: Value,
+    db: &Db,
+    connection: &ConnectionState,
+    shutdown: &mut watch::Receiver<bool>,
+) -> Option<Value> {
+    if let Value::Array(buffer) = item {
+        // RESP2 clients can not tell replies and messages apart
+        if connection.protocol() == ProtocolVersion::Resp2 && !connection.channels().is_empty() {
+            let name = match buffer.first() {
+                Some(Value::BulkString(name)) => String::from_utf8_lossy(name).to_lowercase(),
+                _ => String::new(),
             };
+
+            if !matches!(
+                name.as_str(),
+                "subscribe" | "unsubscribe" | "ping" | "reset"
+            ) {
+                return Some(Value::Error(RedisError {
+                    message: format!("ERR Can't execute '{name}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context"),
+                }));
+            }
         }
-    });
 
-    while let Some(Ok(item)) = stream.next().await {
-        let db = db.clone();
-        let tx = tx.clone();
+        // Only keep a copy around for logging when it is shown
+        let received = log_enabled!(Level::Debug).then(|| Value::Array(buffer.clone()));
+        let parser = CommandParser::new(buffer);
+
+        match parser.parse() {
+            // Blocked clients would keep the server from shutting down, all
+            // other commands finish and reply
+            Ok(command) if command.may_block() => tokio::select! {
+                reply = command.apply(db, connection) => Some(reply),
+                _ = shutdown.changed() => None,
+            },
+            Ok(command) => Some(command.apply(db, connection).await),
+            Err(err) => {
+                if let Some(received) = received {
+                    debug!("Rejected command ({err}):\n{received}");
+                }
 
-        tokio::spawn(async move {
-            let reply = if let Value::Array(buffer) = item {
-                let parser = CommandParser::new(buffer);
+                // A transaction with a command that could not be queued is
+                // never executed
+                connection.fail_transaction();
+
+                Some(Value::Error(RedisError {
+                    message: err.to_string(),
+                }))
+            }
+        }
+    } else {
+        warn!("Received a value that is not a command:\n{item}");
 
-                if let Ok(command) = parser.parse() {
-                    command.apply(&db).await
-                } else {
-                    Value::Error(RedisError {
-                        message: String::from("Failed to parse command"),
-                    })
+        Some(Value::Error(RedisError {
+            message: String::from("Failed to parse command"),
+        }))
+    }
+}
+
+/// Runs the commands of a connection one after another, so that the replies
+/// are in the same order as the commands.
+async fn handle(
+    stream: TcpStream,
+    db: Db,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(), proto::Error> {
+    let max_bulk_len = db
+        .config()
+        .value("proto-max-bulk-len")
+        .parse()
+        .unwrap_or(DEFAULT_MAX_BULK_LEN);
+    let mut stream = RedisProtocol::with_max_bulk_len(max_bulk_len).framed(stream);
+    let connection = ConnectionState::with_message_capacity(db.config().pubsub_message_capacity());
+    let mut messages = connection.take_receiver().unwrap();
+    // Also disconnects when the task panics
+    let _client = db.client_connected();
+
+    let result = async {
+        loop {
+            // Replies to pipelined commands are buffered until no more
+            // commands are immediately available
+            let item = match stream.next().now_or_never() {
+                Some(item) => item,
+                None => {
+                    // A client that does not read blocks the flush, so it is
+                    // dropped without flushing once it fell too far behind
+                    tokio::select! {
+                        flushed = SinkExt::<(Value, ProtocolVersion)>::flush(&mut stream) => flushed?,
+                        _ = connection.overflowed() => return Ok(()),
+                    }
+
+                    tokio::select! {
+                        item = stream.next() => item,
+                        Some(message) = messages.recv() => {
+                            stream.feed((message, connection.protocol())).await?;
+                            continue;
+                        }
+                        _ = connection.overflowed() => return Ok(()),
+                        _ = shutdown.changed() => break,
+                    }
                 }
-            } else {
-                Value::Error(RedisError {
-                    message: String::from("Failed to parse command"),
-                })
             };
 
-            let _ = tx.send(reply);
-        });
+            let item = match item {
+                Some(Ok(item)) => item,
+                // Like Redis, the client learns why it is disconnected
+                Some(Err(proto::Error::ProtocolError(err))) => {
+                    let reply = Value::Error(RedisError {
+                        message: format!("ERR Protocol error: {err}"),
+                    });
+                    stream.feed((reply, connection.protocol())).await?;
+
+                    break;
+                }
+                _ => break,
+            };
+
+            // Like Redis, empty commands are ignored without a reply
+            if matches!(&item, Value::Array(arguments) if arguments.is_empty()) {
+                continue;
+            }
+
+            let Some(reply) = execute(item, &db, &connection, &mut shutdown).await else {
+                break;
+            };
+
+            // Commands with several replies, like subscribing to several
+            // channels, queue all but the last one
+            for reply in connection.take_pending_replies() {
+                stream.feed((reply, connection.protocol())).await?;
+            }
+
+            // HELLO replies in the protocol it switched to
+            stream.feed((reply, connection.protocol())).await?;
+
+            // Keep up with the messages while commands are pipelined
+            while let Ok(message) = messages.try_recv() {
+                stream.feed((message, connection.protocol())).await?;
+            }
+        }
+
+        SinkExt::<(Value, ProtocolVersion)>::flush(&mut stream).await
     }
+    .await;
 
-    Ok(())
+    for channel in connection.channels() {
+        db.pubsub().unsubscribe(&channel, connection.id());
+    }
+
+    result
 }
 
 pub extern "C" fn handler(_: c_int) {
-    std::process::exit(0);
+    // Only async-signal-safe functions may be called here
+    let fd = SIGNAL_PIPE.load(Ordering::Relaxed);
+    unsafe { write(fd, [0u8].as_ptr().cast(), 1) };
 }
 
-unsafe fn set_os_handlers() {
+/// Installs the signal handlers and returns the read end of the pipe they
+/// write to.
+unsafe fn set_os_handlers() -> Result<File, io::Error> {
+    let mut fds = [0; 2];
+
+    if pipe(fds.as_mut_ptr()) == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    for fd in fds {
+        // A full pipe must not block the handler
+        fcntl(fd, F_SETFL, fcntl(fd, F_GETFL) | O_NONBLOCK);
+        fcntl(fd, F_SETFD, FD_CLOEXEC);
+    }
+
+    SIGNAL_PIPE.store(fds[1], Ordering::Relaxed);
+
     signal(SIGINT, handler as extern "C" fn(_) as sighandler_t);
     signal(SIGTERM, handler as extern "C" fn(_) as sighandler_t);
+
+    Ok(File::from_raw_fd(fds[0]))
+}
+
+async fn wait_for_signal(signals: File) -> Result<(), io::Error> {
+    let signals = AsyncFd::new(signals)?;
+
+    loop {
+        let mut guard = signals.readable().await?;
+
+        if let Ok(result) = guard.try_io(|signals| signals.get_ref().read(&mut [0; 1])) {
+            return result.map(|_| ());
+        }
+    }
 }
 
 fn main() -> Result<(), io::Error> {
-    unsafe { set_os_handlers() };
+    let signals = unsafe { set_os_handlers() }?;
 
     if env::var("RUST_LOG").is_err() {
         env::set_var("RUST_LOG", "info");
@@ -105,5 +402,383 @@ fn main() -> Result<(), io::Error> {
         .enable_all()
         .build()
         .unwrap()
-        .block_on(run())
+        .block_on(run(signals))
+}
+
+#[test]
+fn requirepass_works() {
+    let args = |args: &[&str]| {
+        args.iter()
+            .map(|arg| String::from(*arg))
+            .collect::<Vec<_>>()
+            .into_iter()
+    };
+
+    assert_eq!(requirepass(args(&["xylon"])), None);
+    assert_eq!(
+        requirepass(args(&["xylon", "--requirepass", "secret"])),
+        Some(String::from("secret"))
+    );
+    assert_eq!(requirepass(args(&["xylon", "--requirepass"])), None);
+}
+
+#[test]
+fn listen_addr_works() {
+    use std::net::Ipv6Addr;
+
+    let test_data = [
+        (
+            None,
+            None,
+            Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 6379)),
+        ),
+        (
+            Some("127.0.0.1"),
+            Some("7000"),
+            Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 7000)),
+        ),
+        (
+            Some("::1"),
+            None,
+            Ok(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 6379)),
+        ),
+        (
+            Some("localhost"),
+            None,
+            Err(String::from("Invalid bind address 'localhost'")),
+        ),
+        (
+            None,
+            Some("0"),
+            Err(String::from(
+                "Invalid port '0', must be between 1 and 65535",
+            )),
+        ),
+        (
+            None,
+            Some("65536"),
+            Err(String::from(
+                "Invalid port '65536', must be between 1 and 65535",
+            )),
+        ),
+    ];
+
+    for (bind_address, port, expected) in test_data {
+        assert_eq!(listen_addr(bind_address, port), expected);
+    }
+}
+
+#[tokio::test]
+async fn bind_failure_is_described() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let listener = bind(addr).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let err = bind(addr).await.err().unwrap();
+
+    assert_eq!(
+        err,
+        format!(
+            "Could not create server TCP listening socket {addr}: bind: Address already in use"
+        )
+    );
+}
+
+/// A server on a free port for tests, see [`spawn_server`].
+#[cfg(test)]
+struct TestServer {
+    addr: SocketAddr,
+    db: Db,
+    shutdown: Arc<Notify>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(test)]
+impl TestServer {
+    async fn connect(&self) -> tokio_util::codec::Framed<TcpStream, RedisProtocol> {
+        RedisProtocol::default().framed(TcpStream::connect(self.addr).await.unwrap())
+    }
+
+    /// Shuts the server down and waits for it to stop.
+    async fn shutdown(self) {
+        self.shutdown.notify_one();
+
+        tokio::time::timeout(std::time::Duration::from_secs(1), self.task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
+
+/// Runs [`serve`] with an empty database.
+#[cfg(test)]
+async fn spawn_server() -> TestServer {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let listener = bind(addr).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let db = Db::new();
+    let shutdown = Arc::new(Notify::new());
+
+    let task = tokio::spawn(serve(listener, db.clone(), shutdown.clone()));
+
+    TestServer {
+        addr,
+        db,
+        shutdown,
+        task,
+    }
+}
+
+/// A command as clients send it.
+#[cfg(test)]
+fn command(args: &[&str]) -> Value {
+    Value::Array(
+        args.iter()
+            .map(|arg| Value::BulkString(bytes::Bytes::copy_from_slice(arg.as_bytes())))
+            .collect(),
+    )
+}
+
+#[tokio::test]
+async fn unknown_command_keeps_connection() {
+    let server = spawn_server().await;
+    let mut client = server.connect().await;
+
+    for _ in 0..2 {
+        client.send(command(&["FOO"])).await.unwrap();
+
+        let reply = client.next().await.unwrap().unwrap();
+        assert_eq!(
+            reply,
+            Value::Error(RedisError {
+                message: String::from("ERR unknown command 'FOO'"),
+            })
+        );
+    }
+}
+
+#[tokio::test]
+async fn empty_commands_are_ignored() {
+    let server = spawn_server().await;
+    let mut client = server.connect().await;
+
+    // Sent as *0\r\n
+    client.send(command(&[])).await.unwrap();
+
+    let reply = tokio::time::timeout(std::time::Duration::from_millis(100), client.next()).await;
+    assert!(reply.is_err(), "got a reply to an empty command");
+
+    client.send(command(&["PING"])).await.unwrap();
+
+    let reply = client.next().await.unwrap().unwrap();
+    assert_eq!(reply, Value::SimpleString(String::from("PONG")));
+}
+
+#[tokio::test]
+async fn protocol_errors_are_replied_to() {
+    let server = spawn_server().await;
+    let mut client = server.connect().await;
+
+    client.get_mut().writable().await.unwrap();
+    client.get_mut().try_write(b"SET \"key value\r\n").unwrap();
+
+    let reply = client.next().await.unwrap().unwrap();
+    assert_eq!(
+        reply,
+        Value::Error(RedisError {
+            message: String::from("ERR Protocol error: unbalanced quotes in request"),
+        })
+    );
+
+    // The connection is closed afterwards
+    assert!(client.next().await.is_none());
+}
+
+#[tokio::test]
+async fn pipelined_replies_are_ordered() {
+    let server = spawn_server().await;
+    let mut client = server.connect().await;
+
+    // Sent together in a single write
+    client.feed(command(&["SET", "a", "1"])).await.unwrap();
+    client.feed(command(&["INCR", "a"])).await.unwrap();
+    client.feed(command(&["GET", "a"])).await.unwrap();
+    SinkExt::<Value>::flush(&mut client).await.unwrap();
+
+    let expected = [
+        Value::SimpleString(String::from("OK")),
+        Value::Integer(2),
+        Value::BulkString(bytes::Bytes::from("2")),
+    ];
+
+    for expected in expected {
+        assert_eq!(client.next().await.unwrap().unwrap(), expected);
+    }
+}
+
+#[tokio::test]
+async fn pubsub_delivers_messages() {
+    let server = spawn_server().await;
+    let bulk = |string: &'static str| Value::BulkString(bytes::Bytes::from(string));
+
+    let mut subscriber = server.connect().await;
+    let mut publisher = server.connect().await;
+
+    subscriber
+        .send(command(&["SUBSCRIBE", "a", "b"]))
+        .await
+        .unwrap();
+
+    for (channel, count) in [("a", 1), ("b", 2)] {
+        assert_eq!(
+            subscriber.next().await.unwrap().unwrap(),
+            Value::Array(vec![
+                bulk("subscribe"),
+                bulk(channel),
+                Value::Integer(count)
+            ])
+        );
+    }
+
+    // Subscribed connections only accept pub/sub commands
+    subscriber.send(command(&["GET", "a"])).await.unwrap();
+    assert_eq!(
+        subscriber.next().await.unwrap().unwrap(),
+        Value::Error(RedisError {
+            message: String::from("ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context"),
+        })
+    );
+
+    publisher
+        .send(command(&["PUBLISH", "b", "hello"]))
+        .await
+        .unwrap();
+    assert_eq!(publisher.next().await.unwrap().unwrap(), Value::Integer(1));
+
+    assert_eq!(
+        subscriber.next().await.unwrap().unwrap(),
+        Value::Array(vec![bulk("message"), bulk("b"), bulk("hello")])
+    );
+
+    subscriber
+        .send(command(&["UNSUBSCRIBE", "b"]))
+        .await
+        .unwrap();
+    assert_eq!(
+        subscriber.next().await.unwrap().unwrap(),
+        Value::Array(vec![bulk("unsubscribe"), bulk("b"), Value::Integer(1)])
+    );
+
+    publisher
+        .send(command(&["PUBLISH", "b", "hello"]))
+        .await
+        .unwrap();
+    assert_eq!(publisher.next().await.unwrap().unwrap(), Value::Integer(0));
+
+    // Closed connections no longer receive messages
+    drop(subscriber);
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    publisher
+        .send(command(&["PUBLISH", "a", "hello"]))
+        .await
+        .unwrap();
+    assert_eq!(publisher.next().await.unwrap().unwrap(), Value::Integer(0));
+}
+
+#[tokio::test]
+async fn serve_stops_on_shutdown() {
+    let server = spawn_server().await;
+    let addr = server.addr;
+    let mut client = server.connect().await;
+
+    client.send(command(&["PING"])).await.unwrap();
+    assert_eq!(
+        client.next().await.unwrap().unwrap(),
+        Value::SimpleString(String::from("PONG"))
+    );
+
+    server.shutdown().await;
+
+    // The connection was closed and no new ones are accepted
+    assert!(client.next().await.is_none());
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn serve_stops_with_blocked_clients() {
+    let server = spawn_server().await;
+    let mut clients = Vec::new();
+
+    for args in [
+        &["BLPOP", "list", "0"][..],
+        &["XREAD", "BLOCK", "0", "STREAMS", "stream", "$"],
+    ] {
+        let mut client = server.connect().await;
+        client.send(command(args)).await.unwrap();
+
+        // Blocked without a timeout
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(50), client.next())
+                .await
+                .is_err()
+        );
+
+        clients.push(client);
+    }
+
+    // Other commands still reply, like a transaction that is running
+    let mut running = server.connect().await;
+
+    for args in [&["MULTI"][..], &["DEBUG", "SLEEP", "0.2"]] {
+        running.send(command(args)).await.unwrap();
+        running.next().await.unwrap().unwrap();
+    }
+
+    running.send(command(&["EXEC"])).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    server.shutdown().await;
+
+    for mut client in clients {
+        assert!(client.next().await.is_none());
+    }
+
+    assert_eq!(
+        running.next().await.unwrap().unwrap(),
+        Value::Array(vec![Value::SimpleString(String::from("OK"))])
+    );
+    assert!(running.next().await.is_none());
+}
+
+#[tokio::test]
+async fn clients_are_counted() {
+    let server = spawn_server().await;
+
+    let mut first = server.connect().await;
+    let mut second = server.connect().await;
+
+    let mut ids = Vec::new();
+
+    for client in [&mut first, &mut second] {
+        client.send(command(&["CLIENT", "ID"])).await.unwrap();
+
+        match client.next().await.unwrap().unwrap() {
+            Value::Integer(id) => ids.push(id),
+            reply => panic!("CLIENT ID replied with {reply:?}"),
+        }
+    }
+
+    assert_ne!(ids[0], ids[1]);
+
+    first.send(command(&["INFO", "clients"])).await.unwrap();
+    assert_eq!(
+        first.next().await.unwrap().unwrap(),
+        Value::BulkString(bytes::Bytes::from("# Clients\r\nconnected_clients:2\r\n"))
+    );
+
+    // Disconnecting is counted too
+    drop(second);
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert_eq!(server.db.connected_clients(), 1);
 }
@@ -0,0 +1,73 @@
+/// The values stored at keys.
+use bytes::Bytes;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{stream::Stream, zset::SortedSet};
+
+/// The approximate bytes every element of a list, hash or set takes besides
+/// its contents.
+pub const ELEMENT_OVERHEAD: usize = 16;
+
+/// The approximate bytes an element takes, see [`Object::size_estimate`].
+pub fn element_size(element: &str) -> usize {
+    element.len() + ELEMENT_OVERHEAD
+}
+
+#[derive(Clone)]
+pub enum Object {
+    /// Strings are binary-safe and cloning them only bumps a reference
+    /// count.
+    String(Bytes),
+    List(VecDeque<String>),
+    Hash(HashMap<String, String>),
+    Set(HashSet<String>),
+    Stream(Stream),
+    SortedSet(SortedSet),
+}
+
+impl Object {
+    /// The type name reported by `TYPE`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "string",
+            Self::List(_) => "list",
+            Self::Hash(_) => "hash",
+            Self::Set(_) => "set",
+            Self::Stream(_) => "stream",
+            Self::SortedSet(_) => "zset",
+        }
+    }
+
+    /// Returns the value if this is a string.
+    pub fn as_string(&self) -> Option<&Bytes> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Takes the value if this is a string.
+    pub fn into_string(self) -> Option<Bytes> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The approximate bytes the value takes. It is only meant to compare
+    /// values and to enforce maxmemory, not to match the allocations.
+    pub fn size_estimate(&self) -> usize {
+        match self {
+            Self::String(value) => value.len(),
+            Self::List(list) => list.iter().map(|element| element_size(element)).sum(),
+            Self::Hash(hash) => hash
+                .iter()
+                .map(|(field, value)| element_size(field) + element_size(value))
+                .sum(),
+            Self::Set(set) => set.iter().map(|member| element_size(member)).sum(),
+            Self::Stream(stream) => stream.size_estimate(),
+            Self::SortedSet(zset) => zset.size_estimate(),
+        }
+    }
+}
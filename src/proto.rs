@@ -1,36 +1,127 @@
 /// A tokio-util based implementation of the RESP protocol.
 ///
-/// TODO:
-/// - UTF8 validation (with SIMD)
-use bytes::{Buf, BufMut, BytesMut};
-use memchr::memchr_iter;
+/// Bulk strings are binary-safe and kept as bytes, simple strings and errors
+/// have to be valid UTF-8.
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use memchr::{memchr, memchr_iter};
 use tokio_util::codec::{Decoder, Encoder};
 
-use std::io;
+use std::{fmt, io, str::FromStr};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     SimpleString(String),
     Error(RedisError),
     Integer(i64),
-    BulkString(String),
+    BulkString(Bytes),
     Array(Vec<Value>),
+    /// Encoded as a flat array of keys and values in RESP2.
+    Map(Vec<(Value, Value)>),
+    /// Out of band data like pub/sub messages, an array in RESP2.
+    Push(Vec<Value>),
+    /// Encoded as a bulk string in RESP2.
+    Double(f64),
+    /// Encoded as 1 or 0 in RESP2.
+    Boolean(bool),
     NullArray,
     NullString,
 }
 
+/// The protocol version a client negotiated with `HELLO`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProtocolVersion {
+    Resp2,
+    Resp3,
+}
+
 impl Value {
-    pub fn try_as_string(&self) -> Option<String> {
+    /// Writes the value like redis-cli, continuing lines of nested values at
+    /// the given indentation.
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
         match self {
-            Self::SimpleString(string) | Self::BulkString(string) => {
-                Some(string.as_str().to_ascii_uppercase())
+            Self::SimpleString(string) => f.write_str(string),
+            Self::Error(RedisError { message }) => write!(f, "(error) {message}"),
+            Self::Integer(integer) => write!(f, "(integer) {integer}"),
+            Self::Double(double) => write!(f, "(double) {}", format_double(*double)),
+            Self::Boolean(boolean) => write!(f, "({boolean})"),
+            Self::BulkString(bytes) => {
+                f.write_str("\"")?;
+
+                for &byte in bytes.iter() {
+                    match byte {
+                        b'"' => f.write_str("\\\"")?,
+                        b'\\' => f.write_str("\\\\")?,
+                        b'\n' => f.write_str("\\n")?,
+                        b'\r' => f.write_str("\\r")?,
+                        b'\t' => f.write_str("\\t")?,
+                        b' '..=b'~' => write!(f, "{}", byte as char)?,
+                        _ => write!(f, "\\x{byte:02x}")?,
+                    }
+                }
+
+                f.write_str("\"")
             }
-            _ => None,
+            Self::Array(values) | Self::Push(values) if values.is_empty() => {
+                f.write_str("(empty array)")
+            }
+            Self::Array(values) | Self::Push(values) => {
+                let width = values.len().to_string().len();
+
+                for (idx, value) in values.iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, "\n{:indent$}", "")?;
+                    }
+
+                    write!(f, "{:>width$}) ", idx + 1)?;
+                    value.fmt_indented(f, indent + width + 2)?;
+                }
+
+                Ok(())
+            }
+            Self::Map(pairs) if pairs.is_empty() => f.write_str("(empty hash)"),
+            Self::Map(pairs) => {
+                let width = pairs.len().to_string().len();
+
+                for (idx, (key, value)) in pairs.iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, "\n{:indent$}", "")?;
+                    }
+
+                    write!(f, "{:>width$}# ", idx + 1)?;
+                    key.fmt_indented(f, indent + width + 2)?;
+                    f.write_str(" => ")?;
+                    value.fmt_indented(f, indent + width + 2)?;
+                }
+
+                Ok(())
+            }
+            Self::NullArray | Self::NullString => f.write_str("(nil)"),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+/// Renders values for humans in the format of redis-cli, not the wire format.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+/// Formats a double like Redis, which spells NaN in lowercase.
+pub fn format_double(double: f64) -> String {
+    if double.is_nan() {
+        String::from("nan")
+    } else {
+        double.to_string()
+    }
+}
+
+/// Parses a number from the bytes of a string argument or value.
+pub fn parse_bytes<T: FromStr>(bytes: &[u8]) -> Option<T> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct RedisError {
     pub message: String,
 }
@@ -40,13 +131,155 @@ pub enum ProtocolError {
     UnknownType,
     NotAnInteger,
     ExpectedCrlf,
+    InvalidUtf8,
+    InlineTooBig,
+    /// A bulk string length is negative or above the limit.
+    InvalidBulkLength,
+    /// An array length is negative or above the limit.
+    InvalidMultibulkLength,
+    /// An inline command has a quote without its closing quote.
+    UnbalancedQuotes,
+    NotADouble,
+    NotABoolean,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::UnknownType => "unknown type",
+            Self::NotAnInteger => "invalid integer",
+            Self::ExpectedCrlf => "expected CRLF",
+            Self::InvalidUtf8 => "invalid UTF-8",
+            Self::InlineTooBig => "too big inline request",
+            Self::InvalidBulkLength => "invalid bulk length",
+            Self::InvalidMultibulkLength => "invalid multibulk length",
+            Self::UnbalancedQuotes => "unbalanced quotes in request",
+            Self::NotADouble => "invalid double",
+            Self::NotABoolean => "invalid boolean",
+        })
+    }
 }
 
 #[derive(Debug)]
 pub enum ParseError {
     ExpectedString,
     ExpectedInteger,
-    ExpectedAny,
+    ExpectedFloat,
+    WrongArity(&'static str),
+    UnknownSubcommand {
+        command: String,
+        subcommand: String,
+    },
+    InvalidStreamId,
+    UnbalancedStreams(&'static str),
+    NegativeMaxLen,
+    NegativeTimeout,
+    InvalidTimeout,
+    InvalidExpireTime(&'static str),
+    NegativeCount,
+    InvalidCursor,
+    InvalidBitOffset,
+    InvalidBit,
+    OffsetOutOfRange,
+    NegativeNumKeys,
+    TooManyKeys,
+    IncompatibleNxXx,
+    IncompatibleGtLtNx,
+    InvalidScoreBound,
+    InvalidTtl,
+    BadDataFormat,
+    InvalidCoordinates {
+        longitude: f64,
+        latitude: f64,
+    },
+    UnsupportedUnit,
+    NegativeRadius,
+    NegativeBoxSize,
+    GeoSearchCenter,
+    GeoSearchShape,
+    CountNotPositive,
+    /// Options that exclude each other, which Redis reports as a syntax
+    /// error.
+    ConflictingOptions,
+    Syntax,
+    UnknownCommand(String),
+    InvalidUtf8,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ExpectedInteger => f.write_str("ERR value is not an integer or out of range"),
+            Self::ExpectedFloat => f.write_str("ERR value is not a valid float"),
+            Self::WrongArity(command) => {
+                write!(f, "ERR wrong number of arguments for '{command}' command")
+            }
+            Self::UnknownSubcommand {
+                command,
+                subcommand,
+            } => write!(
+                f,
+                "ERR unknown subcommand '{subcommand}'. Try {command} HELP."
+            ),
+            Self::InvalidStreamId => {
+                f.write_str("ERR Invalid stream ID specified as stream command argument")
+            }
+            Self::UnbalancedStreams(command) => write!(
+                f,
+                "ERR Unbalanced '{command}' list of streams: for each stream key an ID or '$' must be specified."
+            ),
+            Self::NegativeMaxLen => f.write_str("ERR The MAXLEN argument must be >= 0."),
+            Self::NegativeTimeout => f.write_str("ERR timeout is negative"),
+            Self::InvalidTimeout => f.write_str("ERR timeout is not a float or out of range"),
+            Self::InvalidExpireTime(command) => {
+                write!(f, "ERR invalid expire time in '{command}' command")
+            }
+            Self::NegativeCount => f.write_str("ERR value is out of range, must be positive"),
+            Self::InvalidCursor => f.write_str("ERR invalid cursor"),
+            Self::InvalidBitOffset => {
+                f.write_str("ERR bit offset is not an integer or out of range")
+            }
+            Self::InvalidBit => f.write_str("ERR bit is not an integer or out of range"),
+            Self::OffsetOutOfRange => f.write_str("ERR offset is out of range"),
+            Self::NegativeNumKeys => f.write_str("ERR Number of keys can't be negative"),
+            Self::TooManyKeys => {
+                f.write_str("ERR Number of keys can't be greater than number of args")
+            }
+            Self::IncompatibleNxXx => {
+                f.write_str("ERR XX and NX options at the same time are not compatible")
+            }
+            Self::IncompatibleGtLtNx => {
+                f.write_str("ERR GT, LT, and/or NX options at the same time are not compatible")
+            }
+            Self::InvalidScoreBound => f.write_str("ERR min or max is not a float"),
+            Self::InvalidTtl => f.write_str("ERR Invalid TTL value, must be >= 0"),
+            Self::BadDataFormat => f.write_str("ERR Bad data format"),
+            Self::InvalidCoordinates {
+                longitude,
+                latitude,
+            } => write!(
+                f,
+                "ERR invalid longitude,latitude pair {longitude:.6},{latitude:.6}"
+            ),
+            Self::UnsupportedUnit => {
+                f.write_str("ERR unsupported unit provided. please use M, KM, FT, MI")
+            }
+            Self::NegativeRadius => f.write_str("ERR radius cannot be negative"),
+            Self::NegativeBoxSize => f.write_str("ERR height or width cannot be negative"),
+            Self::GeoSearchCenter => f.write_str(
+                "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH",
+            ),
+            Self::GeoSearchShape => f.write_str(
+                "ERR exactly one of BYRADIUS and BYBOX arguments must be provided for GEOSEARCH",
+            ),
+            Self::CountNotPositive => f.write_str("ERR COUNT must be > 0"),
+            Self::ExpectedString | Self::ConflictingOptions | Self::Syntax => {
+                f.write_str("ERR syntax error")
+            }
+            Self::UnknownCommand(command) => write!(f, "ERR unknown command '{command}'"),
+            Self::InvalidUtf8 => f.write_str("ERR argument is not valid UTF-8"),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -61,6 +294,17 @@ impl From<io::Error> for Error {
     }
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ProtocolError(err) => write!(f, "Protocol error: {err}"),
+            Self::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 /// Find the next CRLF start byte.
 fn find_next_crlf(input: &[u8]) -> Option<usize> {
     for cr in memchr_iter(b'\r', input) {
@@ -84,8 +328,11 @@ struct ParsedValue {
 }
 
 impl Value {
-    fn parse(src: &[u8]) -> Result<OptionalWithMissingHint<ParsedValue>, Error> {
-        if src.len() < 1 {
+    fn parse(
+        src: &[u8],
+        protocol: &RedisProtocol,
+    ) -> Result<OptionalWithMissingHint<ParsedValue>, Error> {
+        if src.is_empty() {
             return Ok(OptionalWithMissingHint::Missing(1));
         }
 
@@ -94,10 +341,11 @@ impl Value {
                 // Simple string is terminated by CRLF
                 match find_next_crlf(unsafe { src.get_unchecked(1..) }) {
                     Some(crlf_start) => {
-                        let bytes = unsafe { src.get_unchecked(1..crlf_start + 1) }.to_vec();
-                        let string = unsafe { String::from_utf8_unchecked(bytes) };
+                        let bytes = unsafe { src.get_unchecked(1..crlf_start + 1) };
+                        let string = std::str::from_utf8(bytes)
+                            .map_err(|_| Error::ProtocolError(ProtocolError::InvalidUtf8))?;
 
-                        let value = Value::SimpleString(string);
+                        let value = Value::SimpleString(string.to_string());
                         let offset = crlf_start + 3;
 
                         Ok(OptionalWithMissingHint::Some(ParsedValue { value, offset }))
@@ -109,10 +357,13 @@ impl Value {
                 // Error is terminated by CRLF
                 match find_next_crlf(unsafe { src.get_unchecked(1..) }) {
                     Some(crlf_start) => {
-                        let bytes = unsafe { src.get_unchecked(1..crlf_start + 1) }.to_vec();
-                        let string = unsafe { String::from_utf8_unchecked(bytes) };
+                        let bytes = unsafe { src.get_unchecked(1..crlf_start + 1) };
+                        let string = std::str::from_utf8(bytes)
+                            .map_err(|_| Error::ProtocolError(ProtocolError::InvalidUtf8))?;
 
-                        let value = Value::Error(RedisError { message: string });
+                        let value = Value::Error(RedisError {
+                            message: string.to_string(),
+                        });
                         let offset = crlf_start + 3;
 
                         Ok(OptionalWithMissingHint::Some(ParsedValue { value, offset }))
@@ -136,6 +387,29 @@ impl Value {
                     None => Ok(OptionalWithMissingHint::NoClue),
                 }
             }
+            // The RESP3 types without a length are terminated by CRLF as
+            // well
+            b'_' | b',' | b'#' => match find_next_crlf(unsafe { src.get_unchecked(1..) }) {
+                Some(crlf_start) => {
+                    let bytes = unsafe { src.get_unchecked(1..crlf_start + 1) };
+
+                    let value = match (src[0], bytes) {
+                        (b'_', b"") => Value::NullString,
+                        (b',', _) => Value::Double(
+                            parse_bytes(bytes)
+                                .ok_or(Error::ProtocolError(ProtocolError::NotADouble))?,
+                        ),
+                        (b'#', b"t") => Value::Boolean(true),
+                        (b'#', b"f") => Value::Boolean(false),
+                        (b'#', _) => return Err(Error::ProtocolError(ProtocolError::NotABoolean)),
+                        _ => return Err(Error::ProtocolError(ProtocolError::ExpectedCrlf)),
+                    };
+                    let offset = crlf_start + 3;
+
+                    Ok(OptionalWithMissingHint::Some(ParsedValue { value, offset }))
+                }
+                None => Ok(OptionalWithMissingHint::NoClue),
+            },
             b'$' => {
                 let mut offset;
 
@@ -143,16 +417,20 @@ impl Value {
                     Some(crlf_start) => {
                         let bytes = unsafe { src.get_unchecked(1..crlf_start + 1) };
                         offset = crlf_start + 3;
-                        let integer = atoi::atoi(bytes)
-                            .ok_or(Error::ProtocolError(ProtocolError::NotAnInteger))?;
 
-                        integer
+                        atoi::atoi(bytes)
+                            .ok_or(Error::ProtocolError(ProtocolError::NotAnInteger))?
                     }
                     None => return Ok(OptionalWithMissingHint::NoClue),
                 };
 
                 if length != -1 {
-                    let length = length as usize;
+                    // Checked before waiting for the data, which would
+                    // reserve a buffer of that size
+                    let length = usize::try_from(length)
+                        .ok()
+                        .filter(|length| *length <= protocol.max_bulk_len)
+                        .ok_or(Error::ProtocolError(ProtocolError::InvalidBulkLength))?;
                     let rest = unsafe { src.get_unchecked(offset..) };
 
                     // Needs to have a CRLF
@@ -164,13 +442,12 @@ impl Value {
                         return Err(Error::ProtocolError(ProtocolError::ExpectedCrlf));
                     }
 
-                    let bytes = unsafe { rest.get_unchecked(..length) }.to_vec();
-                    let string = unsafe { String::from_utf8_unchecked(bytes) };
+                    let bytes = Bytes::copy_from_slice(unsafe { rest.get_unchecked(..length) });
 
                     offset += length;
                     offset += 2;
 
-                    let value = Value::BulkString(string);
+                    let value = Value::BulkString(bytes);
 
                     Ok(OptionalWithMissingHint::Some(ParsedValue { value, offset }))
                 } else {
@@ -186,21 +463,29 @@ impl Value {
                     Some(crlf_start) => {
                         let bytes = unsafe { src.get_unchecked(1..crlf_start + 1) };
                         offset = crlf_start + 3;
-                        let integer = atoi::atoi(bytes)
-                            .ok_or(Error::ProtocolError(ProtocolError::NotAnInteger))?;
 
-                        integer
+                        atoi::atoi(bytes)
+                            .ok_or(Error::ProtocolError(ProtocolError::NotAnInteger))?
                     }
                     None => return Ok(OptionalWithMissingHint::NoClue),
                 };
 
                 if length != -1 {
-                    let length = length as usize;
+                    let length = usize::try_from(length)
+                        .ok()
+                        .filter(|length| *length <= protocol.max_array_len)
+                        .ok_or(Error::ProtocolError(ProtocolError::InvalidMultibulkLength))?;
 
-                    let mut items = Vec::with_capacity(length);
+                    // Only as much as the received data can fill
+                    let mut items = Vec::with_capacity(length.min(src.len() / 4));
 
                     for _ in 0..length {
-                        match Value::parse(unsafe { src.get_unchecked(offset..) })? {
+                        // Nothing of this frame has been consumed yet, so on a
+                        // partial read the whole frame is parsed again from the
+                        // start once more bytes arrived. The incomplete element
+                        // is always the last thing in the buffer, which makes its
+                        // missing hint valid for the whole frame as well.
+                        match Value::parse(unsafe { src.get_unchecked(offset..) }, protocol)? {
                             OptionalWithMissingHint::Some(value) => {
                                 offset += value.offset;
                                 items.push(value.value);
@@ -223,14 +508,183 @@ impl Value {
     }
 }
 
-pub struct RedisProtocol;
+/// Inline commands longer than this are rejected, the same limit as in
+/// Redis.
+const INLINE_MAX_SIZE: usize = 64 * 1024;
+
+impl Value {
+    /// Parses an inline command like `SET key value\r\n` as typed by telnet
+    /// users into an array of bulk strings.
+    fn parse_inline(src: &[u8]) -> Result<OptionalWithMissingHint<ParsedValue>, Error> {
+        let newline = match memchr(b'\n', src) {
+            Some(newline) if newline <= INLINE_MAX_SIZE => newline,
+            None if src.len() <= INLINE_MAX_SIZE => return Ok(OptionalWithMissingHint::NoClue),
+            _ => return Err(Error::ProtocolError(ProtocolError::InlineTooBig)),
+        };
+
+        // A bare LF ends the line as well
+        let line = &src[..newline];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        let items = split_inline(line)
+            .ok_or(Error::ProtocolError(ProtocolError::UnbalancedQuotes))?
+            .into_iter()
+            .map(Value::BulkString)
+            .collect();
+
+        let value = Value::Array(items);
+        let offset = newline + 1;
+
+        Ok(OptionalWithMissingHint::Some(ParsedValue { value, offset }))
+    }
+}
+
+/// Splits an inline command into its arguments like a shell would, the same
+/// way as Redis. Arguments are separated by whitespace and may be quoted:
+/// Double quotes allow escapes like `\n` and `\xff`, single quotes take
+/// everything literally except for `\'`. A closing quote has to end the
+/// argument.
+///
+/// Returns `None` if the quotes are unbalanced.
+fn split_inline(line: &[u8]) -> Option<Vec<Bytes>> {
+    let mut arguments = Vec::new();
+    let mut position = 0;
+
+    loop {
+        while line.get(position).is_some_and(u8::is_ascii_whitespace) {
+            position += 1;
+        }
+
+        if position == line.len() {
+            return Some(arguments);
+        }
+
+        let mut argument = Vec::new();
+        let mut in_double_quotes = false;
+        let mut in_single_quotes = false;
+
+        loop {
+            let byte = line.get(position).copied();
+            let next = line.get(position + 1).copied();
+
+            if in_double_quotes {
+                // Without two hex digits, the x of \x is taken literally
+                let hex = line
+                    .get(position + 2..position + 4)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+                match (byte?, next, hex) {
+                    (b'\\', Some(b'x'), Some(escaped)) => {
+                        argument.push(escaped);
+                        position += 3;
+                    }
+                    (b'\\', Some(escaped), _) => {
+                        argument.push(match escaped {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            other => other,
+                        });
+                        position += 1;
+                    }
+                    (b'"', next, _) => {
+                        if next.is_some_and(|next| !next.is_ascii_whitespace()) {
+                            return None;
+                        }
+
+                        position += 1;
+                        break;
+                    }
+                    (byte, _, _) => argument.push(byte),
+                }
+            } else if in_single_quotes {
+                match (byte?, next) {
+                    (b'\\', Some(b'\'')) => {
+                        argument.push(b'\'');
+                        position += 1;
+                    }
+                    (b'\'', next) => {
+                        if next.is_some_and(|next| !next.is_ascii_whitespace()) {
+                            return None;
+                        }
+
+                        position += 1;
+                        break;
+                    }
+                    (byte, _) => argument.push(byte),
+                }
+            } else {
+                match byte {
+                    None => break,
+                    Some(byte) if byte.is_ascii_whitespace() => break,
+                    Some(b'"') => in_double_quotes = true,
+                    Some(b'\'') => in_single_quotes = true,
+                    Some(byte) => argument.push(byte),
+                }
+            }
+
+            position += 1;
+        }
+
+        arguments.push(Bytes::from(argument));
+    }
+}
+
+/// The default of `proto-max-bulk-len`, 512 MB like in Redis.
+pub const DEFAULT_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// Arrays can have up to this many elements, the same limit as in Redis.
+const DEFAULT_MAX_ARRAY_LEN: usize = 1024 * 1024;
+
+#[derive(Clone, Copy)]
+pub struct RedisProtocol {
+    /// Longer bulk strings are rejected with a protocol error.
+    max_bulk_len: usize,
+    /// Longer arrays are rejected with a protocol error.
+    max_array_len: usize,
+}
+
+impl RedisProtocol {
+    pub fn new(max_bulk_len: usize, max_array_len: usize) -> Self {
+        Self {
+            max_bulk_len,
+            max_array_len,
+        }
+    }
+
+    /// Limits the length of bulk strings, keeping the default array limit.
+    pub fn with_max_bulk_len(max_bulk_len: usize) -> Self {
+        Self::new(max_bulk_len, DEFAULT_MAX_ARRAY_LEN)
+    }
+}
+
+impl Default for RedisProtocol {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_BULK_LEN, DEFAULT_MAX_ARRAY_LEN)
+    }
+}
 
 impl Decoder for RedisProtocol {
     type Item = Value;
     type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        match Value::parse(src) {
+        let parsed = match src.first() {
+            Some(b'+' | b'-' | b':' | b'$' | b'*' | b'_' | b',' | b'#') | None => {
+                Value::parse(src, self)
+            }
+            // Anything else printable starts an inline command
+            Some(byte) if byte.is_ascii_graphic() || byte.is_ascii_whitespace() => {
+                Value::parse_inline(src)
+            }
+            Some(_) => Err(Error::ProtocolError(ProtocolError::UnknownType)),
+        };
+
+        match parsed {
             Ok(OptionalWithMissingHint::Some(ParsedValue { value, offset })) => {
                 src.advance(offset);
 
@@ -247,10 +701,32 @@ impl Decoder for RedisProtocol {
     }
 }
 
+/// Writes the length prefix of an aggregate type.
+fn encode_length(prefix: u8, length: usize, dst: &mut BytesMut) {
+    let mut buffer = itoa::Buffer::new();
+    let printed = buffer.format(length);
+    dst.reserve(printed.len() + 3);
+    dst.put_u8(prefix);
+    dst.extend_from_slice(printed.as_bytes());
+    dst.extend_from_slice(b"\r\n");
+}
+
 impl Encoder<Value> for RedisProtocol {
     type Error = Error;
 
     fn encode(&mut self, item: Value, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode((item, ProtocolVersion::Resp2), dst)
+    }
+}
+
+impl Encoder<(Value, ProtocolVersion)> for RedisProtocol {
+    type Error = Error;
+
+    fn encode(
+        &mut self,
+        (item, version): (Value, ProtocolVersion),
+        dst: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
         match item {
             Value::SimpleString(string) => {
                 dst.reserve(string.len() + 3);
@@ -260,7 +736,7 @@ impl Encoder<Value> for RedisProtocol {
             }
             Value::Error(RedisError { message }) => {
                 dst.reserve(message.len() + 3);
-                dst.put_u8(b'+');
+                dst.put_u8(b'-');
                 dst.extend_from_slice(message.as_bytes());
                 dst.extend_from_slice(b"\r\n");
             }
@@ -272,28 +748,69 @@ impl Encoder<Value> for RedisProtocol {
                 dst.extend_from_slice(printed.as_bytes());
                 dst.extend_from_slice(b"\r\n");
             }
-            Value::BulkString(string) => {
+            Value::BulkString(bytes) => {
                 let mut buffer = itoa::Buffer::new();
-                let printed = buffer.format(string.len());
-                dst.reserve(printed.len() + string.len() + 5);
+                let printed = buffer.format(bytes.len());
+                dst.reserve(printed.len() + bytes.len() + 5);
                 dst.put_u8(b'$');
                 dst.extend_from_slice(printed.as_bytes());
                 dst.extend_from_slice(b"\r\n");
-                dst.extend_from_slice(string.as_bytes());
+                dst.extend_from_slice(&bytes);
                 dst.extend_from_slice(b"\r\n");
             }
+            Value::Double(double) => {
+                let printed = format_double(double);
+
+                match version {
+                    ProtocolVersion::Resp2 => {
+                        return self.encode((Value::BulkString(Bytes::from(printed)), version), dst)
+                    }
+                    ProtocolVersion::Resp3 => {
+                        dst.reserve(printed.len() + 3);
+                        dst.put_u8(b',');
+                        dst.extend_from_slice(printed.as_bytes());
+                        dst.extend_from_slice(b"\r\n");
+                    }
+                }
+            }
+            Value::Boolean(boolean) => match version {
+                ProtocolVersion::Resp2 => {
+                    return self.encode((Value::Integer(i64::from(boolean)), version), dst)
+                }
+                ProtocolVersion::Resp3 if boolean => dst.extend_from_slice(b"#t\r\n"),
+                ProtocolVersion::Resp3 => dst.extend_from_slice(b"#f\r\n"),
+            },
             Value::Array(array) => {
-                let mut buffer = itoa::Buffer::new();
-                let printed = buffer.format(array.len());
-                dst.reserve(printed.len() + 3);
-                dst.put_u8(b'*');
-                dst.extend_from_slice(printed.as_bytes());
-                dst.extend_from_slice(b"\r\n");
+                encode_length(b'*', array.len(), dst);
 
                 for value in array {
-                    self.encode(value, dst)?;
+                    self.encode((value, version), dst)?;
+                }
+            }
+            Value::Push(values) => {
+                match version {
+                    ProtocolVersion::Resp2 => encode_length(b'*', values.len(), dst),
+                    ProtocolVersion::Resp3 => encode_length(b'>', values.len(), dst),
+                }
+
+                for value in values {
+                    self.encode((value, version), dst)?;
+                }
+            }
+            Value::Map(pairs) => {
+                match version {
+                    ProtocolVersion::Resp2 => encode_length(b'*', pairs.len() * 2, dst),
+                    ProtocolVersion::Resp3 => encode_length(b'%', pairs.len(), dst),
+                }
+
+                for (key, value) in pairs {
+                    self.encode((key, version), dst)?;
+                    self.encode((value, version), dst)?;
                 }
             }
+            Value::NullString | Value::NullArray if version == ProtocolVersion::Resp3 => {
+                dst.extend_from_slice(b"_\r\n");
+            }
             Value::NullString => {
                 dst.extend_from_slice(b"$-1\r\n");
             }
@@ -306,6 +823,58 @@ impl Encoder<Value> for RedisProtocol {
     }
 }
 
+#[test]
+fn display_works() {
+    let bulk = |string: &'static str| Value::BulkString(Bytes::from(string));
+
+    let test_data = [
+        (Value::SimpleString(String::from("OK")), "OK"),
+        (Value::Integer(-3), "(integer) -3"),
+        (bulk("say \"hi\"\r\n"), r#""say \"hi\"\r\n""#),
+        (
+            Value::BulkString(Bytes::from_static(b"a\xffb")),
+            r#""a\xffb""#,
+        ),
+        (Value::NullString, "(nil)"),
+        (Value::Array(Vec::new()), "(empty array)"),
+        (
+            Value::Array(vec![
+                bulk("a"),
+                Value::Array(vec![Value::Integer(1), Value::NullArray]),
+                Value::Error(RedisError {
+                    message: String::from("ERR nope"),
+                }),
+            ]),
+            "1) \"a\"\n2) 1) (integer) 1\n   2) (nil)\n3) (error) ERR nope",
+        ),
+        (
+            Value::Array(vec![
+                bulk("a"),
+                bulk("b"),
+                bulk("c"),
+                bulk("d"),
+                bulk("e"),
+                bulk("f"),
+                bulk("g"),
+                bulk("h"),
+                bulk("i"),
+                Value::Array(vec![bulk("j"), bulk("k")]),
+            ]),
+            " 1) \"a\"\n 2) \"b\"\n 3) \"c\"\n 4) \"d\"\n 5) \"e\"\n 6) \"f\"\n 7) \"g\"\n 8) \"h\"\n 9) \"i\"\n10) 1) \"j\"\n    2) \"k\"",
+        ),
+        (
+            Value::Map(vec![(bulk("proto"), Value::Integer(3))]),
+            "1# \"proto\" => (integer) 3",
+        ),
+        (Value::Double(1.5), "(double) 1.5"),
+        (Value::Boolean(true), "(true)"),
+    ];
+
+    for (value, expected) in test_data {
+        assert_eq!(value.to_string(), expected);
+    }
+}
+
 #[test]
 fn decode_works() {
     use bytes::BufMut;
@@ -323,17 +892,319 @@ fn decode_works() {
         b"*0\r\n",
         b"*2\r\n$5\r\nhello\r\n$5\r\nworld\r\n",
         b"*3\r\n:1\r\n:2\r\n:3\r\n",
-        b"*5\r\n:1\r\n:2\r\n:3\r\n:4\r\n$5\r\nhello\r\n", // TODO: Bulk string is incorrectly not being parsed, everything is None
+        b"*5\r\n:1\r\n:2\r\n:3\r\n:4\r\n$5\r\nhello\r\n",
         b"*-1\r\n",
         b"*2\r\n*3\r\n:1\r\n:2\r\n:3\r\n*2\r\n+Hello\r\n-World\r\n",
         b"*3\r\n$5\r\nhello\r\n$-1\r\n$5\r\nworld\r\n",
         b"*2\r\n$4\r\nLLEN\r\n$6\r\nmylist\r\n",
+        b"_\r\n",
+        b",1.5\r\n",
+        b",-inf\r\n",
+        b"#t\r\n",
+        b"*2\r\n,0.25\r\n#f\r\n",
     ];
 
     for data in test_data {
         let mut input = BytesMut::new();
         input.put_slice(data);
 
-        assert!(matches!(RedisProtocol {}.decode(&mut input), Ok(Some(_))));
+        assert!(matches!(
+            RedisProtocol::default().decode(&mut input),
+            Ok(Some(_))
+        ));
+        assert!(input.is_empty());
+    }
+}
+
+#[test]
+fn decode_binary_bulk_string_works() {
+    let mut input = BytesMut::from(&b"$3\r\na\xffb\r\n"[..]);
+
+    assert_eq!(
+        RedisProtocol::default().decode(&mut input).unwrap(),
+        Some(Value::BulkString(Bytes::from_static(b"a\xffb")))
+    );
+
+    // Simple strings and errors must be valid UTF-8
+    let mut input = BytesMut::from(&b"+a\xffb\r\n"[..]);
+
+    assert!(matches!(
+        RedisProtocol::default().decode(&mut input),
+        Err(Error::ProtocolError(ProtocolError::InvalidUtf8))
+    ));
+}
+
+#[test]
+fn decode_inline_works() {
+    let bulk = |string: &'static str| Value::BulkString(Bytes::from(string));
+    let test_data: &[(&[u8], Value)] = &[
+        (b"PING\r\n", Value::Array(vec![bulk("PING")])),
+        (
+            b"SET k v\r\n",
+            Value::Array(vec![bulk("SET"), bulk("k"), bulk("v")]),
+        ),
+        (
+            b"  get \t key\n",
+            Value::Array(vec![bulk("get"), bulk("key")]),
+        ),
+        (b"\r\n", Value::Array(Vec::new())),
+    ];
+
+    for (data, expected) in test_data {
+        let mut input = BytesMut::from(*data);
+
+        assert_eq!(
+            RedisProtocol::default()
+                .decode(&mut input)
+                .unwrap()
+                .as_ref(),
+            Some(expected)
+        );
+        assert!(input.is_empty());
+    }
+
+    // Incomplete lines wait for more data
+    let mut input = BytesMut::from(&b"PIN"[..]);
+    assert!(matches!(
+        RedisProtocol::default().decode(&mut input),
+        Ok(None)
+    ));
+
+    let mut input = BytesMut::from(&b"\x00\xff\r\n"[..]);
+    assert!(matches!(
+        RedisProtocol::default().decode(&mut input),
+        Err(Error::ProtocolError(ProtocolError::UnknownType))
+    ));
+
+    let mut input = BytesMut::from(&vec![b'a'; INLINE_MAX_SIZE + 1][..]);
+    assert!(matches!(
+        RedisProtocol::default().decode(&mut input),
+        Err(Error::ProtocolError(ProtocolError::InlineTooBig))
+    ));
+}
+
+#[test]
+fn decode_inline_quoting_works() {
+    let bulk = |bytes: &'static [u8]| Value::BulkString(Bytes::from_static(bytes));
+    let test_data: &[(&[u8], Vec<Value>)] = &[
+        (
+            b"SET \"hello world\" 'a b'\r\n",
+            vec![bulk(b"SET"), bulk(b"hello world"), bulk(b"a b")],
+        ),
+        (
+            b"ECHO \"\\x41\\xfF\\n\\t\\\"\\\\\\r\\a\\b\\q\"\r\n",
+            vec![bulk(b"ECHO"), bulk(b"A\xff\n\t\"\\\r\x07\x08q")],
+        ),
+        // Single quotes take escapes literally, except for the quote
+        (
+            b"ECHO '\\x41\\n\\'s'\r\n",
+            vec![bulk(b"ECHO"), bulk(b"\\x41\\n's")],
+        ),
+        (
+            b"ECHO \"\" ''\r\n",
+            vec![bulk(b"ECHO"), bulk(b""), bulk(b"")],
+        ),
+        // Quotes can start within an argument
+        (b"ECHO a\"b c\"\r\n", vec![bulk(b"ECHO"), bulk(b"ab c")]),
+    ];
+
+    for (data, expected) in test_data {
+        let mut input = BytesMut::from(*data);
+
+        assert_eq!(
+            RedisProtocol::default().decode(&mut input).unwrap(),
+            Some(Value::Array(expected.clone())),
+            "{}",
+            String::from_utf8_lossy(data)
+        );
+    }
+
+    for data in [
+        &b"SET \"key value\r\n"[..],
+        b"SET 'key value\r\n",
+        b"SET \"key\"value\r\n",
+        b"SET 'key'value\r\n",
+    ] {
+        let mut input = BytesMut::from(data);
+        let err = RedisProtocol::default().decode(&mut input).unwrap_err();
+
+        assert!(matches!(
+            err,
+            Error::ProtocolError(ProtocolError::UnbalancedQuotes)
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Protocol error: unbalanced quotes in request"
+        );
+    }
+}
+
+#[test]
+fn decode_byte_by_byte_works() {
+    let test_data: &[(&[u8], usize)] = &[
+        (b"*5\r\n:1\r\n:2\r\n:3\r\n:4\r\n$5\r\nhello\r\n", 5),
+        (
+            b"*2\r\n*3\r\n:1\r\n:2\r\n:3\r\n*2\r\n$5\r\nhello\r\n$-1\r\n",
+            2,
+        ),
+    ];
+
+    for (data, expected_len) in test_data {
+        let mut input = BytesMut::new();
+        let mut decoded = None;
+
+        for (idx, byte) in data.iter().enumerate() {
+            input.put_u8(*byte);
+
+            match RedisProtocol::default().decode(&mut input) {
+                Ok(Some(value)) => {
+                    // Only the very last byte may complete the frame
+                    assert_eq!(idx, data.len() - 1);
+                    decoded = Some(value);
+                }
+                Ok(None) => {}
+                Err(err) => panic!("decoding failed: {err}"),
+            }
+        }
+
+        assert!(input.is_empty());
+        assert!(matches!(decoded, Some(Value::Array(items)) if items.len() == *expected_len));
+    }
+}
+
+#[test]
+fn decode_nested_bulk_string_works() {
+    let mut input = BytesMut::new();
+    input.put_slice(b"*5\r\n:1\r\n:2\r\n:3\r\n:4\r\n$5\r\nhel");
+
+    assert!(matches!(
+        RedisProtocol::default().decode(&mut input),
+        Ok(None)
+    ));
+
+    input.put_slice(b"lo\r\n");
+
+    match RedisProtocol::default().decode(&mut input) {
+        Ok(Some(Value::Array(items))) => {
+            assert_eq!(items.len(), 5);
+            assert!(matches!(&items[4], Value::BulkString(string) if string == "hello"));
+        }
+        other => panic!("unexpected decode result: {other:?}"),
+    }
+}
+
+#[test]
+fn decode_oversized_lengths_errors() {
+    let mut protocol = RedisProtocol::new(16, 4);
+
+    let test_data: &[&[u8]] = &[b"$2000000000\r\n", b"$17\r\n", b"$-2\r\n"];
+
+    for data in test_data {
+        let mut input = BytesMut::from(*data);
+
+        assert!(matches!(
+            protocol.decode(&mut input),
+            Err(Error::ProtocolError(ProtocolError::InvalidBulkLength))
+        ));
+        // Nothing was reserved for the announced data
+        assert!(input.capacity() < 64);
+    }
+
+    let mut input = BytesMut::from(&b"*5\r\n"[..]);
+    assert!(matches!(
+        protocol.decode(&mut input),
+        Err(Error::ProtocolError(ProtocolError::InvalidMultibulkLength))
+    ));
+
+    // Lengths within the limits are fine
+    let mut input = BytesMut::from(&b"*1\r\n$16\r\n"[..]);
+    assert!(matches!(protocol.decode(&mut input), Ok(None)));
+}
+
+#[test]
+fn encode_map_works() {
+    let map = || {
+        Value::Map(vec![(
+            Value::BulkString(Bytes::from("proto")),
+            Value::Integer(3),
+        )])
+    };
+
+    let mut dst = BytesMut::new();
+    RedisProtocol::default().encode(map(), &mut dst).unwrap();
+    assert_eq!(&dst[..], b"*2\r\n$5\r\nproto\r\n:3\r\n");
+
+    let mut dst = BytesMut::new();
+    RedisProtocol::default()
+        .encode((map(), ProtocolVersion::Resp3), &mut dst)
+        .unwrap();
+    assert_eq!(&dst[..], b"%1\r\n$5\r\nproto\r\n:3\r\n");
+}
+
+#[test]
+fn encode_push_works() {
+    let push = || Value::Push(vec![Value::BulkString(Bytes::from("message"))]);
+
+    let mut dst = BytesMut::new();
+    RedisProtocol::default().encode(push(), &mut dst).unwrap();
+    assert_eq!(&dst[..], b"*1\r\n$7\r\nmessage\r\n");
+
+    let mut dst = BytesMut::new();
+    RedisProtocol::default()
+        .encode((push(), ProtocolVersion::Resp3), &mut dst)
+        .unwrap();
+    assert_eq!(&dst[..], b">1\r\n$7\r\nmessage\r\n");
+}
+
+#[test]
+fn encode_null_works() {
+    let test_data: &[(Value, ProtocolVersion, &[u8])] = &[
+        (Value::NullString, ProtocolVersion::Resp2, b"$-1\r\n"),
+        (Value::NullArray, ProtocolVersion::Resp2, b"*-1\r\n"),
+        (Value::NullString, ProtocolVersion::Resp3, b"_\r\n"),
+        (Value::NullArray, ProtocolVersion::Resp3, b"_\r\n"),
+    ];
+
+    for (value, version, expected) in test_data {
+        let mut dst = BytesMut::new();
+        RedisProtocol::default()
+            .encode((value.clone(), *version), &mut dst)
+            .unwrap();
+        assert_eq!(&dst[..], *expected);
+    }
+}
+
+#[test]
+fn encode_double_and_boolean_works() {
+    let test_data: &[(Value, ProtocolVersion, &[u8])] = &[
+        (Value::Double(1.5), ProtocolVersion::Resp2, b"$3\r\n1.5\r\n"),
+        (Value::Double(1.5), ProtocolVersion::Resp3, b",1.5\r\n"),
+        (Value::Double(3.0), ProtocolVersion::Resp3, b",3\r\n"),
+        (
+            Value::Double(f64::NEG_INFINITY),
+            ProtocolVersion::Resp3,
+            b",-inf\r\n",
+        ),
+        (Value::Double(f64::NAN), ProtocolVersion::Resp3, b",nan\r\n"),
+        (Value::Boolean(true), ProtocolVersion::Resp2, b":1\r\n"),
+        (Value::Boolean(false), ProtocolVersion::Resp2, b":0\r\n"),
+        (Value::Boolean(true), ProtocolVersion::Resp3, b"#t\r\n"),
+        (Value::Boolean(false), ProtocolVersion::Resp3, b"#f\r\n"),
+    ];
+
+    for (value, version, expected) in test_data {
+        let mut dst = BytesMut::new();
+        RedisProtocol::default()
+            .encode((value.clone(), *version), &mut dst)
+            .unwrap();
+        assert_eq!(&dst[..], *expected);
+
+        // Replies in RESP3 can be read back
+        if *version == ProtocolVersion::Resp3 && !matches!(value, Value::Double(d) if d.is_nan()) {
+            assert_eq!(
+                RedisProtocol::default().decode(&mut dst).unwrap(),
+                Some(value.clone())
+            );
+        }
     }
 }
@@ -0,0 +1,164 @@
+/// The channels of `SUBSCRIBE` and `PUBLISH`.
+///
+/// Messages are sent to the subscribed connections through their message
+/// senders, which are kept per connection id.
+use bytes::Bytes;
+use dashmap::DashMap;
+use std::collections::HashMap;
+
+use crate::{connection::MessageSender, proto::Value};
+
+/// Publish keyspace events to `__keyspace@0__:<key>`.
+pub const KEYSPACE: u32 = 1 << 0;
+/// Publish keyspace events to `__keyevent@0__:<event>`.
+pub const KEYEVENT: u32 = 1 << 1;
+/// Generic commands like DEL, EXPIRE and RENAME.
+pub const GENERIC: u32 = 1 << 2;
+pub const STRING: u32 = 1 << 3;
+pub const LIST: u32 = 1 << 4;
+pub const SET: u32 = 1 << 5;
+pub const HASH: u32 = 1 << 6;
+pub const ZSET: u32 = 1 << 7;
+/// Keys removed because they expired.
+pub const EXPIRED: u32 = 1 << 8;
+/// Keys removed because of maxmemory.
+pub const EVICTED: u32 = 1 << 9;
+pub const STREAM: u32 = 1 << 10;
+/// Every class except for key misses and new keys, like `A` in Redis.
+const ALL: u32 = GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM;
+
+/// Parses the value of `notify-keyspace-events` into the flags above, see
+/// https://redis.io/docs/manual/keyspace-notifications/.
+///
+/// Returns `None` if it contains an unknown class.
+pub fn parse_keyspace_events(classes: &str) -> Option<u32> {
+    classes.chars().try_fold(0, |flags, class| {
+        let flag = match class {
+            'K' => KEYSPACE,
+            'E' => KEYEVENT,
+            'g' => GENERIC,
+            '$' => STRING,
+            'l' => LIST,
+            's' => SET,
+            'h' => HASH,
+            'z' => ZSET,
+            'x' => EXPIRED,
+            'e' => EVICTED,
+            't' => STREAM,
+            'A' => ALL,
+            _ => return None,
+        };
+
+        Some(flags | flag)
+    })
+}
+
+pub struct PubSub {
+    channels: DashMap<String, HashMap<u64, MessageSender>>,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self {
+            channels: DashMap::new(),
+        }
+    }
+
+    pub fn subscribe(&self, channel: String, id: u64, sender: MessageSender) {
+        self.channels.entry(channel).or_default().insert(id, sender);
+    }
+
+    pub fn unsubscribe(&self, channel: &str, id: u64) {
+        if let Some(mut subscribers) = self.channels.get_mut(channel) {
+            subscribers.remove(&id);
+        }
+
+        self.channels
+            .remove_if(channel, |_, subscribers| subscribers.is_empty());
+    }
+
+    /// Sends the message to every subscriber of the channel.
+    ///
+    /// Returns how many subscribers received it.
+    pub fn publish(&self, channel: &str, message: Bytes) -> usize {
+        let Some(subscribers) = self.channels.get(channel) else {
+            return 0;
+        };
+
+        let message = Value::Push(vec![
+            Value::BulkString(Bytes::from_static(b"message")),
+            Value::BulkString(Bytes::copy_from_slice(channel.as_bytes())),
+            Value::BulkString(message),
+        ]);
+
+        // Senders of closed connections fail until they unsubscribe
+        subscribers
+            .values()
+            .filter(|sender| sender.send(message.clone()))
+            .count()
+    }
+
+    /// Publishes a keyspace event of the class if the flags enable it.
+    pub fn notify_keyspace_event(&self, flags: u32, class: u32, event: &str, key: &str) {
+        if flags & class == 0 {
+            return;
+        }
+
+        if flags & KEYSPACE != 0 {
+            self.publish(
+                &format!("__keyspace@0__:{key}"),
+                Bytes::copy_from_slice(event.as_bytes()),
+            );
+        }
+
+        if flags & KEYEVENT != 0 {
+            self.publish(
+                &format!("__keyevent@0__:{event}"),
+                Bytes::copy_from_slice(key.as_bytes()),
+            );
+        }
+    }
+}
+
+#[test]
+fn parse_keyspace_events_works() {
+    assert_eq!(parse_keyspace_events(""), Some(0));
+    assert_eq!(parse_keyspace_events("Ex"), Some(KEYEVENT | EXPIRED));
+    assert_eq!(parse_keyspace_events("KA"), Some(KEYSPACE | ALL));
+    assert_eq!(parse_keyspace_events("Kq"), None);
+}
+
+#[tokio::test]
+async fn slow_subscribers_are_dropped() {
+    use futures_util::FutureExt;
+
+    use crate::connection::ConnectionState;
+
+    let pubsub = PubSub::new();
+    let connection = ConnectionState::with_message_capacity(8);
+    let mut messages = connection.take_receiver().unwrap();
+
+    pubsub.subscribe(
+        String::from("channel"),
+        connection.id(),
+        connection.messages().clone(),
+    );
+
+    // Publishing never waits for the subscriber to read
+    for _ in 0..8 {
+        assert_eq!(pubsub.publish("channel", Bytes::from("message")), 1);
+    }
+
+    assert!(connection.overflowed().now_or_never().is_none());
+    assert_eq!(pubsub.publish("channel", Bytes::from("message")), 0);
+    assert!(connection.overflowed().now_or_never().is_some());
+
+    // Nothing beyond the capacity was buffered
+    let mut received = 0;
+
+    while messages.try_recv().is_ok() {
+        received += 1;
+    }
+
+    assert_eq!(received, 8);
+}
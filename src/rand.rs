@@ -0,0 +1,70 @@
+/// A small xorshift random number generator for picking random members and
+/// keys. It is not suitable for anything security related.
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Makes generators created in the same instant differ.
+static SEED_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Scrambles the seed, see <https://prng.di.unimi.it/splitmix64.c>.
+fn splitmix64(mut seed: u64) -> u64 {
+    seed = seed.wrapping_add(0x9E3779B97F4A7C15);
+    seed = (seed ^ (seed >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    seed = (seed ^ (seed >> 27)).wrapping_mul(0x94D049BB133111EB);
+
+    seed ^ (seed >> 31)
+}
+
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a generator with a fresh seed.
+    pub fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let counter = SEED_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+        Self::with_seed(nanos ^ splitmix64(counter))
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        // The state must never be zero
+        let state = splitmix64(seed).max(1);
+
+        Self { state }
+    }
+
+    /// Returns the next number, see xorshift64* by Sebastiano Vigna.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// Returns a number in `0..bound`, bound must not be 0.
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+#[test]
+fn rng_below_stays_in_bounds() {
+    let mut rng = Rng::with_seed(42);
+    let mut seen = [false; 10];
+
+    for _ in 0..1000 {
+        let n = rng.below(10);
+        seen[n] = true;
+    }
+
+    assert!(seen.iter().all(|seen| *seen));
+    assert_ne!(Rng::new().next_u64(), Rng::new().next_u64());
+}
@@ -0,0 +1,750 @@
+/// The stream data type.
+///
+/// Entries are kept ordered by their ID, which consists of a millisecond
+/// timestamp and a sequence number to tell entries of the same millisecond
+/// apart.
+use bytes::Bytes;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    ops::Bound,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    dump::{Decoder, Encoder},
+    object::element_size,
+    proto::{RedisError, Value},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: Self = Self { ms: 0, seq: 0 };
+    pub const MAX: Self = Self {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Parses `ms-seq`, or just `ms` with the sequence number defaulting to
+    /// `missing_seq`.
+    pub fn parse(id: &str, missing_seq: u64) -> Option<Self> {
+        match id.split_once('-') {
+            Some((ms, seq)) => Some(Self {
+                ms: ms.parse().ok()?,
+                seq: seq.parse().ok()?,
+            }),
+            None => Some(Self {
+                ms: id.parse().ok()?,
+                seq: missing_seq,
+            }),
+        }
+    }
+
+    /// The ID directly after this one, if any.
+    fn next(self) -> Option<Self> {
+        if let Some(seq) = self.seq.checked_add(1) {
+            Some(Self { ms: self.ms, seq })
+        } else {
+            self.ms.checked_add(1).map(|ms| Self { ms, seq: 0 })
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Parses an `XRANGE` bound. `-` and `+` are the smallest and greatest IDs,
+/// a leading `(` makes the bound exclusive and a missing sequence number
+/// defaults to `missing_seq`.
+pub fn parse_bound(bound: &str, missing_seq: u64) -> Option<Bound<StreamId>> {
+    match bound {
+        "-" => Some(Bound::Included(StreamId::MIN)),
+        "+" => Some(Bound::Included(StreamId::MAX)),
+        _ => match bound.strip_prefix('(') {
+            Some(id) => StreamId::parse(id, missing_seq).map(Bound::Excluded),
+            None => StreamId::parse(bound, missing_seq).map(Bound::Included),
+        },
+    }
+}
+
+/// The ID argument of `XADD`.
+pub enum IdSpec {
+    /// `*`, generated from the current time.
+    Auto,
+    /// `ms-*`, with the sequence number generated.
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+impl IdSpec {
+    pub fn parse(id: &str) -> Option<Self> {
+        if id == "*" {
+            Some(Self::Auto)
+        } else if let Some(ms) = id.strip_suffix("-*") {
+            ms.parse().ok().map(Self::AutoSeq)
+        } else {
+            StreamId::parse(id, 0).map(Self::Explicit)
+        }
+    }
+}
+
+pub struct StreamEntry {
+    pub id: StreamId,
+    /// `None` if the entry was trimmed while it was pending.
+    pub fields: Option<Vec<(String, String)>>,
+}
+
+impl StreamEntry {
+    /// The reply format of an entry, its ID followed by the flattened
+    /// field-value pairs.
+    pub fn to_value(&self) -> Value {
+        let fields = match &self.fields {
+            Some(fields) => Value::Array(
+                fields
+                    .iter()
+                    .flat_map(|(field, value)| {
+                        [
+                            Value::BulkString(Bytes::from(field.clone())),
+                            Value::BulkString(Bytes::from(value.clone())),
+                        ]
+                    })
+                    .collect(),
+            ),
+            None => Value::NullArray,
+        };
+
+        Value::Array(vec![
+            Value::BulkString(Bytes::from(self.id.to_string())),
+            fields,
+        ])
+    }
+}
+
+/// How `XTRIM` and `XADD` bound the size of a stream.
+pub enum TrimStrategy {
+    /// Keep at most this many entries.
+    MaxLen(usize),
+    /// Remove entries with smaller IDs.
+    MinId(StreamId),
+}
+
+fn error(message: &str) -> Value {
+    Value::Error(RedisError {
+        message: String::from(message),
+    })
+}
+
+/// An entry delivered to a consumer but not acknowledged yet.
+#[derive(Clone)]
+struct PendingEntry {
+    consumer: String,
+    delivered_at: Instant,
+    deliveries: u64,
+}
+
+/// A pending entry as reported by `XPENDING`.
+pub struct PendingInfo {
+    pub id: StreamId,
+    pub consumer: String,
+    /// The time since the entry was last delivered.
+    pub idle: Duration,
+    pub deliveries: u64,
+}
+
+/// Selects the pending entries reported by `XPENDING`.
+pub struct PendingFilter {
+    pub start: Bound<StreamId>,
+    pub end: Bound<StreamId>,
+    pub count: Option<usize>,
+    pub consumer: Option<String>,
+    pub min_idle: Duration,
+}
+
+impl Default for PendingFilter {
+    fn default() -> Self {
+        Self {
+            start: Bound::Unbounded,
+            end: Bound::Unbounded,
+            count: None,
+            consumer: None,
+            min_idle: Duration::ZERO,
+        }
+    }
+}
+
+/// Whether the range is empty in a way `BTreeMap::range` would panic on.
+fn is_empty_range(start: Bound<StreamId>, end: Bound<StreamId>) -> bool {
+    match (start, end) {
+        (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) => start > end,
+        _ => false,
+    }
+}
+
+/// The approximate bytes an entry takes, its ID and fields.
+pub fn entry_size(fields: &[(String, String)]) -> usize {
+    std::mem::size_of::<StreamId>()
+        + fields
+            .iter()
+            .map(|(field, value)| element_size(field) + element_size(value))
+            .sum::<usize>()
+}
+
+#[derive(Clone, Default)]
+struct ConsumerGroup {
+    /// The ID of the last entry delivered to any consumer of the group.
+    last_delivered: StreamId,
+    /// The pending entries list of all consumers.
+    pending: BTreeMap<StreamId, PendingEntry>,
+}
+
+#[derive(Clone, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Vec<(String, String)>>,
+    /// The ID of the last added entry, which new IDs must be greater than.
+    last_id: StreamId,
+    groups: HashMap<String, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// The approximate bytes the entries take, see
+    /// [`Object::size_estimate`](crate::object::Object::size_estimate).
+    /// Consumer groups are not counted.
+    pub fn size_estimate(&self) -> usize {
+        self.entries.values().map(|fields| entry_size(fields)).sum()
+    }
+
+    /// Writes the entries and consumer groups into a `DUMP` payload. The
+    /// delivery times of pending entries are not kept.
+    pub fn serialize(&self, encoder: &mut Encoder) {
+        encoder.put_len(self.entries.len());
+
+        for (id, fields) in &self.entries {
+            encoder.put_stream_id(*id);
+            encoder.put_len(fields.len());
+
+            for (field, value) in fields {
+                encoder.put_bytes(field.as_bytes());
+                encoder.put_bytes(value.as_bytes());
+            }
+        }
+
+        encoder.put_stream_id(self.last_id);
+        encoder.put_len(self.groups.len());
+
+        for (name, group) in &self.groups {
+            encoder.put_bytes(name.as_bytes());
+            encoder.put_stream_id(group.last_delivered);
+            encoder.put_len(group.pending.len());
+
+            for (id, pending) in &group.pending {
+                encoder.put_stream_id(*id);
+                encoder.put_bytes(pending.consumer.as_bytes());
+                encoder.put_u64(pending.deliveries);
+            }
+        }
+    }
+
+    /// Reads a stream written by [`Stream::serialize`]. Pending entries count
+    /// as delivered just now.
+    pub fn deserialize(decoder: &mut Decoder) -> Option<Self> {
+        let mut stream = Self::new();
+
+        for _ in 0..decoder.get_len()? {
+            let id = decoder.get_stream_id()?;
+            let mut fields = Vec::new();
+
+            for _ in 0..decoder.get_len()? {
+                let field = decoder.get_string()?;
+                let value = decoder.get_string()?;
+
+                fields.push((field, value));
+            }
+
+            stream.entries.insert(id, fields);
+        }
+
+        stream.last_id = decoder.get_stream_id()?;
+
+        for _ in 0..decoder.get_len()? {
+            let name = decoder.get_string()?;
+            let mut group = ConsumerGroup {
+                last_delivered: decoder.get_stream_id()?,
+                pending: BTreeMap::new(),
+            };
+
+            for _ in 0..decoder.get_len()? {
+                let id = decoder.get_stream_id()?;
+                let pending = PendingEntry {
+                    consumer: decoder.get_string()?,
+                    delivered_at: Instant::now(),
+                    deliveries: decoder.get_u64()?,
+                };
+
+                group.pending.insert(id, pending);
+            }
+
+            stream.groups.insert(name, group);
+        }
+
+        Some(stream)
+    }
+
+    /// Adds an entry with an ID greater than all previous ones.
+    ///
+    /// Returns the ID of the entry or the error to reply with.
+    pub fn add(&mut self, id: IdSpec, fields: Vec<(String, String)>) -> Result<StreamId, Value> {
+        let id = match id {
+            IdSpec::Auto => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64;
+
+                if now > self.last_id.ms {
+                    StreamId { ms: now, seq: 0 }
+                } else {
+                    // The clock went backwards or this millisecond already
+                    // has entries
+                    self.last_id.next().ok_or_else(|| {
+                        error("ERR The stream has exhausted the last possible ID, unable to add more items")
+                    })?
+                }
+            }
+            IdSpec::AutoSeq(ms) => {
+                let id = if ms == self.last_id.ms {
+                    self.last_id
+                        .seq
+                        .checked_add(1)
+                        .map(|seq| StreamId { ms, seq })
+                } else if ms > self.last_id.ms {
+                    Some(StreamId { ms, seq: 0 })
+                } else {
+                    None
+                };
+
+                id.ok_or_else(|| {
+                    error("ERR The ID specified in XADD is equal or smaller than the target stream top item")
+                })?
+            }
+            IdSpec::Explicit(id) => id,
+        };
+
+        if id == StreamId::MIN {
+            return Err(error(
+                "ERR The ID specified in XADD must be greater than 0-0",
+            ));
+        }
+
+        if id <= self.last_id {
+            return Err(error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+            ));
+        }
+
+        self.entries.insert(id, fields);
+        self.last_id = id;
+
+        Ok(id)
+    }
+
+    /// Creates a consumer group that delivers entries after last_delivered,
+    /// or only new entries if it is `None`.
+    pub fn create_group(
+        &mut self,
+        name: String,
+        last_delivered: Option<StreamId>,
+    ) -> Result<(), Value> {
+        if self.groups.contains_key(&name) {
+            return Err(error("BUSYGROUP Consumer Group name already exists"));
+        }
+
+        let group = ConsumerGroup {
+            last_delivered: last_delivered.unwrap_or(self.last_id),
+            pending: BTreeMap::new(),
+        };
+        self.groups.insert(name, group);
+
+        Ok(())
+    }
+
+    pub fn has_group(&self, name: &str) -> bool {
+        self.groups.contains_key(name)
+    }
+
+    /// Reads entries for a consumer of a group. Without an ID the entries
+    /// never delivered to the group are read and added to the pending entries
+    /// of the consumer, unless noack is set. With an ID the pending entries
+    /// of the consumer after it are read again.
+    ///
+    /// Returns `None` if the group does not exist.
+    pub fn read_group(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        id: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+    ) -> Option<Vec<StreamEntry>> {
+        let group = self.groups.get_mut(group)?;
+        let count = count.unwrap_or(usize::MAX);
+        let now = Instant::now();
+
+        let entries = match id {
+            None => {
+                let entries: Vec<StreamEntry> = self
+                    .entries
+                    .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+                    .take(count)
+                    .map(|(id, fields)| StreamEntry {
+                        id: *id,
+                        fields: Some(fields.clone()),
+                    })
+                    .collect();
+
+                if let Some(last) = entries.last() {
+                    group.last_delivered = last.id;
+                }
+
+                if !noack {
+                    for entry in &entries {
+                        let pending = PendingEntry {
+                            consumer: consumer.to_string(),
+                            delivered_at: now,
+                            deliveries: 1,
+                        };
+                        group.pending.insert(entry.id, pending);
+                    }
+                }
+
+                entries
+            }
+            Some(after) => group
+                .pending
+                .range_mut((Bound::Excluded(after), Bound::Unbounded))
+                .filter(|(_, pending)| pending.consumer == consumer)
+                .take(count)
+                .map(|(id, pending)| {
+                    pending.delivered_at = now;
+                    pending.deliveries += 1;
+
+                    StreamEntry {
+                        id: *id,
+                        fields: self.entries.get(id).cloned(),
+                    }
+                })
+                .collect(),
+        };
+
+        Some(entries)
+    }
+
+    /// Acknowledges entries of a group, removing them from its pending
+    /// entries.
+    ///
+    /// Returns how many entries were pending.
+    pub fn ack(&mut self, group: &str, ids: &[StreamId]) -> usize {
+        match self.groups.get_mut(group) {
+            Some(group) => ids
+                .iter()
+                .filter(|id| group.pending.remove(id).is_some())
+                .count(),
+            None => 0,
+        }
+    }
+
+    /// Returns the pending entries of a group selected by the filter, or
+    /// `None` if the group does not exist.
+    pub fn pending(&self, group: &str, filter: &PendingFilter) -> Option<Vec<PendingInfo>> {
+        let group = self.groups.get(group)?;
+
+        if is_empty_range(filter.start, filter.end) {
+            return Some(Vec::new());
+        }
+
+        let now = Instant::now();
+
+        let pending = group
+            .pending
+            .range((filter.start, filter.end))
+            .filter(|(_, pending)| {
+                filter
+                    .consumer
+                    .as_ref()
+                    .is_none_or(|consumer| &pending.consumer == consumer)
+            })
+            .map(|(id, pending)| PendingInfo {
+                id: *id,
+                consumer: pending.consumer.clone(),
+                idle: now.duration_since(pending.delivered_at),
+                deliveries: pending.deliveries,
+            })
+            .filter(|info| info.idle >= filter.min_idle)
+            .take(filter.count.unwrap_or(usize::MAX))
+            .collect();
+
+        Some(pending)
+    }
+
+    /// Removes the oldest entries according to the strategy. Entries stay in
+    /// the pending entries lists of consumer groups.
+    ///
+    /// Returns the number of removed entries.
+    pub fn trim(&mut self, strategy: &TrimStrategy) -> usize {
+        let retained = match strategy {
+            TrimStrategy::MaxLen(max_len) => {
+                let excess = self.entries.len().saturating_sub(*max_len);
+
+                match self.entries.keys().nth(excess) {
+                    Some(first) => self.entries.split_off(&first.clone()),
+                    None => BTreeMap::new(),
+                }
+            }
+            TrimStrategy::MinId(min_id) => self.entries.split_off(min_id),
+        };
+
+        let removed = std::mem::replace(&mut self.entries, retained);
+
+        removed.len()
+    }
+
+    /// Returns at most count entries between start and end in order.
+    pub fn range(
+        &self,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+    ) -> Vec<StreamEntry> {
+        if is_empty_range(start, end) {
+            return Vec::new();
+        }
+
+        self.entries
+            .range((start, end))
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| StreamEntry {
+                id: *id,
+                fields: Some(fields.clone()),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+fn fields() -> Vec<(String, String)> {
+    vec![(String::from("field"), String::from("value"))]
+}
+
+#[test]
+fn stream_auto_ids_increase() {
+    let mut stream = Stream::new();
+
+    let mut previous = StreamId::MIN;
+
+    for _ in 0..1000 {
+        let id = stream.add(IdSpec::Auto, fields()).unwrap();
+        assert!(id > previous);
+        previous = id;
+    }
+
+    // IDs from the future are respected by auto-generated IDs
+    let future = StreamId {
+        ms: u64::MAX,
+        seq: 5,
+    };
+    stream.add(IdSpec::Explicit(future), fields()).unwrap();
+
+    let id = stream.add(IdSpec::Auto, fields()).unwrap();
+    assert_eq!(
+        id,
+        StreamId {
+            ms: u64::MAX,
+            seq: 6
+        }
+    );
+
+    let id = stream.add(IdSpec::AutoSeq(u64::MAX), fields()).unwrap();
+    assert_eq!(
+        id,
+        StreamId {
+            ms: u64::MAX,
+            seq: 7
+        }
+    );
+
+    assert_eq!(stream.len(), 1003);
+}
+
+#[test]
+fn stream_add_rejects_smaller_ids() {
+    let mut stream = Stream::new();
+
+    assert!(stream
+        .add(IdSpec::Explicit(StreamId::MIN), fields())
+        .is_err());
+
+    stream
+        .add(IdSpec::Explicit(StreamId { ms: 5, seq: 1 }), fields())
+        .unwrap();
+
+    assert!(stream
+        .add(IdSpec::Explicit(StreamId { ms: 5, seq: 1 }), fields())
+        .is_err());
+    assert!(stream
+        .add(IdSpec::Explicit(StreamId { ms: 4, seq: 9 }), fields())
+        .is_err());
+    assert!(stream.add(IdSpec::AutoSeq(4), fields()).is_err());
+
+    assert_eq!(
+        stream.add(IdSpec::AutoSeq(5), fields()).unwrap(),
+        StreamId { ms: 5, seq: 2 }
+    );
+    assert_eq!(
+        stream.add(IdSpec::AutoSeq(6), fields()).unwrap(),
+        StreamId { ms: 6, seq: 0 }
+    );
+}
+
+#[test]
+fn stream_id_parse_works() {
+    assert_eq!(
+        StreamId::parse("1526919030474-55", 0),
+        Some(StreamId {
+            ms: 1526919030474,
+            seq: 55
+        })
+    );
+    assert_eq!(
+        StreamId::parse("1526919030474", u64::MAX),
+        Some(StreamId {
+            ms: 1526919030474,
+            seq: u64::MAX
+        })
+    );
+    assert_eq!(StreamId::parse("-", 0), None);
+    assert_eq!(StreamId::parse("1-", 0), None);
+    assert_eq!(StreamId::parse("a-1", 0), None);
+    assert!(matches!(IdSpec::parse("*"), Some(IdSpec::Auto)));
+    assert!(matches!(IdSpec::parse("7-*"), Some(IdSpec::AutoSeq(7))));
+    assert!(IdSpec::parse("x-*").is_none());
+}
+
+#[test]
+fn stream_consumers_get_distinct_entries() {
+    let mut stream = Stream::new();
+
+    for seq in 1..=4 {
+        stream
+            .add(IdSpec::Explicit(StreamId { ms: 1, seq }), fields())
+            .unwrap();
+    }
+
+    stream
+        .create_group(String::from("group"), Some(StreamId::MIN))
+        .unwrap();
+    assert!(stream.create_group(String::from("group"), None).is_err());
+
+    let ids =
+        |entries: Vec<StreamEntry>| entries.iter().map(|entry| entry.id.seq).collect::<Vec<_>>();
+
+    let alice = stream
+        .read_group("group", "alice", None, Some(2), false)
+        .unwrap();
+    let bob = stream
+        .read_group("group", "bob", None, None, false)
+        .unwrap();
+    assert_eq!(ids(alice), [1, 2]);
+    assert_eq!(ids(bob), [3, 4]);
+    assert!(stream
+        .read_group("group", "alice", None, None, false)
+        .unwrap()
+        .is_empty());
+    assert!(stream
+        .read_group("missing", "alice", None, None, false)
+        .is_none());
+
+    // Only the consumer's own pending entries are delivered again
+    let alice = stream
+        .read_group("group", "alice", Some(StreamId::MIN), None, false)
+        .unwrap();
+    assert_eq!(ids(alice), [1, 2]);
+
+    let pending = stream.pending("group", &PendingFilter::default()).unwrap();
+    assert_eq!(pending.len(), 4);
+    assert_eq!(pending[0].deliveries, 2);
+    assert_eq!(pending[2].consumer, "bob");
+
+    let acked = [
+        StreamId { ms: 1, seq: 1 },
+        StreamId { ms: 1, seq: 1 },
+        StreamId { ms: 9, seq: 9 },
+    ];
+    assert_eq!(stream.ack("group", &acked), 1);
+
+    let alice = stream
+        .read_group("group", "alice", Some(StreamId::MIN), None, false)
+        .unwrap();
+    assert_eq!(ids(alice), [2]);
+    let filter = PendingFilter {
+        consumer: Some(String::from("alice")),
+        ..PendingFilter::default()
+    };
+    assert_eq!(stream.pending("group", &filter).unwrap().len(), 1);
+    assert_eq!(
+        stream
+            .pending("group", &PendingFilter::default())
+            .unwrap()
+            .len(),
+        3
+    );
+}
+
+#[test]
+fn stream_trim_works() {
+    let mut stream = Stream::new();
+
+    for seq in 1..=5 {
+        stream
+            .add(IdSpec::Explicit(StreamId { ms: 1, seq }), fields())
+            .unwrap();
+    }
+
+    assert_eq!(stream.trim(&TrimStrategy::MaxLen(10)), 0);
+    assert_eq!(stream.trim(&TrimStrategy::MaxLen(3)), 2);
+    assert_eq!(stream.len(), 3);
+    assert_eq!(
+        stream.trim(&TrimStrategy::MinId(StreamId { ms: 1, seq: 5 })),
+        2
+    );
+    assert_eq!(stream.trim(&TrimStrategy::MaxLen(0)), 1);
+    assert_eq!(stream.len(), 0);
+
+    // Trimming never allows smaller IDs to be added again
+    assert!(stream
+        .add(IdSpec::Explicit(StreamId { ms: 1, seq: 5 }), fields())
+        .is_err());
+}
@@ -0,0 +1,177 @@
+/// The sorted set data type.
+///
+/// Members are kept in a map for looking up their scores and in a tree
+/// ordered by score for ranges. Members with the same score are ordered
+/// lexicographically.
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+    ops::Bound,
+};
+
+use crate::object::element_size;
+
+/// A score that is never NaN, which makes scores totally ordered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Which members `ZADD` writes, set by NX and XX.
+#[derive(Clone, Copy)]
+pub enum AddCondition {
+    Always,
+    OnlyNew,
+    OnlyExisting,
+}
+
+/// Which scores of existing members `ZADD` changes, set by GT and LT. New
+/// members are added regardless.
+#[derive(Clone, Copy)]
+pub enum ScoreUpdate {
+    Always,
+    OnlyGreater,
+    OnlyLess,
+}
+
+/// What adding a member did, see [`SortedSet::add`].
+#[derive(Debug, PartialEq)]
+pub enum AddOutcome {
+    Added,
+    Updated,
+    Unchanged,
+}
+
+/// Parses a `ZRANGEBYSCORE` bound. Infinities are spelled `-inf` and `+inf`
+/// and a leading `(` makes the bound exclusive.
+pub fn parse_bound(bound: &str) -> Option<Bound<f64>> {
+    let (score, exclusive) = match bound.strip_prefix('(') {
+        Some(score) => (score, true),
+        None => (bound, false),
+    };
+
+    let score: f64 = score.parse().ok().filter(|score: &f64| !score.is_nan())?;
+
+    if exclusive {
+        Some(Bound::Excluded(score))
+    } else {
+        Some(Bound::Included(score))
+    }
+}
+
+/// The approximate bytes a member takes, see
+/// [`Object::size_estimate`](crate::object::Object::size_estimate). Members
+/// are stored twice, along with their score.
+pub fn member_size(member: &str) -> usize {
+    2 * (element_size(member) + std::mem::size_of::<f64>())
+}
+
+#[derive(Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    ordered: BTreeSet<(Score, String)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds the member or changes its score if the condition and update
+    /// rule allow it. The score must not be NaN.
+    pub fn add(
+        &mut self,
+        member: String,
+        score: f64,
+        condition: AddCondition,
+        update: ScoreUpdate,
+    ) -> AddOutcome {
+        let Some(&current) = self.scores.get(&member) else {
+            if matches!(condition, AddCondition::OnlyExisting) {
+                return AddOutcome::Unchanged;
+            }
+
+            self.ordered.insert((Score(score), member.clone()));
+            self.scores.insert(member, score);
+
+            return AddOutcome::Added;
+        };
+
+        let allowed = match update {
+            ScoreUpdate::Always => true,
+            ScoreUpdate::OnlyGreater => score > current,
+            ScoreUpdate::OnlyLess => score < current,
+        };
+
+        if matches!(condition, AddCondition::OnlyNew) || !allowed || score == current {
+            return AddOutcome::Unchanged;
+        }
+
+        let (_, member) = self.ordered.take(&(Score(current), member)).unwrap();
+        self.scores.insert(member.clone(), score);
+        self.ordered.insert((Score(score), member));
+
+        AddOutcome::Updated
+    }
+
+    /// The members and their scores from the lowest to the highest score.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&String, f64)> {
+        self.ordered.iter().map(|(score, member)| (member, score.0))
+    }
+
+    /// The members and their scores within the bounds from the lowest to the
+    /// highest score.
+    pub fn range_by_score(
+        &self,
+        min: Bound<f64>,
+        max: Bound<f64>,
+    ) -> impl Iterator<Item = (&String, f64)> {
+        // The empty member is the first one of every score
+        let start = match min {
+            Bound::Included(score) | Bound::Excluded(score) => {
+                Bound::Included((Score(score), String::new()))
+            }
+            Bound::Unbounded => Bound::Unbounded,
+        };
+
+        self.ordered
+            .range((start, Bound::Unbounded))
+            .map(|(score, member)| (member, score.0))
+            .skip_while(move |(_, score)| matches!(min, Bound::Excluded(min) if *score == min))
+            .take_while(move |(_, score)| match max {
+                Bound::Included(max) => *score <= max,
+                Bound::Excluded(max) => *score < max,
+                Bound::Unbounded => true,
+            })
+    }
+
+    /// The members that are stored twice, see [`member_size`].
+    pub fn members(&self) -> impl Iterator<Item = &String> {
+        self.scores.keys()
+    }
+
+    /// The approximate bytes the members take, see [`member_size`].
+    pub fn size_estimate(&self) -> usize {
+        self.members().map(|member| member_size(member)).sum()
+    }
+}